@@ -0,0 +1,771 @@
+use std::fmt;
+
+use crate::command::{CommandArgs, RedisCommandError};
+use crate::object::{normalize_range, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::string::{get_string, put_string, MAX_STRING_SIZE};
+use crate::value::{RedisBulkString, RedisValue};
+
+// the number of bits of the largest string
+const MAX_BITS: u64 = MAX_STRING_SIZE as u64 * 8;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BitOperation {
+    And,
+    Or,
+    Xor,
+    Not,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BitFieldOverflow {
+    Wrap,
+    Sat,
+    Fail,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct BitFieldType {
+    pub signed: bool,
+    pub bits: u8,
+}
+
+impl fmt::Display for BitFieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", if self.signed { "i" } else { "u" }, self.bits)
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum BitFieldOp {
+    // the offsets are in bits, `#` offsets are resolved when parsing
+    Get(BitFieldType, u64),
+    Set(BitFieldType, u64, i64),
+    IncrBy(BitFieldType, u64, i64),
+    // the overflow behavior of the following `SET`s and `INCRBY`s
+    Overflow(BitFieldOverflow),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum BitmapCommand {
+    SetBit(RedisBulkString, u64, bool),
+    GetBit(RedisBulkString, u64),
+    // start and end, both inclusive
+    BitCount(RedisBulkString, Option<(i64, i64, BitUnit)>),
+    // the bit looked for, then the optional start and end
+    BitPos(RedisBulkString, bool, Option<i64>, Option<i64>, BitUnit),
+    // the destination key, then the source keys
+    BitOp(BitOperation, RedisBulkString, Vec<RedisBulkString>),
+    BitField(RedisBulkString, Vec<BitFieldOp>),
+    BitFieldRo(RedisBulkString, Vec<BitFieldOp>),
+}
+
+fn invalid(message: &str) -> RedisCommandError {
+    RedisCommandError::Invalid(message.to_string())
+}
+
+fn next_bit_offset(args: &mut CommandArgs) -> Result<u64, RedisCommandError> {
+    match args.next_string()?.parse::<u64>() {
+        Ok(offset) if offset < MAX_BITS => Ok(offset),
+        _ => Err(invalid("bit offset is not an integer or out of range")),
+    }
+}
+
+fn next_unit(args: &mut CommandArgs) -> Result<BitUnit, RedisCommandError> {
+    if args.is_empty() {
+        return Ok(BitUnit::Byte);
+    }
+    match args.next_string()?.to_lowercase().as_str() {
+        "byte" => Ok(BitUnit::Byte),
+        "bit" => Ok(BitUnit::Bit),
+        _ => Err(RedisCommandError::IlleagalArg),
+    }
+}
+
+fn parse_bitfield_op(
+    args: &mut CommandArgs,
+    read_only: bool,
+) -> Result<BitFieldOp, RedisCommandError> {
+    let name = args.next_string()?.to_lowercase();
+    if read_only && name != "get" {
+        return Err(invalid("BITFIELD_RO only supports the GET subcommand"));
+    }
+    if name == "overflow" {
+        return match args.next_string()?.to_lowercase().as_str() {
+            "wrap" => Ok(BitFieldOp::Overflow(BitFieldOverflow::Wrap)),
+            "sat" => Ok(BitFieldOp::Overflow(BitFieldOverflow::Sat)),
+            "fail" => Ok(BitFieldOp::Overflow(BitFieldOverflow::Fail)),
+            _ => Err(invalid("Invalid OVERFLOW type specified")),
+        };
+    }
+    if !matches!(name.as_str(), "get" | "set" | "incrby") {
+        return Err(RedisCommandError::IlleagalArg);
+    }
+    let ty = args.next_string()?;
+    let signed = ty.starts_with(['i', 'I']);
+    let ty = match ty.get(1..).and_then(|bits| bits.parse::<u8>().ok()) {
+        Some(bits) if ty.starts_with(['u', 'U']) && (1..=63).contains(&bits) => BitFieldType {
+            signed: false,
+            bits,
+        },
+        Some(bits) if signed && (1..=64).contains(&bits) => BitFieldType { signed, bits },
+        _ => {
+            return Err(invalid(
+                "Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.",
+            ))
+        }
+    };
+    // `#n` is the offset of the n-th field of the type
+    let offset = args.next_string()?;
+    let offset = match offset.strip_prefix('#') {
+        Some(index) => index
+            .parse::<u64>()
+            .ok()
+            .and_then(|index| index.checked_mul(ty.bits as u64)),
+        None => offset.parse::<u64>().ok(),
+    };
+    let offset = match offset {
+        Some(offset)
+            if offset
+                .checked_add(ty.bits as u64)
+                .is_some_and(|end| end <= MAX_BITS) =>
+        {
+            offset
+        }
+        _ => return Err(invalid("bit offset is not an integer or out of range")),
+    };
+    Ok(match name.as_str() {
+        "get" => BitFieldOp::Get(ty, offset),
+        "set" => BitFieldOp::Set(ty, offset, args.next_integer()?),
+        _ => BitFieldOp::IncrBy(ty, offset, args.next_integer()?),
+    })
+}
+
+impl BitmapCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<BitmapCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "setbit" => {
+                let key = args.next_key()?;
+                let offset = next_bit_offset(&mut args)?;
+                let value = match args.next_string()?.as_str() {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(invalid("bit is not an integer or out of range")),
+                };
+                BitmapCommand::SetBit(key, offset, value)
+            }
+            "getbit" => BitmapCommand::GetBit(args.next_key()?, next_bit_offset(&mut args)?),
+            "bitcount" => {
+                let key = args.next_key()?;
+                let range = if args.is_empty() {
+                    None
+                } else {
+                    let start = args.next_integer()?;
+                    if args.is_empty() {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    Some((start, args.next_integer()?, next_unit(&mut args)?))
+                };
+                BitmapCommand::BitCount(key, range)
+            }
+            "bitpos" => {
+                let key = args.next_key()?;
+                let bit = match args.next_string()?.as_str() {
+                    "0" => false,
+                    "1" => true,
+                    _ => return Err(invalid("The bit argument must be 1 or 0.")),
+                };
+                let start = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_integer()?),
+                };
+                let end = match args.is_empty() {
+                    true => None,
+                    false => Some(args.next_integer()?),
+                };
+                BitmapCommand::BitPos(key, bit, start, end, next_unit(&mut args)?)
+            }
+            "bitop" => {
+                let operation = match args.next_string()?.to_lowercase().as_str() {
+                    "and" => BitOperation::And,
+                    "or" => BitOperation::Or,
+                    "xor" => BitOperation::Xor,
+                    "not" => BitOperation::Not,
+                    _ => return Err(RedisCommandError::IlleagalArg),
+                };
+                let destination = args.next_key()?;
+                let keys = args.rest_keys()?;
+                if operation == BitOperation::Not && keys.len() != 1 {
+                    return Err(invalid(
+                        "BITOP NOT must be called with a single source key.",
+                    ));
+                }
+                BitmapCommand::BitOp(operation, destination, keys)
+            }
+            "bitfield" | "bitfield_ro" => {
+                let key = args.next_key()?;
+                let read_only = command_name == "bitfield_ro";
+                let mut ops = Vec::new();
+                while !args.is_empty() {
+                    ops.push(parse_bitfield_op(&mut args, read_only)?);
+                }
+                match read_only {
+                    true => BitmapCommand::BitFieldRo(key, ops),
+                    false => BitmapCommand::BitField(key, ops),
+                }
+            }
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    // the destination key for `BITOP`
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            BitmapCommand::SetBit(key, _, _)
+            | BitmapCommand::GetBit(key, _)
+            | BitmapCommand::BitCount(key, _)
+            | BitmapCommand::BitPos(key, _, _, _, _)
+            | BitmapCommand::BitOp(_, key, _)
+            | BitmapCommand::BitField(key, _)
+            | BitmapCommand::BitFieldRo(key, _) => key,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            BitmapCommand::SetBit(..) | BitmapCommand::BitOp(..) | BitmapCommand::BitField(..)
+        )
+    }
+}
+
+impl From<&BitmapCommand> for Vec<RedisValue> {
+    fn from(bitmap_command: &BitmapCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let unit = |unit: &BitUnit| match unit {
+            BitUnit::Byte => RedisValue::bulk_string("byte"),
+            BitUnit::Bit => RedisValue::bulk_string("bit"),
+        };
+        let number = |n: String| RedisValue::bulk_string(n.as_str());
+        match bitmap_command {
+            BitmapCommand::SetBit(key, offset, value) => {
+                let mut vs = command("setbit", key);
+                vs.push(number(offset.to_string()));
+                vs.push(number((*value as u8).to_string()));
+                vs
+            }
+            BitmapCommand::GetBit(key, offset) => {
+                let mut vs = command("getbit", key);
+                vs.push(number(offset.to_string()));
+                vs
+            }
+            BitmapCommand::BitCount(key, range) => {
+                let mut vs = command("bitcount", key);
+                if let Some((start, end, u)) = range {
+                    vs.push(number(start.to_string()));
+                    vs.push(number(end.to_string()));
+                    vs.push(unit(u));
+                }
+                vs
+            }
+            BitmapCommand::BitPos(key, bit, start, end, u) => {
+                let mut vs = command("bitpos", key);
+                vs.push(number((*bit as u8).to_string()));
+                vs.extend(start.map(|start| number(start.to_string())));
+                if let Some(end) = end {
+                    vs.push(number(end.to_string()));
+                    vs.push(unit(u));
+                }
+                vs
+            }
+            BitmapCommand::BitOp(operation, destination, keys) => {
+                let operation = match operation {
+                    BitOperation::And => "and",
+                    BitOperation::Or => "or",
+                    BitOperation::Xor => "xor",
+                    BitOperation::Not => "not",
+                };
+                let mut vs = vec![
+                    RedisValue::bulk_string("bitop"),
+                    RedisValue::bulk_string(operation),
+                    destination.into(),
+                ];
+                vs.extend(keys.iter().map(|key| key.into()));
+                vs
+            }
+            BitmapCommand::BitField(key, ops) | BitmapCommand::BitFieldRo(key, ops) => {
+                let mut vs = match bitmap_command {
+                    BitmapCommand::BitFieldRo(..) => command("bitfield_ro", key),
+                    _ => command("bitfield", key),
+                };
+                for op in ops {
+                    match op {
+                        BitFieldOp::Get(ty, offset) => {
+                            vs.push(RedisValue::bulk_string("get"));
+                            vs.push(number(ty.to_string()));
+                            vs.push(number(offset.to_string()));
+                        }
+                        BitFieldOp::Set(ty, offset, value)
+                        | BitFieldOp::IncrBy(ty, offset, value) => {
+                            vs.push(RedisValue::bulk_string(match op {
+                                BitFieldOp::Set(..) => "set",
+                                _ => "incrby",
+                            }));
+                            vs.push(number(ty.to_string()));
+                            vs.push(number(offset.to_string()));
+                            vs.push(number(value.to_string()));
+                        }
+                        BitFieldOp::Overflow(overflow) => {
+                            vs.push(RedisValue::bulk_string("overflow"));
+                            vs.push(RedisValue::bulk_string(match overflow {
+                                BitFieldOverflow::Wrap => "wrap",
+                                BitFieldOverflow::Sat => "sat",
+                                BitFieldOverflow::Fail => "fail",
+                            }));
+                        }
+                    }
+                }
+                vs
+            }
+        }
+    }
+}
+
+// the `bits` bits from the bit `offset`, the most significant bit of a byte being its first,
+// the bits past the end of the string are zeros
+fn get_bits(s: &[u8], offset: u64, bits: u8) -> u64 {
+    let mut value = 0;
+    for position in offset..offset + bits as u64 {
+        let bit = s
+            .get((position / 8) as usize)
+            .map_or(0, |byte| (byte >> (7 - position % 8)) & 1);
+        value = (value << 1) | bit as u64;
+    }
+    value
+}
+
+// the string is grown with zeros to hold the bits
+fn set_bits(s: &mut Vec<u8>, offset: u64, bits: u8, value: u64) {
+    let length = (offset + bits as u64).div_ceil(8) as usize;
+    if s.len() < length {
+        s.resize(length, 0);
+    }
+    for (i, position) in (offset..offset + bits as u64).enumerate() {
+        let bit = (value >> (bits as usize - 1 - i)) & 1;
+        let byte = &mut s[(position / 8) as usize];
+        let mask = 1 << (7 - position % 8);
+        if bit == 1 {
+            *byte |= mask;
+        } else {
+            *byte &= !mask;
+        }
+    }
+}
+
+// the value of a field from its bits
+fn field_value(ty: BitFieldType, raw: u64) -> i64 {
+    if ty.signed && ty.bits < 64 && raw >> (ty.bits - 1) == 1 {
+        (raw | (u64::MAX << ty.bits)) as i64
+    } else {
+        raw as i64
+    }
+}
+
+// the value to store in a field, none when it overflows with `FAIL`
+fn field_overflow(ty: BitFieldType, value: i128, overflow: BitFieldOverflow) -> Option<i128> {
+    let (min, max) = if ty.signed {
+        (-(1i128 << (ty.bits - 1)), (1i128 << (ty.bits - 1)) - 1)
+    } else {
+        (0, (1i128 << ty.bits) - 1)
+    };
+    if (min..=max).contains(&value) {
+        return Some(value);
+    }
+    match overflow {
+        BitFieldOverflow::Wrap => {
+            let raw = (value & ((1i128 << ty.bits) - 1)) as u64;
+            Some(field_value(ty, raw) as i128)
+        }
+        BitFieldOverflow::Sat => Some(value.clamp(min, max)),
+        BitFieldOverflow::Fail => None,
+    }
+}
+
+// the string of the key, created empty if the key does not exist
+fn get_or_create<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<&'a mut Vec<u8>, RedisValue> {
+    if get_string(store, key, now, mode)?.is_none() {
+        put_string(store, key, Vec::new(), now, mode);
+    }
+    Ok(get_string(store, key, now, mode)?.unwrap())
+}
+
+// the first position of `bit` in the bits of the range, both inclusive
+fn bit_position(s: &[u8], bit: bool, start: u64, end: u64) -> Option<u64> {
+    (start..=end).find(|position| get_bits(s, *position, 1) == bit as u64)
+}
+
+pub fn execute(
+    store: &mut Store,
+    command: &BitmapCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> RedisValue {
+    match run(store, command, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    }
+}
+
+fn run(
+    store: &mut Store,
+    command: &BitmapCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let key: String = command.key().into();
+    let response = match command {
+        BitmapCommand::SetBit(_, offset, value) => {
+            let s = get_or_create(store, &key, now, mode)?;
+            let old = get_bits(s, *offset, 1);
+            set_bits(s, *offset, 1, *value as u64);
+            RedisValue::Integer(old as i64)
+        }
+        BitmapCommand::GetBit(_, offset) => {
+            let s = get_string(store, &key, now, mode)?.map_or(&[][..], |s| s.as_slice());
+            RedisValue::Integer(get_bits(s, *offset, 1) as i64)
+        }
+        BitmapCommand::BitCount(_, range) => {
+            let s = get_string(store, &key, now, mode)?.map_or(&[][..], |s| s.as_slice());
+            let count = match range {
+                None => s.iter().map(|byte| byte.count_ones() as u64).sum(),
+                Some((start, end, BitUnit::Byte)) => match normalize_range(*start, *end, s.len()) {
+                    Some((start, end)) => s[start..=end]
+                        .iter()
+                        .map(|byte| byte.count_ones() as u64)
+                        .sum(),
+                    None => 0,
+                },
+                Some((start, end, BitUnit::Bit)) => {
+                    match normalize_range(*start, *end, s.len() * 8) {
+                        Some((start, end)) => (start as u64..=end as u64)
+                            .map(|position| get_bits(s, position, 1))
+                            .sum(),
+                        None => 0,
+                    }
+                }
+            };
+            RedisValue::Integer(count as i64)
+        }
+        BitmapCommand::BitPos(_, bit, start, end, unit) => {
+            let s = match get_string(store, &key, now, mode)? {
+                Some(s) => s.as_slice(),
+                // a missing key is an infinite string of zeros
+                None => return Ok(RedisValue::Integer(if *bit { -1 } else { 0 })),
+            };
+            let length = match unit {
+                BitUnit::Byte => s.len(),
+                BitUnit::Bit => s.len() * 8,
+            };
+            let range = normalize_range(start.unwrap_or(0), end.unwrap_or(-1), length);
+            let (first, last) = match (range, unit) {
+                (None, _) => return Ok(RedisValue::Integer(-1)),
+                (Some((first, last)), BitUnit::Byte) => (first as u64 * 8, last as u64 * 8 + 7),
+                (Some((first, last)), BitUnit::Bit) => (first as u64, last as u64),
+            };
+            match bit_position(s, *bit, first, last) {
+                Some(position) => RedisValue::Integer(position as i64),
+                // without an end, the string is seen as padded with zeros on the right
+                None if !*bit && end.is_none() => RedisValue::Integer(last as i64 + 1),
+                None => RedisValue::Integer(-1),
+            }
+        }
+        BitmapCommand::BitOp(operation, _, keys) => {
+            let mut sources = Vec::with_capacity(keys.len());
+            for key in keys {
+                let key: String = key.into();
+                sources.push(
+                    get_string(store, &key, now, mode)?
+                        .cloned()
+                        .unwrap_or_default(),
+                );
+            }
+            // the shorter strings are padded with zeros
+            let length = sources.iter().map(|s| s.len()).max().unwrap_or(0);
+            let byte = |s: &Vec<u8>, i: usize| s.get(i).copied().unwrap_or(0);
+            let result: Vec<u8> = (0..length)
+                .map(|i| {
+                    let mut bytes = sources.iter().map(|s| byte(s, i));
+                    let first = bytes.next().unwrap_or(0);
+                    match operation {
+                        BitOperation::And => bytes.fold(first, |a, b| a & b),
+                        BitOperation::Or => bytes.fold(first, |a, b| a | b),
+                        BitOperation::Xor => bytes.fold(first, |a, b| a ^ b),
+                        BitOperation::Not => !first,
+                    }
+                })
+                .collect();
+            if result.is_empty() {
+                store.remove(&key);
+            } else {
+                store.insert(
+                    key,
+                    StoreItem {
+                        value: RedisObject::String(result),
+                        expired_at: 0,
+                    },
+                );
+            }
+            RedisValue::Integer(length as i64)
+        }
+        BitmapCommand::BitField(_, ops) | BitmapCommand::BitFieldRo(_, ops) => {
+            // the type of the key is checked even if there are no operations
+            get_string(store, &key, now, mode)?;
+            let mut overflow = BitFieldOverflow::Wrap;
+            let mut values = Vec::new();
+            for op in ops {
+                let value = match *op {
+                    BitFieldOp::Overflow(o) => {
+                        overflow = o;
+                        continue;
+                    }
+                    BitFieldOp::Get(ty, offset) => {
+                        let s =
+                            get_string(store, &key, now, mode)?.map_or(&[][..], |s| s.as_slice());
+                        Some(field_value(ty, get_bits(s, offset, ty.bits)))
+                    }
+                    BitFieldOp::Set(ty, offset, value) => {
+                        // an unsigned field reads the value as its two's complement
+                        let value = match ty.signed {
+                            true => value as i128,
+                            false => value as u64 as i128,
+                        };
+                        match field_overflow(ty, value, overflow) {
+                            Some(value) => {
+                                let s = get_or_create(store, &key, now, mode)?;
+                                let old = field_value(ty, get_bits(s, offset, ty.bits));
+                                set_bits(s, offset, ty.bits, value as u64);
+                                Some(old)
+                            }
+                            None => None,
+                        }
+                    }
+                    BitFieldOp::IncrBy(ty, offset, increment) => {
+                        let s =
+                            get_string(store, &key, now, mode)?.map_or(&[][..], |s| s.as_slice());
+                        let old = field_value(ty, get_bits(s, offset, ty.bits));
+                        match field_overflow(ty, old as i128 + increment as i128, overflow) {
+                            Some(value) => {
+                                let s = get_or_create(store, &key, now, mode)?;
+                                set_bits(s, offset, ty.bits, value as u64);
+                                Some(value as i64)
+                            }
+                            None => None,
+                        }
+                    }
+                };
+                values.push(match value {
+                    Some(value) => RedisValue::Integer(value),
+                    None => RedisValue::null_bulk_string(),
+                });
+            }
+            RedisValue::Array(values)
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    fn set_string(store: &mut Store, key: &str, value: &[u8]) {
+        put_string(store, key, value.to_vec(), 0, ExpiryMode::Delete);
+    }
+
+    #[test]
+    fn test_bits() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["setbit", "b", "7", "1"])
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["setbit", "b", "7", "0"])
+        );
+        run_command(&mut store, &["setbit", "b", "10", "1"]);
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["getbit", "b", "10"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["getbit", "b", "1000"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR bit is not an integer or out of range"),
+            run_command(&mut store, &["setbit", "b", "1", "2"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR bit offset is not an integer or out of range"),
+            run_command(&mut store, &["setbit", "b", "4294967296", "1"])
+        );
+
+        set_string(&mut store, "s", b"foobar");
+        let count = |store: &mut Store, args: &[&str]| {
+            let mut vs = vec!["bitcount", "s"];
+            vs.extend_from_slice(args);
+            run_command(store, &vs)
+        };
+        assert_eq!(RedisValue::Integer(26), count(&mut store, &[]));
+        assert_eq!(RedisValue::Integer(4), count(&mut store, &["0", "0"]));
+        assert_eq!(RedisValue::Integer(6), count(&mut store, &["1", "1"]));
+        assert_eq!(
+            RedisValue::Integer(17),
+            count(&mut store, &["5", "30", "bit"])
+        );
+        assert_eq!(RedisValue::Integer(0), count(&mut store, &["3", "1"]));
+        assert_eq!(
+            RedisValue::error("ERR syntax error"),
+            count(&mut store, &["1"])
+        );
+
+        set_string(&mut store, "p", b"\xff\xf0\x00");
+        let position = |store: &mut Store, args: &[&str]| {
+            let mut vs = vec!["bitpos", "p"];
+            vs.extend_from_slice(args);
+            run_command(store, &vs)
+        };
+        assert_eq!(RedisValue::Integer(12), position(&mut store, &["0"]));
+        assert_eq!(RedisValue::Integer(0), position(&mut store, &["1"]));
+        assert_eq!(RedisValue::Integer(-1), position(&mut store, &["1", "2"]));
+        assert_eq!(
+            RedisValue::Integer(7),
+            position(&mut store, &["1", "7", "15", "bit"])
+        );
+        set_string(&mut store, "p", b"\xff\xff");
+        assert_eq!(RedisValue::Integer(16), position(&mut store, &["0"]));
+        assert_eq!(
+            RedisValue::Integer(-1),
+            position(&mut store, &["0", "0", "-1"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["bitpos", "missing", "0"])
+        );
+    }
+
+    #[test]
+    fn test_bitop() {
+        let mut store = Store::default();
+        set_string(&mut store, "a", b"\x0f\xff");
+        set_string(&mut store, "b", b"\x3c");
+        let bytes = |store: &Store, key: &str| match &store[key].value {
+            RedisObject::String(s) => s.clone(),
+            _ => unreachable!(),
+        };
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["bitop", "and", "d", "a", "b"])
+        );
+        assert_eq!(b"\x0c\x00".to_vec(), bytes(&store, "d"));
+        run_command(&mut store, &["bitop", "or", "d", "a", "b", "missing"]);
+        assert_eq!(b"\x3f\xff".to_vec(), bytes(&store, "d"));
+        run_command(&mut store, &["bitop", "xor", "d", "a", "b"]);
+        assert_eq!(b"\x33\xff".to_vec(), bytes(&store, "d"));
+        run_command(&mut store, &["bitop", "not", "d", "b"]);
+        assert_eq!(b"\xc3".to_vec(), bytes(&store, "d"));
+        assert_eq!(
+            RedisValue::error("ERR BITOP NOT must be called with a single source key."),
+            run_command(&mut store, &["bitop", "not", "d", "a", "b"])
+        );
+
+        // an empty result deletes the destination
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["bitop", "and", "d", "missing"])
+        );
+        assert!(!store.contains_key("d"));
+    }
+
+    #[test]
+    fn test_bitfield() {
+        let mut store = Store::default();
+        let integers = |values: &[Option<i64>]| {
+            RedisValue::Array(
+                values
+                    .iter()
+                    .map(|value| match value {
+                        Some(value) => RedisValue::Integer(*value),
+                        None => RedisValue::null_bulk_string(),
+                    })
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            integers(&[Some(0), Some(-1), Some(255)]),
+            run_command(
+                &mut store,
+                &["bitfield", "f", "set", "i8", "0", "-1", "get", "i8", "0", "get", "u8", "0"]
+            )
+        );
+        assert_eq!(
+            integers(&[Some(1), Some(-64), Some(127), None]),
+            run_command(
+                &mut store,
+                &[
+                    "bitfield", "f", "incrby", "u2", "#4", "1", "incrby", "i8", "8", "128",
+                    "overflow", "sat", "incrby", "i8", "16", "200", "overflow", "fail", "incrby",
+                    "u4", "0", "1"
+                ]
+            )
+        );
+        assert_eq!(
+            integers(&[Some(255), Some(-4)]),
+            run_command(
+                &mut store,
+                &["bitfield_ro", "f", "get", "u8", "0", "get", "i4", "8"]
+            )
+        );
+        assert_eq!(
+            RedisValue::error(
+                "ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is."
+            ),
+            run_command(&mut store, &["bitfield", "f", "get", "u64", "0"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR BITFIELD_RO only supports the GET subcommand"),
+            run_command(&mut store, &["bitfield_ro", "f", "set", "u8", "0", "1"])
+        );
+        assert_eq!(
+            integers(&[Some(0)]),
+            run_command(&mut store, &["bitfield", "missing", "get", "u8", "0"])
+        );
+        assert!(!store.contains_key("missing"));
+        for offset in ["18446744073709551615", "#2305843009213693951"] {
+            assert_eq!(
+                RedisValue::error("ERR bit offset is not an integer or out of range"),
+                run_command(&mut store, &["bitfield", "f", "get", "u8", offset])
+            );
+        }
+    }
+}
@@ -6,31 +6,63 @@ use parser::RedisValueParser;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{Mutex, Notify, RwLock};
 use tokio::task;
 
-use crate::command::{RedisTcpStreamReadExt, RedisTcpStreamWriteExt};
+use crate::command::{RedisCommandError, RedisTcpStreamReadExt, RedisTcpStreamWriteExt};
 use crate::parser;
 use crate::redis::Redis;
 use crate::value::RedisValue;
 use crate::worker::WorkerMessage;
 
+// a value received from the client, or the reason it could not be read
+type FromClient = Result<RedisValue, RedisCommandError>;
+
+// the end of a connection, awaited by the commands still serving it
+#[derive(Debug, Default)]
+pub struct Disconnected {
+    gone: AtomicBool,
+    notify: Notify,
+}
+
+impl Disconnected {
+    fn notify(&self) {
+        self.gone.store(true, Ordering::SeqCst);
+        self.notify.notify_waiters();
+    }
+
+    pub async fn wait(&self) {
+        let notified = self.notify.notified();
+        tokio::pin!(notified);
+        // register before checking, so a disconnection in between is not missed
+        notified.as_mut().enable();
+        if !self.gone.load(Ordering::SeqCst) {
+            notified.await;
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct ClientChannel {
-    pub from_client_receiver: Arc<Mutex<Receiver<RedisValue>>>,
+    pub from_client_receiver: Arc<Mutex<Receiver<FromClient>>>,
     pub to_client_sender: Arc<RwLock<Sender<RedisValue>>>,
+    // the database selected by the connection
+    pub db: usize,
+    pub disconnected: Arc<Disconnected>,
 
-    _from_client_sender: Arc<Mutex<Sender<RedisValue>>>,
+    _from_client_sender: Arc<Mutex<Sender<FromClient>>>,
     _to_client_receiver: Arc<RwLock<Receiver<RedisValue>>>,
 }
 
 impl ClientChannel {
     pub fn new() -> ClientChannel {
-        let (from_client_sender, from_client_receiver) = mpsc::channel::<RedisValue>(128);
+        let (from_client_sender, from_client_receiver) = mpsc::channel::<FromClient>(128);
         let (to_client_sender, to_client_receiver) = mpsc::channel::<RedisValue>(128);
         ClientChannel {
             from_client_receiver: Arc::new(Mutex::new(from_client_receiver)),
             to_client_sender: Arc::new(RwLock::new(to_client_sender)),
+            db: 0,
+            disconnected: Arc::new(Disconnected::default()),
 
             _from_client_sender: Arc::new(Mutex::new(from_client_sender)),
             _to_client_receiver: Arc::new(RwLock::new(to_client_receiver)),
@@ -47,38 +79,54 @@ pub async fn client_process(
     println!("[client][{}] process started. ", client_id);
     let (mut reader, mut writer) = client.into_split();
     let is_running = Arc::new(AtomicBool::new(true));
+    let disconnected = {
+        let channels = redis.channels.read().await;
+        let channel = channels.get(&client_id).unwrap().read().await;
+        channel.disconnected.clone()
+    };
 
     // read from tcp stream and put the value into the channel
     let _redis = redis.clone();
     let _client_id = client_id.clone();
     let _is_running = is_running.clone();
+    let _disconnected = disconnected.clone();
     let read_from_client_task = task::spawn(async move {
         println!("[client][{}] start to read from stream", _client_id);
         let mut parser = RedisValueParser::new();
         while _is_running.load(Ordering::SeqCst) {
-            let from_client = reader.read_command(&mut parser).await;
-            match from_client {
-                Ok((None, _)) => _is_running.store(false, Ordering::SeqCst),
-                Ok((Some(command), _)) => {
-                    let channel = {
-                        let channels = _redis.channels.read().await;
-                        channels.get(&_client_id).unwrap().clone()
-                    };
-                    let from_client_sender = {
-                        let channel = channel.read().await;
-                        channel._from_client_sender.clone()
-                    };
-                    let from_client_sender = from_client_sender.lock().await;
-                    from_client_sender.send((&command).into()).await.unwrap();
+            let from_client = match reader.read_value(&mut parser).await {
+                Ok((None, _)) => {
+                    _is_running.store(false, Ordering::SeqCst);
+                    continue;
                 }
+                Ok((Some(value), _)) => Ok(value),
                 Err(e) => match e.kind() {
                     ErrorKind::ConnectionAborted => {
                         break;
                     }
-                    _ => panic!(""),
+                    // the malformed bytes are dropped and the client may go on
+                    ErrorKind::InvalidData => Err(RedisCommandError::Malform(e.to_string())),
+                    _ => {
+                        println!(
+                            "[client][{}] unable to read from stream: {:?}",
+                            _client_id, e
+                        );
+                        break;
+                    }
                 },
             };
+            let channel = {
+                let channels = _redis.channels.read().await;
+                channels.get(&_client_id).unwrap().clone()
+            };
+            let from_client_sender = {
+                let channel = channel.read().await;
+                channel._from_client_sender.clone()
+            };
+            let from_client_sender = from_client_sender.lock().await;
+            from_client_sender.send(from_client).await.unwrap();
         }
+        _disconnected.notify();
     });
 
     // read from to_client_receiver channel and write the value to tcp stream
@@ -104,8 +152,17 @@ pub async fn client_process(
                 to_client_receiver.close();
                 break;
             };
-            writer.write_value(&response).await.unwrap();
-            writer.flush().await.unwrap();
+            if let Err(e) = writer.write_value(&response).await {
+                println!(
+                    "[client][{}] unable to write to stream: {:?}",
+                    _client_id, e
+                );
+                break;
+            }
+            if let Err(e) = writer.flush().await {
+                println!("[client][{}] unable to flush stream: {:?}", _client_id, e);
+                break;
+            }
             println!(
                 "[client][{}] value {:?} has been writed to client",
                 _client_id, response
@@ -128,15 +185,21 @@ pub async fn client_process(
             channel.from_client_receiver.clone()
         };
         let mut from_client_receiver = from_client_receiver.lock().await;
-        let from_client = from_client_receiver.recv().await;
+        let from_client = tokio::select! {
+            v = from_client_receiver.recv() => v,
+            _ = disconnected.wait() => None,
+        };
 
         if let Some(value) = from_client {
+            // an invalid command still goes through the worker, so that
+            // its error reply keeps the order of the pipelined responses
             worker_sender
                 .send(WorkerMessage {
-                    command: value.try_into().unwrap(),
+                    command: value.and_then(|value| value.try_into()),
                     client_id: Some(client_id.clone()),
                     responser: Some(channel.read().await.to_client_sender.clone()),
                     offset: 0,
+                    db: None,
                 })
                 .await
                 .unwrap();
@@ -152,5 +215,10 @@ pub async fn client_process(
         let mut channels = redis.channels.write().await;
         channels.remove(&client_id);
     }
+    // a replica reconnecting later resumes with psync
+    {
+        let mut replicas = redis.replicas.write().await;
+        replicas.remove(&client_id);
+    }
     println!("[client][{}] finished", client_id);
 }
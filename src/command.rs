@@ -2,8 +2,19 @@ use async_trait::async_trait;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 
+use crate::bitmap::BitmapCommand;
+use crate::geo::{self, GeoCommand};
+use crate::hash::HashCommand;
+use crate::hyperloglog::HyperLogLogCommand;
+use crate::keyspace::KeyCommand;
+use crate::list::ListCommand;
 use crate::parser::{MessageParserStateError, RedisValueParser};
+use crate::set::SetCommand;
+use crate::stream::StreamCommand;
+use crate::string::StringCommand;
+use crate::utilities;
 use crate::value::{RedisBulkString, RedisValue};
+use crate::zset::ZSetCommand;
 use std::io::ErrorKind;
 use std::vec;
 
@@ -12,14 +23,75 @@ pub enum RedisCommand {
     Ping,
     Echo(RedisBulkString),
     Get(RedisBulkString),
-    Set(RedisBulkString, RedisBulkString, Option<u64>),
+    Set(RedisBulkString, RedisBulkString, SetOptions),
     Type(RedisBulkString),
     Replconf(RedisBulkString, RedisBulkString),
     Info(RedisBulkString),
     Psync(RedisBulkString, RedisBulkString),
     Wait(u64, u64),
-    Select(u64),
+    Select(i64),
     Config(RedisBulkString, RedisBulkString),
+    Save,
+    Bgsave,
+    Lastsave,
+    // none stands for `NO ONE`
+    Replicaof(Option<(String, usize)>),
+    List(ListCommand),
+    Hash(HashCommand),
+    // the commands of the set type, `Set` being the string command
+    Sets(SetCommand),
+    ZSet(ZSetCommand),
+    Stream(StreamCommand),
+    // the commands of the string type apart from GET and SET
+    String(StringCommand),
+    // the commands treating strings as arrays of bits
+    Bitmap(BitmapCommand),
+    // the hyperloglogs are stored as strings
+    HyperLogLog(HyperLogLogCommand),
+    // the positions are stored in sorted sets, `GEOADD` being a `ZADD`
+    Geo(GeoCommand),
+    // the commands working on keys of any type
+    Keyspace(KeyCommand),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SetExpiry {
+    // relative, in seconds and milliseconds
+    Ex(u64),
+    Px(u64),
+    // unix time, in seconds and milliseconds
+    ExAt(u64),
+    PxAt(u64),
+    KeepTtl,
+}
+
+impl SetExpiry {
+    // the unix time in milliseconds when the key expires, none for keepttl
+    pub fn expired_at(&self, now: u64) -> Option<u64> {
+        match *self {
+            SetExpiry::Ex(s) => Some(now.saturating_add(s * 1000)),
+            SetExpiry::Px(ms) => Some(now.saturating_add(ms)),
+            SetExpiry::ExAt(s) => Some(s * 1000),
+            SetExpiry::PxAt(ms) => Some(ms),
+            SetExpiry::KeepTtl => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SetCondition {
+    // only set the key if it does not exist
+    Nx,
+    // only set the key if it already exists
+    Xx,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct SetOptions {
+    pub expiry: Option<SetExpiry>,
+    pub condition: Option<SetCondition>,
+    // reply with the old value
+    pub get: bool,
 }
 
 impl RedisCommand {
@@ -29,11 +101,29 @@ impl RedisCommand {
     pub fn pasync(a1: &str, a2: &str) -> RedisCommand {
         RedisCommand::Psync(a1.into(), a2.into())
     }
-}
 
-impl Into<RedisValue> for &RedisCommand {
-    fn into(self) -> RedisValue {
+    // whether the command modifies the dataset
+    pub fn is_write(&self) -> bool {
         match self {
+            RedisCommand::Set(..) => true,
+            RedisCommand::List(command) => command.is_write(),
+            RedisCommand::Hash(command) => command.is_write(),
+            RedisCommand::Sets(command) => command.is_write(),
+            RedisCommand::ZSet(command) => command.is_write(),
+            RedisCommand::Stream(command) => command.is_write(),
+            RedisCommand::String(command) => command.is_write(),
+            RedisCommand::Bitmap(command) => command.is_write(),
+            RedisCommand::HyperLogLog(command) => command.is_write(),
+            RedisCommand::Geo(command) => command.is_write(),
+            RedisCommand::Keyspace(command) => command.is_write(),
+            _ => false,
+        }
+    }
+}
+
+impl From<&RedisCommand> for RedisValue {
+    fn from(command: &RedisCommand) -> RedisValue {
+        match command {
             RedisCommand::Ping => vec![RedisValue::bulk_string("ping")],
             RedisCommand::Echo(v) => {
                 vec![
@@ -41,11 +131,30 @@ impl Into<RedisValue> for &RedisCommand {
                     RedisValue::BulkString(Some(v.clone())),
                 ]
             }
-            RedisCommand::Set(k, v, px) => {
+            RedisCommand::Set(k, v, options) => {
                 let mut vs = vec![RedisValue::bulk_string("set"), k.into(), v.into()];
-                if let Some(px) = px {
-                    vs.push(RedisValue::bulk_string("px"));
-                    vs.push(RedisValue::bulk_string(px.to_string().as_str()));
+                let expiry = match options.expiry {
+                    Some(SetExpiry::Ex(s)) => Some(("ex", s)),
+                    Some(SetExpiry::Px(ms)) => Some(("px", ms)),
+                    Some(SetExpiry::ExAt(s)) => Some(("exat", s)),
+                    Some(SetExpiry::PxAt(ms)) => Some(("pxat", ms)),
+                    Some(SetExpiry::KeepTtl) => {
+                        vs.push(RedisValue::bulk_string("keepttl"));
+                        None
+                    }
+                    None => None,
+                };
+                if let Some((unit, time)) = expiry {
+                    vs.push(RedisValue::bulk_string(unit));
+                    vs.push(RedisValue::bulk_string(time.to_string().as_str()));
+                }
+                match options.condition {
+                    Some(SetCondition::Nx) => vs.push(RedisValue::bulk_string("nx")),
+                    Some(SetCondition::Xx) => vs.push(RedisValue::bulk_string("xx")),
+                    None => {}
+                }
+                if options.get {
+                    vs.push(RedisValue::bulk_string("get"));
                 }
                 vs
             }
@@ -70,6 +179,31 @@ impl Into<RedisValue> for &RedisCommand {
             RedisCommand::Config(method, key) => {
                 vec![RedisValue::bulk_string("config"), method.into(), key.into()]
             }
+            RedisCommand::Save => vec![RedisValue::bulk_string("save")],
+            RedisCommand::Bgsave => vec![RedisValue::bulk_string("bgsave")],
+            RedisCommand::Lastsave => vec![RedisValue::bulk_string("lastsave")],
+            RedisCommand::Replicaof(master) => match master {
+                Some((host, port)) => vec![
+                    RedisValue::bulk_string("replicaof"),
+                    RedisValue::bulk_string(host.as_str()),
+                    RedisValue::bulk_string(port.to_string().as_str()),
+                ],
+                None => vec![
+                    RedisValue::bulk_string("replicaof"),
+                    RedisValue::bulk_string("no"),
+                    RedisValue::bulk_string("one"),
+                ],
+            },
+            RedisCommand::List(command) => command.into(),
+            RedisCommand::Hash(command) => command.into(),
+            RedisCommand::Sets(command) => command.into(),
+            RedisCommand::ZSet(command) => command.into(),
+            RedisCommand::Stream(command) => command.into(),
+            RedisCommand::String(command) => command.into(),
+            RedisCommand::Bitmap(command) => command.into(),
+            RedisCommand::HyperLogLog(command) => command.into(),
+            RedisCommand::Geo(command) => command.into(),
+            RedisCommand::Keyspace(command) => command.into(),
         }
         .into()
     }
@@ -79,15 +213,56 @@ impl Into<RedisValue> for &RedisCommand {
 pub enum RedisCommandError {
     Malform(String),
     ParsingError(MessageParserStateError),
-    DismatchedArgsNum(usize, usize),
+    // command name, expected and received number of arguments
+    DismatchedArgsNum(String, usize, usize),
     UnknownCommand(String),
     IlleagalArg,
+    NotInteger,
+    NotFloat,
+    NotPositive,
+    InvalidCursor,
+    // command name
+    InvalidExpireTime(String),
+    // a key which is not valid utf-8
+    InvalidKey,
+    // an error specific to a command, without its `ERR` prefix
+    Invalid(String),
+}
+
+impl From<&RedisCommandError> for RedisValue {
+    fn from(error: &RedisCommandError) -> RedisValue {
+        let message = match error {
+            RedisCommandError::Malform(s) => format!("ERR Protocol error: {}", s),
+            RedisCommandError::ParsingError(e) => format!("ERR Protocol error: {:?}", e),
+            RedisCommandError::DismatchedArgsNum(name, _, _) => {
+                format!("ERR wrong number of arguments for '{}' command", name)
+            }
+            RedisCommandError::UnknownCommand(name) => format!("ERR unknown command '{}'", name),
+            RedisCommandError::IlleagalArg => "ERR syntax error".to_string(),
+            RedisCommandError::NotInteger => {
+                "ERR value is not an integer or out of range".to_string()
+            }
+            RedisCommandError::NotFloat => "ERR value is not a valid float".to_string(),
+            RedisCommandError::InvalidCursor => "ERR invalid cursor".to_string(),
+            RedisCommandError::NotPositive => {
+                "ERR value is out of range, must be positive".to_string()
+            }
+            RedisCommandError::InvalidExpireTime(name) => {
+                format!("ERR invalid expire time in '{}' command", name)
+            }
+            RedisCommandError::InvalidKey => {
+                "ERR invalid key, keys must be valid UTF-8".to_string()
+            }
+            RedisCommandError::Invalid(message) => format!("ERR {}", message),
+        };
+        RedisValue::Error(message)
+    }
 }
 
 impl TryInto<RedisCommand> for RedisValue {
     type Error = RedisCommandError;
 
-    fn try_into(self) -> Result<RedisCommand, Self::Error> {
+    fn try_into(self) -> Result<RedisCommand, RedisCommandError> {
         let args = match self {
             RedisValue::Array(args) => args,
             _ => {
@@ -102,7 +277,10 @@ impl TryInto<RedisCommand> for RedisValue {
             args
         );
 
-        let (command, args) = args.split_first().unwrap();
+        let (command, args) = match args.split_first() {
+            Some(v) => v,
+            None => return Err(RedisCommandError::Malform("empty command".to_string())),
+        };
 
         let command_name: String = match command {
             RedisValue::BulkString(Some(s)) => s.into(),
@@ -115,158 +293,563 @@ impl TryInto<RedisCommand> for RedisValue {
         };
         let command_name = command_name.to_lowercase();
 
-        let command = match command_name.as_str() {
-            "ping" => RedisCommand::Ping,
-            "echo" => match args.len() {
-                1 => match &args[0] {
-                    RedisValue::BulkString(Some(s)) => RedisCommand::Echo(s.to_owned()),
-                    _ => return Err(RedisCommandError::IlleagalArg),
-                },
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "get" => match args.len() {
-                1 => match &args[0] {
-                    RedisValue::BulkString(Some(s)) => RedisCommand::Get(s.to_owned()),
-                    _ => return Err(RedisCommandError::IlleagalArg),
+        let command =
+            match command_name.as_str() {
+                "ping" => RedisCommand::Ping,
+                "echo" => match args.len() {
+                    1 => match &args[0] {
+                        RedisValue::BulkString(Some(s)) => RedisCommand::Echo(s.to_owned()),
+                        _ => return Err(RedisCommandError::IlleagalArg),
+                    },
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            1,
+                            n,
+                        ))
+                    }
                 },
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "set" => match args.len() {
-                2 => match &args[0] {
-                    RedisValue::BulkString(Some(k)) => match &args[1] {
-                        RedisValue::BulkString(Some(v)) => {
-                            RedisCommand::Set(k.to_owned(), v.to_owned(), None)
+                "get" => match args.len() {
+                    1 => match &args[0] {
+                        RedisValue::BulkString(Some(s)) => {
+                            check_key(s)?;
+                            RedisCommand::Get(s.to_owned())
                         }
                         _ => return Err(RedisCommandError::IlleagalArg),
                     },
-                    _ => return Err(RedisCommandError::IlleagalArg),
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            1,
+                            n,
+                        ))
+                    }
                 },
-                4 => {
-                    let k = match &args[0] {
-                        RedisValue::BulkString(Some(k)) => k.to_owned(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let v = match &args[1] {
-                        RedisValue::BulkString(Some(v)) => v.to_owned(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let px = match &args[3] {
-                        RedisValue::BulkString(Some(px)) => String::from_utf8(px.data.to_vec())
-                            .unwrap()
-                            .parse()
-                            .unwrap(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    RedisCommand::Set(k.to_owned(), v.to_owned(), Some(px))
+                "set" => {
+                    let mut args = CommandArgs::new(&command_name, args);
+                    let key = args.next_key()?;
+                    let value = args.next_bulk_string()?;
+                    let mut options = SetOptions::default();
+                    while !args.is_empty() {
+                        let option = args.next_string()?.to_lowercase();
+                        match option.as_str() {
+                            "nx" if options.condition != Some(SetCondition::Xx) => {
+                                options.condition = Some(SetCondition::Nx)
+                            }
+                            "xx" if options.condition != Some(SetCondition::Nx) => {
+                                options.condition = Some(SetCondition::Xx)
+                            }
+                            "get" => options.get = true,
+                            "keepttl" | "ex" | "px" | "exat" | "pxat" => {
+                                let expiry = if option == "keepttl" {
+                                    SetExpiry::KeepTtl
+                                } else {
+                                    if args.is_empty() {
+                                        return Err(RedisCommandError::IlleagalArg);
+                                    }
+                                    let time = args.next_expire_time()?;
+                                    match option.as_str() {
+                                        "ex" => SetExpiry::Ex(time),
+                                        "px" => SetExpiry::Px(time),
+                                        "exat" => SetExpiry::ExAt(time),
+                                        _ => SetExpiry::PxAt(time),
+                                    }
+                                };
+                                // repeating the same option is fine, mixing them is not
+                                if let Some(previous) = options.expiry {
+                                    if std::mem::discriminant(&previous)
+                                        != std::mem::discriminant(&expiry)
+                                    {
+                                        return Err(RedisCommandError::IlleagalArg);
+                                    }
+                                }
+                                options.expiry = Some(expiry);
+                            }
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        }
+                    }
+                    RedisCommand::Set(key, value, options)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(4, n)),
-            },
-            "type" => match args.len() {
-                1 => {
-                    let v = match &args[0] {
-                        RedisValue::BulkString(Some(k)) => k.to_owned(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
+                // these are shorthands of `SET`
+                "getset" | "setex" | "psetex" => {
+                    let mut args = CommandArgs::new(&command_name, args);
+                    let key = args.next_key()?;
+                    let options = match command_name.as_str() {
+                        "getset" => SetOptions {
+                            get: true,
+                            ..Default::default()
+                        },
+                        name => {
+                            let time = args.next_expire_time()?;
+                            SetOptions {
+                                expiry: Some(match name {
+                                    "setex" => SetExpiry::Ex(time),
+                                    _ => SetExpiry::Px(time),
+                                }),
+                                ..Default::default()
+                            }
+                        }
                     };
-                    RedisCommand::Type(v)
+                    let value = args.next_bulk_string()?;
+                    args.end()?;
+                    RedisCommand::Set(key, value, options)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "info" => match args.len() {
-                1 => {
-                    let v = match &args[0] {
-                        RedisValue::BulkString(Some(k)) => k.to_owned(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    RedisCommand::Info(v)
+                "type" => match args.len() {
+                    1 => {
+                        let v = match &args[0] {
+                            RedisValue::BulkString(Some(k)) => k.to_owned(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        check_key(&v)?;
+                        RedisCommand::Type(v)
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            1,
+                            n,
+                        ))
+                    }
+                },
+                "info" => match args.len() {
+                    0 => RedisCommand::Info("default".into()),
+                    1 => {
+                        let v = match &args[0] {
+                            RedisValue::BulkString(Some(k)) => k.to_owned(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        RedisCommand::Info(v)
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            1,
+                            n,
+                        ))
+                    }
+                },
+                // several options may be given at once, the first one is kept
+                "replconf" => match args.len() {
+                    n if n >= 2 => {
+                        let arg1 = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let arg2 = match &args[1] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        RedisCommand::Replconf(arg1.to_owned(), arg2.to_owned())
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            2,
+                            n,
+                        ))
+                    }
+                },
+                "psync" => match args.len() {
+                    2 => {
+                        let arg1 = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let arg2 = match &args[1] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+
+                        RedisCommand::Psync(arg1.to_owned(), arg2.to_owned())
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            2,
+                            n,
+                        ))
+                    }
+                },
+                "wait" => match args.len() {
+                    2 => {
+                        let number: String = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s.into(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let number: u64 = match number.parse() {
+                            Ok(number) => number,
+                            Err(_) => return Err(RedisCommandError::NotInteger),
+                        };
+                        let timeout: String = match &args[1] {
+                            RedisValue::BulkString(Some(s)) => s.into(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let timeout: u64 = match timeout.parse() {
+                            Ok(timeout) => timeout,
+                            Err(_) => return Err(RedisCommandError::NotInteger),
+                        };
+                        RedisCommand::Wait(number, timeout)
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            2,
+                            n,
+                        ))
+                    }
+                },
+                "select" => match args.len() {
+                    1 => {
+                        let index: String = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s.into(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let index: i64 = match index.parse() {
+                            Ok(index) => index,
+                            Err(_) => return Err(RedisCommandError::NotInteger),
+                        };
+                        RedisCommand::Select(index)
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            1,
+                            n,
+                        ))
+                    }
+                },
+                "config" => match args.len() {
+                    2 => {
+                        let method = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let key = match &args[1] {
+                            RedisValue::BulkString(Some(s)) => s,
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        RedisCommand::Config(method.to_owned(), key.to_owned())
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            2,
+                            n,
+                        ))
+                    }
+                },
+                "save" => match args.len() {
+                    0 => RedisCommand::Save,
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            0,
+                            n,
+                        ))
+                    }
+                },
+                "bgsave" => match args.len() {
+                    0 => RedisCommand::Bgsave,
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            0,
+                            n,
+                        ))
+                    }
+                },
+                "lastsave" => match args.len() {
+                    0 => RedisCommand::Lastsave,
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            0,
+                            n,
+                        ))
+                    }
+                },
+                "replicaof" | "slaveof" => match args.len() {
+                    2 => {
+                        let host: String = match &args[0] {
+                            RedisValue::BulkString(Some(s)) => s.into(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        let port: String = match &args[1] {
+                            RedisValue::BulkString(Some(s)) => s.into(),
+                            _ => return Err(RedisCommandError::IlleagalArg),
+                        };
+                        if host.to_lowercase() == "no" && port.to_lowercase() == "one" {
+                            RedisCommand::Replicaof(None)
+                        } else {
+                            let port = match port.parse() {
+                                Ok(port) => port,
+                                Err(_) => return Err(RedisCommandError::NotInteger),
+                            };
+                            RedisCommand::Replicaof(Some((host, port)))
+                        }
+                    }
+                    n => {
+                        return Err(RedisCommandError::DismatchedArgsNum(
+                            command_name.clone(),
+                            2,
+                            n,
+                        ))
+                    }
+                },
+                "incr" | "decr" | "incrby" | "decrby" | "incrbyfloat" | "append" | "strlen"
+                | "getrange" | "substr" | "setrange" | "getdel" | "getex" | "mget" | "mset"
+                | "msetnx" | "setnx" => {
+                    RedisCommand::String(StringCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "replconf" => match args.len() {
-                n => {
-                    let arg1 = match &args[0] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let arg2 = match &args[1] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    RedisCommand::Replconf(arg1.to_owned(), arg2.to_owned())
+                "setbit" | "getbit" | "bitcount" | "bitpos" | "bitop" | "bitfield"
+                | "bitfield_ro" => RedisCommand::Bitmap(BitmapCommand::parse(&command_name, args)?),
+                "pfadd" | "pfcount" | "pfmerge" => {
+                    RedisCommand::HyperLogLog(HyperLogLogCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(2, n)),
-            },
-            "psync" => match args.len() {
-                2 => {
-                    let arg1 = match &args[0] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let arg2 = match &args[1] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-
-                    RedisCommand::Psync(arg1.to_owned(), arg2.to_owned())
+                "geoadd" => RedisCommand::ZSet(geo::parse_add(&command_name, args)?),
+                "geopos" | "geodist" | "geohash" | "geosearch" | "geosearchstore" => {
+                    RedisCommand::Geo(GeoCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(2, n)),
-            },
-            "wait" => match args.len() {
-                2 => {
-                    let number: String = match &args[0] {
-                        RedisValue::BulkString(Some(s)) => s.into(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let number: u64 = number.parse().unwrap();
-                    let timeout: String = match &args[1] {
-                        RedisValue::BulkString(Some(s)) => s.into(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let timeout: u64 = timeout.parse().unwrap();
-                    RedisCommand::Wait(number, timeout)
+                "del" | "unlink" | "exists" | "touch" | "keys" | "scan" | "rename" | "renamenx"
+                | "move" | "copy" | "swapdb" | "randomkey" | "dbsize" | "flushdb" | "flushall"
+                | "expire" | "pexpire" | "expireat" | "pexpireat" | "ttl" | "pttl"
+                | "expiretime" | "pexpiretime" | "persist" => {
+                    RedisCommand::Keyspace(KeyCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "select" => match args.len() {
-                1 => {
-                    let index: String = match &args[0] {
-                        RedisValue::BulkString(Some(s)) => s.into(),
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let index: u64 = index.parse().unwrap();
-                    RedisCommand::Select(index)
+                "lpush" | "rpush" | "lpop" | "rpop" | "lrange" | "llen" | "lindex" | "lset"
+                | "lrem" | "ltrim" | "linsert" => {
+                    RedisCommand::List(ListCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(1, n)),
-            },
-            "config" => match args.len() {
-                2 => {
-                    let method = match &args[0] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    let key = match &args[1] {
-                        RedisValue::BulkString(Some(s)) => s,
-                        _ => return Err(RedisCommandError::IlleagalArg),
-                    };
-                    RedisCommand::Config(method.to_owned(), key.to_owned())
+                "hset" | "hsetnx" | "hmset" | "hget" | "hmget" | "hdel" | "hgetall" | "hincrby"
+                | "hincrbyfloat" | "hexists" | "hkeys" | "hvals" | "hlen" | "hscan" => {
+                    RedisCommand::Hash(HashCommand::parse(&command_name, args)?)
                 }
-                n => return Err(RedisCommandError::DismatchedArgsNum(2, n)),
-            },
-            s => return Err(RedisCommandError::UnknownCommand(s.to_string())),
-        };
+                "sadd" | "srem" | "smembers" | "sismember" | "smismember" | "scard" | "sinter"
+                | "sunion" | "sdiff" | "sinterstore" | "sunionstore" | "sdiffstore" | "spop"
+                | "srandmember" | "sscan" => {
+                    RedisCommand::Sets(SetCommand::parse(&command_name, args)?)
+                }
+                "zadd" | "zincrby" | "zrem" | "zcard" | "zscore" | "zmscore" | "zrank"
+                | "zrevrank" | "zcount" | "zrange" | "zrevrange" | "zrangebyscore"
+                | "zrevrangebyscore" | "zrangebylex" | "zrevrangebylex" | "zpopmin" | "zpopmax"
+                | "zscan" => RedisCommand::ZSet(ZSetCommand::parse(&command_name, args)?),
+                "xadd" | "xrange" | "xrevrange" | "xlen" | "xtrim" | "xread" | "xgroup"
+                | "xreadgroup" | "xack" | "xpending" | "xclaim" | "xautoclaim" => {
+                    RedisCommand::Stream(StreamCommand::parse(&command_name, args)?)
+                }
+                s => return Err(RedisCommandError::UnknownCommand(s.to_string())),
+            };
         Ok(command)
     }
 }
 
+// the keyspace is indexed by strings, a key which is not valid utf-8 is rejected rather than
+// turned into one that may already name another key
+pub fn check_key(key: &RedisBulkString) -> Result<(), RedisCommandError> {
+    match std::str::from_utf8(&key.data) {
+        Ok(_) => Ok(()),
+        Err(_) => Err(RedisCommandError::InvalidKey),
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ScanOptions {
+    pub pattern: Option<RedisBulkString>,
+    pub count: usize,
+    // only the fields of a hash are returned
+    pub novalues: bool,
+    pub object_type: Option<String>,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        ScanOptions {
+            pattern: None,
+            count: 10,
+            novalues: false,
+            object_type: None,
+        }
+    }
+}
+
+impl ScanOptions {
+    pub fn matches(&self, member: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => utilities::glob_match(&pattern.data, member),
+            None => true,
+        }
+    }
+
+    pub fn to_args(&self) -> Vec<RedisValue> {
+        let mut vs = vec![];
+        if let Some(pattern) = &self.pattern {
+            vs.push(RedisValue::bulk_string("match"));
+            vs.push(pattern.into());
+        }
+        vs.push(RedisValue::bulk_string("count"));
+        vs.push(RedisValue::bulk_string(self.count.to_string().as_str()));
+        if self.novalues {
+            vs.push(RedisValue::bulk_string("novalues"));
+        }
+        if let Some(object_type) = &self.object_type {
+            vs.push(RedisValue::bulk_string("type"));
+            vs.push(RedisValue::bulk_string(object_type.as_str()));
+        }
+        vs
+    }
+}
+
+// reads the arguments of a command one after another
+pub struct CommandArgs<'a> {
+    command_name: &'a str,
+    args: &'a [RedisValue],
+    position: usize,
+}
+
+impl<'a> CommandArgs<'a> {
+    pub fn new(command_name: &'a str, args: &'a [RedisValue]) -> CommandArgs<'a> {
+        CommandArgs {
+            command_name,
+            args,
+            position: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.args.len()
+    }
+
+    pub fn wrong_number(&self) -> RedisCommandError {
+        RedisCommandError::DismatchedArgsNum(
+            self.command_name.to_string(),
+            self.position,
+            self.args.len(),
+        )
+    }
+
+    pub fn next_bulk_string(&mut self) -> Result<RedisBulkString, RedisCommandError> {
+        let arg = match self.args.get(self.position) {
+            Some(RedisValue::BulkString(Some(s))) => s.to_owned(),
+            Some(_) => return Err(RedisCommandError::IlleagalArg),
+            None => {
+                return Err(RedisCommandError::DismatchedArgsNum(
+                    self.command_name.to_string(),
+                    self.position + 1,
+                    self.args.len(),
+                ))
+            }
+        };
+        self.position += 1;
+        Ok(arg)
+    }
+
+    pub fn next_string(&mut self) -> Result<String, RedisCommandError> {
+        let arg = self.next_bulk_string()?;
+        Ok((&arg).into())
+    }
+
+    pub fn next_integer<T: std::str::FromStr>(&mut self) -> Result<T, RedisCommandError> {
+        match self.next_string()?.parse() {
+            Ok(n) => Ok(n),
+            Err(_) => Err(RedisCommandError::NotInteger),
+        }
+    }
+
+    pub fn next_float(&mut self) -> Result<f64, RedisCommandError> {
+        match self.next_string()?.parse::<f64>() {
+            Ok(f) if !f.is_nan() => Ok(f),
+            _ => Err(RedisCommandError::NotFloat),
+        }
+    }
+
+    pub fn next_cursor(&mut self) -> Result<u64, RedisCommandError> {
+        self.next_string()?
+            .parse()
+            .map_err(|_| RedisCommandError::InvalidCursor)
+    }
+
+    // the options following the cursor of the scan family, besides `MATCH` and `COUNT`
+    // a command may accept some of the flags `NOVALUES` and `TYPE`
+    pub fn next_scan_options(
+        &mut self,
+        accepted: &[&str],
+    ) -> Result<ScanOptions, RedisCommandError> {
+        let mut options = ScanOptions::default();
+        while !self.is_empty() {
+            let option = self.next_string()?.to_lowercase();
+            if option != "match" && option != "count" && !accepted.contains(&option.as_str()) {
+                return Err(RedisCommandError::IlleagalArg);
+            }
+            if option != "novalues" && self.is_empty() {
+                return Err(RedisCommandError::IlleagalArg);
+            }
+            match option.as_str() {
+                "match" => options.pattern = Some(self.next_bulk_string()?),
+                "count" => {
+                    let count: i64 = self.next_integer()?;
+                    if count < 1 {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    options.count = count as usize;
+                }
+                "novalues" => options.novalues = true,
+                _ => options.object_type = Some(self.next_string()?.to_lowercase()),
+            }
+        }
+        Ok(options)
+    }
+
+    // a positive time of an expiry, which must still fit once converted to milliseconds
+    pub fn next_expire_time(&mut self) -> Result<u64, RedisCommandError> {
+        let time: i64 = self.next_integer()?;
+        match u64::try_from(time) {
+            Ok(time) if time > 0 && time <= u64::MAX / 1000 => Ok(time),
+            _ => Err(RedisCommandError::InvalidExpireTime(
+                self.command_name.to_string(),
+            )),
+        }
+    }
+
+    pub fn next_key(&mut self) -> Result<RedisBulkString, RedisCommandError> {
+        let key = self.next_bulk_string()?;
+        check_key(&key)?;
+        Ok(key)
+    }
+
+    pub fn rest_keys(&mut self) -> Result<Vec<RedisBulkString>, RedisCommandError> {
+        let keys = self.rest()?;
+        keys.iter().try_for_each(check_key)?;
+        Ok(keys)
+    }
+
+    // all the remaining arguments, at least one is required
+    pub fn rest(&mut self) -> Result<Vec<RedisBulkString>, RedisCommandError> {
+        if self.is_empty() {
+            return Err(self.wrong_number());
+        }
+        let mut rest = Vec::new();
+        while !self.is_empty() {
+            rest.push(self.next_bulk_string()?);
+        }
+        Ok(rest)
+    }
+
+    // fails if there are arguments left
+    pub fn end(&self) -> Result<(), RedisCommandError> {
+        if self.is_empty() {
+            Ok(())
+        } else {
+            Err(self.wrong_number())
+        }
+    }
+}
+
 #[async_trait]
 pub trait RedisTcpStreamReadExt {
     async fn read_value(
         &mut self,
         parser: &mut RedisValueParser,
     ) -> Result<(Option<RedisValue>, usize), std::io::Error>;
-    async fn read_command(
-        &mut self,
-        parser: &mut RedisValueParser,
-    ) -> Result<(Option<RedisCommand>, usize), std::io::Error>;
     async fn read_rdb(
         &mut self,
         parser: &mut RedisValueParser,
@@ -285,47 +868,31 @@ impl RedisTcpStreamReadExt for OwnedReadHalf {
         );
         let mut buffer: [u8; 1024] = [0; 1024];
 
-        if let Ok((Some(value), offset)) = parser.parse() {
-            return Ok((Some(value), offset + 1));
-        }
-
-        match self.read(buffer.as_mut_slice()).await {
-            Err(e) => Err(e),
-            Ok(0) => Err(ErrorKind::ConnectionAborted.into()),
-            Ok(n) => {
-                println!("[read_value] read bytes, length: {}", n);
-                parser.append(&buffer[0..n]);
-                match parser.parse() {
-                    Ok((value, offset)) => {
-                        println!("[read value] parse success: {:?}", value);
-                        Ok((value, offset + 1))
-                    }
-                    Err(e) => panic!("{:?}", e),
+        // keep reading until the buffered bytes hold a complete value
+        loop {
+            match parser.parse() {
+                Ok((Some(value), offset)) => {
+                    println!("[read value] parse success: {:?}", value);
+                    return Ok((Some(value), offset + 1));
+                }
+                Ok((None, _)) => {}
+                Err(e) => {
+                    println!("[read_value] unable to parse the received bytes: {:?}", e);
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{:?}", e),
+                    ));
                 }
             }
-        }
-    }
 
-    async fn read_command(
-        &mut self,
-        parser: &mut RedisValueParser,
-    ) -> Result<(Option<RedisCommand>, usize), std::io::Error> {
-        let (value, length) = match self.read_value(parser).await {
-            Ok(v) => v,
-            Err(e) => return Err(e),
-        };
-        let value = if let Some(value) = value {
-            value
-        } else {
-            return Ok((None, length));
-        };
-        match value {
-            RedisValue::Array(a) => {
-                let command: RedisCommand = RedisValue::Array(a).try_into().unwrap();
-                println!("[read_command] received command({}): {:?}", length, command);
-                Ok((Some(command), length))
+            match self.read(buffer.as_mut_slice()).await {
+                Err(e) => return Err(e),
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(n) => {
+                    println!("[read_value] read bytes, length: {}", n);
+                    parser.append(&buffer[0..n]);
+                }
             }
-            _ => panic!(),
         }
     }
 
@@ -336,23 +903,29 @@ impl RedisTcpStreamReadExt for OwnedReadHalf {
         println!("[read_rdb] try to read a rdb {}", parser.buffer_len());
         let mut buffer: [u8; 102400] = [0; 102400];
 
-        if let Ok((Some(value), offset)) = parser.parse_rdb() {
-            return Ok((Some(value), offset + 1));
-        }
+        // a snapshot usually spans many reads
+        loop {
+            match parser.parse_rdb() {
+                Ok((Some(value), offset)) => {
+                    let length = offset + 1;
+                    println!("[read_command] received rdb({})", length);
+                    return Ok((Some(value), length));
+                }
+                Ok((None, _)) => {}
+                Err(e) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("{:?}", e),
+                    ))
+                }
+            }
 
-        match self.read(buffer.as_mut_slice()).await {
-            Err(e) => Err(e),
-            Ok(0) => Err(ErrorKind::ConnectionAborted.into()),
-            Ok(n) => {
-                println!("[read_value] read bytes {}", n);
-                parser.append(&buffer[0..n]);
-                match parser.parse_rdb() {
-                    Ok((v, offset)) => {
-                        let length = offset + 1;
-                        println!("[read_command] received rdb({})", length);
-                        Ok((v, length))
-                    }
-                    Err(e) => panic!("{:?}", e),
+            match self.read(buffer.as_mut_slice()).await {
+                Err(e) => return Err(e),
+                Ok(0) => return Err(ErrorKind::ConnectionAborted.into()),
+                Ok(n) => {
+                    println!("[read_value] read bytes {}", n);
+                    parser.append(&buffer[0..n]);
                 }
             }
         }
@@ -379,20 +952,160 @@ impl RedisTcpStreamWriteExt for OwnedWriteHalf {
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use crate::parser;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(args: &[&str]) -> Result<RedisCommand, RedisCommandError> {
+        let args: Vec<RedisValue> = args.iter().map(|s| RedisValue::bulk_string(*s)).collect();
+        RedisValue::Array(args).try_into()
+    }
+
+    #[test]
+    fn test_echo_command() {
+        let input = b"*2\r\n$4\r\necho\r\n$3\r\nhey\r\n";
+        let mut parser = RedisValueParser::new();
+        parser.append(input);
+
+        let (value, _) = parser.parse().unwrap();
+        let c: RedisCommand = value.unwrap().try_into().unwrap();
+        assert_eq!(RedisCommand::Echo("hey".into()), c);
+    }
+
+    #[test]
+    fn test_set_options() {
+        let set = |args: &[&str]| {
+            let mut vs = vec!["set", "k", "v"];
+            vs.extend_from_slice(args);
+            command(&vs)
+        };
+        let options = |expiry, condition, get| {
+            Ok(RedisCommand::Set(
+                "k".into(),
+                "v".into(),
+                SetOptions {
+                    expiry,
+                    condition,
+                    get,
+                },
+            ))
+        };
+        assert_eq!(options(None, None, false), set(&[]));
+        assert_eq!(
+            options(Some(SetExpiry::Ex(10)), Some(SetCondition::Nx), true),
+            set(&["EX", "10", "nx", "GET"])
+        );
+        assert_eq!(
+            options(Some(SetExpiry::PxAt(5)), None, false),
+            set(&["pxat", "5"])
+        );
+        assert_eq!(
+            options(Some(SetExpiry::KeepTtl), Some(SetCondition::Xx), false),
+            set(&["keepttl", "xx"])
+        );
+
+        assert_eq!(Err(RedisCommandError::IlleagalArg), set(&["nx", "xx"]));
+        assert_eq!(
+            Err(RedisCommandError::IlleagalArg),
+            set(&["ex", "1", "px", "1"])
+        );
+        assert_eq!(
+            Err(RedisCommandError::IlleagalArg),
+            set(&["keepttl", "ex", "1"])
+        );
+        assert_eq!(Err(RedisCommandError::IlleagalArg), set(&["ex"]));
+        assert_eq!(Err(RedisCommandError::IlleagalArg), set(&["foo"]));
+        assert_eq!(Err(RedisCommandError::NotInteger), set(&["ex", "abc"]));
+        assert_eq!(
+            Err(RedisCommandError::InvalidExpireTime("set".to_string())),
+            set(&["px", "0"])
+        );
+    }
 
-//     use super::*;
+    #[test]
+    fn test_set_shorthands() {
+        let set = |expiry, get| {
+            Ok(RedisCommand::Set(
+                "k".into(),
+                "v".into(),
+                SetOptions {
+                    expiry,
+                    get,
+                    ..Default::default()
+                },
+            ))
+        };
+        assert_eq!(set(None, true), command(&["getset", "k", "v"]));
+        assert_eq!(
+            set(Some(SetExpiry::Ex(10)), false),
+            command(&["setex", "k", "10", "v"])
+        );
+        assert_eq!(
+            set(Some(SetExpiry::Px(10)), false),
+            command(&["psetex", "k", "10", "v"])
+        );
+        assert_eq!(
+            Err(RedisCommandError::InvalidExpireTime("setex".to_string())),
+            command(&["setex", "k", "-1", "v"])
+        );
+    }
+
+    #[test]
+    fn test_set_round_trip() {
+        let c = command(&["set", "k", "v", "pxat", "100", "xx", "get"]).unwrap();
+        let value: RedisValue = (&c).into();
+        assert_eq!(Ok(c), value.try_into());
+    }
 
-//     #[test]
-//     fn test_echo_command() {
-//         let input = b"*2\r\n$4\r\necho\r\n$3\r\nhey\r\n";
-//         let mut parser = RedisValueParser::new();
-//         parser.append(input);
+    #[test]
+    fn test_replconf_options() {
+        assert_eq!(
+            Ok(RedisCommand::replconf("capa", "eof")),
+            command(&["replconf", "capa", "eof", "capa", "psync2"])
+        );
+        assert!(matches!(
+            command(&["replconf", "ack"]),
+            Err(RedisCommandError::DismatchedArgsNum(..))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_command_replies() {
+        let reply = |args: &[&str]| -> RedisValue { (&command(args).unwrap_err()).into() };
+        assert_eq!(
+            RedisValue::error("ERR wrong number of arguments for 'get' command"),
+            reply(&["GET"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR unknown command 'foo'"),
+            reply(&["foo", "bar"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR value is not an integer or out of range"),
+            reply(&["wait", "x", "0"])
+        );
+        let empty: Result<RedisCommand, RedisCommandError> = RedisValue::Array(vec![]).try_into();
+        assert!(matches!(empty, Err(RedisCommandError::Malform(_))));
+    }
 
-//         let (value, t) = parser.parse().unwrap();
-//         let c: RedisCommand = value.unwrap().try_into().unwrap();
-//         assert_eq!(RedisCommand::Echo("hey".into()), c);
-//     }
-// }
+    #[test]
+    fn test_non_utf8_keys_are_rejected() {
+        let command = |args: &[&[u8]]| -> Result<RedisCommand, RedisCommandError> {
+            let args = args.iter().map(|s| RedisValue::bulk_string_from_bytes(*s));
+            RedisValue::Array(args.collect()).try_into()
+        };
+        let key: &[u8] = b"k\xff";
+        for args in [
+            vec![b"get".as_slice(), key],
+            vec![b"set", key, b"v"],
+            vec![b"del", b"a", key],
+            vec![b"mset", b"a", b"\xff", key, b"v"],
+            vec![b"hset", key, b"f", b"v"],
+            vec![b"xread", b"streams", key, b"0"],
+        ] {
+            assert_eq!(Err(RedisCommandError::InvalidKey), command(&args));
+        }
+        // only keys have to be valid utf-8
+        assert!(command(&[b"set", b"k", b"\xff"]).is_ok());
+    }
+}
@@ -0,0 +1,962 @@
+use crate::command::{CommandArgs, RedisCommandError, SetCondition};
+use crate::object::RedisObject;
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+use crate::zset::{get_zset, AddOptions, SortedSet, ZSetCommand};
+
+// positions are stored in sorted sets, scored by the 52 bits geohash of their
+// coordinates, in which the bits of the latitude and the longitude are interleaved
+const GEO_STEP: u32 = 26;
+const GEO_LONG_MIN: f64 = -180.0;
+const GEO_LONG_MAX: f64 = 180.0;
+// the limits of the web mercator projection
+const GEO_LAT_MIN: f64 = -85.05112878;
+const GEO_LAT_MAX: f64 = 85.05112878;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GeoUnit {
+    M,
+    Km,
+    Mi,
+    Ft,
+}
+
+impl GeoUnit {
+    fn parse(unit: &str) -> Result<GeoUnit, RedisCommandError> {
+        match unit.to_lowercase().as_str() {
+            "m" => Ok(GeoUnit::M),
+            "km" => Ok(GeoUnit::Km),
+            "mi" => Ok(GeoUnit::Mi),
+            "ft" => Ok(GeoUnit::Ft),
+            _ => Err(RedisCommandError::Invalid(
+                "unsupported unit provided. please use M, KM, FT, MI".to_string(),
+            )),
+        }
+    }
+
+    fn meters(&self) -> f64 {
+        match self {
+            GeoUnit::M => 1.0,
+            GeoUnit::Km => 1000.0,
+            GeoUnit::Mi => 1609.34,
+            GeoUnit::Ft => 0.3048,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            GeoUnit::M => "m",
+            GeoUnit::Km => "km",
+            GeoUnit::Mi => "mi",
+            GeoUnit::Ft => "ft",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum GeoFrom {
+    Member(RedisBulkString),
+    // longitude and latitude
+    LonLat(f64, f64),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GeoShape {
+    Radius(f64),
+    // width and height
+    Box(f64, f64),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GeoSearchOptions {
+    pub from: GeoFrom,
+    // the sizes are in the unit
+    pub by: GeoShape,
+    pub unit: GeoUnit,
+    // true for the farthest positions first
+    pub desc: Option<bool>,
+    // the count, and whether any positions will do instead of the nearest ones
+    pub count: Option<(usize, bool)>,
+    pub withdist: bool,
+    pub withcoord: bool,
+    pub withhash: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum GeoCommand {
+    Pos(RedisBulkString, Vec<RedisBulkString>),
+    Dist(RedisBulkString, RedisBulkString, RedisBulkString, GeoUnit),
+    Hash(RedisBulkString, Vec<RedisBulkString>),
+    Search(RedisBulkString, GeoSearchOptions),
+    // the destination key, the source key, and whether to store the distances as scores
+    SearchStore(RedisBulkString, RedisBulkString, GeoSearchOptions, bool),
+}
+
+fn invalid(message: String) -> RedisCommandError {
+    RedisCommandError::Invalid(message)
+}
+
+fn parse_position(lon: f64, lat: f64) -> Result<(f64, f64), RedisCommandError> {
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&lon) || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&lat)
+    {
+        return Err(invalid(format!(
+            "invalid longitude,latitude pair {:.6},{:.6}",
+            lon, lat
+        )));
+    }
+    Ok((lon, lat))
+}
+
+// `GEOADD` is a `ZADD` of the geohashes of the positions, which is also what replicas replay
+pub fn parse_add(
+    command_name: &str,
+    args: &[RedisValue],
+) -> Result<ZSetCommand, RedisCommandError> {
+    let mut args = CommandArgs::new(command_name, args);
+    let key = args.next_key()?;
+    let mut options = AddOptions::default();
+    let mut elements = Vec::new();
+    while !args.is_empty() {
+        let arg = args.next_string()?;
+        match arg.to_lowercase().as_str() {
+            "nx" if elements.is_empty() && options.condition != Some(SetCondition::Xx) => {
+                options.condition = Some(SetCondition::Nx)
+            }
+            "xx" if elements.is_empty() && options.condition != Some(SetCondition::Nx) => {
+                options.condition = Some(SetCondition::Xx)
+            }
+            "ch" if elements.is_empty() => options.ch = true,
+            _ => {
+                let lon = match arg.parse::<f64>() {
+                    Ok(lon) if !lon.is_nan() => lon,
+                    _ => return Err(RedisCommandError::NotFloat),
+                };
+                if args.is_empty() {
+                    return Err(RedisCommandError::IlleagalArg);
+                }
+                let (lon, lat) = parse_position(lon, args.next_float()?)?;
+                if args.is_empty() {
+                    return Err(RedisCommandError::IlleagalArg);
+                }
+                elements.push((encode(lon, lat) as f64, args.next_bulk_string()?));
+            }
+        }
+    }
+    if elements.is_empty() {
+        return Err(args.wrong_number());
+    }
+    Ok(ZSetCommand::Add(key, options, elements))
+}
+
+fn parse_search(
+    command_name: &str,
+    args: &mut CommandArgs,
+    store: bool,
+) -> Result<(GeoSearchOptions, bool), RedisCommandError> {
+    let mut from = None;
+    let mut by = None;
+    let mut unit = GeoUnit::M;
+    let mut options = GeoSearchOptions {
+        from: GeoFrom::LonLat(0.0, 0.0),
+        by: GeoShape::Radius(0.0),
+        unit,
+        desc: None,
+        count: None,
+        withdist: false,
+        withcoord: false,
+        withhash: false,
+    };
+    let mut any = false;
+    let mut storedist = false;
+    let exactly_one = |what: &str| {
+        invalid(format!(
+            "exactly one of {} can be specified for {}",
+            what, command_name
+        ))
+    };
+    while !args.is_empty() {
+        let option = args.next_string()?.to_lowercase();
+        match option.as_str() {
+            "frommember" | "fromlonlat" if from.is_some() => {
+                return Err(exactly_one("FROMMEMBER or FROMLONLAT"))
+            }
+            "frommember" => from = Some(GeoFrom::Member(args.next_bulk_string()?)),
+            "fromlonlat" => {
+                let lon = args.next_float()?;
+                let (lon, lat) = parse_position(lon, args.next_float()?)?;
+                from = Some(GeoFrom::LonLat(lon, lat));
+            }
+            "byradius" | "bybox" if by.is_some() => return Err(exactly_one("BYRADIUS and BYBOX")),
+            "byradius" => {
+                let radius = args.next_float()?;
+                if radius < 0.0 {
+                    return Err(invalid("radius cannot be negative".to_string()));
+                }
+                by = Some(GeoShape::Radius(radius));
+                unit = GeoUnit::parse(&args.next_string()?)?;
+            }
+            "bybox" => {
+                let width = args.next_float()?;
+                let height = args.next_float()?;
+                if width < 0.0 || height < 0.0 {
+                    return Err(invalid("height or width cannot be negative".to_string()));
+                }
+                by = Some(GeoShape::Box(width, height));
+                unit = GeoUnit::parse(&args.next_string()?)?;
+            }
+            "asc" => options.desc = Some(false),
+            "desc" => options.desc = Some(true),
+            "count" => {
+                let count: i64 = args.next_integer()?;
+                if count <= 0 {
+                    return Err(invalid("COUNT must be > 0".to_string()));
+                }
+                options.count = Some((count as usize, false));
+            }
+            "any" => any = true,
+            "withdist" if !store => options.withdist = true,
+            "withcoord" if !store => options.withcoord = true,
+            "withhash" if !store => options.withhash = true,
+            "storedist" if store => storedist = true,
+            _ => return Err(RedisCommandError::IlleagalArg),
+        }
+    }
+    options.from = from.ok_or_else(|| exactly_one("FROMMEMBER or FROMLONLAT"))?;
+    options.by = by.ok_or_else(|| exactly_one("BYRADIUS and BYBOX"))?;
+    options.unit = unit;
+    match (&mut options.count, any) {
+        (Some((_, a)), true) => *a = true,
+        (None, true) => {
+            return Err(invalid(
+                "the ANY argument requires COUNT argument".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    Ok((options, storedist))
+}
+
+impl GeoCommand {
+    pub fn parse(command_name: &str, args: &[RedisValue]) -> Result<GeoCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "geosearchstore" => {
+                let destination = args.next_key()?;
+                let source = args.next_key()?;
+                let (options, storedist) = parse_search(command_name, &mut args, true)?;
+                GeoCommand::SearchStore(destination, source, options, storedist)
+            }
+            _ => {
+                let key = args.next_key()?;
+                match command_name {
+                    "geopos" => {
+                        let mut members = Vec::new();
+                        while !args.is_empty() {
+                            members.push(args.next_bulk_string()?);
+                        }
+                        GeoCommand::Pos(key, members)
+                    }
+                    "geodist" => {
+                        let member1 = args.next_bulk_string()?;
+                        let member2 = args.next_bulk_string()?;
+                        let unit = match args.is_empty() {
+                            true => GeoUnit::M,
+                            false => GeoUnit::parse(&args.next_string()?)?,
+                        };
+                        GeoCommand::Dist(key, member1, member2, unit)
+                    }
+                    "geohash" => {
+                        let mut members = Vec::new();
+                        while !args.is_empty() {
+                            members.push(args.next_bulk_string()?);
+                        }
+                        GeoCommand::Hash(key, members)
+                    }
+                    "geosearch" => {
+                        GeoCommand::Search(key, parse_search(command_name, &mut args, false)?.0)
+                    }
+                    name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+                }
+            }
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(self, GeoCommand::SearchStore(..))
+    }
+}
+
+impl From<&GeoCommand> for Vec<RedisValue> {
+    fn from(geo_command: &GeoCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let number = |n: f64| RedisValue::bulk_string(utilities::format_float(n).as_str());
+        let search = |vs: &mut Vec<RedisValue>, options: &GeoSearchOptions| {
+            match &options.from {
+                GeoFrom::Member(member) => {
+                    vs.push(RedisValue::bulk_string("frommember"));
+                    vs.push(member.into());
+                }
+                GeoFrom::LonLat(lon, lat) => {
+                    vs.push(RedisValue::bulk_string("fromlonlat"));
+                    vs.push(number(*lon));
+                    vs.push(number(*lat));
+                }
+            }
+            match options.by {
+                GeoShape::Radius(radius) => {
+                    vs.push(RedisValue::bulk_string("byradius"));
+                    vs.push(number(radius));
+                }
+                GeoShape::Box(width, height) => {
+                    vs.push(RedisValue::bulk_string("bybox"));
+                    vs.push(number(width));
+                    vs.push(number(height));
+                }
+            }
+            vs.push(RedisValue::bulk_string(options.unit.name()));
+            match options.desc {
+                Some(true) => vs.push(RedisValue::bulk_string("desc")),
+                Some(false) => vs.push(RedisValue::bulk_string("asc")),
+                None => {}
+            }
+            if let Some((count, any)) = options.count {
+                vs.push(RedisValue::bulk_string("count"));
+                vs.push(RedisValue::bulk_string(count.to_string().as_str()));
+                if any {
+                    vs.push(RedisValue::bulk_string("any"));
+                }
+            }
+            for (flag, name) in [
+                (options.withdist, "withdist"),
+                (options.withcoord, "withcoord"),
+                (options.withhash, "withhash"),
+            ] {
+                if flag {
+                    vs.push(RedisValue::bulk_string(name));
+                }
+            }
+        };
+        match geo_command {
+            GeoCommand::Pos(key, members) | GeoCommand::Hash(key, members) => {
+                let name = match geo_command {
+                    GeoCommand::Pos(..) => "geopos",
+                    _ => "geohash",
+                };
+                let mut vs = command(name, key);
+                vs.extend(members.iter().map(|member| member.into()));
+                vs
+            }
+            GeoCommand::Dist(key, member1, member2, unit) => {
+                let mut vs = command("geodist", key);
+                vs.push(member1.into());
+                vs.push(member2.into());
+                vs.push(RedisValue::bulk_string(unit.name()));
+                vs
+            }
+            GeoCommand::Search(key, options) => {
+                let mut vs = command("geosearch", key);
+                search(&mut vs, options);
+                vs
+            }
+            GeoCommand::SearchStore(destination, source, options, storedist) => {
+                let mut vs = command("geosearchstore", destination);
+                vs.push(source.into());
+                search(&mut vs, options);
+                if *storedist {
+                    vs.push(RedisValue::bulk_string("storedist"));
+                }
+                vs
+            }
+        }
+    }
+}
+
+// the bits of `x` at the even positions of the result
+fn spread(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | (x << 16)) & 0x0000_ffff_0000_ffff;
+    x = (x | (x << 8)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x << 4)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x << 2)) & 0x3333_3333_3333_3333;
+    (x | (x << 1)) & 0x5555_5555_5555_5555
+}
+
+// the bits at the even positions of `x`
+fn squash(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | (x >> 1)) & 0x3333_3333_3333_3333;
+    x = (x | (x >> 2)) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | (x >> 4)) & 0x00ff_00ff_00ff_00ff;
+    x = (x | (x >> 8)) & 0x0000_ffff_0000_ffff;
+    ((x | (x >> 16)) & 0xffff_ffff) as u32
+}
+
+// the cell of a coordinate within its range, among 2^step
+fn cell(value: f64, min: f64, max: f64, step: u32) -> u32 {
+    let cells = 1u64 << step;
+    (((value - min) / (max - min) * cells as f64) as u64).min(cells - 1) as u32
+}
+
+fn interleave(lat: u32, lon: u32) -> u64 {
+    spread(lat) | (spread(lon) << 1)
+}
+
+fn encode_with(lon: f64, lat: f64, lat_min: f64, lat_max: f64) -> u64 {
+    interleave(
+        cell(lat, lat_min, lat_max, GEO_STEP),
+        cell(lon, GEO_LONG_MIN, GEO_LONG_MAX, GEO_STEP),
+    )
+}
+
+fn encode(lon: f64, lat: f64) -> u64 {
+    encode_with(lon, lat, GEO_LAT_MIN, GEO_LAT_MAX)
+}
+
+// the center of the cell of a geohash
+fn decode(hash: u64) -> (f64, f64) {
+    let cells = (1u64 << GEO_STEP) as f64;
+    let center = |index: u32, min: f64, max: f64| {
+        let low = min + index as f64 / cells * (max - min);
+        let high = min + (index as f64 + 1.0) / cells * (max - min);
+        ((low + high) / 2.0).clamp(min, max)
+    };
+    (
+        center(squash(hash >> 1), GEO_LONG_MIN, GEO_LONG_MAX),
+        center(squash(hash), GEO_LAT_MIN, GEO_LAT_MAX),
+    )
+}
+
+// the standard geohash of a position, whose latitudes range from -90 to 90
+fn geohash_string(lon: f64, lat: f64) -> String {
+    let hash = encode_with(lon, lat, -90.0, 90.0);
+    // 11 characters are 55 bits, the missing ones are zeros
+    (0..11)
+        .map(|i| {
+            let index = match i {
+                10 => 0,
+                i => (hash >> (52 - (i + 1) * 5)) & 0x1f,
+            };
+            GEOHASH_ALPHABET[index as usize] as char
+        })
+        .collect()
+}
+
+// the great circle distance in meters, with the haversine formula
+fn distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+// the distance in meters of a position from the center if it lies within the shape
+fn within(shape: GeoShape, unit: GeoUnit, center: (f64, f64), position: (f64, f64)) -> Option<f64> {
+    let ((lon1, lat1), (lon2, lat2)) = (center, position);
+    match shape {
+        GeoShape::Radius(radius) => {
+            let d = distance(lon1, lat1, lon2, lat2);
+            (d <= radius * unit.meters()).then_some(d)
+        }
+        GeoShape::Box(width, height) => {
+            let lat_distance = EARTH_RADIUS_IN_METERS * (lat2 - lat1).to_radians().abs();
+            if lat_distance > height * unit.meters() / 2.0 {
+                return None;
+            }
+            // along the parallel of the position
+            if distance(lon1, lat2, lon2, lat2) > width * unit.meters() / 2.0 {
+                return None;
+            }
+            Some(distance(lon1, lat1, lon2, lat2))
+        }
+    }
+}
+
+// the ranges of scores of the cells around the center which cover the shape: cells are
+// chosen at least as large as the shape, so that the center cell and its eight neighbors
+// do. a shape around a pole is covered by every score
+fn candidate_ranges(shape: GeoShape, unit: GeoUnit, (lon, lat): (f64, f64)) -> Vec<(u64, u64)> {
+    let everything = vec![(0, 1 << (2 * GEO_STEP))];
+    let (lat_delta, lon_delta) = match shape {
+        GeoShape::Radius(radius) => {
+            let d = radius * unit.meters() / EARTH_RADIUS_IN_METERS;
+            let x = d.sin() / lat.to_radians().cos();
+            if d >= std::f64::consts::FRAC_PI_2 || x >= 1.0 {
+                return everything;
+            }
+            (d, x.asin())
+        }
+        GeoShape::Box(width, height) => {
+            let lat_delta = height * unit.meters() / 2.0 / EARTH_RADIUS_IN_METERS;
+            let farthest = lat.to_radians().abs() + lat_delta;
+            let w = width * unit.meters() / 4.0 / EARTH_RADIUS_IN_METERS;
+            let x = w.min(std::f64::consts::FRAC_PI_2).sin() / farthest.cos();
+            if farthest >= std::f64::consts::FRAC_PI_2 || x >= 1.0 {
+                return everything;
+            }
+            (lat_delta, 2.0 * x.asin())
+        }
+    };
+    // with a margin for the rounding errors
+    let (lat_delta, lon_delta) = (lat_delta.to_degrees() * 1.01, lon_delta.to_degrees() * 1.01);
+    let step = (1..=GEO_STEP).rev().find(|step| {
+        let cells = (1u64 << step) as f64;
+        (GEO_LAT_MAX - GEO_LAT_MIN) / cells >= lat_delta
+            && (GEO_LONG_MAX - GEO_LONG_MIN) / cells >= lon_delta
+    });
+    let step = match step {
+        Some(step) => step,
+        None => return everything,
+    };
+    let cells = 1i64 << step;
+    let lat_cell = cell(lat, GEO_LAT_MIN, GEO_LAT_MAX, step) as i64;
+    let lon_cell = cell(lon, GEO_LONG_MIN, GEO_LONG_MAX, step) as i64;
+    let shift = 2 * (GEO_STEP - step);
+    let mut ranges = Vec::with_capacity(9);
+    for lat_cell in lat_cell - 1..=lat_cell + 1 {
+        if !(0..cells).contains(&lat_cell) {
+            continue;
+        }
+        for lon_cell in lon_cell - 1..=lon_cell + 1 {
+            // the longitudes wrap around the antimeridian
+            let lon_cell = lon_cell.rem_euclid(cells);
+            let hash = interleave(lat_cell as u32, lon_cell as u32);
+            ranges.push((hash << shift, (hash + 1) << shift));
+        }
+    }
+    ranges.sort_unstable();
+    ranges.dedup();
+    ranges
+}
+
+// a position found by a search
+struct GeoMatch<'a> {
+    member: &'a [u8],
+    distance: f64,
+    hash: u64,
+}
+
+fn search<'a>(
+    zset: &'a SortedSet,
+    options: &GeoSearchOptions,
+    center: (f64, f64),
+) -> Vec<GeoMatch<'a>> {
+    let mut matches = Vec::new();
+    // the nearest positions are only known once they all are found
+    let limit = match options.count {
+        Some((count, true)) => count,
+        _ => usize::MAX,
+    };
+    'ranges: for (min, max) in candidate_ranges(options.by, options.unit, center) {
+        let first = zset.count_while(|score, _| score < min as f64);
+        for (member, score) in zset.iter_from(first, false) {
+            if score >= max as f64 {
+                break;
+            }
+            let hash = score as u64;
+            if let Some(distance) = within(options.by, options.unit, center, decode(hash)) {
+                matches.push(GeoMatch {
+                    member,
+                    distance,
+                    hash,
+                });
+                if matches.len() >= limit {
+                    break 'ranges;
+                }
+            }
+        }
+    }
+    // a count without `ANY` is the nearest positions
+    let desc = match (options.desc, options.count) {
+        (None, Some((_, false))) => Some(false),
+        (desc, _) => desc,
+    };
+    if let Some(desc) = desc {
+        matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if desc {
+            matches.reverse();
+        }
+    }
+    if let Some((count, _)) = options.count {
+        matches.truncate(count);
+    }
+    matches
+}
+
+fn position_reply((lon, lat): (f64, f64)) -> RedisValue {
+    RedisValue::Array(vec![
+        RedisValue::bulk_string(utilities::format_float(lon).as_str()),
+        RedisValue::bulk_string(utilities::format_float(lat).as_str()),
+    ])
+}
+
+fn distance_reply(distance: f64, unit: GeoUnit) -> RedisValue {
+    RedisValue::bulk_string(format!("{:.4}", distance / unit.meters()).as_str())
+}
+
+pub fn execute(store: &mut Store, command: &GeoCommand, now: u64, mode: ExpiryMode) -> RedisValue {
+    match run(store, command, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    }
+}
+
+fn run(
+    store: &mut Store,
+    command: &GeoCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let empty = SortedSet::new();
+    let response = match command {
+        GeoCommand::Pos(key, members) | GeoCommand::Hash(key, members) => {
+            let key: String = key.into();
+            let zset = get_zset(store, &key, now, mode)?.map_or(&empty, |zset| &*zset);
+            let values = members
+                .iter()
+                .map(|member| match zset.score(&member.data) {
+                    Some(score) => {
+                        let (lon, lat) = decode(score as u64);
+                        match command {
+                            GeoCommand::Pos(..) => position_reply((lon, lat)),
+                            _ => RedisValue::bulk_string(geohash_string(lon, lat).as_str()),
+                        }
+                    }
+                    None => RedisValue::null_bulk_string(),
+                })
+                .collect();
+            RedisValue::Array(values)
+        }
+        GeoCommand::Dist(key, member1, member2, unit) => {
+            let key: String = key.into();
+            let zset = get_zset(store, &key, now, mode)?.map_or(&empty, |zset| &*zset);
+            match (zset.score(&member1.data), zset.score(&member2.data)) {
+                (Some(score1), Some(score2)) => {
+                    let (lon1, lat1) = decode(score1 as u64);
+                    let (lon2, lat2) = decode(score2 as u64);
+                    distance_reply(distance(lon1, lat1, lon2, lat2), *unit)
+                }
+                _ => RedisValue::null_bulk_string(),
+            }
+        }
+        GeoCommand::Search(key, options) => {
+            let key: String = key.into();
+            let zset = match get_zset(store, &key, now, mode)? {
+                Some(zset) => &*zset,
+                None => return Ok(RedisValue::Array(vec![])),
+            };
+            let center = center(zset, options)?;
+            let withany = options.withdist || options.withcoord || options.withhash;
+            let values = search(zset, options, center)
+                .into_iter()
+                .map(|m| {
+                    let member = RedisValue::bulk_string_from_bytes(m.member);
+                    if !withany {
+                        return member;
+                    }
+                    let mut vs = vec![member];
+                    if options.withdist {
+                        vs.push(distance_reply(m.distance, options.unit));
+                    }
+                    if options.withhash {
+                        vs.push(RedisValue::Integer(m.hash as i64));
+                    }
+                    if options.withcoord {
+                        vs.push(position_reply(decode(m.hash)));
+                    }
+                    RedisValue::Array(vs)
+                })
+                .collect();
+            RedisValue::Array(values)
+        }
+        GeoCommand::SearchStore(destination, source, options, storedist) => {
+            let source: String = source.into();
+            let mut result = SortedSet::new();
+            if let Some(zset) = get_zset(store, &source, now, mode)? {
+                let zset = &*zset;
+                let center = center(zset, options)?;
+                for m in search(zset, options, center) {
+                    let score = match storedist {
+                        true => m.distance / options.unit.meters(),
+                        false => m.hash as f64,
+                    };
+                    result.insert(m.member.to_vec(), score);
+                }
+            }
+            let destination: String = destination.into();
+            let count = result.len();
+            if result.is_empty() {
+                store.remove(&destination);
+            } else {
+                store.insert(
+                    destination,
+                    StoreItem {
+                        value: RedisObject::ZSet(result),
+                        expired_at: 0,
+                    },
+                );
+            }
+            RedisValue::Integer(count as i64)
+        }
+    };
+    Ok(response)
+}
+
+// the center of a search, a member must be in the sorted set
+fn center(zset: &SortedSet, options: &GeoSearchOptions) -> Result<(f64, f64), RedisValue> {
+    match &options.from {
+        GeoFrom::LonLat(lon, lat) => Ok((*lon, *lat)),
+        GeoFrom::Member(member) => match zset.score(&member.data) {
+            Some(score) => Ok(decode(score as u64)),
+            None => Err(RedisValue::error(
+                "ERR could not decode requested zset member",
+            )),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    fn sicily() -> Store {
+        let mut store = Store::default();
+        run_command(
+            &mut store,
+            &[
+                "geoadd",
+                "Sicily",
+                "13.361389",
+                "38.115556",
+                "Palermo",
+                "15.087269",
+                "37.502669",
+                "Catania",
+            ],
+        );
+        store
+    }
+
+    fn strings(values: &[&str]) -> RedisValue {
+        RedisValue::Array(values.iter().map(|v| RedisValue::bulk_string(*v)).collect())
+    }
+
+    #[test]
+    fn test_encoding() {
+        let mut store = sicily();
+        assert_eq!(
+            Some(3479099956230698.0),
+            get_zset(&mut store, "Sicily", 0, ExpiryMode::Delete)
+                .unwrap()
+                .unwrap()
+                .score(b"Palermo")
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                strings(&["13.361389338970184", "38.1155563954963"]),
+                RedisValue::null_bulk_string(),
+            ]),
+            run_command(&mut store, &["geopos", "Sicily", "Palermo", "Nowhere"])
+        );
+        assert_eq!(
+            strings(&["sqc8b49rny0", "sqdtr74hyu0"]),
+            run_command(&mut store, &["geohash", "Sicily", "Palermo", "Catania"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("166274.1516"),
+            run_command(&mut store, &["geodist", "Sicily", "Palermo", "Catania"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("103.3182"),
+            run_command(
+                &mut store,
+                &["geodist", "Sicily", "Palermo", "Catania", "mi"]
+            )
+        );
+        assert_eq!(
+            RedisValue::error("ERR invalid longitude,latitude pair 200.000000,10.000000"),
+            run_command(&mut store, &["geoadd", "Sicily", "200", "10", "x"])
+        );
+    }
+
+    #[test]
+    fn test_search() {
+        let mut store = sicily();
+        run_command(
+            &mut store,
+            &[
+                "geoadd",
+                "Sicily",
+                "12.758489",
+                "38.788135",
+                "edge1",
+                "17.241510",
+                "38.788135",
+                "edge2",
+            ],
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::Array(vec![
+                    RedisValue::bulk_string("Catania"),
+                    RedisValue::bulk_string("56.4413"),
+                ]),
+                RedisValue::Array(vec![
+                    RedisValue::bulk_string("Palermo"),
+                    RedisValue::bulk_string("190.4424"),
+                ]),
+            ]),
+            run_command(
+                &mut store,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "fromlonlat",
+                    "15",
+                    "37",
+                    "byradius",
+                    "200",
+                    "km",
+                    "asc",
+                    "withdist"
+                ]
+            )
+        );
+        assert_eq!(
+            strings(&["edge1", "edge2", "Palermo", "Catania"]),
+            run_command(
+                &mut store,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "fromlonlat",
+                    "15",
+                    "37",
+                    "bybox",
+                    "400",
+                    "400",
+                    "km",
+                    "desc"
+                ]
+            )
+        );
+        assert_eq!(
+            strings(&["Palermo"]),
+            run_command(
+                &mut store,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "frommember",
+                    "Palermo",
+                    "byradius",
+                    "100",
+                    "km",
+                    "count",
+                    "1"
+                ]
+            )
+        );
+        assert_eq!(
+            RedisValue::error(
+                "ERR exactly one of BYRADIUS and BYBOX can be specified for geosearch"
+            ),
+            run_command(
+                &mut store,
+                &["geosearch", "Sicily", "frommember", "Palermo"]
+            )
+        );
+        assert_eq!(
+            RedisValue::error("ERR could not decode requested zset member"),
+            run_command(
+                &mut store,
+                &[
+                    "geosearch",
+                    "Sicily",
+                    "frommember",
+                    "x",
+                    "byradius",
+                    "1",
+                    "m"
+                ]
+            )
+        );
+
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(
+                &mut store,
+                &[
+                    "geosearchstore",
+                    "near",
+                    "Sicily",
+                    "fromlonlat",
+                    "15",
+                    "37",
+                    "byradius",
+                    "200",
+                    "km",
+                    "storedist"
+                ]
+            )
+        );
+        let near = get_zset(&mut store, "near", 0, ExpiryMode::Delete)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            190.4424,
+            (near.score(b"Palermo").unwrap() * 1e4).round() / 1e4
+        );
+    }
+
+    #[test]
+    fn test_candidate_ranges() {
+        // the covered positions are the ones the whole set gives
+        let mut zset = SortedSet::new();
+        for i in 0..2000 {
+            let lon = (i as f64 * 7.919) % 360.0 - 180.0;
+            let lat = (i as f64 * 3.137) % 170.0 - 85.0;
+            zset.insert(i.to_string().into_bytes(), encode(lon, lat) as f64);
+        }
+        for (center, shape) in [
+            ((0.0, 0.0), GeoShape::Radius(500.0)),
+            ((179.9, 10.0), GeoShape::Radius(300.0)),
+            ((-50.0, 80.0), GeoShape::Radius(1000.0)),
+            ((100.0, -60.0), GeoShape::Box(2000.0, 500.0)),
+        ] {
+            let options = GeoSearchOptions {
+                from: GeoFrom::LonLat(center.0, center.1),
+                by: shape,
+                unit: GeoUnit::Km,
+                desc: Some(false),
+                count: None,
+                withdist: false,
+                withcoord: false,
+                withhash: false,
+            };
+            let found: Vec<&[u8]> = search(&zset, &options, center)
+                .iter()
+                .map(|m| m.member)
+                .collect();
+            let expected: Vec<&[u8]> = {
+                let mut all: Vec<(f64, &[u8])> = zset
+                    .iter_from(0, false)
+                    .filter_map(|(member, score)| {
+                        within(shape, GeoUnit::Km, center, decode(score as u64))
+                            .map(|d| (d, member))
+                    })
+                    .collect();
+                all.sort_by(|a, b| a.0.total_cmp(&b.0));
+                all.into_iter().map(|(_, member)| member).collect()
+            };
+            assert!(!expected.is_empty());
+            assert_eq!(expected, found);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use crate::command::{CommandArgs, RedisCommandError, ScanOptions};
+use crate::object::{scan, wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+
+type FieldValues = Vec<(RedisBulkString, RedisBulkString)>;
+
+type Fields = HashMap<Vec<u8>, Vec<u8>>;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum HashCommand {
+    Set(RedisBulkString, FieldValues),
+    // the deprecated form of `HSET`, which replies `OK`
+    MSet(RedisBulkString, FieldValues),
+    SetNx(RedisBulkString, RedisBulkString, RedisBulkString),
+    Get(RedisBulkString, RedisBulkString),
+    MGet(RedisBulkString, Vec<RedisBulkString>),
+    Del(RedisBulkString, Vec<RedisBulkString>),
+    GetAll(RedisBulkString),
+    IncrBy(RedisBulkString, RedisBulkString, i64),
+    IncrByFloat(RedisBulkString, RedisBulkString, f64),
+    Exists(RedisBulkString, RedisBulkString),
+    Keys(RedisBulkString),
+    Vals(RedisBulkString),
+    Len(RedisBulkString),
+    Scan(RedisBulkString, u64, ScanOptions),
+}
+
+impl HashCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<HashCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let key = args.next_key()?;
+        let command = match command_name {
+            "hset" | "hmset" => {
+                let rest = args.rest()?;
+                if rest.len() % 2 != 0 {
+                    return Err(args.wrong_number());
+                }
+                let pairs = rest
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                if command_name == "hset" {
+                    HashCommand::Set(key, pairs)
+                } else {
+                    HashCommand::MSet(key, pairs)
+                }
+            }
+            "hsetnx" => {
+                let field = args.next_bulk_string()?;
+                HashCommand::SetNx(key, field, args.next_bulk_string()?)
+            }
+            "hget" => HashCommand::Get(key, args.next_bulk_string()?),
+            "hmget" => HashCommand::MGet(key, args.rest()?),
+            "hdel" => HashCommand::Del(key, args.rest()?),
+            "hgetall" => HashCommand::GetAll(key),
+            "hincrby" => {
+                let field = args.next_bulk_string()?;
+                HashCommand::IncrBy(key, field, args.next_integer()?)
+            }
+            "hincrbyfloat" => {
+                let field = args.next_bulk_string()?;
+                HashCommand::IncrByFloat(key, field, args.next_float()?)
+            }
+            "hexists" => HashCommand::Exists(key, args.next_bulk_string()?),
+            "hkeys" => HashCommand::Keys(key),
+            "hvals" => HashCommand::Vals(key),
+            "hlen" => HashCommand::Len(key),
+            "hscan" => {
+                let cursor = args.next_cursor()?;
+                HashCommand::Scan(key, cursor, args.next_scan_options(&["novalues"])?)
+            }
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            HashCommand::Set(key, _)
+            | HashCommand::MSet(key, _)
+            | HashCommand::SetNx(key, _, _)
+            | HashCommand::Get(key, _)
+            | HashCommand::MGet(key, _)
+            | HashCommand::Del(key, _)
+            | HashCommand::GetAll(key)
+            | HashCommand::IncrBy(key, _, _)
+            | HashCommand::IncrByFloat(key, _, _)
+            | HashCommand::Exists(key, _)
+            | HashCommand::Keys(key)
+            | HashCommand::Vals(key)
+            | HashCommand::Len(key)
+            | HashCommand::Scan(key, _, _) => key,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            HashCommand::Set(..)
+                | HashCommand::MSet(..)
+                | HashCommand::SetNx(..)
+                | HashCommand::Del(..)
+                | HashCommand::IncrBy(..)
+                | HashCommand::IncrByFloat(..)
+        )
+    }
+}
+
+impl From<&HashCommand> for Vec<RedisValue> {
+    fn from(hash_command: &HashCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let pairs = |name: &str, key: &RedisBulkString, pairs: &FieldValues| {
+            let mut vs = command(name, key);
+            for (field, value) in pairs {
+                vs.push(field.into());
+                vs.push(value.into());
+            }
+            vs
+        };
+        let fields = |name: &str, key: &RedisBulkString, fields: &[RedisBulkString]| {
+            let mut vs = command(name, key);
+            vs.extend(fields.iter().map(|f| f.into()));
+            vs
+        };
+        match hash_command {
+            HashCommand::Set(key, fvs) => pairs("hset", key, fvs),
+            HashCommand::MSet(key, fvs) => pairs("hmset", key, fvs),
+            HashCommand::SetNx(key, field, value) => {
+                let mut vs = command("hsetnx", key);
+                vs.extend([field.into(), value.into()]);
+                vs
+            }
+            HashCommand::Get(key, field) => fields("hget", key, std::slice::from_ref(field)),
+            HashCommand::MGet(key, fs) => fields("hmget", key, fs),
+            HashCommand::Del(key, fs) => fields("hdel", key, fs),
+            HashCommand::GetAll(key) => command("hgetall", key),
+            HashCommand::IncrBy(key, field, increment) => {
+                let mut vs = command("hincrby", key);
+                vs.push(field.into());
+                vs.push(RedisValue::bulk_string(increment.to_string().as_str()));
+                vs
+            }
+            HashCommand::IncrByFloat(key, field, increment) => {
+                let mut vs = command("hincrbyfloat", key);
+                vs.push(field.into());
+                vs.push(RedisValue::bulk_string(
+                    utilities::format_float(*increment).as_str(),
+                ));
+                vs
+            }
+            HashCommand::Exists(key, field) => fields("hexists", key, std::slice::from_ref(field)),
+            HashCommand::Keys(key) => command("hkeys", key),
+            HashCommand::Vals(key) => command("hvals", key),
+            HashCommand::Len(key) => command("hlen", key),
+            HashCommand::Scan(key, cursor, options) => {
+                let mut vs = command("hscan", key);
+                vs.push(RedisValue::bulk_string(cursor.to_string().as_str()));
+                vs.extend(options.to_args());
+                vs
+            }
+        }
+    }
+}
+
+// the command replicas apply, `HINCRBYFLOAT` is sent as the value it produced so that
+// the replicas do not round the float differently
+pub fn replicated(command: &HashCommand, response: &RedisValue) -> HashCommand {
+    match (command, response) {
+        (HashCommand::IncrByFloat(key, field, _), RedisValue::BulkString(Some(value))) => {
+            HashCommand::Set(key.clone(), vec![(field.clone(), value.clone())])
+        }
+        (command, _) => command.clone(),
+    }
+}
+
+// the hash stored at the key, none if the key does not exist
+fn get_hash<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut Fields>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::Hash(hash),
+            ..
+        }) => Ok(Some(hash)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+// the hash stored at the key, an empty one is created if the key does not exist
+fn get_or_create_hash<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<&'a mut Fields, RedisValue> {
+    get_hash(store, key, now, mode)?;
+    let item = store.get_or_insert_with(key, || StoreItem {
+        value: RedisObject::Hash(HashMap::new()),
+        expired_at: 0,
+    });
+    match &mut item.value {
+        RedisObject::Hash(hash) => Ok(hash),
+        _ => Err(wrong_type()),
+    }
+}
+
+fn bulk_string(data: Option<&Vec<u8>>) -> RedisValue {
+    match data {
+        Some(data) => RedisValue::bulk_string_from_bytes(data.as_slice()),
+        None => RedisValue::null_bulk_string(),
+    }
+}
+
+pub fn execute(store: &mut Store, command: &HashCommand, now: u64, mode: ExpiryMode) -> RedisValue {
+    let key: String = command.key().into();
+    let response = match run(store, command, &key, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    };
+    store.remove_if_empty(&key);
+    response
+}
+
+fn run(
+    store: &mut Store,
+    command: &HashCommand,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        HashCommand::Set(_, pairs) | HashCommand::MSet(_, pairs) => {
+            let hash = get_or_create_hash(store, key, now, mode)?;
+            let mut created = 0;
+            for (field, value) in pairs {
+                if hash
+                    .insert(field.data.clone(), value.data.clone())
+                    .is_none()
+                {
+                    created += 1;
+                }
+            }
+            match command {
+                HashCommand::MSet(..) => RedisValue::simple_string("OK"),
+                _ => RedisValue::Integer(created),
+            }
+        }
+        HashCommand::SetNx(_, field, value) => {
+            let hash = get_or_create_hash(store, key, now, mode)?;
+            if hash.contains_key(&field.data) {
+                RedisValue::Integer(0)
+            } else {
+                hash.insert(field.data.clone(), value.data.clone());
+                RedisValue::Integer(1)
+            }
+        }
+        HashCommand::Get(_, field) => {
+            let hash = get_hash(store, key, now, mode)?;
+            bulk_string(hash.and_then(|hash| hash.get(&field.data)))
+        }
+        HashCommand::MGet(_, fields) => {
+            let hash = get_hash(store, key, now, mode)?;
+            let values = fields
+                .iter()
+                .map(|field| bulk_string(hash.as_ref().and_then(|hash| hash.get(&field.data))))
+                .collect();
+            RedisValue::Array(values)
+        }
+        HashCommand::Del(_, fields) => {
+            let removed = match get_hash(store, key, now, mode)? {
+                Some(hash) => fields
+                    .iter()
+                    .filter(|field| hash.remove(&field.data).is_some())
+                    .count(),
+                None => 0,
+            };
+            RedisValue::Integer(removed as i64)
+        }
+        HashCommand::GetAll(_) => {
+            let hash = get_hash(store, key, now, mode)?;
+            let mut values = vec![];
+            for (field, value) in hash.into_iter().flatten() {
+                values.push(RedisValue::bulk_string_from_bytes(field.as_slice()));
+                values.push(RedisValue::bulk_string_from_bytes(value.as_slice()));
+            }
+            RedisValue::Array(values)
+        }
+        HashCommand::IncrBy(_, field, increment) => {
+            let hash = get_or_create_hash(store, key, now, mode)?;
+            let current: i64 = match hash.get(&field.data) {
+                Some(value) => match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                    Some(current) => current,
+                    None => return Err(RedisValue::error("ERR hash value is not an integer")),
+                },
+                None => 0,
+            };
+            let value = match current.checked_add(*increment) {
+                Some(value) => value,
+                None => {
+                    return Err(RedisValue::error(
+                        "ERR increment or decrement would overflow",
+                    ))
+                }
+            };
+            hash.insert(field.data.clone(), value.to_string().into_bytes());
+            RedisValue::Integer(value)
+        }
+        HashCommand::IncrByFloat(_, field, increment) => {
+            let hash = get_or_create_hash(store, key, now, mode)?;
+            let current: f64 = match hash.get(&field.data) {
+                Some(value) => match std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| !v.is_nan())
+                {
+                    Some(current) => current,
+                    None => return Err(RedisValue::error("ERR hash value is not a float")),
+                },
+                None => 0.0,
+            };
+            let value = current + increment;
+            if !value.is_finite() {
+                return Err(RedisValue::error(
+                    "ERR increment would produce NaN or Infinity",
+                ));
+            }
+            let value = utilities::format_float(value);
+            hash.insert(field.data.clone(), value.clone().into_bytes());
+            RedisValue::bulk_string(value.as_str())
+        }
+        HashCommand::Exists(_, field) => {
+            let hash = get_hash(store, key, now, mode)?;
+            let exists = hash.is_some_and(|hash| hash.contains_key(&field.data));
+            RedisValue::Integer(exists as i64)
+        }
+        HashCommand::Keys(_) => {
+            let hash = get_hash(store, key, now, mode)?;
+            let fields = hash
+                .into_iter()
+                .flat_map(|hash| hash.keys())
+                .map(|field| RedisValue::bulk_string_from_bytes(field.as_slice()))
+                .collect();
+            RedisValue::Array(fields)
+        }
+        HashCommand::Vals(_) => {
+            let hash = get_hash(store, key, now, mode)?;
+            let values = hash
+                .into_iter()
+                .flat_map(|hash| hash.values())
+                .map(|value| RedisValue::bulk_string_from_bytes(value.as_slice()))
+                .collect();
+            RedisValue::Array(values)
+        }
+        HashCommand::Len(_) => {
+            let length = get_hash(store, key, now, mode)?.map_or(0, |hash| hash.len());
+            RedisValue::Integer(length as i64)
+        }
+        HashCommand::Scan(_, cursor, options) => {
+            let hash = match get_hash(store, key, now, mode)? {
+                Some(hash) => hash,
+                None => {
+                    return Ok(RedisValue::Array(vec![
+                        RedisValue::bulk_string("0"),
+                        RedisValue::Array(vec![]),
+                    ]))
+                }
+            };
+            let (cursor, pairs) = scan(
+                hash.iter()
+                    .map(|(field, value)| (field.as_slice(), (field, value))),
+                *cursor,
+                options.count,
+            );
+            let mut values = vec![];
+            for (field, value) in pairs
+                .into_iter()
+                .filter(|(field, _)| options.matches(field))
+            {
+                values.push(RedisValue::bulk_string_from_bytes(field.as_slice()));
+                if !options.novalues {
+                    values.push(RedisValue::bulk_string_from_bytes(value.as_slice()));
+                }
+            }
+            RedisValue::Array(vec![
+                RedisValue::bulk_string(cursor.to_string().as_str()),
+                RedisValue::Array(values),
+            ])
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    #[test]
+    fn test_set_get_del() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["hset", "h", "a", "1", "b", "2"])
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["hset", "h", "a", "3", "c", "4"])
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::bulk_string("3"),
+                RedisValue::null_bulk_string()
+            ]),
+            run_command(&mut store, &["hmget", "h", "a", "x"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["hsetnx", "h", "a", "5"])
+        );
+        assert_eq!(
+            RedisValue::Integer(3),
+            run_command(&mut store, &["hdel", "h", "a", "b", "c", "d"])
+        );
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_increments() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(-5),
+            run_command(&mut store, &["hincrby", "h", "n", "-5"])
+        );
+        run_command(
+            &mut store,
+            &["hset", "h", "max", "9223372036854775807", "s", "x"],
+        );
+        assert_eq!(
+            RedisValue::error("ERR increment or decrement would overflow"),
+            run_command(&mut store, &["hincrby", "h", "max", "1"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR hash value is not an integer"),
+            run_command(&mut store, &["hincrby", "h", "s", "1"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("-4.5"),
+            run_command(&mut store, &["hincrbyfloat", "h", "n", "0.5"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR increment would produce NaN or Infinity"),
+            run_command(&mut store, &["hincrbyfloat", "h", "n", "inf"])
+        );
+
+        let command = HashCommand::IncrByFloat("h".into(), "n".into(), 0.1);
+        let response = execute(&mut store, &command, 0, ExpiryMode::Delete);
+        assert_eq!(RedisValue::bulk_string("-4.4"), response);
+        assert_eq!(
+            HashCommand::Set("h".into(), vec![("n".into(), "-4.4".into())]),
+            replicated(&command, &response)
+        );
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut store = Store::default();
+        run_command(&mut store, &["hset", "h", "a1", "1", "a2", "2", "b1", "3"]);
+        let response = run_command(
+            &mut store,
+            &["hscan", "h", "0", "match", "a*", "count", "100"],
+        );
+        let mut fields = match response {
+            RedisValue::Array(reply) => {
+                assert_eq!(RedisValue::bulk_string("0"), reply[0]);
+                match &reply[1] {
+                    RedisValue::Array(values) => values.clone(),
+                    _ => panic!(),
+                }
+            }
+            _ => panic!(),
+        };
+        fields.sort_by_key(|v| format!("{:?}", v));
+        assert_eq!(
+            vec![
+                RedisValue::bulk_string("1"),
+                RedisValue::bulk_string("2"),
+                RedisValue::bulk_string("a1"),
+                RedisValue::bulk_string("a2"),
+            ],
+            fields
+        );
+    }
+}
@@ -0,0 +1,556 @@
+use crate::command::{CommandArgs, RedisCommandError};
+use crate::redis::{ExpiryMode, Store};
+use crate::string::{get_string, put_string};
+use crate::value::{RedisBulkString, RedisValue};
+
+// the layout is the one of redis, so that the values can be exchanged with it:
+// a 16 bytes header of the magic `HYLL`, the encoding, 3 unused bytes and the cached
+// cardinality in little endian, whose most significant bit is set when it is stale,
+// followed by the registers in the dense or the sparse encoding
+const HLL_P: u32 = 14;
+const HLL_Q: u32 = 64 - HLL_P;
+const HLL_REGISTERS: usize = 1 << HLL_P;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+// a sparse value larger than this is converted to the dense encoding
+const HLL_SPARSE_MAX_BYTES: usize = 3000;
+
+// the opcodes of the sparse encoding:
+// `00xxxxxx` is a run of up to 64 zero registers,
+// `01xxxxxx yyyyyyyy` a run of up to 16384 zero registers,
+// `1vvvvvxx` a run of up to 4 registers of the value up to 32
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+
+const HLL_ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum HyperLogLogCommand {
+    Add(RedisBulkString, Vec<RedisBulkString>),
+    Count(Vec<RedisBulkString>),
+    // the destination key, then the source keys
+    Merge(RedisBulkString, Vec<RedisBulkString>),
+}
+
+impl HyperLogLogCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<HyperLogLogCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "pfadd" => {
+                let key = args.next_key()?;
+                let mut elements = Vec::new();
+                while !args.is_empty() {
+                    elements.push(args.next_bulk_string()?);
+                }
+                HyperLogLogCommand::Add(key, elements)
+            }
+            "pfcount" => HyperLogLogCommand::Count(args.rest_keys()?),
+            "pfmerge" => {
+                let destination = args.next_key()?;
+                let mut sources = Vec::new();
+                while !args.is_empty() {
+                    sources.push(args.next_key()?);
+                }
+                HyperLogLogCommand::Merge(destination, sources)
+            }
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn is_write(&self) -> bool {
+        // `PFCOUNT` only refreshes the cached cardinality
+        !matches!(self, HyperLogLogCommand::Count(_))
+    }
+}
+
+impl From<&HyperLogLogCommand> for Vec<RedisValue> {
+    fn from(hll_command: &HyperLogLogCommand) -> Vec<RedisValue> {
+        let (name, args): (&str, Vec<&RedisBulkString>) = match hll_command {
+            HyperLogLogCommand::Add(key, elements) => {
+                ("pfadd", std::iter::once(key).chain(elements).collect())
+            }
+            HyperLogLogCommand::Count(keys) => ("pfcount", keys.iter().collect()),
+            HyperLogLogCommand::Merge(destination, sources) => (
+                "pfmerge",
+                std::iter::once(destination).chain(sources).collect(),
+            ),
+        };
+        let mut vs = vec![RedisValue::bulk_string(name)];
+        vs.extend(args.into_iter().map(|arg| arg.into()));
+        vs
+    }
+}
+
+fn not_hll() -> RedisValue {
+    RedisValue::error("WRONGTYPE Key is not a valid HyperLogLog string value.")
+}
+
+fn corrupted() -> RedisValue {
+    RedisValue::error("INVALIDOBJ Corrupted HLL object detected")
+}
+
+// 64 bits MurmurHash2 by Austin Appleby, reading the words in little endian
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4_a793_5bd1_e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+    let chunks = key.chunks_exact(8);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+// the register of an element, and the length of the run of zeros ending the rest of
+// its hash plus one
+fn register_of(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, 0xadc8_3b19);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    // the bit past the hash bounds the count to `HLL_Q + 1`
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+fn dense_register(registers: &[u8], index: usize) -> u8 {
+    let bit = index * HLL_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let low = registers[byte] as u16;
+    let high = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((low | (high << 8)) >> shift) as u8) & HLL_REGISTER_MAX
+}
+
+fn set_dense_register(registers: &mut [u8], index: usize, value: u8) {
+    let bit = index * HLL_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let mask = (HLL_REGISTER_MAX as u16) << shift;
+    let value = (value as u16) << shift;
+    registers[byte] = (registers[byte] & !(mask as u8)) | value as u8;
+    if let Some(high) = registers.get_mut(byte + 1) {
+        *high = (*high & !((mask >> 8) as u8)) | (value >> 8) as u8;
+    }
+}
+
+// the registers of a valid value, one per byte
+fn registers(hll: &[u8]) -> Result<Vec<u8>, RedisValue> {
+    let data = &hll[HLL_HDR_SIZE..];
+    if hll[4] == HLL_DENSE {
+        return Ok((0..HLL_REGISTERS)
+            .map(|index| dense_register(data, index))
+            .collect());
+    }
+    let mut registers = Vec::with_capacity(HLL_REGISTERS);
+    let mut i = 0;
+    while i < data.len() {
+        let opcode = data[i];
+        let (value, length) = if opcode & 0x80 != 0 {
+            (((opcode >> 2) & 0x1f) + 1, (opcode & 0x3) as usize + 1)
+        } else if opcode & 0x40 != 0 {
+            let next = *data.get(i + 1).ok_or_else(corrupted)?;
+            i += 1;
+            (0, (((opcode & 0x3f) as usize) << 8 | next as usize) + 1)
+        } else {
+            (0, (opcode & 0x3f) as usize + 1)
+        };
+        i += 1;
+        if registers.len() + length > HLL_REGISTERS {
+            return Err(corrupted());
+        }
+        registers.resize(registers.len() + length, value);
+    }
+    if registers.len() != HLL_REGISTERS {
+        return Err(corrupted());
+    }
+    Ok(registers)
+}
+
+fn header(encoding: u8) -> Vec<u8> {
+    let mut hll = b"HYLL".to_vec();
+    hll.push(encoding);
+    hll.resize(HLL_HDR_SIZE, 0);
+    hll
+}
+
+fn dense(registers: &[u8]) -> Vec<u8> {
+    let mut hll = header(HLL_DENSE);
+    hll.resize(HLL_DENSE_SIZE, 0);
+    for (index, register) in registers.iter().enumerate() {
+        set_dense_register(&mut hll[HLL_HDR_SIZE..], index, *register);
+    }
+    hll
+}
+
+// none if a register is too large for the sparse encoding or the value would be too long
+fn sparse(registers: &[u8]) -> Option<Vec<u8>> {
+    let mut hll = header(HLL_SPARSE);
+    let mut index = 0;
+    while index < registers.len() {
+        let value = registers[index];
+        let mut run = registers[index..]
+            .iter()
+            .take_while(|register| **register == value)
+            .count();
+        index += run;
+        if value > HLL_SPARSE_VAL_MAX_VALUE {
+            return None;
+        }
+        while run > 0 {
+            if value != 0 {
+                let length = run.min(HLL_SPARSE_VAL_MAX_LEN);
+                hll.push(0x80 | ((value - 1) << 2) | (length - 1) as u8);
+                run -= length;
+            } else if run > HLL_SPARSE_ZERO_MAX_LEN {
+                let length = run.min(HLL_SPARSE_XZERO_MAX_LEN) - 1;
+                hll.push(0x40 | (length >> 8) as u8);
+                hll.push((length & 0xff) as u8);
+                run -= length + 1;
+            } else {
+                hll.push((run - 1) as u8);
+                run = 0;
+            }
+        }
+        if hll.len() > HLL_SPARSE_MAX_BYTES {
+            return None;
+        }
+    }
+    Some(hll)
+}
+
+fn is_cache_valid(hll: &[u8]) -> bool {
+    hll[15] & 0x80 == 0
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[15] |= 0x80;
+}
+
+// the sum of the harmonic series of 2^-register, following "New cardinality estimation
+// algorithms for HyperLogLog sketches" by Otmar Ertl
+fn count(registers: &[u8]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+    let m = HLL_REGISTERS as f64;
+    let mut z = m * tau((m - histogram[HLL_Q as usize + 1] as f64) / m);
+    for j in (1..=HLL_Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+// the value of the key, checking that it is a hyperloglog
+fn get_hll<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut Vec<u8>>, RedisValue> {
+    match get_string(store, key, now, mode)? {
+        Some(hll)
+            if hll.len() < HLL_HDR_SIZE
+                || &hll[..4] != b"HYLL"
+                || hll[4] > HLL_SPARSE
+                || (hll[4] == HLL_DENSE && hll.len() != HLL_DENSE_SIZE) =>
+        {
+            Err(not_hll())
+        }
+        hll => Ok(hll),
+    }
+}
+
+pub fn execute(
+    store: &mut Store,
+    command: &HyperLogLogCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> RedisValue {
+    match run(store, command, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    }
+}
+
+fn run(
+    store: &mut Store,
+    command: &HyperLogLogCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        HyperLogLogCommand::Add(key, elements) => {
+            let key: String = key.into();
+            let created = get_hll(store, &key, now, mode)?.is_none();
+            if created {
+                put_string(store, &key, sparse(&[0; HLL_REGISTERS]).unwrap(), now, mode);
+            }
+            let hll = get_hll(store, &key, now, mode)?.unwrap();
+            let mut changed = false;
+            if hll[4] == HLL_DENSE {
+                for element in elements {
+                    let (index, count) = register_of(&element.data);
+                    if dense_register(&hll[HLL_HDR_SIZE..], index) < count {
+                        set_dense_register(&mut hll[HLL_HDR_SIZE..], index, count);
+                        changed = true;
+                    }
+                }
+            } else {
+                let mut registers = registers(hll)?;
+                for element in elements {
+                    let (index, count) = register_of(&element.data);
+                    if registers[index] < count {
+                        registers[index] = count;
+                        changed = true;
+                    }
+                }
+                if changed {
+                    *hll = sparse(&registers).unwrap_or_else(|| dense(&registers));
+                }
+            }
+            if changed {
+                invalidate_cache(hll);
+            }
+            RedisValue::Integer((created || changed) as i64)
+        }
+        HyperLogLogCommand::Count(keys) if keys.len() == 1 => {
+            let key: String = (&keys[0]).into();
+            let hll = match get_hll(store, &key, now, mode)? {
+                Some(hll) => hll,
+                None => return Ok(RedisValue::Integer(0)),
+            };
+            if !is_cache_valid(hll) {
+                let cardinality = count(&registers(hll)?);
+                hll[8..HLL_HDR_SIZE].copy_from_slice(&cardinality.to_le_bytes());
+            }
+            let cardinality = u64::from_le_bytes(hll[8..HLL_HDR_SIZE].try_into().unwrap());
+            RedisValue::Integer(cardinality as i64)
+        }
+        // the union of several keys is counted on the fly
+        HyperLogLogCommand::Count(keys) => {
+            let (registers, _) = union(store, keys, now, mode)?;
+            RedisValue::Integer(count(&registers) as i64)
+        }
+        HyperLogLogCommand::Merge(destination, sources) => {
+            let mut keys = vec![destination.clone()];
+            keys.extend(sources.iter().cloned());
+            let (registers, all_sparse) = union(store, &keys, now, mode)?;
+            let mut hll = match all_sparse {
+                true => sparse(&registers).unwrap_or_else(|| dense(&registers)),
+                false => dense(&registers),
+            };
+            invalidate_cache(&mut hll);
+            let destination: String = destination.into();
+            put_string(store, &destination, hll, now, mode);
+            RedisValue::simple_string("OK")
+        }
+    };
+    Ok(response)
+}
+
+// the maximum of the registers of the keys, and whether none of them was dense
+fn union(
+    store: &mut Store,
+    keys: &[RedisBulkString],
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<(Vec<u8>, bool), RedisValue> {
+    let mut union = vec![0; HLL_REGISTERS];
+    let mut all_sparse = true;
+    for key in keys {
+        let key: String = key.into();
+        if let Some(hll) = get_hll(store, &key, now, mode)? {
+            all_sparse &= hll[4] == HLL_SPARSE;
+            for (max, register) in union.iter_mut().zip(registers(hll)?) {
+                *max = (*max).max(register);
+            }
+        }
+    }
+    Ok((union, all_sparse))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::RedisObject;
+    use crate::redis::StoreItem;
+    use crate::testing::run_command;
+
+    fn value(store: &Store, key: &str) -> Vec<u8> {
+        match &store[key].value {
+            RedisObject::String(s) => s.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_encoding() {
+        // an empty value is a single run of zeros, as created by redis
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["pfadd", "h"])
+        );
+        assert_eq!(
+            b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff".to_vec(),
+            value(&store, "h")
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["pfadd", "h"])
+        );
+
+        assert_eq!(
+            0xa919_bc30_51f6_24b7,
+            murmurhash64a(b"hello world", 0xadc8_3b19)
+        );
+        assert_eq!(
+            0xf09b_e870_ed71_5d16,
+            murmurhash64a(b"element:12345", 0xadc8_3b19)
+        );
+        let mut registers = vec![0; HLL_REGISTERS];
+        registers[3] = 2;
+        registers[4] = 2;
+        registers[100] = 40;
+        let hll = dense(&registers);
+        assert_eq!(HLL_DENSE_SIZE, hll.len());
+        assert_eq!(registers, super::registers(&hll).unwrap());
+        registers[100] = 5;
+        let hll = sparse(&registers).unwrap();
+        assert_eq!(
+            b"\x02\x85\x40\x5e\x90\x7f\x9a".to_vec(),
+            hll[HLL_HDR_SIZE..]
+        );
+        assert_eq!(registers, super::registers(&hll).unwrap());
+    }
+
+    #[test]
+    fn test_add_and_count() {
+        let mut store = Store::default();
+        let args = |key: &'static str, range: std::ops::Range<usize>| {
+            let mut args = vec!["pfadd".to_string(), key.to_string()];
+            args.extend(range.map(|i| format!("element:{}", i)));
+            args
+        };
+        let add = |store: &mut Store, args: Vec<String>| {
+            let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+            run_command(store, &args)
+        };
+        assert_eq!(RedisValue::Integer(1), add(&mut store, args("a", 0..7)));
+        assert_eq!(
+            RedisValue::Integer(7),
+            run_command(&mut store, &["pfcount", "a"])
+        );
+        // the count is cached until the next change
+        assert!(is_cache_valid(&value(&store, "a")));
+        assert_eq!(RedisValue::Integer(0), add(&mut store, args("a", 0..7)));
+        assert!(is_cache_valid(&value(&store, "a")));
+
+        // a large cardinality goes dense, the standard error is 0.81%
+        add(&mut store, args("a", 0..20000));
+        assert_eq!(HLL_DENSE, value(&store, "a")[4]);
+        let count = |store: &mut Store, keys: &[&str]| {
+            let mut args = vec!["pfcount"];
+            args.extend_from_slice(keys);
+            match run_command(store, &args) {
+                RedisValue::Integer(n) => n,
+                v => panic!("unexpected {:?}", v),
+            }
+        };
+        assert!((19600..20400).contains(&count(&mut store, &["a"])));
+        add(&mut store, args("b", 10000..30000));
+        assert!((29400..30600).contains(&count(&mut store, &["a", "b", "missing"])));
+
+        store.insert(
+            "s".to_string(),
+            StoreItem {
+                value: RedisObject::String(b"HYLL".to_vec()),
+                expired_at: 0,
+            },
+        );
+        assert_eq!(not_hll(), run_command(&mut store, &["pfadd", "s", "x"]));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut store = Store::default();
+        run_command(&mut store, &["pfadd", "a", "x", "y", "z"]);
+        run_command(&mut store, &["pfadd", "b", "z", "w"]);
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command(&mut store, &["pfmerge", "d", "a", "b", "missing"])
+        );
+        assert_eq!(HLL_SPARSE, value(&store, "d")[4]);
+        assert_eq!(
+            RedisValue::Integer(4),
+            run_command(&mut store, &["pfcount", "d"])
+        );
+        // the destination is part of the union
+        run_command(&mut store, &["pfadd", "c", "v"]);
+        run_command(&mut store, &["pfmerge", "d", "c"]);
+        assert_eq!(
+            RedisValue::Integer(5),
+            run_command(&mut store, &["pfcount", "d"])
+        );
+    }
+}
@@ -0,0 +1,872 @@
+use crate::command::{CommandArgs, RedisCommand, RedisCommandError, ScanOptions};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+
+// the attempts of `RANDOMKEY` at finding a key which has not expired
+const RANDOM_KEY_TRIES: usize = 100;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ExpireTime {
+    // relative, in seconds and milliseconds
+    Ex(i64),
+    Px(i64),
+    // unix time, in seconds and milliseconds
+    ExAt(i64),
+    PxAt(i64),
+}
+
+impl ExpireTime {
+    // the unix time in milliseconds of the expiry, none if it overflows
+    fn expired_at(&self, now: u64) -> Option<i64> {
+        match *self {
+            ExpireTime::Ex(s) => s.checked_mul(1000)?.checked_add(now as i64),
+            ExpireTime::Px(ms) => ms.checked_add(now as i64),
+            ExpireTime::ExAt(s) => s.checked_mul(1000),
+            ExpireTime::PxAt(ms) => Some(ms),
+        }
+    }
+
+    fn command_name(&self) -> &'static str {
+        match self {
+            ExpireTime::Ex(_) => "expire",
+            ExpireTime::Px(_) => "pexpire",
+            ExpireTime::ExAt(_) => "expireat",
+            ExpireTime::PxAt(_) => "pexpireat",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ExpireCondition {
+    // only if the key has no expiry
+    Nx,
+    // only if the key has an expiry
+    Xx,
+    // only if the new expiry is later, a key without expiry never expiring
+    Gt,
+    // only if the new expiry is sooner
+    Lt,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum KeyCommand {
+    Del(Vec<RedisBulkString>),
+    // like `DEL`, the items being freed in background
+    Unlink(Vec<RedisBulkString>),
+    Exists(Vec<RedisBulkString>),
+    Touch(Vec<RedisBulkString>),
+    // the pattern of the keys
+    Keys(RedisBulkString),
+    Scan(u64, ScanOptions),
+    // source, destination, whether only if the destination does not exist
+    Rename(RedisBulkString, RedisBulkString, bool),
+    RandomKey,
+    DbSize,
+    // whether all the databases, whether the items are freed in background
+    Flush(bool, bool),
+    // key, destination database
+    Move(RedisBulkString, i64),
+    // source, destination, destination database, whether to replace the destination
+    Copy(RedisBulkString, RedisBulkString, Option<i64>, bool),
+    SwapDb(i64, i64),
+    Expire(RedisBulkString, ExpireTime, Option<ExpireCondition>),
+    // key, whether in milliseconds
+    Ttl(RedisBulkString, bool),
+    ExpireTime(RedisBulkString, bool),
+    Persist(RedisBulkString),
+}
+
+impl KeyCommand {
+    pub fn parse(command_name: &str, args: &[RedisValue]) -> Result<KeyCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "del" => KeyCommand::Del(args.rest_keys()?),
+            "unlink" => KeyCommand::Unlink(args.rest_keys()?),
+            "exists" => KeyCommand::Exists(args.rest_keys()?),
+            "touch" => KeyCommand::Touch(args.rest_keys()?),
+            "keys" => KeyCommand::Keys(args.next_bulk_string()?),
+            "scan" => {
+                let cursor = args.next_cursor()?;
+                KeyCommand::Scan(cursor, args.next_scan_options(&["type"])?)
+            }
+            "rename" | "renamenx" => KeyCommand::Rename(
+                args.next_key()?,
+                args.next_key()?,
+                command_name == "renamenx",
+            ),
+            "move" => KeyCommand::Move(args.next_key()?, args.next_integer()?),
+            "copy" => {
+                let source = args.next_key()?;
+                let destination = args.next_key()?;
+                let (mut db, mut replace) = (None, false);
+                while !args.is_empty() {
+                    match args.next_string()?.to_lowercase().as_str() {
+                        "db" => db = Some(args.next_integer()?),
+                        "replace" => replace = true,
+                        _ => return Err(RedisCommandError::IlleagalArg),
+                    }
+                }
+                KeyCommand::Copy(source, destination, db, replace)
+            }
+            "swapdb" => {
+                let invalid =
+                    |which: &str| RedisCommandError::Invalid(format!("invalid {} DB index", which));
+                let first = args.next_integer().map_err(|_| invalid("first"))?;
+                let second = args.next_integer().map_err(|_| invalid("second"))?;
+                KeyCommand::SwapDb(first, second)
+            }
+            "randomkey" => KeyCommand::RandomKey,
+            "dbsize" => KeyCommand::DbSize,
+            "flushdb" | "flushall" => {
+                let lazy = match args.is_empty() {
+                    true => false,
+                    false => match args.next_string()?.to_lowercase().as_str() {
+                        "async" => true,
+                        "sync" => false,
+                        _ => return Err(RedisCommandError::IlleagalArg),
+                    },
+                };
+                KeyCommand::Flush(command_name == "flushall", lazy)
+            }
+            "expire" | "pexpire" | "expireat" | "pexpireat" => {
+                let key = args.next_key()?;
+                let time: i64 = args.next_integer()?;
+                let time = match command_name {
+                    "expire" => ExpireTime::Ex(time),
+                    "pexpire" => ExpireTime::Px(time),
+                    "expireat" => ExpireTime::ExAt(time),
+                    _ => ExpireTime::PxAt(time),
+                };
+                let mut flags = Vec::new();
+                while !args.is_empty() {
+                    let flag = args.next_string()?.to_lowercase();
+                    match flag.as_str() {
+                        "nx" | "xx" | "gt" | "lt" => flags.push(flag),
+                        _ => {
+                            return Err(RedisCommandError::Invalid(format!(
+                                "Unsupported option {}",
+                                flag
+                            )))
+                        }
+                    }
+                }
+                let has = |flag: &str| flags.iter().any(|f| f == flag);
+                let invalid = |message: &str| Err(RedisCommandError::Invalid(message.to_string()));
+                if has("nx") && (has("xx") || has("gt") || has("lt")) {
+                    return invalid(
+                        "NX and XX, GT or LT options at the same time are not compatible",
+                    );
+                }
+                if has("gt") && has("lt") {
+                    return invalid("GT and LT options at the same time are not compatible");
+                }
+                let condition = if has("nx") {
+                    Some(ExpireCondition::Nx)
+                } else if has("gt") {
+                    Some(ExpireCondition::Gt)
+                } else if has("lt") {
+                    Some(ExpireCondition::Lt)
+                } else if has("xx") {
+                    Some(ExpireCondition::Xx)
+                } else {
+                    None
+                };
+                KeyCommand::Expire(key, time, condition)
+            }
+            "ttl" | "pttl" => KeyCommand::Ttl(args.next_key()?, command_name == "pttl"),
+            "expiretime" | "pexpiretime" => {
+                KeyCommand::ExpireTime(args.next_key()?, command_name == "pexpiretime")
+            }
+            "persist" => KeyCommand::Persist(args.next_key()?),
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            KeyCommand::Del(_)
+                | KeyCommand::Unlink(_)
+                | KeyCommand::Rename(..)
+                | KeyCommand::Flush(..)
+                | KeyCommand::Move(..)
+                | KeyCommand::Copy(..)
+                | KeyCommand::SwapDb(..)
+                | KeyCommand::Expire(..)
+                | KeyCommand::Persist(_)
+        )
+    }
+}
+
+impl From<&KeyCommand> for Vec<RedisValue> {
+    fn from(key_command: &KeyCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let keys = |name: &str, keys: &Vec<RedisBulkString>| {
+            let mut vs = vec![RedisValue::bulk_string(name)];
+            vs.extend(keys.iter().map(|key| key.into()));
+            vs
+        };
+        match key_command {
+            KeyCommand::Del(ks) => keys("del", ks),
+            KeyCommand::Unlink(ks) => keys("unlink", ks),
+            KeyCommand::Exists(ks) => keys("exists", ks),
+            KeyCommand::Touch(ks) => keys("touch", ks),
+            KeyCommand::Keys(pattern) => command("keys", pattern),
+            KeyCommand::Scan(cursor, options) => {
+                let mut vs = vec![
+                    RedisValue::bulk_string("scan"),
+                    RedisValue::bulk_string(cursor.to_string().as_str()),
+                ];
+                vs.extend(options.to_args());
+                vs
+            }
+            KeyCommand::Rename(source, destination, nx) => {
+                let mut vs = command(if *nx { "renamenx" } else { "rename" }, source);
+                vs.push(destination.into());
+                vs
+            }
+            KeyCommand::Move(key, db) => {
+                let mut vs = command("move", key);
+                vs.push(RedisValue::bulk_string(db.to_string().as_str()));
+                vs
+            }
+            KeyCommand::Copy(source, destination, db, replace) => {
+                let mut vs = command("copy", source);
+                vs.push(destination.into());
+                if let Some(db) = db {
+                    vs.push(RedisValue::bulk_string("db"));
+                    vs.push(RedisValue::bulk_string(db.to_string().as_str()));
+                }
+                if *replace {
+                    vs.push(RedisValue::bulk_string("replace"));
+                }
+                vs
+            }
+            KeyCommand::SwapDb(first, second) => vec![
+                RedisValue::bulk_string("swapdb"),
+                RedisValue::bulk_string(first.to_string().as_str()),
+                RedisValue::bulk_string(second.to_string().as_str()),
+            ],
+            KeyCommand::RandomKey => vec![RedisValue::bulk_string("randomkey")],
+            KeyCommand::DbSize => vec![RedisValue::bulk_string("dbsize")],
+            KeyCommand::Flush(all, lazy) => {
+                let mut vs = vec![RedisValue::bulk_string(if *all {
+                    "flushall"
+                } else {
+                    "flushdb"
+                })];
+                if *lazy {
+                    vs.push(RedisValue::bulk_string("async"));
+                }
+                vs
+            }
+            KeyCommand::Expire(key, time, condition) => {
+                let mut vs = command(time.command_name(), key);
+                let time = match time {
+                    ExpireTime::Ex(t)
+                    | ExpireTime::Px(t)
+                    | ExpireTime::ExAt(t)
+                    | ExpireTime::PxAt(t) => t,
+                };
+                vs.push(RedisValue::bulk_string(time.to_string().as_str()));
+                if let Some(condition) = condition {
+                    vs.push(RedisValue::bulk_string(match condition {
+                        ExpireCondition::Nx => "nx",
+                        ExpireCondition::Xx => "xx",
+                        ExpireCondition::Gt => "gt",
+                        ExpireCondition::Lt => "lt",
+                    }));
+                }
+                vs
+            }
+            KeyCommand::Ttl(key, false) => command("ttl", key),
+            KeyCommand::Ttl(key, true) => command("pttl", key),
+            KeyCommand::ExpireTime(key, false) => command("expiretime", key),
+            KeyCommand::ExpireTime(key, true) => command("pexpiretime", key),
+            KeyCommand::Persist(key) => command("persist", key),
+        }
+    }
+}
+
+// the command replicas replay, none if nothing changed. expiries are sent as an absolute
+// time so that both sides expire the key together, and a key expiring at once is deleted
+pub fn replicated(command: &KeyCommand, response: &RedisValue, now: u64) -> Option<RedisCommand> {
+    if *response == RedisValue::Integer(0) {
+        return None;
+    }
+    match command {
+        KeyCommand::Expire(key, time, _) => {
+            let expired_at = time.expired_at(now)?;
+            if expired_at <= now as i64 {
+                return Some(RedisCommand::Keyspace(KeyCommand::Del(vec![key.clone()])));
+            }
+            Some(RedisCommand::Keyspace(KeyCommand::Expire(
+                key.clone(),
+                ExpireTime::PxAt(expired_at),
+                None,
+            )))
+        }
+        command => Some(RedisCommand::Keyspace(command.clone())),
+    }
+}
+
+// the `keyspace` section of `INFO`, with a line for each database holding keys
+pub fn info(dbs: &[Store], now: u64) -> String {
+    let mut lines = vec!["# Keyspace".to_string()];
+    for (db, store) in dbs.iter().enumerate() {
+        if store.is_empty() {
+            continue;
+        }
+        let ttls: Vec<u64> = store
+            .values()
+            .filter(|item| item.expired_at != 0)
+            .map(|item| item.expired_at.saturating_sub(now))
+            .collect();
+        let avg_ttl = match ttls.len() {
+            0 => 0,
+            n => ttls.iter().sum::<u64>() / n as u64,
+        };
+        lines.push(format!(
+            "db{}:keys={},expires={},avg_ttl={}",
+            db,
+            store.len(),
+            ttls.len(),
+            avg_ttl
+        ));
+    }
+    lines.join("\n")
+}
+
+// the index of a database, which must be one of those configured
+pub fn db_index(index: i64, databases: usize) -> Result<usize, RedisValue> {
+    match usize::try_from(index) {
+        Ok(index) if index < databases => Ok(index),
+        _ => Err(RedisValue::error("ERR DB index is out of range")),
+    }
+}
+
+// execute a command in the selected database, some of them reaching the others
+pub fn execute(
+    dbs: &mut [Store],
+    db: usize,
+    command: &KeyCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> RedisValue {
+    match run(dbs, db, command, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    }
+}
+
+fn run(
+    dbs: &mut [Store],
+    db: usize,
+    command: &KeyCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let databases = dbs.len();
+    let store = &mut dbs[db];
+    let response = match command {
+        KeyCommand::Del(keys) | KeyCommand::Unlink(keys) => {
+            let mut removed = Store::default();
+            for key in keys {
+                let key: String = key.into();
+                if store.get_alive(&key, now, mode).is_some() {
+                    if let Some(item) = store.remove(&key) {
+                        removed.insert(key, item);
+                    }
+                }
+            }
+            let deleted = removed.len() as i64;
+            if matches!(command, KeyCommand::Unlink(_)) {
+                store.free_later(removed);
+            }
+            RedisValue::Integer(deleted)
+        }
+        KeyCommand::Exists(keys) | KeyCommand::Touch(keys) => {
+            let mut existing = 0;
+            for key in keys {
+                let key: String = key.into();
+                if store.get_alive(&key, now, mode).is_some() {
+                    existing += 1;
+                }
+            }
+            RedisValue::Integer(existing)
+        }
+        KeyCommand::Keys(pattern) => {
+            let keys: Vec<String> = store
+                .keys()
+                .filter(|key| utilities::glob_match(&pattern.data, key.as_bytes()))
+                .map(String::from)
+                .collect();
+            let keys = keys
+                .into_iter()
+                .filter(|key| store.get_alive(key, now, mode).is_some())
+                .map(|key| RedisValue::bulk_string(key.as_str()))
+                .collect();
+            RedisValue::Array(keys)
+        }
+        KeyCommand::Scan(cursor, options) => {
+            let (cursor, keys) = store.scan(*cursor, options.count);
+            let keys: Vec<String> = keys.into_iter().map(String::from).collect();
+            // the filters apply once the keys are picked, like in redis
+            let keys = keys
+                .into_iter()
+                .filter(|key| options.matches(key.as_bytes()))
+                .filter(
+                    |key| match (store.get_alive(key, now, mode), &options.object_type) {
+                        (None, _) => false,
+                        (Some(item), Some(object_type)) => item.value.type_name() == object_type,
+                        (Some(_), None) => true,
+                    },
+                )
+                .map(|key| RedisValue::bulk_string(key.as_str()))
+                .collect();
+            RedisValue::Array(vec![
+                RedisValue::bulk_string(cursor.to_string().as_str()),
+                RedisValue::Array(keys),
+            ])
+        }
+        KeyCommand::Rename(source, destination, nx) => {
+            let source: String = source.into();
+            let destination: String = destination.into();
+            if store.get_alive(&source, now, mode).is_none() {
+                return Err(RedisValue::error("ERR no such key"));
+            }
+            if *nx && (source == destination || store.get_alive(&destination, now, mode).is_some())
+            {
+                return Ok(RedisValue::Integer(0));
+            }
+            // the key keeps its time to live
+            if let Some(item) = store.remove(&source) {
+                store.insert(destination, item);
+            }
+            match nx {
+                true => RedisValue::Integer(1),
+                false => RedisValue::simple_string("OK"),
+            }
+        }
+        KeyCommand::RandomKey => {
+            for _ in 0..RANDOM_KEY_TRIES {
+                let key = match store.random_key() {
+                    Some(key) => key.to_string(),
+                    None => break,
+                };
+                if store.get_alive(&key, now, mode).is_some() {
+                    return Ok(RedisValue::bulk_string(key.as_str()));
+                }
+            }
+            RedisValue::null_bulk_string()
+        }
+        // the keys which have expired but not been removed yet are counted, like in redis
+        KeyCommand::DbSize => RedisValue::Integer(store.len() as i64),
+        KeyCommand::Flush(all, lazy) => {
+            let flushed = match all {
+                true => dbs,
+                false => &mut dbs[db..=db],
+            };
+            for store in flushed {
+                let items = store.flush();
+                if *lazy {
+                    store.free_later(items);
+                }
+            }
+            RedisValue::simple_string("OK")
+        }
+        KeyCommand::Move(key, to) => {
+            let key: String = key.into();
+            let to = db_index(*to, databases)?;
+            if to == db {
+                return Err(RedisValue::error(
+                    "ERR source and destination objects are the same",
+                ));
+            }
+            if store.get_alive(&key, now, mode).is_none()
+                || dbs[to].get_alive(&key, now, mode).is_some()
+            {
+                return Ok(RedisValue::Integer(0));
+            }
+            // the key keeps its time to live
+            if let Some(item) = dbs[db].remove(&key) {
+                dbs[to].insert(key, item);
+            }
+            RedisValue::Integer(1)
+        }
+        KeyCommand::Copy(source, destination, to, replace) => {
+            let source: String = source.into();
+            let destination: String = destination.into();
+            let to = match to {
+                Some(to) => db_index(*to, databases)?,
+                None => db,
+            };
+            if to == db && source == destination {
+                return Err(RedisValue::error(
+                    "ERR source and destination objects are the same",
+                ));
+            }
+            let item = match store.get_alive(&source, now, mode) {
+                Some(item) => item.clone(),
+                None => return Ok(RedisValue::Integer(0)),
+            };
+            if !replace && dbs[to].get_alive(&destination, now, mode).is_some() {
+                return Ok(RedisValue::Integer(0));
+            }
+            dbs[to].insert(destination, item);
+            RedisValue::Integer(1)
+        }
+        KeyCommand::SwapDb(first, second) => {
+            let first = db_index(*first, databases)?;
+            let second = db_index(*second, databases)?;
+            dbs.swap(first, second);
+            RedisValue::simple_string("OK")
+        }
+        KeyCommand::Expire(key, time, condition) => {
+            let key: String = key.into();
+            let expired_at = match time.expired_at(now) {
+                Some(expired_at) => expired_at,
+                None => {
+                    let e = RedisCommandError::InvalidExpireTime(time.command_name().to_string());
+                    return Err((&e).into());
+                }
+            };
+            let item = match store.get_alive(&key, now, mode) {
+                Some(item) => item,
+                None => return Ok(RedisValue::Integer(0)),
+            };
+            // zero is no expiry, which is later than any other
+            let current = match item.expired_at {
+                0 => None,
+                expired_at => Some(expired_at as i64),
+            };
+            let accepted = match condition {
+                None => true,
+                Some(ExpireCondition::Nx) => current.is_none(),
+                Some(ExpireCondition::Xx) => current.is_some(),
+                Some(ExpireCondition::Gt) => current.is_some_and(|current| expired_at > current),
+                Some(ExpireCondition::Lt) => match current {
+                    Some(current) => expired_at < current,
+                    None => true,
+                },
+            };
+            if !accepted {
+                return Ok(RedisValue::Integer(0));
+            }
+            // an expiry in the past deletes the key
+            if expired_at <= now as i64 {
+                store.remove(&key);
+            } else {
+                store.set_expiry(&key, expired_at as u64);
+            }
+            RedisValue::Integer(1)
+        }
+        KeyCommand::Ttl(key, millis) | KeyCommand::ExpireTime(key, millis) => {
+            let key: String = key.into();
+            let expired_at = match store.get_alive(&key, now, mode) {
+                None => return Ok(RedisValue::Integer(-2)),
+                Some(StoreItem { expired_at: 0, .. }) => return Ok(RedisValue::Integer(-1)),
+                Some(item) => item.expired_at,
+            };
+            let time = match command {
+                KeyCommand::Ttl(..) => {
+                    let ttl = expired_at.saturating_sub(now);
+                    match millis {
+                        true => ttl,
+                        // rounded to the nearest second
+                        false => (ttl + 500) / 1000,
+                    }
+                }
+                _ => match millis {
+                    true => expired_at,
+                    false => expired_at / 1000,
+                },
+            };
+            RedisValue::Integer(time as i64)
+        }
+        KeyCommand::Persist(key) => {
+            let key: String = key.into();
+            match store.get_alive(&key, now, mode) {
+                Some(item) if item.expired_at != 0 => {
+                    item.expired_at = 0;
+                    RedisValue::Integer(1)
+                }
+                _ => RedisValue::Integer(0),
+            }
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::RedisObject;
+    use crate::testing::{run_command_at, run_command_in};
+
+    // the time the commands of the tests run at
+    const NOW: u64 = 10_000;
+
+    fn store() -> Store {
+        let mut store = Store::default();
+        store.insert(
+            "k".to_string(),
+            StoreItem {
+                value: RedisObject::String(b"v".to_vec()),
+                expired_at: 0,
+            },
+        );
+        store
+    }
+
+    #[test]
+    fn test_expire() {
+        let mut store = store();
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["expire", "k", "10", "xx"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["expire", "k", "10", "gt"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["expire", "k", "10", "nx"], NOW)
+        );
+        assert_eq!(20_000, store["k"].expired_at);
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["pexpire", "k", "5000", "gt"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["pexpireat", "k", "15000", "lt"], NOW)
+        );
+        assert_eq!(15_000, store["k"].expired_at);
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["expire", "missing", "10"], NOW)
+        );
+        assert_eq!(
+            RedisValue::error(
+                "ERR NX and XX, GT or LT options at the same time are not compatible"
+            ),
+            run_command_at(&mut store, &["expire", "k", "10", "nx", "gt"], NOW)
+        );
+        assert_eq!(
+            RedisValue::error("ERR invalid expire time in 'expire' command"),
+            run_command_at(&mut store, &["expire", "k", &i64::MAX.to_string()], NOW)
+        );
+
+        // an expiry in the past deletes the key
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["expireat", "k", "1"], NOW)
+        );
+        assert!(!store.contains_key("k"));
+    }
+
+    #[test]
+    fn test_ttl_and_persist() {
+        let mut store = store();
+        assert_eq!(
+            RedisValue::Integer(-1),
+            run_command_at(&mut store, &["ttl", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(-2),
+            run_command_at(&mut store, &["pttl", "missing"], NOW)
+        );
+        run_command_at(&mut store, &["pexpire", "k", "2600"], NOW);
+        assert_eq!(
+            RedisValue::Integer(3),
+            run_command_at(&mut store, &["ttl", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(2600),
+            run_command_at(&mut store, &["pttl", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(12),
+            run_command_at(&mut store, &["expiretime", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(12600),
+            run_command_at(&mut store, &["pexpiretime", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["persist", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["persist", "k"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(-1),
+            run_command_at(&mut store, &["ttl", "k"], NOW)
+        );
+    }
+
+    fn bulk_strings(values: &[&str]) -> RedisValue {
+        RedisValue::Array(values.iter().map(|v| RedisValue::bulk_string(*v)).collect())
+    }
+
+    #[test]
+    fn test_generic_commands() {
+        let mut flushed = store();
+        assert_eq!(
+            RedisValue::error("ERR syntax error"),
+            run_command_at(&mut flushed, &["flushall", "now"], NOW)
+        );
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command_at(&mut flushed, &["flushdb", "async"], NOW)
+        );
+        assert!(flushed.is_empty());
+        assert_eq!(1, flushed.take_lazy_free().len());
+
+        let mut store = store();
+        run_command_at(&mut store, &["pexpire", "k", "5000"], NOW);
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command_at(&mut store, &["rename", "k", "a"], NOW)
+        );
+        assert_eq!(15_000, store["a"].expired_at);
+        assert_eq!(
+            RedisValue::error("ERR no such key"),
+            run_command_at(&mut store, &["rename", "k", "a"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["renamenx", "a", "a"], NOW)
+        );
+        store.insert(
+            "b".to_string(),
+            StoreItem {
+                value: RedisObject::String(b"w".to_vec()),
+                expired_at: 0,
+            },
+        );
+        assert_eq!(
+            RedisValue::Integer(3),
+            run_command_at(&mut store, &["exists", "a", "b", "a", "c"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command_at(&mut store, &["dbsize"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["del", "a", "a", "c"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["unlink", "b"], NOW)
+        );
+        assert_eq!(1, store.take_lazy_free().len());
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command_at(&mut store, &["randomkey"], NOW)
+        );
+    }
+
+    #[test]
+    fn test_keys_and_scan() {
+        let mut store = Store::default();
+        for i in 0..50 {
+            let value = match i % 2 {
+                0 => RedisObject::String(b"v".to_vec()),
+                _ => RedisObject::List(Default::default()),
+            };
+            let expired_at = if i == 0 { 1 } else { 0 };
+            store.insert(format!("key:{}", i), StoreItem { value, expired_at });
+        }
+        assert_eq!(
+            bulk_strings(&["key:1"]),
+            run_command_at(&mut store, &["keys", "key:1"], NOW)
+        );
+        match run_command_at(&mut store, &["keys", "key:[0-2]?"], NOW) {
+            RedisValue::Array(keys) => assert_eq!(20, keys.len()),
+            v => panic!("unexpected reply {:?}", v),
+        }
+
+        // every string is returned by a full iteration, except the expired one
+        let mut cursor = "0".to_string();
+        let mut keys = vec![];
+        loop {
+            let reply = run_command_at(
+                &mut store,
+                &["scan", &cursor, "count", "7", "type", "string"],
+                NOW,
+            );
+            let (next, batch) = match reply {
+                RedisValue::Array(mut vs) => (vs.remove(0), vs.remove(0)),
+                v => panic!("unexpected reply {:?}", v),
+            };
+            keys.extend(match batch {
+                RedisValue::Array(batch) => batch,
+                v => panic!("unexpected batch {:?}", v),
+            });
+            cursor = match next {
+                RedisValue::BulkString(Some(next)) => (&next).into(),
+                v => panic!("unexpected cursor {:?}", v),
+            };
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(24, keys.len());
+        assert!(!keys.contains(&RedisValue::bulk_string("key:0")));
+    }
+
+    #[test]
+    fn test_databases() {
+        let mut dbs = vec![store(), Store::default(), Store::default()];
+        run_command_in(&mut dbs, 0, &["pexpire", "k", "5000"], NOW);
+        assert_eq!(
+            RedisValue::error("ERR DB index is out of range"),
+            run_command_in(&mut dbs, 0, &["move", "k", "3"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_in(&mut dbs, 0, &["move", "k", "1"], NOW)
+        );
+        assert_eq!(15_000, dbs[1]["k"].expired_at);
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_in(&mut dbs, 1, &["copy", "k", "k", "db", "0"], NOW)
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_in(&mut dbs, 1, &["copy", "k", "k", "db", "0"], NOW)
+        );
+        assert_eq!(
+            RedisValue::error("ERR source and destination objects are the same"),
+            run_command_in(&mut dbs, 1, &["copy", "k", "k"], NOW)
+        );
+        assert_eq!(
+            "# Keyspace\ndb0:keys=1,expires=1,avg_ttl=5000\ndb1:keys=1,expires=1,avg_ttl=5000",
+            info(&dbs, NOW)
+        );
+
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command_in(&mut dbs, 0, &["swapdb", "1", "2"], NOW)
+        );
+        assert!(dbs[1].is_empty());
+        assert_eq!(
+            RedisValue::error("ERR invalid second DB index"),
+            run_command_in(&mut dbs, 0, &["swapdb", "0", "x"], NOW)
+        );
+        run_command_in(&mut dbs, 2, &["flushdb"], NOW);
+        assert!(dbs[2].is_empty() && !dbs[0].is_empty());
+        run_command_in(&mut dbs, 2, &["flushall"], NOW);
+        assert!(dbs.iter().all(|store| store.is_empty()));
+    }
+}
@@ -0,0 +1,450 @@
+use std::collections::VecDeque;
+
+use crate::command::{CommandArgs, RedisCommandError};
+use crate::object::{normalize_index, normalize_range, wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::value::{RedisBulkString, RedisValue};
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ListEnd {
+    Left,
+    Right,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ListCommand {
+    Push(ListEnd, RedisBulkString, Vec<RedisBulkString>),
+    // the count is only given in the `LPOP key count` form, which replies with an array
+    Pop(ListEnd, RedisBulkString, Option<usize>),
+    Range(RedisBulkString, i64, i64),
+    Len(RedisBulkString),
+    Index(RedisBulkString, i64),
+    Set(RedisBulkString, i64, RedisBulkString),
+    Rem(RedisBulkString, i64, RedisBulkString),
+    Trim(RedisBulkString, i64, i64),
+    // key, whether to insert before the pivot, pivot, element
+    Insert(RedisBulkString, bool, RedisBulkString, RedisBulkString),
+}
+
+impl ListCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<ListCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let key = args.next_key()?;
+        let command = match command_name {
+            "lpush" => ListCommand::Push(ListEnd::Left, key, args.rest()?),
+            "rpush" => ListCommand::Push(ListEnd::Right, key, args.rest()?),
+            "lpop" | "rpop" => {
+                let end = if command_name == "lpop" {
+                    ListEnd::Left
+                } else {
+                    ListEnd::Right
+                };
+                let count = if args.is_empty() {
+                    None
+                } else {
+                    let count: i64 = args.next_integer()?;
+                    if count < 0 {
+                        return Err(RedisCommandError::NotPositive);
+                    }
+                    Some(count as usize)
+                };
+                ListCommand::Pop(end, key, count)
+            }
+            "lrange" => ListCommand::Range(key, args.next_integer()?, args.next_integer()?),
+            "llen" => ListCommand::Len(key),
+            "lindex" => ListCommand::Index(key, args.next_integer()?),
+            "lset" => ListCommand::Set(key, args.next_integer()?, args.next_bulk_string()?),
+            "lrem" => ListCommand::Rem(key, args.next_integer()?, args.next_bulk_string()?),
+            "ltrim" => ListCommand::Trim(key, args.next_integer()?, args.next_integer()?),
+            "linsert" => {
+                let before = match args.next_string()?.to_lowercase().as_str() {
+                    "before" => true,
+                    "after" => false,
+                    _ => return Err(RedisCommandError::IlleagalArg),
+                };
+                let pivot = args.next_bulk_string()?;
+                ListCommand::Insert(key, before, pivot, args.next_bulk_string()?)
+            }
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            ListCommand::Push(_, key, _)
+            | ListCommand::Pop(_, key, _)
+            | ListCommand::Range(key, _, _)
+            | ListCommand::Len(key)
+            | ListCommand::Index(key, _)
+            | ListCommand::Set(key, _, _)
+            | ListCommand::Rem(key, _, _)
+            | ListCommand::Trim(key, _, _)
+            | ListCommand::Insert(key, _, _, _) => key,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            ListCommand::Range(..) | ListCommand::Len(_) | ListCommand::Index(..)
+        )
+    }
+}
+
+impl From<&ListCommand> for Vec<RedisValue> {
+    fn from(list_command: &ListCommand) -> Vec<RedisValue> {
+        let integer = |i: &i64| RedisValue::bulk_string(i.to_string().as_str());
+        match list_command {
+            ListCommand::Push(end, key, elements) => {
+                let name = match end {
+                    ListEnd::Left => "lpush",
+                    ListEnd::Right => "rpush",
+                };
+                let mut vs = vec![RedisValue::bulk_string(name), key.into()];
+                vs.extend(elements.iter().map(|e| e.into()));
+                vs
+            }
+            ListCommand::Pop(end, key, count) => {
+                let name = match end {
+                    ListEnd::Left => "lpop",
+                    ListEnd::Right => "rpop",
+                };
+                let mut vs = vec![RedisValue::bulk_string(name), key.into()];
+                if let Some(count) = count {
+                    vs.push(RedisValue::bulk_string(count.to_string().as_str()));
+                }
+                vs
+            }
+            ListCommand::Range(key, start, end) => vec![
+                RedisValue::bulk_string("lrange"),
+                key.into(),
+                integer(start),
+                integer(end),
+            ],
+            ListCommand::Len(key) => vec![RedisValue::bulk_string("llen"), key.into()],
+            ListCommand::Index(key, index) => {
+                vec![
+                    RedisValue::bulk_string("lindex"),
+                    key.into(),
+                    integer(index),
+                ]
+            }
+            ListCommand::Set(key, index, element) => vec![
+                RedisValue::bulk_string("lset"),
+                key.into(),
+                integer(index),
+                element.into(),
+            ],
+            ListCommand::Rem(key, count, element) => vec![
+                RedisValue::bulk_string("lrem"),
+                key.into(),
+                integer(count),
+                element.into(),
+            ],
+            ListCommand::Trim(key, start, end) => vec![
+                RedisValue::bulk_string("ltrim"),
+                key.into(),
+                integer(start),
+                integer(end),
+            ],
+            ListCommand::Insert(key, before, pivot, element) => vec![
+                RedisValue::bulk_string("linsert"),
+                key.into(),
+                RedisValue::bulk_string(if *before { "before" } else { "after" }),
+                pivot.into(),
+                element.into(),
+            ],
+        }
+    }
+}
+
+// the list stored at the key, none if the key does not exist
+fn get_list<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut VecDeque<Vec<u8>>>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::List(list),
+            ..
+        }) => Ok(Some(list)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+pub fn execute(store: &mut Store, command: &ListCommand, now: u64, mode: ExpiryMode) -> RedisValue {
+    let key: String = command.key().into();
+    let response = match run(store, command, &key, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    };
+    store.remove_if_empty(&key);
+    response
+}
+
+fn run(
+    store: &mut Store,
+    command: &ListCommand,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        ListCommand::Push(end, _, elements) => {
+            get_list(store, key, now, mode)?;
+            let item = store.get_or_insert_with(key, || StoreItem {
+                value: RedisObject::List(VecDeque::new()),
+                expired_at: 0,
+            });
+            let list = match &mut item.value {
+                RedisObject::List(list) => list,
+                _ => return Err(wrong_type()),
+            };
+            for element in elements {
+                match end {
+                    ListEnd::Left => list.push_front(element.data.clone()),
+                    ListEnd::Right => list.push_back(element.data.clone()),
+                }
+            }
+            RedisValue::Integer(list.len() as i64)
+        }
+        ListCommand::Pop(end, _, count) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                // a count always replies an array, which is nil for a missing list
+                None if count.is_some() => return Ok(RedisValue::null_array()),
+                None => return Ok(RedisValue::null_bulk_string()),
+            };
+            let popped: Vec<Vec<u8>> = (0..count.unwrap_or(1))
+                .map_while(|_| match end {
+                    ListEnd::Left => list.pop_front(),
+                    ListEnd::Right => list.pop_back(),
+                })
+                .collect();
+            let mut popped = popped
+                .iter()
+                .map(|e| RedisValue::bulk_string_from_bytes(e.as_slice()));
+            match count {
+                Some(_) => RedisValue::Array(popped.collect()),
+                None => popped.next().unwrap_or(RedisValue::null_bulk_string()),
+            }
+        }
+        ListCommand::Range(_, start, end) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                None => return Ok(RedisValue::Array(vec![])),
+            };
+            let elements = match normalize_range(*start, *end, list.len()) {
+                Some((start, end)) => list
+                    .range(start..=end)
+                    .map(|e| RedisValue::bulk_string_from_bytes(e.as_slice()))
+                    .collect(),
+                None => vec![],
+            };
+            RedisValue::Array(elements)
+        }
+        ListCommand::Len(_) => {
+            let length = get_list(store, key, now, mode)?.map_or(0, |list| list.len());
+            RedisValue::Integer(length as i64)
+        }
+        ListCommand::Index(_, index) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                None => return Ok(RedisValue::null_bulk_string()),
+            };
+            let index = normalize_index(*index, list.len());
+            match usize::try_from(index)
+                .ok()
+                .and_then(|index| list.get(index))
+            {
+                Some(e) => RedisValue::bulk_string_from_bytes(e.as_slice()),
+                None => RedisValue::null_bulk_string(),
+            }
+        }
+        ListCommand::Set(_, index, element) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                None => return Err(RedisValue::error("ERR no such key")),
+            };
+            let index = normalize_index(*index, list.len());
+            match usize::try_from(index)
+                .ok()
+                .and_then(|index| list.get_mut(index))
+            {
+                Some(e) => *e = element.data.clone(),
+                None => return Err(RedisValue::error("ERR index out of range")),
+            }
+            RedisValue::simple_string("OK")
+        }
+        ListCommand::Rem(_, count, element) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                None => return Ok(RedisValue::Integer(0)),
+            };
+            // a positive count removes from the head, a negative one from the tail
+            let limit = match count {
+                0 => usize::MAX,
+                count => count.unsigned_abs() as usize,
+            };
+            let mut removed = 0;
+            let mut kept = VecDeque::with_capacity(list.len());
+            if *count >= 0 {
+                for e in list.drain(..) {
+                    if removed < limit && e == element.data {
+                        removed += 1;
+                    } else {
+                        kept.push_back(e);
+                    }
+                }
+            } else {
+                for e in list.drain(..).rev() {
+                    if removed < limit && e == element.data {
+                        removed += 1;
+                    } else {
+                        kept.push_front(e);
+                    }
+                }
+            }
+            *list = kept;
+            RedisValue::Integer(removed as i64)
+        }
+        ListCommand::Trim(_, start, end) => {
+            if let Some(list) = get_list(store, key, now, mode)? {
+                match normalize_range(*start, *end, list.len()) {
+                    Some((start, end)) => {
+                        list.truncate(end + 1);
+                        list.drain(..start);
+                    }
+                    None => list.clear(),
+                }
+            }
+            RedisValue::simple_string("OK")
+        }
+        ListCommand::Insert(_, before, pivot, element) => {
+            let list = match get_list(store, key, now, mode)? {
+                Some(list) => list,
+                None => return Ok(RedisValue::Integer(0)),
+            };
+            match list.iter().position(|e| *e == pivot.data) {
+                Some(position) => {
+                    let position = if *before { position } else { position + 1 };
+                    list.insert(position, element.data.clone());
+                    RedisValue::Integer(list.len() as i64)
+                }
+                None => RedisValue::Integer(-1),
+            }
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    fn elements(values: &[&str]) -> RedisValue {
+        RedisValue::Array(values.iter().map(|s| RedisValue::bulk_string(*s)).collect())
+    }
+
+    #[test]
+    fn test_push_and_range() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(3),
+            run_command(&mut store, &["lpush", "l", "a", "b", "c"])
+        );
+        assert_eq!(
+            RedisValue::Integer(4),
+            run_command(&mut store, &["rpush", "l", "d"])
+        );
+        assert_eq!(
+            elements(&["c", "b", "a", "d"]),
+            run_command(&mut store, &["lrange", "l", "0", "-1"])
+        );
+        assert_eq!(
+            elements(&["a", "d"]),
+            run_command(&mut store, &["lrange", "l", "-2", "10"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("b"),
+            run_command(&mut store, &["lindex", "l", "-3"])
+        );
+    }
+
+    #[test]
+    fn test_pop_removes_empty_list() {
+        let mut store = Store::default();
+        run_command(&mut store, &["rpush", "l", "a", "b", "c"]);
+        assert_eq!(
+            RedisValue::bulk_string("c"),
+            run_command(&mut store, &["rpop", "l"])
+        );
+        assert_eq!(
+            elements(&["a", "b"]),
+            run_command(&mut store, &["lpop", "l", "5"])
+        );
+        assert!(store.is_empty());
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command(&mut store, &["lpop", "l"])
+        );
+        assert_eq!(
+            RedisValue::null_array(),
+            run_command(&mut store, &["rpop", "l", "2"])
+        );
+    }
+
+    #[test]
+    fn test_rem_trim_insert() {
+        let mut store = Store::default();
+        run_command(&mut store, &["rpush", "l", "x", "a", "x", "b", "x"]);
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["lrem", "l", "-2", "x"])
+        );
+        assert_eq!(
+            elements(&["x", "a", "b"]),
+            run_command(&mut store, &["lrange", "l", "0", "-1"])
+        );
+        assert_eq!(
+            RedisValue::Integer(4),
+            run_command(&mut store, &["linsert", "l", "after", "a", "y"])
+        );
+        assert_eq!(
+            RedisValue::Integer(-1),
+            run_command(&mut store, &["linsert", "l", "before", "z", "y"])
+        );
+        run_command(&mut store, &["ltrim", "l", "1", "2"]);
+        assert_eq!(
+            elements(&["a", "y"]),
+            run_command(&mut store, &["lrange", "l", "0", "-1"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR index out of range"),
+            run_command(&mut store, &["lset", "l", "2", "v"])
+        );
+    }
+
+    #[test]
+    fn test_wrong_type() {
+        let mut store = Store::default();
+        store.insert(
+            "s".to_string(),
+            StoreItem {
+                value: RedisObject::String(b"v".to_vec()),
+                expired_at: 0,
+            },
+        );
+        assert_eq!(wrong_type(), run_command(&mut store, &["lpush", "s", "a"]));
+        assert_eq!(wrong_type(), run_command(&mut store, &["llen", "s"]));
+    }
+}
@@ -1,15 +1,29 @@
+mod bitmap;
 mod client;
 mod command;
+mod geo;
+mod hash;
+mod hyperloglog;
+mod keyspace;
+mod list;
+mod object;
 mod parser;
+mod rdb;
 mod redis;
 mod replica;
+mod set;
+mod skiplist;
+mod stream;
+mod string;
+#[cfg(test)]
+mod testing;
 mod utilities;
 mod value;
 mod worker;
+mod zset;
 
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
-use std::iter::repeat;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
@@ -23,7 +37,7 @@ use worker::worker_process;
 use crate::client::ClientChannel;
 use crate::redis::Redis;
 
-use crate::replica::{handle_replica_handshake, listen_to_master_progate};
+use crate::replica::supervise_master_link;
 use crate::worker::WorkerMessage;
 
 fn get_client_id(client: &TcpStream) -> String {
@@ -32,7 +46,7 @@ fn get_client_id(client: &TcpStream) -> String {
     addr.hash(&mut hasher);
     let id = hasher.finish().to_string();
     if id.len() < 40 {
-        let padding: String = repeat('0').take(40 - id.len()).collect();
+        let padding = "0".repeat(40 - id.len());
         id + &padding
     } else {
         id.as_str()[..40].into()
@@ -41,41 +55,49 @@ fn get_client_id(client: &TcpStream) -> String {
 
 pub async fn launch(redis: Redis) {
     let running = Arc::new(AtomicBool::new(true));
+
+    // restore the dataset from the rdb file before accepting any client
+    let dbfile = redis.config.dbfile();
+    match rdb::load(&dbfile, redis.config.databases).await {
+        Ok(Some(snapshot)) => {
+            let dbs = snapshot.into_databases(redis.config.databases);
+            let mut store = redis.store.write().await;
+            *store = dbs;
+            println!(
+                "[main] loaded {} keys from rdb file {:?}",
+                store.iter().map(|db| db.len()).sum::<usize>(),
+                dbfile
+            );
+        }
+        Ok(None) => println!("[main] rdb file {:?} not found, start empty", dbfile),
+        Err(e) => {
+            eprintln!("[main] unable to load rdb file {:?}: {}", dbfile, e);
+            std::process::exit(1);
+        }
+    }
+
     let host = redis.host();
     let listener = TcpListener::bind(host.clone())
         .await
-        .expect(&format!("unable to launch service in {}", host));
+        .unwrap_or_else(|_| panic!("unable to launch service in {}", host));
     println!("main process launched; {}", host);
 
     // launch worker
     let (worker_sender, worker_receiver) = mpsc::channel::<WorkerMessage>(128);
-    let worker = task::spawn(worker_process(redis.clone(), worker_receiver));
-
-    // handle handshake for replica
-    let replica_handler = if let Some((master_host, master_port)) = redis.config.get_replica_of() {
-        // try to handshake
+    let worker = task::spawn(worker_process(
+        redis.clone(),
+        worker_sender.clone(),
+        worker_receiver,
+    ));
+
+    // keep the link with the master while the node is a replica
+    if let Some((master_host, master_port)) = redis.config.get_replica_of() {
         println!(
             "current node is a replica node of {}:{}, try to handshake",
             master_host, master_port
         );
-        let (connection, parser) = handle_replica_handshake(redis.clone())
-            .await
-            .expect(&format!(
-                "handshake with {}:{} failed",
-                master_host, master_port
-            ));
-        // successed, start to listen to master progration
-        println!(
-            "handshake with {}:{} success, launch progate thread",
-            master_host, master_port
-        );
-        let task: task::JoinHandle<Result<(), std::io::Error>> = task::spawn(
-            listen_to_master_progate(redis.clone(), connection, parser, worker_sender.clone()),
-        );
-        Some(task)
-    } else {
-        None
-    };
+    }
+    let replica_handler = task::spawn(supervise_master_link(redis.clone(), worker_sender.clone()));
 
     while running.load(Ordering::SeqCst) {
         match listener.accept().await {
@@ -111,10 +133,8 @@ pub async fn launch(redis: Redis) {
 
     worker.abort();
 
-    if let Some(replica_handler) = replica_handler {
-        replica_handler.abort();
-        replica_handler.await.unwrap().unwrap();
-    }
+    replica_handler.abort();
+    let _ = replica_handler.await;
 
     worker.await.unwrap();
 }
@@ -0,0 +1,149 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
+
+use crate::set::RedisSet;
+use crate::stream::Stream;
+use crate::value::RedisValue;
+use crate::zset::SortedSet;
+
+// the value held by a key of the keyspace, unlike `RedisValue` it is never sent over the wire
+#[derive(PartialEq, Debug, Clone)]
+pub enum RedisObject {
+    String(Vec<u8>),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashMap<Vec<u8>, Vec<u8>>),
+    Set(RedisSet),
+    ZSet(SortedSet),
+    Stream(Stream),
+}
+
+impl RedisObject {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RedisObject::String(_) => "string",
+            RedisObject::List(_) => "list",
+            RedisObject::Hash(_) => "hash",
+            RedisObject::Set(_) => "set",
+            RedisObject::ZSet(_) => "zset",
+            RedisObject::Stream(_) => "stream",
+        }
+    }
+
+    // an empty aggregate is removed from the keyspace, except for a stream
+    // which keeps its last id
+    pub fn is_empty(&self) -> bool {
+        match self {
+            RedisObject::String(_) => false,
+            RedisObject::List(list) => list.is_empty(),
+            RedisObject::Hash(hash) => hash.is_empty(),
+            RedisObject::Set(set) => set.is_empty(),
+            RedisObject::ZSet(zset) => zset.is_empty(),
+            RedisObject::Stream(_) => false,
+        }
+    }
+}
+
+pub fn wrong_type() -> RedisValue {
+    RedisValue::error("WRONGTYPE Operation against a key holding the wrong kind of value")
+}
+
+// resolve a possibly negative index of a sequence with `length` elements,
+// the result may still be out of range
+pub fn normalize_index(index: i64, length: usize) -> i64 {
+    if index < 0 {
+        index + length as i64
+    } else {
+        index
+    }
+}
+
+// the inclusive range of elements selected by `start` and `end`, none if empty
+pub fn normalize_range(start: i64, end: i64, length: usize) -> Option<(usize, usize)> {
+    let start = normalize_index(start, length).max(0);
+    let end = normalize_index(end, length).min(length as i64 - 1);
+    if start > end || start >= length as i64 {
+        None
+    } else {
+        Some((start as usize, end as usize))
+    }
+}
+
+// the position of a member in the iteration order of `scan`: like redis walks the buckets of
+// its tables, members are visited in the order of their hash with the bits reversed
+fn scan_position(member: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    member.hash(&mut hasher);
+    hasher.finish().reverse_bits()
+}
+
+// iterate a collection with a cursor, which is the hash of the next member to return. the
+// members present during the whole iteration are returned at least once, however the collection
+// changes or grows between two calls. only the positions which may be returned are kept while
+// walking the collection, rather than sorting all of them
+pub fn scan<'a, T>(
+    members: impl Iterator<Item = (&'a [u8], T)>,
+    cursor: u64,
+    count: usize,
+) -> (u64, Vec<T>) {
+    let start = cursor.reverse_bits();
+    let count = count.max(1);
+    // the members at each position, more than one only when their hashes collide. one position
+    // more than the batch needs is kept, it is the cursor to go on from
+    let mut positions: BTreeMap<u64, Vec<T>> = BTreeMap::new();
+    for (member, t) in members {
+        let position = scan_position(member);
+        if position < start {
+            continue;
+        }
+        positions.entry(position).or_default().push(t);
+        if positions.len() > count + 1 {
+            positions.pop_last();
+        }
+    }
+    let mut batch = vec![];
+    for (position, members) in positions {
+        if batch.len() >= count {
+            return (position.reverse_bits(), batch);
+        }
+        batch.extend(members);
+    }
+    (0, batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_range() {
+        assert_eq!(Some((0, 4)), normalize_range(0, -1, 5));
+        assert_eq!(Some((3, 4)), normalize_range(-2, 100, 5));
+        assert_eq!(Some((0, 1)), normalize_range(-100, 1, 5));
+        assert_eq!(None, normalize_range(3, 1, 5));
+        assert_eq!(None, normalize_range(5, 10, 5));
+        assert_eq!(None, normalize_range(0, -1, 0));
+    }
+
+    #[test]
+    fn test_scan_visits_every_member() {
+        let mut members: Vec<String> = (0..100).map(|i| format!("m{}", i)).collect();
+        let mut cursor = 0;
+        let mut visited = vec![];
+        loop {
+            let (next, batch) = scan(members.iter().map(|m| (m.as_bytes(), m.clone())), cursor, 7);
+            assert!(batch.len() >= 7 || next == 0);
+            visited.extend(batch);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+            // members added or removed meanwhile do not affect the others
+            members.push(format!("n{}", cursor));
+            members.retain(|m| m != "m99");
+        }
+        for i in 0..99 {
+            assert!(visited.contains(&format!("m{}", i)));
+        }
+    }
+}
@@ -12,6 +12,7 @@ enum LengthState {
 #[derive(PartialEq, Debug, Clone)]
 enum MessageParserState {
     Initial,
+    InitialRdb,
     WaitForSr,
     WaitForSn,
     ReadingLength {
@@ -89,6 +90,10 @@ pub struct RedisValueParser {
     bytes_buffer: VecDeque<u8>,
     value_buffer: Vec<RedisValue>,
     state_stack: Vec<MessageParserState>,
+    // bytes of the pending value already consumed
+    pending: Vec<u8>,
+    // bytes of the value parsed last, as they were received
+    raw: Vec<u8>,
 }
 
 impl RedisValueParser {
@@ -97,6 +102,8 @@ impl RedisValueParser {
             bytes_buffer: VecDeque::new(),
             state_stack: Vec::new(),
             value_buffer: Vec::new(),
+            pending: Vec::new(),
+            raw: Vec::new(),
         }
     }
 
@@ -112,13 +119,7 @@ impl RedisValueParser {
         let mut input = self.bytes_buffer.iter().enumerate();
         let mut last_pos: usize = 0;
 
-        loop {
-            let state = if let Some(state) = self.state_stack.pop() {
-                state
-            } else {
-                break;
-            };
-
+        while let Some(state) = self.state_stack.pop() {
             // println!(
             //     "* ; state: {:?}; stack: {:?}; buffer: {:?}",
             //     state, self.state_stack, self.value_buffer
@@ -157,7 +158,26 @@ impl RedisValueParser {
                     Some((t, eb)) => {
                         return Err(MessageParserStateError::UnexceptedToken(*eb, t, line!()))
                     }
-                    None => return Ok((None, last_pos)),
+                    None => {
+                        self.state_stack.push(MessageParserState::Initial);
+                        return Ok((None, last_pos));
+                    }
+                },
+                MessageParserState::InitialRdb => match input.next() {
+                    Some((t, b'$')) => {
+                        last_pos = t;
+                        self.state_stack.push(MessageParserState::ReadingRdb {
+                            length: LengthState::Reading,
+                            content: Vec::new(),
+                        });
+                    }
+                    Some((t, eb)) => {
+                        return Err(MessageParserStateError::UnexceptedToken(*eb, t, line!()))
+                    }
+                    None => {
+                        self.state_stack.push(MessageParserState::InitialRdb);
+                        return Ok((None, last_pos));
+                    }
                 },
                 MessageParserState::ReadingBulkString {
                     length,
@@ -174,6 +194,7 @@ impl RedisValueParser {
                     }
                     LengthState::Loading => match self.value_buffer.pop() {
                         Some(RedisValue::Integer(l)) => {
+                            let l = l as usize;
                             self.state_stack.push_in_reverse(vec![
                                 MessageParserState::ReadingBulkString {
                                     length: LengthState::Loaded(l),
@@ -188,28 +209,25 @@ impl RedisValueParser {
                             )))
                         }
                     },
-                    LengthState::Loaded(l) => match input.next() {
-                        Some((t, b)) => {
+                    LengthState::Loaded(l) => {
+                        // copy as much of the payload as is available at once
+                        for (t, b) in input.by_ref().take(l - content.len()) {
                             content.push(*b);
                             last_pos = t;
-                            if content.len() < l {
-                                self.state_stack
-                                    .push(MessageParserState::ReadingBulkString {
-                                        length,
-                                        content,
-                                    });
-                            } else {
-                                let s = RedisValue::bulk_string_from_bytes(content.as_slice());
-                                self.value_buffer.push(s);
-
-                                self.state_stack.push_in_reverse(vec![
-                                    MessageParserState::WaitForSr,
-                                    MessageParserState::WaitForSn,
-                                ]);
-                            }
                         }
-                        None => return Ok((None, last_pos)),
-                    },
+                        if content.len() < l {
+                            self.state_stack
+                                .push(MessageParserState::ReadingBulkString { length, content });
+                            return Ok((None, last_pos));
+                        }
+                        let s = RedisValue::bulk_string_from_bytes(content.as_slice());
+                        self.value_buffer.push(s);
+
+                        self.state_stack.push_in_reverse(vec![
+                            MessageParserState::WaitForSr,
+                            MessageParserState::WaitForSn,
+                        ]);
+                    }
                 },
                 MessageParserState::ReadingRdb {
                     length,
@@ -226,6 +244,7 @@ impl RedisValueParser {
                     }
                     LengthState::Loading => match self.value_buffer.pop() {
                         Some(RedisValue::Integer(l)) => {
+                            let l = l as usize;
                             self.state_stack.push_in_reverse(vec![
                                 MessageParserState::ReadingRdb {
                                     length: LengthState::Loaded(l),
@@ -240,20 +259,18 @@ impl RedisValueParser {
                             )))
                         }
                     },
-                    LengthState::Loaded(l) => match input.next() {
-                        Some((t, b)) => {
+                    LengthState::Loaded(l) => {
+                        for (t, b) in input.by_ref().take(l - content.len()) {
                             content.push(*b);
                             last_pos = t;
-                            if content.len() < l {
-                                self.state_stack
-                                    .push(MessageParserState::ReadingRdb { length, content });
-                            } else {
-                                let s = RedisValue::Rdb(content.to_vec());
-                                self.value_buffer.push(s);
-                            }
                         }
-                        None => return Ok((None, last_pos)),
-                    },
+                        if content.len() < l {
+                            self.state_stack
+                                .push(MessageParserState::ReadingRdb { length, content });
+                            return Ok((None, last_pos));
+                        }
+                        self.value_buffer.push(RedisValue::Rdb(content));
+                    }
                 },
                 MessageParserState::ReadingArray { length, collected } => match length {
                     LengthState::Reading => {
@@ -267,6 +284,7 @@ impl RedisValueParser {
                     }
                     LengthState::Loading => match self.value_buffer.pop() {
                         Some(RedisValue::Integer(l)) => {
+                            let l = l as usize;
                             self.state_stack.push(MessageParserState::ReadingArray {
                                 length: LengthState::Loaded(l),
                                 collected,
@@ -305,21 +323,31 @@ impl RedisValueParser {
                         self.state_stack
                             .push(MessageParserState::ReadingSimpleString { content })
                     }
-                    None => return Ok((None, last_pos)),
+                    None => {
+                        self.state_stack
+                            .push(MessageParserState::ReadingSimpleString { content });
+                        return Ok((None, last_pos));
+                    }
                 },
                 MessageParserState::WaitForSn => match input.next() {
                     Some((t, b'\n')) => last_pos = t,
                     Some((t, eb)) => {
                         return Err(MessageParserStateError::UnexceptedToken(*eb, t, line!()))
                     }
-                    None => return Ok((None, last_pos)),
+                    None => {
+                        self.state_stack.push(MessageParserState::WaitForSn);
+                        return Ok((None, last_pos));
+                    }
                 },
                 MessageParserState::WaitForSr => match input.next() {
                     Some((t, b'\r')) => last_pos = t,
                     Some((t, eb)) => {
                         return Err(MessageParserStateError::UnexceptedToken(*eb, t, line!()))
                     }
-                    None => return Ok((None, last_pos)),
+                    None => {
+                        self.state_stack.push(MessageParserState::WaitForSr);
+                        return Ok((None, last_pos));
+                    }
                 },
                 MessageParserState::ReadingLength {
                     length,
@@ -341,7 +369,13 @@ impl RedisValueParser {
                                 ))
                             }
                         },
-                        None => return Ok((None, last_pos)),
+                        None => {
+                            self.state_stack.push(MessageParserState::ReadingLength {
+                                length,
+                                heading_zero,
+                            });
+                            return Ok((None, last_pos));
+                        }
                     },
                     Some(length) => match input.next() {
                         Some((t, b)) => match b {
@@ -359,7 +393,7 @@ impl RedisValueParser {
                                 });
                             }
                             b'\r' => {
-                                self.value_buffer.push(RedisValue::Integer(length));
+                                self.value_buffer.push(RedisValue::Integer(length as i64));
                                 self.state_stack.push(MessageParserState::WaitForSn);
                             }
                             eb => {
@@ -370,34 +404,68 @@ impl RedisValueParser {
                                 ))
                             }
                         },
-                        None => return Ok((None, last_pos)),
+                        None => {
+                            self.state_stack.push(MessageParserState::ReadingLength {
+                                length: Some(length),
+                                heading_zero,
+                            });
+                            return Ok((None, last_pos));
+                        }
                     },
                 },
             }
         }
 
         let v = self.value_buffer.pop();
-        self.bytes_buffer.drain(0..=last_pos);
-        return Ok((v, last_pos));
+        self.pending.extend(self.bytes_buffer.drain(0..=last_pos));
+        Ok((v, last_pos))
     }
 
-    pub fn parse_rdb(&mut self) -> Result<(Option<RedisValue>, usize), MessageParserStateError> {
-        if let Some(first) = self.bytes_buffer.front() {
-            assert_eq!(b'$', *first);
-            self.bytes_buffer.pop_front().unwrap();
-            self.state_stack.push(MessageParserState::ReadingRdb {
-                length: LengthState::Reading,
-                content: Vec::new(),
-            });
-            self.parse_loop()
-        } else {
-            Ok((None, 0))
+    fn parse_from(
+        &mut self,
+        state: MessageParserState,
+    ) -> Result<(Option<RedisValue>, usize), MessageParserStateError> {
+        // an incomplete value keeps its states, parsing resumes where it stopped
+        if self.state_stack.is_empty() {
+            self.state_stack.push(state);
+        }
+        match self.parse_loop() {
+            Ok((Some(value), _)) => {
+                self.raw = std::mem::take(&mut self.pending);
+                Ok((Some(value), self.raw.len() - 1))
+            }
+            Ok((None, _)) => {
+                // the pending states hold everything read so far
+                self.pending.extend(self.bytes_buffer.drain(..));
+                if self.pending.is_empty() {
+                    // nothing read yet, the next call may expect another kind of value
+                    self.state_stack.clear();
+                }
+                Ok((None, self.pending.len()))
+            }
+            Err(e) => {
+                // there is no way to find where the next value starts in a malformed
+                // input, so everything received so far is discarded
+                self.state_stack.clear();
+                self.value_buffer.clear();
+                self.bytes_buffer.clear();
+                self.pending.clear();
+                Err(e)
+            }
         }
     }
 
+    // the bytes of the value parsed last, as they were received
+    pub fn raw(&self) -> &[u8] {
+        &self.raw
+    }
+
+    pub fn parse_rdb(&mut self) -> Result<(Option<RedisValue>, usize), MessageParserStateError> {
+        self.parse_from(MessageParserState::InitialRdb)
+    }
+
     pub fn parse(&mut self) -> Result<(Option<RedisValue>, usize), MessageParserStateError> {
-        self.state_stack.push(MessageParserState::Initial);
-        self.parse_loop()
+        self.parse_from(MessageParserState::Initial)
     }
 }
 
@@ -411,13 +479,13 @@ mod tests {
         let mut parser = RedisValueParser::new();
 
         parser.append(input);
-        let (values, t) = parser.parse().unwrap();
+        let (values, _) = parser.parse().unwrap();
         assert_eq!(RedisValue::bulk_string("12345"), values.unwrap());
 
-        let (values, t) = parser.parse().unwrap();
+        let (values, _) = parser.parse().unwrap();
         assert_eq!(RedisValue::bulk_string("xyz"), values.unwrap());
 
-        let (values, t) = parser.parse().unwrap();
+        let (values, _) = parser.parse().unwrap();
         assert_eq!(RedisValue::bulk_string("abcde"), values.unwrap());
 
         assert!(parser.parse().unwrap().0.is_none());
@@ -442,14 +510,32 @@ mod tests {
             }
         }
 
-        let (values, t) = parser.parse().unwrap();
+        let (values, _) = parser.parse().unwrap();
         assert_eq!(None, values);
 
         parser.append("bcde\r\n".as_bytes());
-        let (values, t) = parser.parse().unwrap();
+        let (values, _) = parser.parse().unwrap();
         assert_eq!(Some(RedisValue::bulk_string("abcde")), values);
     }
 
+    #[test]
+    fn test_raw_bytes_of_values() {
+        let mut parser = RedisValueParser::new();
+        parser.append(b"*1\r\n$04\r\nPING\r\n*1\r\n$4\r\nPI");
+        let (value, offset) = parser.parse().unwrap();
+        assert!(value.is_some());
+        // the value is kept as it was received, not as it would be encoded
+        assert_eq!(b"*1\r\n$04\r\nPING\r\n", parser.raw());
+        assert_eq!(parser.raw().len(), offset + 1);
+
+        assert_eq!(None, parser.parse().unwrap().0);
+        parser.append(b"NG\r\n");
+        let (value, offset) = parser.parse().unwrap();
+        assert!(value.is_some());
+        assert_eq!(b"*1\r\n$4\r\nPING\r\n", parser.raw());
+        assert_eq!(parser.raw().len(), offset + 1);
+    }
+
     // #[test]
     // fn test_parse_array2() {
     //     let input = "*3\r\n$5\r\nPSYNC\r\n$1\r\n?\r\n$2\r\n-1\r\n".as_bs();
@@ -472,17 +558,96 @@ mod tests {
     fn test_parse_empty_array() {
         let input = "*0\r\n".as_bytes();
         let mut parser = RedisValueParser::new();
+        parser.append(input);
         let (value, t) = parser.parse().unwrap();
         assert_eq!(Some(RedisValue::Array(vec![])), value);
         assert_eq!(3, t);
     }
 
+    #[test]
+    fn test_parse_partial_array() {
+        let mut parser = RedisValueParser::new();
+        parser.append("*2\r\n$3\r\nset\r\n$2\r".as_bytes());
+        assert_eq!(None, parser.parse().unwrap().0);
+
+        parser.append("\nab\r\n".as_bytes());
+        let (value, t) = parser.parse().unwrap();
+        assert_eq!(
+            Some(RedisValue::Array(vec![
+                RedisValue::bulk_string("set"),
+                RedisValue::bulk_string("ab")
+            ])),
+            value
+        );
+        assert_eq!(20, t);
+        assert_eq!(0, parser.buffer_len());
+    }
+
+    #[test]
+    fn test_parse_rdb() {
+        let mut parser = RedisValueParser::new();
+        parser.append("$6\r\nREDI".as_bytes());
+        assert_eq!(None, parser.parse_rdb().unwrap().0);
+
+        parser.append("S0*0\r\n".as_bytes());
+        let (value, t) = parser.parse_rdb().unwrap();
+        assert_eq!(Some(RedisValue::Rdb(b"REDIS0".to_vec())), value);
+        assert_eq!(9, t);
+
+        let (value, _) = parser.parse().unwrap();
+        assert_eq!(Some(RedisValue::Array(vec![])), value);
+    }
+
+    #[test]
+    fn test_parse_resumes_large_bulk_string() {
+        let payload = "x".repeat(10_000);
+        let input = format!("*2\r\n$3\r\nset\r\n${}\r\n{}\r\n", payload.len(), payload);
+        let mut parser = RedisValueParser::new();
+
+        for chunk in input.as_bytes()[..input.len() - 1].chunks(1024) {
+            parser.append(chunk);
+            assert_eq!(None, parser.parse().unwrap().0);
+            // the partial value is kept in the parser states, not in the buffer
+            assert_eq!(0, parser.buffer_len());
+        }
+
+        parser.append(b"\n$0\r\n\r\n");
+        let (value, t) = parser.parse().unwrap();
+        assert_eq!(
+            Some(RedisValue::Array(vec![
+                RedisValue::bulk_string("set"),
+                RedisValue::bulk_string(payload.as_str())
+            ])),
+            value
+        );
+        assert_eq!(input.len() - 1, t);
+
+        let (value, t) = parser.parse().unwrap();
+        assert_eq!(Some(RedisValue::bulk_string("")), value);
+        assert_eq!(5, t);
+    }
+
     #[test]
     fn test_parse_simple_string() {
         let input = "+HAPPY\r\n".as_bytes();
         let mut parser = RedisValueParser::new();
         parser.append(input);
-        let (value, t) = parser.parse().unwrap();
+        let (value, _) = parser.parse().unwrap();
         assert_eq!(Some(RedisValue::SimpleString("HAPPY".into())), value);
     }
+
+    #[test]
+    fn test_parse_recovers_from_malformed_input() {
+        let mut parser = RedisValueParser::new();
+        parser.append("*1\r\n$x\r\n".as_bytes());
+        assert!(parser.parse().is_err());
+        assert_eq!(0, parser.buffer_len());
+
+        parser.append("*1\r\n$4\r\nping\r\n".as_bytes());
+        let (value, _) = parser.parse().unwrap();
+        assert_eq!(
+            Some(RedisValue::Array(vec![RedisValue::bulk_string("ping")])),
+            value
+        );
+    }
 }
@@ -0,0 +1,1451 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::io::AsyncWriteExt;
+
+use crate::object::RedisObject;
+use crate::redis::{Store, StoreItem};
+use crate::set::RedisSet;
+use crate::stream::{Consumer, ConsumerGroup, PendingEntry, Stream, StreamFields, StreamId};
+use crate::utilities;
+use crate::zset::SortedSet;
+
+const RDB_MAGIC: &[u8; 5] = b"REDIS";
+const RDB_VERSION: u32 = 11;
+// the oldest version written by redis 7, since when small hashes, lists and sorted sets are
+// encoded as listpacks. the ziplists and zipmaps of the older versions are not supported
+const RDB_MIN_VERSION: u32 = 10;
+const RDB_MAX_VERSION: u32 = 12;
+
+const RDB_OPCODE_IDLE: u8 = 0xf8;
+const RDB_OPCODE_FREQ: u8 = 0xf9;
+const RDB_OPCODE_AUX: u8 = 0xfa;
+const RDB_OPCODE_RESIZEDB: u8 = 0xfb;
+const RDB_OPCODE_EXPIRETIME_MS: u8 = 0xfc;
+const RDB_OPCODE_EXPIRETIME: u8 = 0xfd;
+const RDB_OPCODE_SELECTDB: u8 = 0xfe;
+const RDB_OPCODE_EOF: u8 = 0xff;
+
+const RDB_TYPE_STRING: u8 = 0;
+const RDB_TYPE_LIST: u8 = 1;
+const RDB_TYPE_SET: u8 = 2;
+const RDB_TYPE_ZSET: u8 = 3;
+const RDB_TYPE_HASH: u8 = 4;
+const RDB_TYPE_ZSET_2: u8 = 5;
+const RDB_TYPE_SET_INTSET: u8 = 11;
+const RDB_TYPE_HASH_LISTPACK: u8 = 16;
+const RDB_TYPE_ZSET_LISTPACK: u8 = 17;
+const RDB_TYPE_LIST_QUICKLIST_2: u8 = 18;
+const RDB_TYPE_STREAM_LISTPACKS: u8 = 15;
+const RDB_TYPE_STREAM_LISTPACKS_2: u8 = 19;
+const RDB_TYPE_SET_LISTPACK: u8 = 20;
+const RDB_TYPE_STREAM_LISTPACKS_3: u8 = 21;
+
+const QUICKLIST_NODE_PLAIN: usize = 1;
+
+const STREAM_NODE_MAX_ENTRIES: usize = 100;
+const STREAM_ITEM_FLAG_DELETED: i64 = 1;
+const STREAM_ITEM_FLAG_SAMEFIELDS: i64 = 2;
+
+const RDB_ENC_INT8: u8 = 0;
+const RDB_ENC_INT16: u8 = 1;
+const RDB_ENC_INT32: u8 = 2;
+const RDB_ENC_LZF: u8 = 3;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RdbError {
+    Io(String),
+    InvalidMagic,
+    InvalidVersion(String),
+    UnsupportedVersion(u32),
+    UnexpectedEof(usize),
+    UnknownEncoding(u8, usize),
+    UnsupportedValueType(u8, usize),
+    LengthOverflow(u64, usize),
+    LzfCorrupted(usize),
+    ListpackCorrupted(usize),
+    IntsetCorrupted(usize),
+    StreamCorrupted(usize),
+    InvalidScore(usize),
+    ChecksumMismatch(u64, u64),
+    // a database index beyond the configured databases
+    DbIndexOutOfRange(usize, usize),
+    // a key which is not valid utf-8
+    InvalidKey(usize),
+}
+
+impl fmt::Display for RdbError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RdbError::Io(e) => write!(f, "{}", e),
+            RdbError::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported rdb version {}, only versions {} to {} are supported",
+                version, RDB_MIN_VERSION, RDB_MAX_VERSION
+            ),
+            e => write!(f, "{:?}", e),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum RdbLength {
+    Length(u64),
+    Encoded(u8),
+}
+
+#[derive(Debug, Default)]
+pub struct RdbSnapshot {
+    pub aux: HashMap<String, Vec<u8>>,
+    // the keyspaces indexed by their database, up to the last one holding keys
+    pub dbs: Vec<Store>,
+}
+
+impl RdbSnapshot {
+    fn db(&mut self, db: usize) -> &mut Store {
+        if self.dbs.len() <= db {
+            self.dbs.resize_with(db + 1, Store::default);
+        }
+        &mut self.dbs[db]
+    }
+
+    // the keyspaces of the number of databases configured
+    pub fn into_databases(mut self, databases: usize) -> Vec<Store> {
+        self.dbs.resize_with(databases, Store::default);
+        self.dbs
+    }
+}
+
+struct RdbReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> RdbReader<'a> {
+    fn new(data: &'a [u8]) -> RdbReader<'a> {
+        RdbReader { data, pos: 0 }
+    }
+
+    fn read_bytes(&mut self, n: usize) -> Result<&'a [u8], RdbError> {
+        if self.data.len() - self.pos < n {
+            return Err(RdbError::UnexpectedEof(self.pos));
+        }
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], RdbError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.read_bytes(N)?);
+        Ok(array)
+    }
+
+    // the lengths read from the file only hint the allocations up to the bytes left,
+    // which a corrupt length cannot go beyond
+    fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    fn read_u8(&mut self) -> Result<u8, RdbError> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    fn read_length_with_encoding(&mut self) -> Result<RdbLength, RdbError> {
+        let first = self.read_u8()?;
+        let length = match first >> 6 {
+            0b00 => (first & 0x3f) as u64,
+            0b01 => (((first & 0x3f) as u64) << 8) | self.read_u8()? as u64,
+            0b10 => match first {
+                0x80 => u32::from_be_bytes(self.read_array()?) as u64,
+                0x81 => u64::from_be_bytes(self.read_array()?),
+                _ => return Err(RdbError::UnknownEncoding(first, self.pos - 1)),
+            },
+            _ => return Ok(RdbLength::Encoded(first & 0x3f)),
+        };
+        Ok(RdbLength::Length(length))
+    }
+
+    fn read_length(&mut self) -> Result<usize, RdbError> {
+        let at = self.pos;
+        match self.read_length_with_encoding()? {
+            RdbLength::Length(l) => usize::try_from(l).map_err(|_| RdbError::LengthOverflow(l, at)),
+            RdbLength::Encoded(e) => Err(RdbError::UnknownEncoding(e, at)),
+        }
+    }
+
+    fn read_string(&mut self) -> Result<Vec<u8>, RdbError> {
+        let at = self.pos;
+        match self.read_length_with_encoding()? {
+            RdbLength::Length(l) => {
+                let l = usize::try_from(l).map_err(|_| RdbError::LengthOverflow(l, at))?;
+                Ok(self.read_bytes(l)?.to_vec())
+            }
+            RdbLength::Encoded(RDB_ENC_INT8) => {
+                Ok((self.read_u8()? as i8).to_string().into_bytes())
+            }
+            RdbLength::Encoded(RDB_ENC_INT16) => Ok(i16::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            RdbLength::Encoded(RDB_ENC_INT32) => Ok(i32::from_le_bytes(self.read_array()?)
+                .to_string()
+                .into_bytes()),
+            RdbLength::Encoded(RDB_ENC_LZF) => {
+                let compressed_length = self.read_length()?;
+                let length = self.read_length()?;
+                let start = self.pos;
+                let compressed = self.read_bytes(compressed_length)?;
+                lzf_decompress(compressed, length).ok_or(RdbError::LzfCorrupted(start))
+            }
+            RdbLength::Encoded(e) => Err(RdbError::UnknownEncoding(e, at)),
+        }
+    }
+
+    // a length which is not bound to the size of the memory, as the parts of a stream id
+    fn read_u64_length(&mut self) -> Result<u64, RdbError> {
+        let at = self.pos;
+        match self.read_length_with_encoding()? {
+            RdbLength::Length(l) => Ok(l),
+            RdbLength::Encoded(e) => Err(RdbError::UnknownEncoding(e, at)),
+        }
+    }
+
+    fn read_stream_id(&mut self) -> Result<StreamId, RdbError> {
+        Ok(StreamId {
+            ms: self.read_u64_length()?,
+            seq: self.read_u64_length()?,
+        })
+    }
+
+    // an id in its 16 bytes big endian form, as the keys of stream nodes
+    fn read_raw_stream_id(&mut self) -> Result<StreamId, RdbError> {
+        Ok(StreamId {
+            ms: u64::from_be_bytes(self.read_array()?),
+            seq: u64::from_be_bytes(self.read_array()?),
+        })
+    }
+
+    // the nodes of a stream, each one a listpack keyed by the id its entries are relative to,
+    // followed by the metadata which the later types extend
+    fn read_stream(&mut self, value_type: u8) -> Result<Stream, RdbError> {
+        let mut stream = Stream::default();
+        let nodes = self.read_length()?;
+        for _ in 0..nodes {
+            let at = self.pos;
+            let key = self.read_string()?;
+            let master = stream_node_id(&key).ok_or(RdbError::StreamCorrupted(at))?;
+            let at = self.pos;
+            let node =
+                listpack_entries(&self.read_string()?).ok_or(RdbError::ListpackCorrupted(at))?;
+            stream_node_entries(master, &node, &mut stream.entries)
+                .ok_or(RdbError::StreamCorrupted(at))?;
+        }
+        // the length is the number of entries read
+        self.read_length()?;
+        stream.last_id = self.read_stream_id()?;
+        stream.entries_added = stream.entries.len() as u64;
+        if value_type != RDB_TYPE_STREAM_LISTPACKS {
+            // the first id follows from the entries, and nothing is deleted apart from trimming
+            self.read_stream_id()?;
+            self.read_stream_id()?;
+            stream.entries_added = self.read_u64_length()?;
+        }
+
+        let groups = self.read_length()?;
+        for _ in 0..groups {
+            let name = self.read_string()?;
+            let mut group = ConsumerGroup {
+                last_id: self.read_stream_id()?,
+                ..Default::default()
+            };
+            if value_type != RDB_TYPE_STREAM_LISTPACKS {
+                // the number of entries read by the group, which is not tracked
+                self.read_u64_length()?;
+            }
+            let pending = self.read_length()?;
+            for _ in 0..pending {
+                // the consumer is found with the entries pending for it
+                let id = self.read_raw_stream_id()?;
+                let delivery_time = u64::from_le_bytes(self.read_array()?);
+                let delivery_count = self.read_u64_length()?;
+                group.pending.insert(
+                    id,
+                    PendingEntry {
+                        consumer: Vec::new(),
+                        delivery_time,
+                        delivery_count,
+                    },
+                );
+            }
+            let consumers = self.read_length()?;
+            for _ in 0..consumers {
+                let name = self.read_string()?;
+                let seen_time = u64::from_le_bytes(self.read_array()?);
+                let active_time = if value_type == RDB_TYPE_STREAM_LISTPACKS_3 {
+                    u64::from_le_bytes(self.read_array()?)
+                } else {
+                    seen_time
+                };
+                let mut consumer = Consumer {
+                    seen_time,
+                    active_time,
+                    ..Default::default()
+                };
+                let pending = self.read_length()?;
+                for _ in 0..pending {
+                    let at = self.pos;
+                    let id = self.read_raw_stream_id()?;
+                    let entry = group
+                        .pending
+                        .get_mut(&id)
+                        .ok_or(RdbError::StreamCorrupted(at))?;
+                    entry.consumer = name.clone();
+                    consumer.pending.insert(id);
+                }
+                group.consumers.insert(name, consumer);
+            }
+            stream.groups.insert(name, group);
+        }
+        Ok(stream)
+    }
+
+    // the score of the first sorted set encoding, a string prefixed by its length in one byte
+    fn read_score_string(&mut self) -> Result<f64, RdbError> {
+        let at = self.pos;
+        let score = match self.read_u8()? {
+            253 => f64::NAN,
+            254 => f64::INFINITY,
+            255 => f64::NEG_INFINITY,
+            length => {
+                let score = self.read_bytes(length as usize)?;
+                parse_score(score).ok_or(RdbError::InvalidScore(at))?
+            }
+        };
+        if score.is_nan() {
+            return Err(RdbError::InvalidScore(at));
+        }
+        Ok(score)
+    }
+
+    fn read_object(&mut self, value_type: u8, at: usize) -> Result<RedisObject, RdbError> {
+        match value_type {
+            RDB_TYPE_STRING => Ok(RedisObject::String(self.read_string()?)),
+            RDB_TYPE_LIST => {
+                let length = self.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..length {
+                    list.push_back(self.read_string()?);
+                }
+                Ok(RedisObject::List(list))
+            }
+            RDB_TYPE_LIST_QUICKLIST_2 => {
+                let nodes = self.read_length()?;
+                let mut list = VecDeque::new();
+                for _ in 0..nodes {
+                    let container = self.read_length()?;
+                    let at = self.pos;
+                    let node = self.read_string()?;
+                    if container == QUICKLIST_NODE_PLAIN {
+                        list.push_back(node);
+                    } else {
+                        let entries =
+                            listpack_entries(&node).ok_or(RdbError::ListpackCorrupted(at))?;
+                        list.extend(entries);
+                    }
+                }
+                Ok(RedisObject::List(list))
+            }
+            RDB_TYPE_HASH => {
+                let length = self.read_length()?;
+                let mut hash = HashMap::new();
+                for _ in 0..length {
+                    let field = self.read_string()?;
+                    hash.insert(field, self.read_string()?);
+                }
+                Ok(RedisObject::Hash(hash))
+            }
+            RDB_TYPE_HASH_LISTPACK => {
+                let at = self.pos;
+                let entries = listpack_entries(&self.read_string()?)
+                    .ok_or(RdbError::ListpackCorrupted(at))?;
+                if entries.len() % 2 != 0 {
+                    return Err(RdbError::ListpackCorrupted(at));
+                }
+                let mut entries = entries.into_iter();
+                let mut hash = HashMap::new();
+                while let (Some(field), Some(value)) = (entries.next(), entries.next()) {
+                    hash.insert(field, value);
+                }
+                Ok(RedisObject::Hash(hash))
+            }
+            RDB_TYPE_ZSET | RDB_TYPE_ZSET_2 => {
+                let length = self.read_length()?;
+                let mut zset = SortedSet::new();
+                for _ in 0..length {
+                    let member = self.read_string()?;
+                    let score = if value_type == RDB_TYPE_ZSET {
+                        self.read_score_string()?
+                    } else {
+                        let at = self.pos;
+                        let score = f64::from_le_bytes(self.read_array()?);
+                        if score.is_nan() {
+                            return Err(RdbError::InvalidScore(at));
+                        }
+                        score
+                    };
+                    zset.insert(member, score);
+                }
+                Ok(RedisObject::ZSet(zset))
+            }
+            RDB_TYPE_ZSET_LISTPACK => {
+                let at = self.pos;
+                let entries = listpack_entries(&self.read_string()?)
+                    .ok_or(RdbError::ListpackCorrupted(at))?;
+                if entries.len() % 2 != 0 {
+                    return Err(RdbError::ListpackCorrupted(at));
+                }
+                let mut entries = entries.into_iter();
+                let mut zset = SortedSet::new();
+                while let (Some(member), Some(score)) = (entries.next(), entries.next()) {
+                    zset.insert(
+                        member,
+                        parse_score(&score).ok_or(RdbError::InvalidScore(at))?,
+                    );
+                }
+                Ok(RedisObject::ZSet(zset))
+            }
+            RDB_TYPE_SET => {
+                let length = self.read_length()?;
+                let mut members = Vec::with_capacity(length.min(self.remaining()));
+                for _ in 0..length {
+                    members.push(self.read_string()?);
+                }
+                Ok(RedisObject::Set(RedisSet::from_members(members)))
+            }
+            RDB_TYPE_SET_INTSET => {
+                let at = self.pos;
+                let ints =
+                    intset_entries(&self.read_string()?).ok_or(RdbError::IntsetCorrupted(at))?;
+                Ok(RedisObject::Set(RedisSet::from_members(
+                    ints.iter().map(|i| i.to_string().into_bytes()),
+                )))
+            }
+            RDB_TYPE_SET_LISTPACK => {
+                let at = self.pos;
+                let members = listpack_entries(&self.read_string()?)
+                    .ok_or(RdbError::ListpackCorrupted(at))?;
+                Ok(RedisObject::Set(RedisSet::from_members(members)))
+            }
+            RDB_TYPE_STREAM_LISTPACKS
+            | RDB_TYPE_STREAM_LISTPACKS_2
+            | RDB_TYPE_STREAM_LISTPACKS_3 => Ok(RedisObject::Stream(self.read_stream(value_type)?)),
+            t => Err(RdbError::UnsupportedValueType(t, at)),
+        }
+    }
+}
+
+struct RdbWriter {
+    buffer: Vec<u8>,
+}
+
+impl RdbWriter {
+    fn new() -> RdbWriter {
+        RdbWriter { buffer: Vec::new() }
+    }
+
+    fn write_u8(&mut self, b: u8) {
+        self.buffer.push(b);
+    }
+
+    fn write_length(&mut self, length: usize) {
+        if length < 1 << 6 {
+            self.buffer.push(length as u8);
+        } else if length < 1 << 14 {
+            self.buffer.push(0x40 | (length >> 8) as u8);
+            self.buffer.push(length as u8);
+        } else if length <= u32::MAX as usize {
+            self.buffer.push(0x80);
+            self.buffer
+                .extend_from_slice(&(length as u32).to_be_bytes());
+        } else {
+            self.buffer.push(0x81);
+            self.buffer
+                .extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+
+    fn write_string(&mut self, s: &[u8]) {
+        // strings holding a canonical small integer are stored in the integer encodings
+        if let Some(i) = std::str::from_utf8(s)
+            .ok()
+            .filter(|v| v.len() <= 11)
+            .and_then(|v| v.parse::<i32>().ok())
+            .filter(|i| i.to_string().as_bytes() == s)
+        {
+            if let Ok(i) = i8::try_from(i) {
+                self.buffer.push(0xc0 | RDB_ENC_INT8);
+                self.buffer.push(i as u8);
+            } else if let Ok(i) = i16::try_from(i) {
+                self.buffer.push(0xc0 | RDB_ENC_INT16);
+                self.buffer.extend_from_slice(&i.to_le_bytes());
+            } else {
+                self.buffer.push(0xc0 | RDB_ENC_INT32);
+                self.buffer.extend_from_slice(&i.to_le_bytes());
+            }
+            return;
+        }
+        self.write_length(s.len());
+        self.buffer.extend_from_slice(s);
+    }
+
+    fn write_aux(&mut self, key: &str, value: &[u8]) {
+        self.write_u8(RDB_OPCODE_AUX);
+        self.write_string(key.as_bytes());
+        self.write_string(value);
+    }
+
+    fn write_object_type(&mut self, value: &RedisObject) {
+        match value {
+            RedisObject::String(_) => self.write_u8(RDB_TYPE_STRING),
+            RedisObject::List(_) => self.write_u8(RDB_TYPE_LIST),
+            RedisObject::Hash(_) => self.write_u8(RDB_TYPE_HASH),
+            RedisObject::Set(RedisSet::IntSet(_)) => self.write_u8(RDB_TYPE_SET_INTSET),
+            RedisObject::Set(RedisSet::HashSet(_)) => self.write_u8(RDB_TYPE_SET),
+            RedisObject::ZSet(_) => self.write_u8(RDB_TYPE_ZSET_2),
+            RedisObject::Stream(_) => self.write_u8(RDB_TYPE_STREAM_LISTPACKS_3),
+        }
+    }
+
+    fn write_object(&mut self, value: &RedisObject) {
+        match value {
+            RedisObject::String(s) => self.write_string(s),
+            RedisObject::List(list) => {
+                self.write_length(list.len());
+                for element in list {
+                    self.write_string(element);
+                }
+            }
+            RedisObject::Hash(hash) => {
+                self.write_length(hash.len());
+                for (field, value) in hash.iter() {
+                    self.write_string(field);
+                    self.write_string(value);
+                }
+            }
+            RedisObject::Set(RedisSet::IntSet(ints)) => self.write_string(&intset(ints)),
+            RedisObject::Set(RedisSet::HashSet(set)) => {
+                self.write_length(set.len());
+                for member in set {
+                    self.write_string(member);
+                }
+            }
+            RedisObject::ZSet(zset) => {
+                self.write_length(zset.len());
+                for (member, score) in zset.iter() {
+                    self.write_string(member);
+                    self.buffer.extend_from_slice(&score.to_le_bytes());
+                }
+            }
+            RedisObject::Stream(stream) => self.write_stream(stream),
+        }
+    }
+
+    fn write_stream_id(&mut self, id: StreamId) {
+        self.write_length(id.ms as usize);
+        self.write_length(id.seq as usize);
+    }
+
+    fn write_stream(&mut self, stream: &Stream) {
+        let entries: Vec<(&StreamId, &StreamFields)> = stream.entries.iter().collect();
+        let nodes = entries.chunks(STREAM_NODE_MAX_ENTRIES);
+        self.write_length(nodes.len());
+        for node in nodes {
+            let master = *node[0].0;
+            let mut key = master.ms.to_be_bytes().to_vec();
+            key.extend_from_slice(&master.seq.to_be_bytes());
+            self.write_string(&key);
+            self.write_string(&stream_node(master, node));
+        }
+        self.write_length(stream.entries.len());
+        self.write_stream_id(stream.last_id);
+        let first = stream.entries.keys().next().copied();
+        self.write_stream_id(first.unwrap_or(StreamId::MIN));
+        // the greatest id deleted other than by trimming, which never happens here
+        self.write_stream_id(StreamId::MIN);
+        self.write_length(stream.entries_added as usize);
+
+        self.write_length(stream.groups.len());
+        for (name, group) in stream.groups.iter() {
+            self.write_string(name);
+            self.write_stream_id(group.last_id);
+            // the number of entries read by the group is not tracked, which -1 stands for
+            self.write_length(u64::MAX as usize);
+            self.write_length(group.pending.len());
+            for (id, entry) in group.pending.iter() {
+                self.write_raw_stream_id(*id);
+                self.buffer
+                    .extend_from_slice(&entry.delivery_time.to_le_bytes());
+                self.write_length(entry.delivery_count as usize);
+            }
+            self.write_length(group.consumers.len());
+            for (name, consumer) in group.consumers.iter() {
+                self.write_string(name);
+                self.buffer
+                    .extend_from_slice(&consumer.seen_time.to_le_bytes());
+                self.buffer
+                    .extend_from_slice(&consumer.active_time.to_le_bytes());
+                self.write_length(consumer.pending.len());
+                for id in consumer.pending.iter() {
+                    self.write_raw_stream_id(*id);
+                }
+            }
+        }
+    }
+
+    fn write_raw_stream_id(&mut self, id: StreamId) {
+        self.buffer.extend_from_slice(&id.ms.to_be_bytes());
+        self.buffer.extend_from_slice(&id.seq.to_be_bytes());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.write_u8(RDB_OPCODE_EOF);
+        let checksum = crc64(0, &self.buffer);
+        self.buffer.extend_from_slice(&checksum.to_le_bytes());
+        self.buffer
+    }
+}
+
+const CRC64_TABLE: [u64; 256] = crc64_table();
+
+// crc-64-jones in its reflected form, the checksum redis appends to rdb files
+const fn crc64_table() -> [u64; 256] {
+    const POLY: u64 = 0x95ac9329ac4bc9b5;
+    let mut table = [0u64; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ POLY
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+pub fn crc64(mut crc: u64, data: &[u8]) -> u64 {
+    for b in data {
+        crc = CRC64_TABLE[((crc ^ *b as u64) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+pub fn lzf_decompress(input: &[u8], length: usize) -> Option<Vec<u8>> {
+    // the declared length is checked as the output grows, not trusted up front
+    let mut output: Vec<u8> = Vec::new();
+    let mut i = 0;
+
+    while i < input.len() {
+        let ctrl = input[i] as usize;
+        i += 1;
+
+        if ctrl < 32 {
+            // literal run of ctrl + 1 bytes
+            let run = ctrl + 1;
+            if i + run > input.len() {
+                return None;
+            }
+            output.extend_from_slice(&input[i..i + run]);
+            i += run;
+        } else {
+            // back reference into the already decompressed output
+            let mut run = ctrl >> 5;
+            if run == 7 {
+                run += *input.get(i)? as usize;
+                i += 1;
+            }
+            let distance = ((ctrl & 0x1f) << 8) + *input.get(i)? as usize + 1;
+            i += 1;
+            if distance > output.len() {
+                return None;
+            }
+            let start = output.len() - distance;
+            for k in 0..run + 2 {
+                output.push(output[start + k]);
+            }
+        }
+
+        if output.len() > length {
+            return None;
+        }
+    }
+
+    if output.len() == length {
+        Some(output)
+    } else {
+        None
+    }
+}
+
+// the entries of a listpack, integers are turned back into their string form
+pub fn listpack_entries(listpack: &[u8]) -> Option<Vec<Vec<u8>>> {
+    // 4 bytes of total length and 2 bytes of element count
+    let mut i = 6;
+    let mut entries = Vec::new();
+    loop {
+        let b = *listpack.get(i)?;
+        let bytes = |from: usize, n: usize| listpack.get(i + from..i + from + n);
+        let (entry, length) = if b == 0xff {
+            break;
+        } else if b & 0x80 == 0 {
+            ((b as i64).to_string().into_bytes(), 1)
+        } else if b & 0xc0 == 0x80 {
+            let l = (b & 0x3f) as usize;
+            (bytes(1, l)?.to_vec(), 1 + l)
+        } else if b & 0xe0 == 0xc0 {
+            let v = (((b & 0x1f) as i64) << 8) | *listpack.get(i + 1)? as i64;
+            let v = if v >= 1 << 12 { v - (1 << 13) } else { v };
+            (v.to_string().into_bytes(), 2)
+        } else if b & 0xf0 == 0xe0 {
+            let l = (((b & 0x0f) as usize) << 8) | *listpack.get(i + 1)? as usize;
+            (bytes(2, l)?.to_vec(), 2 + l)
+        } else {
+            match b {
+                0xf0 => {
+                    let l = u32::from_le_bytes(bytes(1, 4)?.try_into().ok()?) as usize;
+                    (bytes(5, l)?.to_vec(), 5 + l)
+                }
+                0xf1 => {
+                    let v = i16::from_le_bytes(bytes(1, 2)?.try_into().ok()?);
+                    (v.to_string().into_bytes(), 3)
+                }
+                0xf2 => {
+                    let b = bytes(1, 3)?;
+                    let v = i32::from_le_bytes([0, b[0], b[1], b[2]]) >> 8;
+                    (v.to_string().into_bytes(), 4)
+                }
+                0xf3 => {
+                    let v = i32::from_le_bytes(bytes(1, 4)?.try_into().ok()?);
+                    (v.to_string().into_bytes(), 5)
+                }
+                0xf4 => {
+                    let v = i64::from_le_bytes(bytes(1, 8)?.try_into().ok()?);
+                    (v.to_string().into_bytes(), 9)
+                }
+                _ => return None,
+            }
+        };
+        // each entry is followed by its own length, 7 bits per byte
+        i += length + backlen_size(length);
+        entries.push(entry);
+    }
+    Some(entries)
+}
+
+// the size of the length which follows a listpack entry of `length` bytes
+fn backlen_size(length: usize) -> usize {
+    match length {
+        l if l <= 127 => 1,
+        l if l < 16383 => 2,
+        l if l < 2097151 => 3,
+        l if l < 268435455 => 4,
+        _ => 5,
+    }
+}
+
+// a listpack of the entries, those holding a canonical integer are stored as integers
+fn listpack(entries: &[Vec<u8>]) -> Vec<u8> {
+    let mut listpack = vec![0; 6];
+    for entry in entries {
+        let start = listpack.len();
+        let int = std::str::from_utf8(entry)
+            .ok()
+            .and_then(|e| e.parse::<i64>().ok())
+            .filter(|i| i.to_string().as_bytes() == entry.as_slice());
+        match int {
+            Some(i) if (0..=127).contains(&i) => listpack.push(i as u8),
+            Some(i) if (-4096..=4095).contains(&i) => {
+                let v = (i as u16) & 0x1fff;
+                listpack.push(0xc0 | (v >> 8) as u8);
+                listpack.push(v as u8);
+            }
+            Some(i) if i16::try_from(i).is_ok() => {
+                listpack.push(0xf1);
+                listpack.extend_from_slice(&(i as i16).to_le_bytes());
+            }
+            Some(i) if (-(1 << 23)..1 << 23).contains(&i) => {
+                listpack.push(0xf2);
+                listpack.extend_from_slice(&(i as i32).to_le_bytes()[..3]);
+            }
+            Some(i) if i32::try_from(i).is_ok() => {
+                listpack.push(0xf3);
+                listpack.extend_from_slice(&(i as i32).to_le_bytes());
+            }
+            Some(i) => {
+                listpack.push(0xf4);
+                listpack.extend_from_slice(&i.to_le_bytes());
+            }
+            None if entry.len() < 64 => listpack.push(0x80 | entry.len() as u8),
+            None if entry.len() < 4096 => {
+                listpack.push(0xe0 | (entry.len() >> 8) as u8);
+                listpack.push(entry.len() as u8);
+            }
+            None => {
+                listpack.push(0xf0);
+                listpack.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+            }
+        }
+        if int.is_none() {
+            listpack.extend_from_slice(entry);
+        }
+        // the length is read backwards, 7 bits per byte with the highest bit set but on the last
+        let length = listpack.len() - start;
+        let size = backlen_size(length);
+        for k in (0..size).rev() {
+            let bits = ((length >> (7 * k)) & 0x7f) as u8;
+            listpack.push(if k == size - 1 { bits } else { bits | 0x80 });
+        }
+    }
+    listpack.push(0xff);
+    let total = listpack.len() as u32;
+    listpack[0..4].copy_from_slice(&total.to_le_bytes());
+    let count = u16::try_from(entries.len()).unwrap_or(u16::MAX);
+    listpack[4..6].copy_from_slice(&count.to_le_bytes());
+    listpack
+}
+
+// the id a stream node is keyed by, in big endian
+fn stream_node_id(key: &[u8]) -> Option<StreamId> {
+    Some(StreamId {
+        ms: u64::from_be_bytes(key.get(0..8)?.try_into().ok()?),
+        seq: u64::from_be_bytes(key.get(8..16)?.try_into().ok()?),
+    })
+}
+
+// the entries of a stream node: a master entry with its count of valid and deleted entries
+// and the fields which entries flagged with SAMEFIELDS share, then every entry with its id
+// relative to the node id, followed by its number of listpack elements
+fn stream_node_entries(
+    master: StreamId,
+    node: &[Vec<u8>],
+    entries: &mut BTreeMap<StreamId, StreamFields>,
+) -> Option<()> {
+    let mut node = node.iter();
+    let int = |node: &mut std::slice::Iter<Vec<u8>>| -> Option<i64> {
+        std::str::from_utf8(node.next()?).ok()?.parse().ok()
+    };
+    let count = int(&mut node)? as usize + int(&mut node)? as usize;
+    let master_fields = (0..int(&mut node)?)
+        .map(|_| node.next().cloned())
+        .collect::<Option<Vec<Vec<u8>>>>()?;
+    if int(&mut node)? != 0 {
+        return None;
+    }
+    for _ in 0..count {
+        let flags = int(&mut node)?;
+        let id = StreamId {
+            ms: master.ms.wrapping_add(int(&mut node)? as u64),
+            seq: master.seq.wrapping_add(int(&mut node)? as u64),
+        };
+        let fields = if flags & STREAM_ITEM_FLAG_SAMEFIELDS != 0 {
+            master_fields
+                .iter()
+                .map(|field| Some((field.clone(), node.next()?.clone())))
+                .collect::<Option<StreamFields>>()?
+        } else {
+            (0..int(&mut node)?)
+                .map(|_| Some((node.next()?.clone(), node.next()?.clone())))
+                .collect::<Option<StreamFields>>()?
+        };
+        int(&mut node)?;
+        if flags & STREAM_ITEM_FLAG_DELETED == 0 {
+            entries.insert(id, fields);
+        }
+    }
+    Some(())
+}
+
+// the listpack of a stream node, whose master entry takes the fields of the first entry
+fn stream_node(master: StreamId, node: &[(&StreamId, &StreamFields)]) -> Vec<u8> {
+    let int = |i: i64| i.to_string().into_bytes();
+    let master_fields: Vec<&Vec<u8>> = node[0].1.iter().map(|(field, _)| field).collect();
+    let mut entries = vec![
+        int(node.len() as i64),
+        int(0),
+        int(master_fields.len() as i64),
+    ];
+    entries.extend(master_fields.iter().map(|field| field.to_vec()));
+    entries.push(int(0));
+    for (id, fields) in node {
+        let same = fields.len() == master_fields.len()
+            && fields
+                .iter()
+                .zip(master_fields.iter())
+                .all(|((f, _), m)| f == *m);
+        let flags = if same { STREAM_ITEM_FLAG_SAMEFIELDS } else { 0 };
+        entries.push(int(flags));
+        entries.push(int(id.ms.wrapping_sub(master.ms) as i64));
+        entries.push(int(id.seq.wrapping_sub(master.seq) as i64));
+        let elements = if same {
+            entries.extend(fields.iter().map(|(_, value)| value.clone()));
+            fields.len()
+        } else {
+            entries.push(int(fields.len() as i64));
+            for (field, value) in fields.iter() {
+                entries.push(field.clone());
+                entries.push(value.clone());
+            }
+            1 + 2 * fields.len()
+        };
+        entries.push(int(3 + elements as i64));
+    }
+    listpack(&entries)
+}
+
+fn parse_score(score: &[u8]) -> Option<f64> {
+    std::str::from_utf8(score)
+        .ok()?
+        .parse::<f64>()
+        .ok()
+        .filter(|score| !score.is_nan())
+}
+
+// the integers of an intset: the byte width of every integer, their count, then the
+// integers in ascending order, all little endian
+pub fn intset_entries(intset: &[u8]) -> Option<Vec<i64>> {
+    let width = u32::from_le_bytes(intset.get(0..4)?.try_into().ok()?) as usize;
+    let length = u32::from_le_bytes(intset.get(4..8)?.try_into().ok()?) as usize;
+    if !matches!(width, 2 | 4 | 8)
+        || width.checked_mul(length).and_then(|n| n.checked_add(8)) != Some(intset.len())
+    {
+        return None;
+    }
+    let mut ints = Vec::with_capacity(length);
+    for b in intset[8..].chunks(width) {
+        let i = match width {
+            2 => i16::from_le_bytes(b.try_into().ok()?) as i64,
+            4 => i32::from_le_bytes(b.try_into().ok()?) as i64,
+            _ => i64::from_le_bytes(b.try_into().ok()?),
+        };
+        ints.push(i);
+    }
+    Some(ints)
+}
+
+// the intset of sorted integers, in the smallest width which holds all of them
+fn intset(ints: &[i64]) -> Vec<u8> {
+    let width = if ints.iter().all(|i| i16::try_from(*i).is_ok()) {
+        2
+    } else if ints.iter().all(|i| i32::try_from(*i).is_ok()) {
+        4
+    } else {
+        8
+    };
+    let mut intset = Vec::with_capacity(8 + width * ints.len());
+    intset.extend_from_slice(&(width as u32).to_le_bytes());
+    intset.extend_from_slice(&(ints.len() as u32).to_le_bytes());
+    for i in ints {
+        // the low bytes of a little endian integer are the integer in a narrower width
+        intset.extend_from_slice(&i.to_le_bytes()[..width]);
+    }
+    intset
+}
+
+// decode a snapshot whose keys fit in the given number of databases
+pub fn decode(data: &[u8], databases: usize) -> Result<RdbSnapshot, RdbError> {
+    let mut reader = RdbReader::new(data);
+
+    if reader
+        .read_bytes(RDB_MAGIC.len())
+        .map_err(|_| RdbError::InvalidMagic)?
+        != RDB_MAGIC
+    {
+        return Err(RdbError::InvalidMagic);
+    }
+    let version = reader.read_bytes(4)?;
+    let version: u32 = std::str::from_utf8(version)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .ok_or_else(|| RdbError::InvalidVersion(String::from_utf8_lossy(version).to_string()))?;
+    if !(RDB_MIN_VERSION..=RDB_MAX_VERSION).contains(&version) {
+        return Err(RdbError::UnsupportedVersion(version));
+    }
+
+    let mut snapshot = RdbSnapshot::default();
+    let mut db: usize = 0;
+    let mut expired_at: u64 = 0;
+    let now = utilities::now();
+
+    loop {
+        let opcode = reader.read_u8()?;
+        match opcode {
+            RDB_OPCODE_EOF => break,
+            RDB_OPCODE_SELECTDB => {
+                let at = reader.pos;
+                db = reader.read_length()?;
+                if db >= databases {
+                    return Err(RdbError::DbIndexOutOfRange(db, at));
+                }
+            }
+            RDB_OPCODE_RESIZEDB => {
+                let db_size = reader.read_length()?;
+                let _expires_size = reader.read_length()?;
+                snapshot.db(db).reserve(db_size.min(reader.remaining()));
+            }
+            RDB_OPCODE_AUX => {
+                let key = reader.read_string()?;
+                let value = reader.read_string()?;
+                snapshot
+                    .aux
+                    .insert(String::from_utf8_lossy(&key).to_string(), value);
+            }
+            RDB_OPCODE_EXPIRETIME => {
+                expired_at = u32::from_le_bytes(reader.read_array()?) as u64 * 1000;
+            }
+            RDB_OPCODE_EXPIRETIME_MS => {
+                expired_at = u64::from_le_bytes(reader.read_array()?);
+            }
+            RDB_OPCODE_IDLE => {
+                reader.read_length()?;
+            }
+            RDB_OPCODE_FREQ => {
+                reader.read_u8()?;
+            }
+            value_type => {
+                let at = reader.pos - 1;
+                let key = String::from_utf8(reader.read_string()?)
+                    .map_err(|_| RdbError::InvalidKey(at))?;
+                let value = reader.read_object(value_type, at)?;
+                let item = StoreItem { value, expired_at };
+                expired_at = 0;
+
+                // keys that already expired while the server was down are not loaded
+                if item.expired_at != 0 && item.expired_at < now {
+                    continue;
+                }
+                snapshot.db(db).insert(key, item);
+            }
+        }
+    }
+
+    // the file ends with a 8 bytes checksum, zero means it was disabled
+    let content = &data[..reader.pos];
+    let expected = u64::from_le_bytes(reader.read_array()?);
+    if expected != 0 {
+        let actual = crc64(0, content);
+        if actual != expected {
+            return Err(RdbError::ChecksumMismatch(expected, actual));
+        }
+    }
+
+    Ok(snapshot)
+}
+
+pub fn encode(dbs: &[Store]) -> Vec<u8> {
+    let mut writer = RdbWriter::new();
+    writer.buffer.extend_from_slice(RDB_MAGIC);
+    writer
+        .buffer
+        .extend_from_slice(format!("{:04}", RDB_VERSION).as_bytes());
+
+    let ctime = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    writer.write_aux("redis-ver", b"7.2.0");
+    writer.write_aux("redis-bits", b"64");
+    writer.write_aux("ctime", ctime.to_string().as_bytes());
+
+    let now = utilities::now();
+    for (db, store) in dbs.iter().enumerate() {
+        let items: Vec<(&str, &StoreItem)> = store
+            .iter()
+            .filter(|(_, item)| item.expired_at == 0 || item.expired_at >= now)
+            .collect();
+        // like redis, the empty databases are left out
+        if items.is_empty() {
+            continue;
+        }
+        let expires = items
+            .iter()
+            .filter(|(_, item)| item.expired_at != 0)
+            .count();
+
+        writer.write_u8(RDB_OPCODE_SELECTDB);
+        writer.write_length(db);
+        writer.write_u8(RDB_OPCODE_RESIZEDB);
+        writer.write_length(items.len());
+        writer.write_length(expires);
+
+        for (key, item) in items {
+            if item.expired_at != 0 {
+                writer.write_u8(RDB_OPCODE_EXPIRETIME_MS);
+                writer
+                    .buffer
+                    .extend_from_slice(&item.expired_at.to_le_bytes());
+            }
+            writer.write_object_type(&item.value);
+            writer.write_string(key.as_bytes());
+            writer.write_object(&item.value);
+        }
+    }
+
+    writer.finish()
+}
+
+// write the snapshot to a temp file first so a crash never leaves a half written dump behind
+pub async fn save(path: &Path, dbs: &[Store]) -> Result<(), RdbError> {
+    let data = encode(dbs);
+    write_atomically(path, &data).await
+}
+
+pub async fn write_atomically(path: &Path, data: &[u8]) -> Result<(), RdbError> {
+    let temp = path.with_file_name(format!("temp-{}.rdb", std::process::id()));
+    let result = async {
+        let mut file = tokio::fs::File::create(&temp).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp, path).await
+    }
+    .await;
+    if let Err(e) = result {
+        let _ = tokio::fs::remove_file(&temp).await;
+        return Err(RdbError::Io(e.to_string()));
+    }
+    Ok(())
+}
+
+pub async fn load(path: &Path, databases: usize) -> Result<Option<RdbSnapshot>, RdbError> {
+    match tokio::fs::read(path).await {
+        Ok(data) => decode(&data, databases).map(Some),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(RdbError::Io(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> RedisObject {
+        RedisObject::String(s.as_bytes().to_vec())
+    }
+
+    const EMPTY_RDB: &str = "UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==";
+
+    fn rdb(body: &[u8]) -> Vec<u8> {
+        let mut data = b"REDIS0011".to_vec();
+        data.extend_from_slice(body);
+        data.push(RDB_OPCODE_EOF);
+        data.extend_from_slice(&[0; 8]);
+        data
+    }
+
+    #[test]
+    fn test_decode_empty_rdb() {
+        #[allow(deprecated)]
+        let data = base64::decode(EMPTY_RDB).unwrap();
+        let snapshot = decode(&data, 16).unwrap();
+        assert!(snapshot.dbs.is_empty());
+        assert_eq!(Some(&b"7.2.0".to_vec()), snapshot.aux.get("redis-ver"));
+        assert_eq!(Some(&b"64".to_vec()), snapshot.aux.get("redis-bits"));
+    }
+
+    #[test]
+    fn test_decode_strings_with_expiry() {
+        let mut body = vec![RDB_OPCODE_SELECTDB, 0x00, RDB_OPCODE_RESIZEDB, 0x03, 0x02];
+        body.extend_from_slice(&[
+            RDB_TYPE_STRING,
+            0x03,
+            b'f',
+            b'o',
+            b'o',
+            0x03,
+            b'b',
+            b'a',
+            b'r',
+        ]);
+        body.push(RDB_OPCODE_EXPIRETIME_MS);
+        body.extend_from_slice(&u64::MAX.to_le_bytes());
+        body.extend_from_slice(&[RDB_TYPE_STRING, 0x01, b'a', 0xc0, 0xfe]);
+        body.push(RDB_OPCODE_EXPIRETIME);
+        body.extend_from_slice(&1u32.to_le_bytes());
+        body.extend_from_slice(&[RDB_TYPE_STRING, 0x01, b'b', 0x01, b'x']);
+
+        let snapshot = decode(&rdb(&body), 16).unwrap();
+        assert_eq!(2, snapshot.dbs[0].len());
+        let foo = snapshot.dbs[0].get("foo").unwrap();
+        assert_eq!(string("bar"), foo.value);
+        assert_eq!(0, foo.expired_at);
+        let a = snapshot.dbs[0].get("a").unwrap();
+        assert_eq!(string("-2"), a.value);
+        assert_eq!(u64::MAX, a.expired_at);
+        // already expired
+        assert!(!snapshot.dbs[0].contains_key("b"));
+    }
+
+    #[test]
+    fn test_decode_integer_encodings() {
+        let mut body = vec![RDB_TYPE_STRING, 0x01, b'a', 0xc1];
+        body.extend_from_slice(&1000i16.to_le_bytes());
+        body.extend_from_slice(&[RDB_TYPE_STRING, 0x01, b'b', 0xc2]);
+        body.extend_from_slice(&(-70000i32).to_le_bytes());
+
+        let snapshot = decode(&rdb(&body), 16).unwrap();
+        assert_eq!(string("1000"), snapshot.dbs[0]["a"].value);
+        assert_eq!(string("-70000"), snapshot.dbs[0]["b"].value);
+    }
+
+    #[test]
+    fn test_decode_lzf_string() {
+        // "aaaaaaaaaa": literal 'a' followed by a back reference of 9 bytes at distance 1
+        let compressed = [0x00, b'a', 0xe0, 0x00, 0x00];
+        let mut body = vec![RDB_TYPE_STRING, 0x01, b'k', 0xc3, 0x05, 0x0a];
+        body.extend_from_slice(&compressed);
+
+        let snapshot = decode(&rdb(&body), 16).unwrap();
+        assert_eq!(string("aaaaaaaaaa"), snapshot.dbs[0]["k"].value);
+    }
+
+    #[test]
+    fn test_decode_long_lengths() {
+        let value = vec![b'x'; 300];
+        let mut body = vec![RDB_TYPE_STRING, 0x01, b'k', 0x41, 0x2c];
+        body.extend_from_slice(&value);
+        body.extend_from_slice(&[RDB_TYPE_STRING, 0x01, b'l', 0x80, 0x00, 0x00, 0x00, 0x02]);
+        body.extend_from_slice(b"yy");
+
+        let snapshot = decode(&rdb(&body), 16).unwrap();
+        assert_eq!(RedisObject::String(value), snapshot.dbs[0]["k"].value);
+        assert_eq!(string("yy"), snapshot.dbs[0]["l"].value);
+    }
+
+    #[test]
+    fn test_decode_quicklist() {
+        // "a", 5 and -1 as a 13 bits integer
+        let listpack = [
+            0x0e, 0x00, 0x00, 0x00, 0x03, 0x00, 0x81, b'a', 0x02, 0x05, 0x01, 0xdf, 0xff, 0x02,
+            0xff,
+        ];
+        let mut body = vec![RDB_TYPE_LIST_QUICKLIST_2, 0x01, b'l', 0x02, 0x02, 0x0f];
+        body.extend_from_slice(&listpack);
+        body.extend_from_slice(&[0x01, 0x05]);
+        body.extend_from_slice(b"plain");
+
+        let snapshot = decode(&rdb(&body), 16).unwrap();
+        let expected: Vec<Vec<u8>> = vec![
+            b"a".to_vec(),
+            b"5".to_vec(),
+            b"-1".to_vec(),
+            b"plain".to_vec(),
+        ];
+        assert_eq!(
+            RedisObject::List(expected.into()),
+            snapshot.dbs[0]["l"].value
+        );
+    }
+
+    #[test]
+    fn test_crc64() {
+        assert_eq!(0xe9c6d914c4b8d9ca, crc64(0, b"123456789"));
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let mut store = Store::default();
+        let items = vec![
+            ("s", string("hello"), 0),
+            ("i8", string("-12"), 0),
+            ("i16", string("30000"), u64::MAX - 1),
+            ("i32", string("-2000000"), 0),
+            ("padded", string("007"), 0),
+            ("long", string("x".repeat(20000).as_str()), 0),
+            ("expired", string("gone"), 1),
+            (
+                "list",
+                RedisObject::List(vec![b"a".to_vec(), b"12".to_vec()].into()),
+                0,
+            ),
+            (
+                "hash",
+                RedisObject::Hash(HashMap::from([(b"f".to_vec(), b"1".to_vec())])),
+                0,
+            ),
+            (
+                "zset",
+                RedisObject::ZSet({
+                    let mut zset = SortedSet::new();
+                    zset.insert(b"a".to_vec(), 1.5);
+                    zset.insert(b"b".to_vec(), f64::NEG_INFINITY);
+                    zset
+                }),
+                0,
+            ),
+            (
+                "intset",
+                RedisObject::Set(RedisSet::IntSet(vec![-70000, 3, i64::MAX])),
+                0,
+            ),
+            (
+                "set",
+                RedisObject::Set(RedisSet::from_members([b"m".to_vec(), b"1".to_vec()])),
+                0,
+            ),
+            (
+                "stream",
+                RedisObject::Stream({
+                    // more entries than a node holds, with fields differing from the first entry
+                    let mut stream = Stream::default();
+                    for i in 0..150u64 {
+                        let field = if i % 3 == 0 { "f" } else { "g" };
+                        let value = format!("{}", i * 1000).repeat(i as usize % 7);
+                        stream.entries.insert(
+                            StreamId {
+                                ms: 1 << 40,
+                                seq: i * i,
+                            },
+                            vec![(field.as_bytes().to_vec(), value.into_bytes())],
+                        );
+                    }
+                    stream.last_id = StreamId {
+                        ms: 1 << 41,
+                        seq: 0,
+                    };
+                    stream.entries_added = 200;
+                    let pending = StreamId {
+                        ms: 1 << 40,
+                        seq: 4,
+                    };
+                    let mut group = ConsumerGroup {
+                        last_id: pending,
+                        ..Default::default()
+                    };
+                    group.pending.insert(
+                        pending,
+                        PendingEntry {
+                            consumer: b"c".to_vec(),
+                            delivery_time: 1700000000000,
+                            delivery_count: 3,
+                        },
+                    );
+                    group.consumers.insert(
+                        b"c".to_vec(),
+                        Consumer {
+                            seen_time: 1700000000001,
+                            active_time: 1700000000000,
+                            pending: [pending].into(),
+                        },
+                    );
+                    group
+                        .consumers
+                        .insert(b"idle".to_vec(), Consumer::default());
+                    stream.groups.insert(b"g".to_vec(), group);
+                    stream
+                }),
+                0,
+            ),
+        ];
+        for (key, value, expired_at) in items {
+            store.insert(key.to_string(), StoreItem { value, expired_at });
+        }
+
+        // the keys are in the third database, the empty ones are left out
+        let data = encode(&[Store::default(), Store::default(), store.clone()]);
+        assert_eq!(b"REDIS0011", &data[..9]);
+        let snapshot = decode(&data, 16).unwrap();
+        assert_eq!(12, snapshot.dbs[2].len());
+        for (key, item) in snapshot.dbs[2].iter() {
+            assert_eq!(store[key].value, item.value);
+            assert_eq!(store[key].expired_at, item.expired_at);
+        }
+        assert_eq!(Some(&b"64".to_vec()), snapshot.aux.get("redis-bits"));
+        assert!(snapshot.dbs[0].is_empty() && snapshot.dbs[1].is_empty());
+        assert_eq!(16, snapshot.into_databases(16).len());
+        assert_eq!(
+            Err(RdbError::DbIndexOutOfRange(2, 53)),
+            decode(&data, 2).map(|_| ())
+        );
+    }
+
+    #[test]
+    fn test_decode_checksum_mismatch() {
+        let mut data = encode(&[Store::default()]);
+        let last = data.len() - 1;
+        data[last] ^= 0xff;
+        assert!(matches!(
+            decode(&data, 16),
+            Err(RdbError::ChecksumMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        assert_eq!(Err(RdbError::InvalidMagic), decode(b"RED", 16).map(|_| ()));
+        assert_eq!(
+            Err(RdbError::InvalidVersion("00x1".to_string())),
+            decode(b"REDIS00x1", 16).map(|_| ())
+        );
+        assert_eq!(
+            Err(RdbError::UnsupportedVersion(99)),
+            decode(b"REDIS0099", 16).map(|_| ())
+        );
+        // the versions before redis 7 may hold ziplists, they are rejected up front
+        assert_eq!(
+            Err(RdbError::UnsupportedVersion(9)),
+            decode(b"REDIS0009", 16).map(|_| ())
+        );
+        assert_eq!(
+            "unsupported rdb version 9, only versions 10 to 12 are supported",
+            RdbError::UnsupportedVersion(9).to_string()
+        );
+        assert_eq!(
+            Err(RdbError::UnexpectedEof(15)),
+            decode(b"REDIS0011\x00\x03foo\x05ba", 16).map(|_| ())
+        );
+        assert_eq!(
+            Err(RdbError::UnsupportedValueType(0x42, 9)),
+            decode(b"REDIS0011\x42\x01k", 16).map(|_| ())
+        );
+        assert_eq!(
+            Err(RdbError::LzfCorrupted(15)),
+            decode(b"REDIS0011\x00\x01k\xc3\x02\x05\xe0\x00", 16).map(|_| ())
+        );
+
+        // lengths out of any proportion to the file are not allocated up front
+        let huge = [0x81, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        let mut body = vec![RDB_OPCODE_RESIZEDB];
+        body.extend_from_slice(&huge);
+        body.push(0x00);
+        assert!(decode(&rdb(&body), 16).is_ok());
+        let mut body = vec![RDB_OPCODE_SELECTDB];
+        body.extend_from_slice(&huge);
+        assert_eq!(
+            Err(RdbError::DbIndexOutOfRange(usize::MAX, 10)),
+            decode(&rdb(&body), 16).map(|_| ())
+        );
+        assert_eq!(
+            Err(RdbError::InvalidKey(9)),
+            decode(&rdb(&[RDB_TYPE_STRING, 0x01, 0xff, 0x01, b'v']), 16).map(|_| ())
+        );
+
+        let mut body = vec![RDB_TYPE_SET, 0x01, b'k'];
+        body.extend_from_slice(&huge);
+        assert!(decode(&rdb(&body), 16).is_err());
+        let mut body = vec![RDB_TYPE_STRING, 0x01, b'k', 0xc3, 0x02];
+        body.extend_from_slice(&huge);
+        body.extend_from_slice(&[0x01, b'a', b'b']);
+        assert_eq!(
+            Err(RdbError::LzfCorrupted(23)),
+            decode(&rdb(&body), 16).map(|_| ())
+        );
+    }
+}
@@ -1,9 +1,15 @@
 use crate::client::ClientChannel;
-use crate::value::RedisValue;
-use std::collections::HashMap;
+use crate::object::{scan, RedisObject};
+use crate::replica::{ReplicaState, Replication};
+use crate::utilities;
+use std::collections::{HashMap, HashSet};
+use std::ops::Index;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
-use tokio::sync::RwLock;
+use tokio::sync::{Notify, RwLock};
 
 #[derive(Debug, StructOpt, Clone)]
 #[structopt(name = "redis")]
@@ -18,6 +24,20 @@ pub struct RedisConfig {
     pub dir: Option<String>,
     #[structopt(long, default_value = "dump.rdb")]
     pub dbfilename: String,
+    #[structopt(long, default_value = "1048576")]
+    pub repl_backlog_size: usize,
+    #[structopt(long, default_value = "yes", parse(try_from_str = parse_yes_no))]
+    pub replica_read_only: bool,
+    #[structopt(long, default_value = "16")]
+    pub databases: usize,
+}
+
+fn parse_yes_no(s: &str) -> Result<bool, String> {
+    match s.to_lowercase().as_str() {
+        "yes" => Ok(true),
+        "no" => Ok(false),
+        _ => Err(format!("argument must be 'yes' or 'no', but got {}", s)),
+    }
 }
 
 impl RedisConfig {
@@ -32,46 +52,304 @@ impl RedisConfig {
         }
     }
 
-    // pub fn dbfile(&self) -> Option<String> {
-    //     if let Some(config_dir) = self.dir {
-    //         let mut path = PathBuf::new();
-    //         path.push(config_dir);
-    //         path.push(self.dbfilename);
-    //         let path = path.to_str().unwrap().to_string();
-    //         Some(path)
-    //     } else {
-    //         None
-    //     }
-    // }
+    pub fn dbfile(&self) -> PathBuf {
+        let mut path = PathBuf::new();
+        path.push(self.dir.clone().unwrap_or(".".to_string()));
+        path.push(&self.dbfilename);
+        path
+    }
+}
+
+// how an expired key is treated when accessed
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ExpiryMode {
+    // a master removes it, its deletion being propagated to the replicas
+    #[default]
+    Delete,
+    // a replica hides it from its clients, until its master deletes it
+    Hide,
+    // the commands of the master apply to it as if it had not expired
+    Keep,
+}
+
+// the keyspace, a map of the keys to their items. the items are kept in a vector, so that a key
+// is picked at random in constant time, and the keys are shared with the map of their slots
+#[derive(Debug, Clone, Default)]
+pub struct Store {
+    slots: HashMap<Arc<str>, usize>,
+    items: Vec<(Arc<str>, StoreItem)>,
+    // the keys given an expiry, visited in turn by the active expiry. a key is only
+    // dropped from the index when visited after it is gone or has lost its expiry
+    expires: Vec<String>,
+    indexed: HashSet<String>,
+    cursor: usize,
+    // the keys removed since they expired, not yet propagated to the replicas
+    expired: Vec<String>,
+    // the items removed by `UNLINK` and asynchronous flushes, with their indexes, to be
+    // dropped in background
+    lazy_free: Vec<Store>,
+}
+
+impl Index<&str> for Store {
+    type Output = StoreItem;
+
+    fn index(&self, key: &str) -> &StoreItem {
+        self.get(key).expect("no item for the key")
+    }
+}
+
+impl Store {
+    pub fn insert(&mut self, key: String, item: StoreItem) -> Option<StoreItem> {
+        if item.expired_at != 0 {
+            self.index_expiry(&key);
+        }
+        match self.slots.get(key.as_str()) {
+            Some(&slot) => Some(std::mem::replace(&mut self.items[slot].1, item)),
+            None => {
+                let key: Arc<str> = key.into();
+                self.slots.insert(key.clone(), self.items.len());
+                self.items.push((key, item));
+                None
+            }
+        }
+    }
+
+    // the item of the key, inserting the one given if there is none
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &str,
+        item: impl FnOnce() -> StoreItem,
+    ) -> &mut StoreItem {
+        if !self.contains_key(key) {
+            self.insert(key.to_string(), item());
+        }
+        self.get_mut(key).unwrap()
+    }
+
+    pub fn reserve(&mut self, additional: usize) {
+        self.slots.reserve(additional);
+        self.items.reserve(additional);
+    }
+
+    pub fn get(&self, key: &str) -> Option<&StoreItem> {
+        let slot = *self.slots.get(key)?;
+        Some(&self.items[slot].1)
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut StoreItem> {
+        let slot = *self.slots.get(key)?;
+        Some(&mut self.items[slot].1)
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.slots.contains_key(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &StoreItem)> {
+        self.items.iter().map(|(key, item)| (key.as_ref(), item))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &str> {
+        self.items.iter().map(|(key, _)| key.as_ref())
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &StoreItem> {
+        self.items.iter().map(|(_, item)| item)
+    }
+
+    // the item which took the slot of the removed one is given its slot
+    pub fn remove(&mut self, key: &str) -> Option<StoreItem> {
+        let slot = self.slots.remove(key)?;
+        let (_, item) = self.items.swap_remove(slot);
+        if let Some((moved, _)) = self.items.get(slot) {
+            self.slots.insert(moved.clone(), slot);
+        }
+        Some(item)
+    }
+
+    // a key picked at random, expired or not
+    pub fn random_key(&self) -> Option<&str> {
+        if self.items.is_empty() {
+            return None;
+        }
+        let slot = utilities::random_u64() % self.items.len() as u64;
+        Some(&self.items[slot as usize].0)
+    }
+
+    // the keys from the cursor on, and the cursor to go on from
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&str>) {
+        scan(self.keys().map(|key| (key.as_bytes(), key)), cursor, count)
+    }
+
+    // set the expiry of an existing key, zero to persist it
+    pub fn set_expiry(&mut self, key: &str, expired_at: u64) {
+        if let Some(item) = self.get_mut(key) {
+            item.expired_at = expired_at;
+            if expired_at != 0 {
+                self.index_expiry(key);
+            }
+        }
+    }
+
+    fn index_expiry(&mut self, key: &str) {
+        if self.indexed.insert(key.to_string()) {
+            self.expires.push(key.to_string());
+        }
+    }
+
+    // an expired key is removed on access by a master, or only hidden by a replica
+    pub fn get_alive(&mut self, key: &str, now: u64, mode: ExpiryMode) -> Option<&mut StoreItem> {
+        if self.get(key).is_some_and(|item| item.is_expired(now)) {
+            match mode {
+                ExpiryMode::Delete => self.remove_expired(key),
+                ExpiryMode::Hide => return None,
+                ExpiryMode::Keep => {}
+            }
+        }
+        self.get_mut(key)
+    }
+
+    pub fn remove_if_empty(&mut self, key: &str) {
+        if self.get(key).is_some_and(|item| item.value.is_empty()) {
+            self.remove(key);
+        }
+    }
+
+    fn remove_expired(&mut self, key: &str) {
+        self.remove(key);
+        self.expired.push(key.to_string());
+    }
+
+    // visit up to `count` keys of the expiry index from where the last visit stopped and
+    // remove the expired ones, returning the numbers of keys visited and removed
+    pub fn expire_keys(&mut self, count: usize, now: u64) -> (usize, usize) {
+        let (mut visited, mut removed) = (0, 0);
+        while visited < count && !self.expires.is_empty() {
+            if self.cursor >= self.expires.len() {
+                self.cursor = 0;
+            }
+            visited += 1;
+            let key = &self.expires[self.cursor];
+            let (stale, expired) = match self.get(key) {
+                Some(item) if item.expired_at != 0 => (false, item.is_expired(now)),
+                _ => (true, false),
+            };
+            if !stale && !expired {
+                self.cursor += 1;
+                continue;
+            }
+            let key = self.expires.swap_remove(self.cursor);
+            self.indexed.remove(&key);
+            if expired {
+                self.remove_expired(&key);
+                removed += 1;
+            }
+        }
+        (visited, removed)
+    }
+
+    pub fn has_expired(&self) -> bool {
+        !self.expired.is_empty()
+    }
+
+    // the keys removed since they expired, since the last call
+    pub fn take_expired(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.expired)
+    }
+
+    // remove every key, returning their items and expiry index as a store of their own
+    pub fn flush(&mut self) -> Store {
+        self.cursor = 0;
+        Store {
+            slots: std::mem::take(&mut self.slots),
+            items: std::mem::take(&mut self.items),
+            expires: std::mem::take(&mut self.expires),
+            indexed: std::mem::take(&mut self.indexed),
+            ..Default::default()
+        }
+    }
+
+    pub fn free_later(&mut self, store: Store) {
+        if !store.is_empty() {
+            self.lazy_free.push(store);
+        }
+    }
+
+    pub fn take_lazy_free(&mut self) -> Vec<Store> {
+        std::mem::take(&mut self.lazy_free)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct StoreItem {
-    pub value: RedisValue,
+    pub value: RedisObject,
     pub expired_at: u64,
 }
 
+impl StoreItem {
+    // the expiry is a unix time in milliseconds, zero for keys that never expire
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expired_at != 0 && self.expired_at < now
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Redis {
     pub config: Arc<RedisConfig>,
 
-    pub store: Arc<RwLock<HashMap<String, StoreItem>>>,
+    // the keyspaces of the databases, selected by their index
+    pub store: Arc<RwLock<Vec<Store>>>,
 
     pub channels: Arc<RwLock<HashMap<String, Arc<RwLock<ClientChannel>>>>>,
-    pub replicas: Arc<RwLock<HashMap<String, usize>>>,
+    pub replicas: Arc<RwLock<HashMap<String, ReplicaState>>>,
+    pub replication: Arc<RwLock<Replication>>,
+    pub master_changed: Arc<Notify>,
+    // notified whenever a replica acknowledges its offset
+    pub replica_acked: Arc<Notify>,
+    // notified whenever entries are added to a stream, for blocked `XREAD`s
+    pub stream_appended: Arc<Notify>,
+
+    // unix time in seconds of the last successful save
+    pub last_save: Arc<AtomicU64>,
+    pub bgsave_in_progress: Arc<AtomicBool>,
 }
 
 impl Redis {
     pub fn new() -> Self {
-        let config = RedisConfig::from_args();
+        Redis::from_config(RedisConfig::from_args())
+    }
+
+    pub fn from_config(config: RedisConfig) -> Self {
+        let databases = config.databases;
+        let replication = Replication::new(config.get_replica_of(), config.repl_backlog_size);
 
         Redis {
             config: Arc::new(config),
 
-            store: Arc::new(RwLock::new(HashMap::new())),
+            store: Arc::new(RwLock::new(vec![Store::default(); databases])),
 
             channels: Arc::new(RwLock::new(HashMap::new())),
             replicas: Arc::new(RwLock::new(HashMap::new())),
+            replication: Arc::new(RwLock::new(replication)),
+            master_changed: Arc::new(Notify::new()),
+            replica_acked: Arc::new(Notify::new()),
+            stream_appended: Arc::new(Notify::new()),
+
+            last_save: Arc::new(AtomicU64::new(
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            )),
+            bgsave_in_progress: Arc::new(AtomicBool::new(false)),
         }
     }
 
@@ -79,3 +357,90 @@ impl Redis {
         format!("{}:{}", self.config.host, self.config.port)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(expired_at: u64) -> StoreItem {
+        StoreItem {
+            value: RedisObject::String(b"v".to_vec()),
+            expired_at,
+        }
+    }
+
+    #[test]
+    fn test_expire_keys() {
+        let mut store = Store::default();
+        for i in 0..10 {
+            store.insert(format!("k{}", i), item(if i < 6 { 100 } else { 300 }));
+        }
+        store.insert("persistent".to_string(), item(0));
+        store.insert("persisted".to_string(), item(100));
+        store.set_expiry("persisted", 0);
+        store.insert("deleted".to_string(), item(100));
+        store.remove("deleted");
+
+        // the stale entries of the index are dropped without being counted as removed
+        assert_eq!((3, 1), store.expire_keys(3, 200));
+        assert_eq!((100, 5), store.expire_keys(100, 200));
+        assert_eq!(6, store.len());
+        let mut expired = store.take_expired();
+        expired.sort();
+        assert_eq!(vec!["k0", "k1", "k2", "k3", "k4", "k5"], expired);
+        assert!(store.take_expired().is_empty());
+
+        assert_eq!((4, 4), store.expire_keys(10, 400));
+        assert_eq!((0, 0), store.expire_keys(10, 400));
+        assert_eq!(2, store.len());
+    }
+
+    #[test]
+    fn test_expiry_modes() {
+        let mut store = Store::default();
+        store.insert("k".to_string(), item(100));
+
+        assert!(store.get_alive("k", 200, ExpiryMode::Keep).is_some());
+        assert!(store.get_alive("k", 200, ExpiryMode::Hide).is_none());
+        assert!(store.contains_key("k"));
+        assert!(store.take_expired().is_empty());
+
+        assert!(store.get_alive("k", 200, ExpiryMode::Delete).is_none());
+        assert!(!store.contains_key("k"));
+        assert_eq!(vec!["k"], store.take_expired());
+    }
+
+    #[test]
+    fn test_flush_takes_the_expiry_index() {
+        let mut store = Store::default();
+        store.insert("k".to_string(), item(100));
+        store.insert("persistent".to_string(), item(0));
+
+        let mut flushed = store.flush();
+        assert!(store.is_empty());
+        assert_eq!((0, 0), store.expire_keys(10, 200));
+        assert_eq!(2, flushed.len());
+        assert_eq!((1, 1), flushed.expire_keys(10, 200));
+    }
+
+    #[test]
+    fn test_remove_keeps_the_slots() {
+        let mut store = Store::default();
+        for i in 0..5 {
+            store.insert(format!("k{}", i), item(0));
+        }
+        assert!(store.remove("k1").is_some());
+        assert!(store.remove("k1").is_none());
+        assert!(store.remove("k4").is_some());
+        let mut keys: Vec<&str> = store.keys().collect();
+        keys.sort();
+        assert_eq!(vec!["k0", "k2", "k3"], keys);
+        for key in keys {
+            assert!(store.get(key).is_some());
+        }
+        assert!(store
+            .random_key()
+            .is_some_and(|key| store.contains_key(key)));
+        assert_eq!(None, Store::default().random_key());
+    }
+}
@@ -1,42 +1,245 @@
+use std::io::ErrorKind;
 use std::sync::Arc;
 
 use crate::command::{RedisCommand, RedisTcpStreamReadExt, RedisTcpStreamWriteExt};
 use crate::parser::RedisValueParser;
+use crate::rdb;
 use crate::redis::Redis;
+use crate::utilities;
 use crate::value::RedisValue;
 use crate::worker::WorkerMessage;
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::{self, Sender};
 use tokio::sync::RwLock;
-use tokio::{spawn, task};
+use tokio::task;
+use tokio::time::{sleep, Duration};
+
+const RECONNECT_MIN_BACKOFF: Duration = Duration::from_millis(100);
+const RECONNECT_MAX_BACKOFF: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub struct ReplicaState {
+    // the offset acknowledged by the replica
+    pub offset: usize,
+    // commands propagated while the replica is still receiving the snapshot
+    pub pending: Option<Vec<RedisValue>>,
+}
+
+impl ReplicaState {
+    pub fn syncing() -> ReplicaState {
+        ReplicaState {
+            offset: 0,
+            pending: Some(Vec::new()),
+        }
+    }
+
+    pub fn online() -> ReplicaState {
+        ReplicaState {
+            offset: 0,
+            pending: None,
+        }
+    }
+}
+
+// a fixed size circular buffer holding the latest bytes of the replication stream
+#[derive(Debug)]
+pub struct ReplicationBacklog {
+    buffer: Vec<u8>,
+    size: usize,
+    // the position where the next byte is written
+    idx: usize,
+    // the number of valid bytes in the buffer
+    histlen: usize,
+}
+
+impl ReplicationBacklog {
+    pub fn new(size: usize) -> ReplicationBacklog {
+        ReplicationBacklog {
+            buffer: Vec::new(),
+            size,
+            idx: 0,
+            histlen: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.histlen
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.idx = 0;
+        self.histlen = 0;
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        if self.size == 0 {
+            return;
+        }
+        // only the last `size` bytes can be held anyway
+        let data = &data[data.len().saturating_sub(self.size)..];
+        for b in data {
+            if self.buffer.len() < self.size {
+                self.buffer.push(*b);
+            } else {
+                self.buffer[self.idx] = *b;
+            }
+            self.idx = (self.idx + 1) % self.size;
+        }
+        self.histlen = (self.histlen + data.len()).min(self.size);
+    }
+
+    // the last `n` bytes fed into the backlog, if they are still held
+    pub fn tail(&self, n: usize) -> Option<Vec<u8>> {
+        if n > self.histlen {
+            return None;
+        }
+        let start = (self.idx + self.size - n) % self.size.max(1);
+        let mut data = Vec::with_capacity(n);
+        for i in 0..n {
+            data.push(self.buffer[(start + i) % self.size]);
+        }
+        Some(data)
+    }
+}
+
+// the stages of the link a replica keeps with its master
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LinkState {
+    Connecting,
+    Handshaking,
+    Syncing,
+    Connected,
+}
+
+#[derive(Debug)]
+pub struct Replication {
+    // the master the node replicates, none for a master node
+    pub master: Option<(String, usize)>,
+    // the id of the replication history the dataset belongs to
+    pub replid: String,
+    // the previous history and the offset up to which the dataset still follows it
+    pub replid2: Option<(String, usize)>,
+    // the number of bytes of the replication stream the dataset went through
+    pub offset: usize,
+    pub backlog: ReplicationBacklog,
+    // whether the replica already synced with a master and may try a partial resync
+    pub resumable: bool,
+    pub link: LinkState,
+    // the unix time in ms when the replica last heard from its master
+    pub last_io: u64,
+    // the database last selected in the replication stream sent to the replicas
+    pub selected_db: Option<usize>,
+    // the database selected by the master in the stream received from it
+    pub master_db: usize,
+}
+
+impl Replication {
+    pub fn new(master: Option<(String, usize)>, backlog_size: usize) -> Replication {
+        Replication {
+            master,
+            replid: utilities::random_id(),
+            replid2: None,
+            offset: 0,
+            backlog: ReplicationBacklog::new(backlog_size),
+            resumable: false,
+            link: LinkState::Connecting,
+            last_io: 0,
+            selected_db: None,
+            master_db: 0,
+        }
+    }
+
+    pub fn feed(&mut self, data: &[u8]) {
+        self.backlog.feed(data);
+        self.offset += data.len();
+    }
+
+    // the stream a replica misses, given the history and the offset it has reached
+    pub fn continue_from(&self, replid: &str, offset: usize) -> Option<Vec<u8>> {
+        let known = match &self.replid2 {
+            Some((replid2, until)) => replid2 == replid && offset <= *until,
+            None => false,
+        };
+        if (replid != self.replid && !known) || offset > self.offset {
+            return None;
+        }
+        self.backlog.tail(self.offset - offset)
+    }
+
+    // go on with a new history from the current offset, the replicas following
+    // the previous one can still continue from it
+    pub fn change_replid(&mut self, replid: String) {
+        let previous = std::mem::replace(&mut self.replid, replid);
+        self.replid2 = Some((previous, self.offset));
+    }
+
+    // start a new history when a replica turns into a master
+    pub fn promote(&mut self) {
+        self.change_replid(utilities::random_id());
+        self.master = None;
+        self.selected_db = None;
+    }
+}
+
+pub struct MasterLinkInfo {
+    pub host: String,
+    pub port: usize,
+    pub link: LinkState,
+    // the time in ms since the last interaction with the master, if any
+    pub last_io_ago: Option<u64>,
+}
 
 pub struct ReplicationInfo {
     pub role: String,
-    pub replica_id: String,
+    pub master: Option<MasterLinkInfo>,
+    pub master_replid: String,
+    pub master_replid2: Option<(String, usize)>,
+    pub master_repl_offset: usize,
 }
 
-impl<'a> Into<RedisValue> for ReplicationInfo {
-    fn into(self) -> RedisValue {
-        let role = format!("role:{}", self.role);
-        let master_replid = format!("master_replid:{}", self.replica_id);
-        let master_repl_offset = format!("master_repl_offset:{}", 0);
-        let content = vec!["# Replication", &role, &master_replid, &master_repl_offset].join("\n");
-        RedisValue::bulk_string(content.as_str())
+impl From<ReplicationInfo> for String {
+    fn from(info: ReplicationInfo) -> String {
+        let mut lines = vec!["# Replication".to_string(), format!("role:{}", info.role)];
+        if let Some(master) = info.master {
+            let link_status = match master.link {
+                LinkState::Connected => "up",
+                _ => "down",
+            };
+            let last_io = master
+                .last_io_ago
+                .map_or("-1".to_string(), |ago| (ago / 1000).to_string());
+            let sync_in_progress = match master.link {
+                LinkState::Syncing => 1,
+                _ => 0,
+            };
+            lines.push(format!("master_host:{}", master.host));
+            lines.push(format!("master_port:{}", master.port));
+            lines.push(format!("master_link_status:{}", link_status));
+            lines.push(format!("master_last_io_seconds_ago:{}", last_io));
+            lines.push(format!("master_sync_in_progress:{}", sync_in_progress));
+        }
+        let (replid2, second_offset) = match info.master_replid2 {
+            Some((replid2, until)) => (replid2, (until + 1).to_string()),
+            None => ("0".repeat(40), "-1".to_string()),
+        };
+        lines.push(format!("master_replid:{}", info.master_replid));
+        lines.push(format!("master_replid2:{}", replid2));
+        lines.push(format!("master_repl_offset:{}", info.master_repl_offset));
+        lines.push(format!("second_repl_offset:{}", second_offset));
+        lines.join("\n")
     }
 }
 
 pub async fn handle_replica_handshake(
     redis: Redis,
+    master_host: &str,
+    master_port: usize,
 ) -> Result<((OwnedReadHalf, OwnedWriteHalf), RedisValueParser), std::io::Error> {
-    let (master_host, master_port) = if let Some(c) = redis.clone().config.get_replica_of() {
-        c
-    } else {
-        panic!();
-    };
-
     let master_url = format!("{}:{}", master_host, master_port);
 
+    redis.replication.write().await.link = LinkState::Connecting;
     let connection = match TcpStream::connect(&master_url).await {
         Ok(c) => c,
         Err(e) => return Err(e),
@@ -46,38 +249,196 @@ pub async fn handle_replica_handshake(
     let mut parser = RedisValueParser::new();
 
     println!("connection to master {} success", master_url);
-    writer.write_command(&RedisCommand::Ping).await.unwrap();
+    redis.replication.write().await.link = LinkState::Handshaking;
+    writer.write_command(&RedisCommand::Ping).await?;
 
-    reader.read_value(&mut parser).await.unwrap();
+    reader.read_value(&mut parser).await?;
 
     writer
         .write_command(&RedisCommand::replconf(
             "listening-port",
             redis.clone().config.port.to_string().as_str(),
         ))
-        .await
-        .unwrap();
+        .await?;
 
-    reader.read_value(&mut parser).await.unwrap();
+    reader.read_value(&mut parser).await?;
 
     writer
         .write_command(&RedisCommand::replconf("capa", "psync2"))
-        .await
-        .unwrap();
+        .await?;
 
-    reader.read_value(&mut parser).await.unwrap();
+    reader.read_value(&mut parser).await?;
 
+    // try to continue from where the dataset is, the master decides whether it's possible
+    let (replid, offset) = {
+        let replication = redis.replication.read().await;
+        if replication.resumable {
+            (
+                replication.replid.clone(),
+                (replication.offset + 1).to_string(),
+            )
+        } else {
+            ("?".to_string(), "-1".to_string())
+        }
+    };
     writer
-        .write_command(&RedisCommand::pasync("?", "-1"))
-        .await
-        .unwrap();
+        .write_command(&RedisCommand::pasync(replid.as_str(), offset.as_str()))
+        .await?;
 
-    reader.read_value(&mut parser).await.unwrap();
+    redis.replication.write().await.link = LinkState::Syncing;
+    let response = match reader.read_value(&mut parser).await? {
+        (Some(RedisValue::SimpleString(s)), _) => s,
+        (v, _) => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected psync response {:?}", v),
+            ))
+        }
+    };
+    let response: Vec<&str> = response.split(' ').collect();
+    match response[..] {
+        ["CONTINUE"] => {
+            // the stream goes on from where it stopped, in the database it had selected
+            println!("[replica] partial resync accepted by master");
+            return Ok(((reader, writer), parser));
+        }
+        ["CONTINUE", master_replid] => {
+            println!("[replica] partial resync accepted by master, new replication id");
+            let mut replication = redis.replication.write().await;
+            if replication.replid != master_replid {
+                replication.change_replid(master_replid.to_string());
+                replication.master_db = 0;
+            }
+            return Ok(((reader, writer), parser));
+        }
+        ["FULLRESYNC", master_replid, master_offset] => {
+            let offset = master_offset.parse().map_err(|_| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("invalid offset in psync response {:?}", response),
+                )
+            })?;
+            let mut replication = redis.replication.write().await;
+            replication.replid = master_replid.to_string();
+            replication.offset = offset;
+            replication.master_db = 0;
+            // the history held so far does not lead to the offset of the master
+            replication.backlog.clear();
+            replication.replid2 = None;
+            replication.resumable = true;
+            println!(
+                "[replica] full resync from master, {} {}",
+                master_replid, master_offset
+            );
+        }
+        _ => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("unexpected psync response {:?}", response),
+            ))
+        }
+    }
+
+    // replace the local dataset with the snapshot of the master
+    let snapshot = match reader.read_rdb(&mut parser).await? {
+        (Some(RedisValue::Rdb(data)), _) => rdb::decode(&data, redis.config.databases)
+            .map(|snapshot| snapshot.into_databases(redis.config.databases))
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?,
+        (v, _) => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("expect a rdb from master, but got {:?}", v),
+            ))
+        }
+    };
+    {
+        let mut store = redis.store.write().await;
+        *store = snapshot;
+        println!(
+            "[replica] loaded {} keys from master snapshot",
+            store.iter().map(|db| db.len()).sum::<usize>()
+        );
+    }
 
-    reader.read_rdb(&mut parser).await.unwrap();
     Ok(((reader, writer), parser))
 }
 
+// keep the replica linked to its master, reconnecting with an exponential backoff
+pub async fn supervise_master_link(redis: Redis, worker_sender: Sender<WorkerMessage>) {
+    let mut backoff = RECONNECT_MIN_BACKOFF;
+
+    loop {
+        let master = redis.replication.read().await.master.clone();
+        let (master_host, master_port) = match master {
+            Some(master) => master,
+            None => {
+                // a master node, wait until it is turned into a replica
+                redis.master_changed.notified().await;
+                backoff = RECONNECT_MIN_BACKOFF;
+                continue;
+            }
+        };
+
+        tokio::select! {
+            result = link_with_master(redis.clone(), &master_host, master_port, worker_sender.clone()) => {
+                if let Err(e) = result {
+                    println!(
+                        "[replica] link with {}:{} lost: {:?}",
+                        master_host, master_port, e
+                    );
+                }
+            }
+            _ = redis.master_changed.notified() => {
+                println!("[replica] master changed, drop the link with {}:{}", master_host, master_port);
+                backoff = RECONNECT_MIN_BACKOFF;
+                continue;
+            }
+        }
+
+        {
+            let mut replication = redis.replication.write().await;
+            if replication.link == LinkState::Connected {
+                backoff = RECONNECT_MIN_BACKOFF;
+            }
+            replication.link = LinkState::Connecting;
+        }
+        println!("[replica] reconnect to master in {:?}", backoff);
+        tokio::select! {
+            _ = sleep(backoff) => backoff = (backoff * 2).min(RECONNECT_MAX_BACKOFF),
+            _ = redis.master_changed.notified() => backoff = RECONNECT_MIN_BACKOFF,
+        }
+    }
+}
+
+async fn link_with_master(
+    redis: Redis,
+    master_host: &str,
+    master_port: usize,
+    worker_sender: Sender<WorkerMessage>,
+) -> Result<(), std::io::Error> {
+    let (connection, parser) =
+        match handle_replica_handshake(redis.clone(), master_host, master_port).await {
+            Ok(v) => v,
+            Err(e) => {
+                println!(
+                    "[replica] handshake with {}:{} failed",
+                    master_host, master_port
+                );
+                return Err(e);
+            }
+        };
+    println!(
+        "[replica] handshake with {}:{} success, start to listen to master",
+        master_host, master_port
+    );
+    {
+        let mut replication = redis.replication.write().await;
+        replication.link = LinkState::Connected;
+        replication.last_io = utilities::now();
+    }
+    listen_to_master_progate(redis, connection, parser, worker_sender).await
+}
+
 // read the command from master node and send them to the worker node
 pub async fn listen_to_master_progate(
     redis: Redis,
@@ -88,23 +449,36 @@ pub async fn listen_to_master_progate(
     println!("[replica progate] start to listen to master node");
     let (mut reader, mut writer) = connection;
     let (sender, mut receiver) = mpsc::channel::<RedisValue>(128);
-    let mut offset: usize = 0;
 
     task::spawn(async move {
         println!("[replica] replica has a responser, try to receive");
-        loop {
-            if let Some(response) = receiver.recv().await {
-                writer.write_value(&response).await.unwrap();
-            } else {
+        while let Some(response) = receiver.recv().await {
+            if let Err(e) = writer.write_value(&response).await {
+                println!("[replica] unable to respond to master: {:?}", e);
                 break;
             }
         }
     });
 
     loop {
-        let (command, length) = match reader.read_command(&mut parser).await {
-            Ok(command) => command,
-            Err(e) => return Err(e),
+        let (value, length) = match reader.read_value(&mut parser).await? {
+            (Some(value), length) => (value, length),
+            (None, _) => continue,
+        };
+        // the stream is kept in the backlog as it was received, so that the replicas of this
+        // node can continue from it once it is promoted, and the offset matches the master's
+        let bytes = parser.raw().to_vec();
+        let command: Option<RedisCommand> = match value.try_into() {
+            Ok(command) => Some(command),
+            Err(e) => {
+                println!("[replica] ignore invalid command from master: {:?}", e);
+                None
+            }
+        };
+        let offset = {
+            let mut replication = redis.replication.write().await;
+            replication.last_io = utilities::now();
+            replication.offset
         };
         println!(
             "[replica] receive a progate commmand ({}) from master, offset: {}: {:?}",
@@ -126,16 +500,194 @@ pub async fn listen_to_master_progate(
             };
 
             let message = WorkerMessage {
-                command: command.clone(),
+                command: Ok(command.clone()),
                 client_id: None,
                 responser: responser.clone().map(|r| Arc::new(RwLock::new(r))),
                 offset,
+                db: None,
             };
             worker_sender.send(message).await.unwrap();
             println!("[replica] send command to replica worker: {:?}", command);
         }
 
-        offset += length;
+        redis.replication.write().await.feed(&bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redis::RedisConfig;
+    use structopt::StructOpt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_backlog_wraps_around() {
+        let mut backlog = ReplicationBacklog::new(8);
+        backlog.feed(b"abcde");
+        assert_eq!(5, backlog.len());
+        assert_eq!(Some(b"cde".to_vec()), backlog.tail(3));
+        assert_eq!(None, backlog.tail(6));
+
+        backlog.feed(b"fghij");
+        assert_eq!(8, backlog.len());
+        assert_eq!(Some(b"cdefghij".to_vec()), backlog.tail(8));
+        assert_eq!(Some(b"hij".to_vec()), backlog.tail(3));
+        assert_eq!(Some(vec![]), backlog.tail(0));
+
+        backlog.feed(b"0123456789");
+        assert_eq!(Some(b"23456789".to_vec()), backlog.tail(8));
+    }
+
+    #[test]
+    fn test_replication_continue_from() {
+        let mut replication = Replication::new(None, 4);
+        replication.feed(b"abcdef");
+        let replid = replication.replid.clone();
+        assert_eq!(6, replication.offset);
+
+        assert_eq!(Some(b"ef".to_vec()), replication.continue_from(&replid, 4));
+        assert_eq!(Some(vec![]), replication.continue_from(&replid, 6));
+        // no longer in the backlog
+        assert_eq!(None, replication.continue_from(&replid, 1));
+        // ahead of the master
+        assert_eq!(None, replication.continue_from(&replid, 7));
+        assert_eq!(None, replication.continue_from("?", 4));
+    }
+
+    #[test]
+    fn test_replication_promote() {
+        let mut replication = Replication::new(Some(("localhost".to_string(), 6379)), 16);
+        replication.feed(b"abcdef");
+        let replid = replication.replid.clone();
+
+        replication.promote();
+        assert_eq!(None, replication.master);
+        assert_ne!(replid, replication.replid);
+        replication.feed(b"gh");
+
+        // replicas of the former master continue with the new history
+        assert_eq!(
+            Some(b"efgh".to_vec()),
+            replication.continue_from(&replid, 4)
+        );
+        assert_eq!(None, replication.continue_from(&replid, 7));
+        let new_replid = replication.replid.clone();
+        assert_eq!(
+            Some(b"h".to_vec()),
+            replication.continue_from(&new_replid, 7)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_promoted_replica_continues_master_stream() {
+        let config = RedisConfig::from_iter(["redis", "--replicaof", "localhost", "6379"]);
+        let redis = Redis::from_config(config);
+        let master_replid = "8371b4fb1155b71f4a04d3e1bc3e18c4a990aeeb".to_string();
+        redis.replication.write().await.replid = master_replid.clone();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let link = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (mut master, _) = listener.accept().await.unwrap();
+        let (worker_sender, mut worker_receiver) = mpsc::channel(8);
+        task::spawn(listen_to_master_progate(
+            redis.clone(),
+            link.into_split(),
+            RedisValueParser::new(),
+            worker_sender,
+        ));
+
+        let first = b"*3\r\n$3\r\nSET\r\n$1\r\na\r\n$1\r\n1\r\n";
+        // the offset follows the bytes sent by the master, however they encode the command
+        let second = b"*2\r\n$04\r\nINCR\r\n$1\r\na\r\n";
+        master.write_all(first).await.unwrap();
+        master.write_all(second).await.unwrap();
+        for _ in 0..2 {
+            worker_receiver.recv().await.unwrap();
+        }
+        while redis.replication.read().await.offset < first.len() + second.len() {
+            sleep(Duration::from_millis(10)).await;
+        }
+
+        // the replicas of the promoted node get the stream of the former master
+        let mut replication = redis.replication.write().await;
+        replication.promote();
+        assert_eq!(
+            Some(second.to_vec()),
+            replication.continue_from(&master_replid, first.len())
+        );
+        assert_eq!(None, replication.continue_from("?", first.len()));
+    }
+
+    #[tokio::test]
+    async fn test_full_resync_resets_master_db() {
+        let config = RedisConfig::from_iter(["redis", "--replicaof", "localhost", "6379"]);
+        let redis = Redis::from_config(config);
+        redis.replication.write().await.master_db = 3;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port() as usize;
+        let handshake = task::spawn(handle_replica_handshake(redis.clone(), "127.0.0.1", port));
+        let (master, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = master.into_split();
+        let mut parser = RedisValueParser::new();
+        for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+            reader.read_value(&mut parser).await.unwrap();
+            writer.write_all(reply.as_bytes()).await.unwrap();
+        }
+        reader.read_value(&mut parser).await.unwrap();
+        let data = rdb::encode(&[]);
+        let reply = format!("+FULLRESYNC {} 42\r\n${}\r\n", "a".repeat(40), data.len());
+        let mut reply = reply.into_bytes();
+        reply.extend_from_slice(&data);
+        writer.write_all(&reply).await.unwrap();
+        handshake.await.unwrap().unwrap();
+
+        // the stream of the new history starts in the first database
+        let replication = redis.replication.read().await;
+        assert_eq!(42, replication.offset);
+        assert_eq!(0, replication.master_db);
+    }
+
+    #[tokio::test]
+    async fn test_continue_with_new_replid_keeps_previous_history() {
+        let config = RedisConfig::from_iter(["redis", "--replicaof", "localhost", "6379"]);
+        let redis = Redis::from_config(config);
+        let replid = {
+            let mut replication = redis.replication.write().await;
+            replication.feed(b"abcdef");
+            replication.resumable = true;
+            replication.replid.clone()
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port() as usize;
+        let handshake = task::spawn(handle_replica_handshake(redis.clone(), "127.0.0.1", port));
+        let (master, _) = listener.accept().await.unwrap();
+        let (mut reader, mut writer) = master.into_split();
+        let mut parser = RedisValueParser::new();
+        for reply in ["+PONG\r\n", "+OK\r\n", "+OK\r\n"] {
+            reader.read_value(&mut parser).await.unwrap();
+            writer.write_all(reply.as_bytes()).await.unwrap();
+        }
+        reader.read_value(&mut parser).await.unwrap();
+        let new_replid = "b".repeat(40);
+        let reply = format!("+CONTINUE {}\r\n", new_replid);
+        writer.write_all(reply.as_bytes()).await.unwrap();
+        handshake.await.unwrap().unwrap();
+
+        // the replicas chained behind the node still continue with the previous history
+        let mut replication = redis.replication.write().await;
+        assert_eq!(new_replid, replication.replid);
+        assert_eq!(Some((replid.clone(), 6)), replication.replid2);
+        replication.feed(b"gh");
+        assert_eq!(
+            Some(b"efgh".to_vec()),
+            replication.continue_from(&replid, 4)
+        );
+        assert_eq!(None, replication.continue_from(&replid, 7));
     }
-    println!("[replica progate] progation done");
 }
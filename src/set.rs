@@ -0,0 +1,652 @@
+use std::collections::HashSet;
+
+use crate::command::{CommandArgs, RedisCommandError, ScanOptions};
+use crate::object::{scan, wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+
+// a set of integers is kept sorted in a vector up to this size, like `set-max-intset-entries`
+const MAX_INTSET_ENTRIES: usize = 512;
+
+// the members picked by `SRANDMEMBER` are all held in its reply, so a count repeating
+// them is bounded
+const RAND_COUNT_MAX: i64 = 1 << 20;
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RedisSet {
+    // the compact encoding of a set whose members are all integers
+    IntSet(Vec<i64>),
+    HashSet(HashSet<Vec<u8>>),
+}
+
+// the integer a member stands for, only if it is written in its canonical form
+fn as_integer(member: &[u8]) -> Option<i64> {
+    std::str::from_utf8(member)
+        .ok()
+        .and_then(|m| m.parse::<i64>().ok())
+        .filter(|i| i.to_string().as_bytes() == member)
+}
+
+impl RedisSet {
+    pub fn new() -> RedisSet {
+        RedisSet::IntSet(Vec::new())
+    }
+
+    pub fn from_members(members: impl IntoIterator<Item = Vec<u8>>) -> RedisSet {
+        let mut set = RedisSet::new();
+        for member in members {
+            set.insert(member);
+        }
+        set
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            RedisSet::IntSet(ints) => ints.len(),
+            RedisSet::HashSet(set) => set.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        match self {
+            RedisSet::IntSet(ints) => {
+                as_integer(member).is_some_and(|i| ints.binary_search(&i).is_ok())
+            }
+            RedisSet::HashSet(set) => set.contains(member),
+        }
+    }
+
+    pub fn insert(&mut self, member: Vec<u8>) -> bool {
+        if let RedisSet::IntSet(ints) = self {
+            if let Some(i) = as_integer(&member) {
+                let position = match ints.binary_search(&i) {
+                    Ok(_) => return false,
+                    Err(position) => position,
+                };
+                ints.insert(position, i);
+                if ints.len() > MAX_INTSET_ENTRIES {
+                    self.convert_to_hash_set();
+                }
+                return true;
+            }
+        }
+        self.convert_to_hash_set().insert(member)
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self {
+            RedisSet::IntSet(ints) => {
+                match as_integer(member).and_then(|i| ints.binary_search(&i).ok()) {
+                    Some(position) => {
+                        ints.remove(position);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            RedisSet::HashSet(set) => set.remove(member),
+        }
+    }
+
+    pub fn members(&self) -> Vec<Vec<u8>> {
+        match self {
+            RedisSet::IntSet(ints) => ints.iter().map(|i| i.to_string().into_bytes()).collect(),
+            RedisSet::HashSet(set) => set.iter().cloned().collect(),
+        }
+    }
+
+    // the members from the cursor on, and the cursor to go on from. like redis, the
+    // members of an intset are all returned at once
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Vec<u8>>) {
+        match self {
+            RedisSet::IntSet(_) => (0, self.members()),
+            RedisSet::HashSet(set) => {
+                let (cursor, members) = scan(
+                    set.iter().map(|member| (member.as_slice(), member)),
+                    cursor,
+                    count,
+                );
+                (cursor, members.into_iter().cloned().collect())
+            }
+        }
+    }
+
+    // switch to the hash table encoding, a set never goes back to an intset
+    fn convert_to_hash_set(&mut self) -> &mut HashSet<Vec<u8>> {
+        if let RedisSet::IntSet(_) = self {
+            *self = RedisSet::HashSet(self.members().into_iter().collect());
+        }
+        match self {
+            RedisSet::HashSet(set) => set,
+            RedisSet::IntSet(_) => unreachable!(),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+impl SetOperation {
+    fn name(&self) -> &'static str {
+        match self {
+            SetOperation::Inter => "sinter",
+            SetOperation::Union => "sunion",
+            SetOperation::Diff => "sdiff",
+        }
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum SetCommand {
+    Add(RedisBulkString, Vec<RedisBulkString>),
+    Rem(RedisBulkString, Vec<RedisBulkString>),
+    Members(RedisBulkString),
+    IsMember(RedisBulkString, RedisBulkString),
+    MIsMember(RedisBulkString, Vec<RedisBulkString>),
+    Card(RedisBulkString),
+    Combine(SetOperation, Vec<RedisBulkString>),
+    // the destination, then the keys to combine
+    Store(SetOperation, RedisBulkString, Vec<RedisBulkString>),
+    // the count is only given in the `SPOP key count` form, which replies with an array
+    Pop(RedisBulkString, Option<usize>),
+    // a negative count may return the same member several times
+    RandMember(RedisBulkString, Option<i64>),
+    Scan(RedisBulkString, u64, ScanOptions),
+}
+
+impl SetCommand {
+    pub fn parse(command_name: &str, args: &[RedisValue]) -> Result<SetCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let key = args.next_key()?;
+        let operation = match command_name.trim_end_matches("store") {
+            "sinter" => Some(SetOperation::Inter),
+            "sunion" => Some(SetOperation::Union),
+            "sdiff" => Some(SetOperation::Diff),
+            _ => None,
+        };
+        let command = match (command_name, operation) {
+            ("sadd", _) => SetCommand::Add(key, args.rest()?),
+            ("srem", _) => SetCommand::Rem(key, args.rest()?),
+            ("smembers", _) => SetCommand::Members(key),
+            ("sismember", _) => SetCommand::IsMember(key, args.next_bulk_string()?),
+            ("smismember", _) => SetCommand::MIsMember(key, args.rest()?),
+            ("scard", _) => SetCommand::Card(key),
+            ("sinterstore" | "sunionstore" | "sdiffstore", Some(operation)) => {
+                SetCommand::Store(operation, key, args.rest_keys()?)
+            }
+            (_, Some(operation)) => {
+                let mut keys = vec![key];
+                while !args.is_empty() {
+                    keys.push(args.next_key()?);
+                }
+                SetCommand::Combine(operation, keys)
+            }
+            ("spop", _) => {
+                let count = if args.is_empty() {
+                    None
+                } else {
+                    let count: i64 = args.next_integer()?;
+                    if count < 0 {
+                        return Err(RedisCommandError::NotPositive);
+                    }
+                    Some(count as usize)
+                };
+                SetCommand::Pop(key, count)
+            }
+            ("srandmember", _) => {
+                let count = if args.is_empty() {
+                    None
+                } else {
+                    let count: i64 = args.next_integer()?;
+                    if !(-RAND_COUNT_MAX..=RAND_COUNT_MAX).contains(&count) {
+                        return Err(RedisCommandError::Invalid(format!(
+                            "value is out of range, value must between {} and {}",
+                            -RAND_COUNT_MAX, RAND_COUNT_MAX
+                        )));
+                    }
+                    Some(count)
+                };
+                SetCommand::RandMember(key, count)
+            }
+            ("sscan", _) => {
+                let cursor = args.next_cursor()?;
+                SetCommand::Scan(key, cursor, args.next_scan_options(&[])?)
+            }
+            (name, _) => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    // the key written by the command, or the first one it reads
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            SetCommand::Add(key, _)
+            | SetCommand::Rem(key, _)
+            | SetCommand::Members(key)
+            | SetCommand::IsMember(key, _)
+            | SetCommand::MIsMember(key, _)
+            | SetCommand::Card(key)
+            | SetCommand::Store(_, key, _)
+            | SetCommand::Pop(key, _)
+            | SetCommand::RandMember(key, _)
+            | SetCommand::Scan(key, _, _) => key,
+            SetCommand::Combine(_, keys) => &keys[0],
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            SetCommand::Add(..) | SetCommand::Rem(..) | SetCommand::Store(..) | SetCommand::Pop(..)
+        )
+    }
+}
+
+impl From<&SetCommand> for Vec<RedisValue> {
+    fn from(set_command: &SetCommand) -> Vec<RedisValue> {
+        let command = |name: &str, key: &RedisBulkString, members: &[RedisBulkString]| {
+            let mut vs = vec![RedisValue::bulk_string(name), key.into()];
+            vs.extend(members.iter().map(|m| m.into()));
+            vs
+        };
+        let integer = |name: &str, key: &RedisBulkString, count: Option<String>| {
+            let mut vs = vec![RedisValue::bulk_string(name), key.into()];
+            if let Some(count) = count {
+                vs.push(RedisValue::bulk_string(count.as_str()));
+            }
+            vs
+        };
+        match set_command {
+            SetCommand::Add(key, members) => command("sadd", key, members),
+            SetCommand::Rem(key, members) => command("srem", key, members),
+            SetCommand::Members(key) => command("smembers", key, &[]),
+            SetCommand::IsMember(key, member) => {
+                command("sismember", key, std::slice::from_ref(member))
+            }
+            SetCommand::MIsMember(key, members) => command("smismember", key, members),
+            SetCommand::Card(key) => command("scard", key, &[]),
+            SetCommand::Combine(operation, keys) => command(operation.name(), &keys[0], &keys[1..]),
+            SetCommand::Store(operation, destination, keys) => {
+                command(&format!("{}store", operation.name()), destination, keys)
+            }
+            SetCommand::Pop(key, count) => integer("spop", key, count.map(|c| c.to_string())),
+            SetCommand::RandMember(key, count) => {
+                integer("srandmember", key, count.map(|c| c.to_string()))
+            }
+            SetCommand::Scan(key, cursor, options) => {
+                let mut vs = integer("sscan", key, Some(cursor.to_string()));
+                vs.extend(options.to_args());
+                vs
+            }
+        }
+    }
+}
+
+// the command replicas replay, the members popped at random are removed explicitly,
+// none if nothing has changed
+pub fn replicated(command: &SetCommand, response: &RedisValue) -> Option<SetCommand> {
+    let key = match command {
+        SetCommand::Pop(key, _) => key,
+        command => return Some(command.clone()),
+    };
+    let popped = match response {
+        RedisValue::BulkString(Some(member)) => vec![member.clone()],
+        RedisValue::Array(members) => members
+            .iter()
+            .filter_map(|member| match member {
+                RedisValue::BulkString(Some(member)) => Some(member.clone()),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    if popped.is_empty() {
+        None
+    } else {
+        Some(SetCommand::Rem(key.clone(), popped))
+    }
+}
+
+// the set stored at the key, none if the key does not exist
+fn get_set<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut RedisSet>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::Set(set),
+            ..
+        }) => Ok(Some(set)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+// the members resulting from the operation over the sets of the keys, a missing key being empty
+fn combine(
+    store: &mut Store,
+    operation: SetOperation,
+    keys: &[RedisBulkString],
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Vec<Vec<u8>>, RedisValue> {
+    let mut sets = Vec::with_capacity(keys.len());
+    for key in keys {
+        let key: String = key.into();
+        let set = get_set(store, &key, now, mode)?;
+        sets.push(set.map(|set| set.members()).unwrap_or_default());
+    }
+    let mut sets = sets.into_iter();
+    let first = sets.next().unwrap_or_default();
+    let members = match operation {
+        SetOperation::Inter => {
+            let others: Vec<HashSet<Vec<u8>>> = sets.map(|set| set.into_iter().collect()).collect();
+            first
+                .into_iter()
+                .filter(|member| others.iter().all(|set| set.contains(member)))
+                .collect()
+        }
+        SetOperation::Union => {
+            let mut union: HashSet<Vec<u8>> = first.into_iter().collect();
+            sets.for_each(|set| union.extend(set));
+            union.into_iter().collect()
+        }
+        SetOperation::Diff => {
+            let others: HashSet<Vec<u8>> = sets.flatten().collect();
+            first
+                .into_iter()
+                .filter(|member| !others.contains(member))
+                .collect()
+        }
+    };
+    Ok(members)
+}
+
+// up to `count` distinct members picked at random
+fn pick_distinct(mut members: Vec<Vec<u8>>, count: usize) -> Vec<Vec<u8>> {
+    let count = count.min(members.len());
+    for i in 0..count {
+        let j = i + (utilities::random_u64() % (members.len() - i) as u64) as usize;
+        members.swap(i, j);
+    }
+    members.truncate(count);
+    members
+}
+
+fn bulk_strings(members: Vec<Vec<u8>>) -> RedisValue {
+    RedisValue::Array(
+        members
+            .iter()
+            .map(|member| RedisValue::bulk_string_from_bytes(member.as_slice()))
+            .collect(),
+    )
+}
+
+pub fn execute(store: &mut Store, command: &SetCommand, now: u64, mode: ExpiryMode) -> RedisValue {
+    let key: String = command.key().into();
+    let response = match run(store, command, &key, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    };
+    store.remove_if_empty(&key);
+    response
+}
+
+fn run(
+    store: &mut Store,
+    command: &SetCommand,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        SetCommand::Add(_, members) => {
+            get_set(store, key, now, mode)?;
+            let item = store.get_or_insert_with(key, || StoreItem {
+                value: RedisObject::Set(RedisSet::new()),
+                expired_at: 0,
+            });
+            let set = match &mut item.value {
+                RedisObject::Set(set) => set,
+                _ => return Err(wrong_type()),
+            };
+            let added = members
+                .iter()
+                .filter(|member| set.insert(member.data.clone()))
+                .count();
+            RedisValue::Integer(added as i64)
+        }
+        SetCommand::Rem(_, members) => {
+            let removed = match get_set(store, key, now, mode)? {
+                Some(set) => members
+                    .iter()
+                    .filter(|member| set.remove(&member.data))
+                    .count(),
+                None => 0,
+            };
+            RedisValue::Integer(removed as i64)
+        }
+        SetCommand::Members(_) => {
+            let set = get_set(store, key, now, mode)?;
+            bulk_strings(set.map(|set| set.members()).unwrap_or_default())
+        }
+        SetCommand::IsMember(_, member) => {
+            let set = get_set(store, key, now, mode)?;
+            RedisValue::Integer(set.is_some_and(|set| set.contains(&member.data)) as i64)
+        }
+        SetCommand::MIsMember(_, members) => {
+            let set = get_set(store, key, now, mode)?;
+            let flags = members
+                .iter()
+                .map(|member| {
+                    let found = set.as_ref().is_some_and(|set| set.contains(&member.data));
+                    RedisValue::Integer(found as i64)
+                })
+                .collect();
+            RedisValue::Array(flags)
+        }
+        SetCommand::Card(_) => {
+            let length = get_set(store, key, now, mode)?.map_or(0, |set| set.len());
+            RedisValue::Integer(length as i64)
+        }
+        SetCommand::Combine(operation, keys) => {
+            bulk_strings(combine(store, *operation, keys, now, mode)?)
+        }
+        SetCommand::Store(operation, _, keys) => {
+            let members = combine(store, *operation, keys, now, mode)?;
+            let length = members.len();
+            // an empty result deletes the destination once the command is done
+            store.insert(
+                key.to_string(),
+                StoreItem {
+                    value: RedisObject::Set(RedisSet::from_members(members)),
+                    expired_at: 0,
+                },
+            );
+            RedisValue::Integer(length as i64)
+        }
+        SetCommand::Pop(_, count) => {
+            let set = match get_set(store, key, now, mode)? {
+                Some(set) => set,
+                None if count.is_some() => return Ok(RedisValue::Array(vec![])),
+                None => return Ok(RedisValue::null_bulk_string()),
+            };
+            let popped = pick_distinct(set.members(), count.unwrap_or(1));
+            for member in popped.iter() {
+                set.remove(member);
+            }
+            match count {
+                Some(_) => bulk_strings(popped),
+                None => RedisValue::bulk_string_from_bytes(popped[0].as_slice()),
+            }
+        }
+        SetCommand::RandMember(_, count) => {
+            let members = match get_set(store, key, now, mode)? {
+                Some(set) => set.members(),
+                None if count.is_some() => return Ok(RedisValue::Array(vec![])),
+                None => return Ok(RedisValue::null_bulk_string()),
+            };
+            match count {
+                None => {
+                    let picked = pick_distinct(members, 1);
+                    RedisValue::bulk_string_from_bytes(picked[0].as_slice())
+                }
+                Some(count) if *count >= 0 => bulk_strings(pick_distinct(members, *count as usize)),
+                Some(count) => {
+                    let picked = (0..count.unsigned_abs())
+                        .map(|_| {
+                            let i = utilities::random_u64() % members.len() as u64;
+                            members[i as usize].clone()
+                        })
+                        .collect();
+                    bulk_strings(picked)
+                }
+            }
+        }
+        SetCommand::Scan(_, cursor, options) => {
+            let (cursor, members) = get_set(store, key, now, mode)?
+                .map_or((0, vec![]), |set| set.scan(*cursor, options.count));
+            let members = members
+                .into_iter()
+                .filter(|member| options.matches(member))
+                .map(|member| RedisValue::bulk_string_from_bytes(member.as_slice()))
+                .collect();
+            RedisValue::Array(vec![
+                RedisValue::bulk_string(cursor.to_string().as_str()),
+                RedisValue::Array(members),
+            ])
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    fn sorted(value: RedisValue) -> Vec<String> {
+        let mut members: Vec<String> = match value {
+            RedisValue::Array(members) => members
+                .iter()
+                .map(|member| match member {
+                    RedisValue::BulkString(Some(member)) => member.into(),
+                    _ => panic!("{:?} is not a member", member),
+                })
+                .collect(),
+            value => panic!("{:?} is not an array", value),
+        };
+        members.sort();
+        members
+    }
+
+    #[test]
+    fn test_intset_encoding() {
+        let mut set = RedisSet::from_members([b"3".to_vec(), b"-1".to_vec(), b"3".to_vec()]);
+        assert_eq!(RedisSet::IntSet(vec![-1, 3]), set);
+        assert!(set.contains(b"3"));
+        assert!(!set.contains(b"03"));
+        // a member which is not a canonical integer converts the encoding
+        assert!(set.insert(b"03".to_vec()));
+        assert!(matches!(set, RedisSet::HashSet(_)));
+        assert_eq!(3, set.len());
+
+        let set =
+            RedisSet::from_members((0..=MAX_INTSET_ENTRIES).map(|i| i.to_string().into_bytes()));
+        assert!(matches!(set, RedisSet::HashSet(_)));
+    }
+
+    #[test]
+    fn test_add_rem() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["sadd", "s", "a", "b", "a"])
+        );
+        assert_eq!(
+            RedisValue::Array(vec![RedisValue::Integer(1), RedisValue::Integer(0)]),
+            run_command(&mut store, &["smismember", "s", "b", "c"])
+        );
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["srem", "s", "a", "b", "c"])
+        );
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_algebra() {
+        let mut store = Store::default();
+        run_command(&mut store, &["sadd", "a", "1", "2", "3", "x"]);
+        run_command(&mut store, &["sadd", "b", "2", "3", "4"]);
+        assert_eq!(
+            vec!["2", "3"],
+            sorted(run_command(&mut store, &["sinter", "a", "b"]))
+        );
+        assert_eq!(
+            vec!["1", "2", "3", "4", "x"],
+            sorted(run_command(&mut store, &["sunion", "a", "b", "missing"]))
+        );
+        assert_eq!(
+            vec!["1", "x"],
+            sorted(run_command(&mut store, &["sdiff", "a", "b"]))
+        );
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["sinterstore", "c", "a", "b"])
+        );
+        assert_eq!(
+            Some(&RedisObject::Set(RedisSet::IntSet(vec![2, 3]))),
+            store.get("c").map(|item| &item.value)
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["sinterstore", "c", "a", "missing"])
+        );
+        assert!(!store.contains_key("c"));
+    }
+
+    #[test]
+    fn test_pop_and_random_members() {
+        let mut store = Store::default();
+        run_command(&mut store, &["sadd", "s", "a", "b", "c"]);
+        assert_eq!(
+            3,
+            sorted(run_command(&mut store, &["srandmember", "s", "10"])).len()
+        );
+        assert_eq!(
+            5,
+            sorted(run_command(&mut store, &["srandmember", "s", "-5"])).len()
+        );
+        let args = ["s", "-9223372036854775807"].map(RedisValue::bulk_string);
+        let error = SetCommand::parse("srandmember", &args).unwrap_err();
+        assert_eq!(
+            RedisValue::error("ERR value is out of range, value must between -1048576 and 1048576"),
+            (&error).into()
+        );
+        let popped = sorted(run_command(&mut store, &["spop", "s", "2"]));
+        assert_eq!(2, popped.len());
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["scard", "s"])
+        );
+        run_command(&mut store, &["spop", "s"]);
+        assert!(store.is_empty());
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command(&mut store, &["spop", "s"])
+        );
+    }
+}
@@ -0,0 +1,339 @@
+use crate::utilities;
+
+const MAX_LEVEL: usize = 32;
+// the header is always the first node, its member and score are never read
+const HEAD: usize = 0;
+
+#[derive(Debug, Clone, Copy)]
+struct Level {
+    forward: Option<usize>,
+    // the number of nodes the forward link moves past, used to compute ranks
+    span: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Node {
+    member: Vec<u8>,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Level>,
+}
+
+impl Node {
+    // whether the node is ordered before the element, by score and then by member
+    fn is_before(&self, score: f64, member: &[u8]) -> bool {
+        self.score < score || (self.score == score && self.member.as_slice() < member)
+    }
+
+    fn is(&self, score: f64, member: &[u8]) -> bool {
+        self.score == score && self.member == member
+    }
+}
+
+// the ordered index of a sorted set, as in redis the nodes carry the span of their links
+// so that the rank of an element is found along with it in logarithmic time.
+// nodes live in a vector and link to each other by their position in it
+#[derive(Debug, Clone)]
+pub struct SkipList {
+    nodes: Vec<Node>,
+    // positions of removed nodes, reused by the next insertions
+    free: Vec<usize>,
+    level: usize,
+    length: usize,
+}
+
+// every level above the first one is kept with a probability of 1/4
+fn random_level() -> usize {
+    let mut bits = utilities::random_u64();
+    let mut level = 1;
+    while level < MAX_LEVEL && bits & 3 == 0 {
+        level += 1;
+        bits >>= 2;
+    }
+    level
+}
+
+impl SkipList {
+    pub fn new() -> SkipList {
+        let head = Node {
+            member: Vec::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                MAX_LEVEL
+            ],
+        };
+        SkipList {
+            nodes: vec![head],
+            free: Vec::new(),
+            level: 1,
+            length: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.length
+    }
+
+    fn forward(&self, node: usize, level: usize) -> Option<usize> {
+        self.nodes[node].levels[level].forward
+    }
+
+    // the element must not be in the list yet
+    pub fn insert(&mut self, score: f64, member: Vec<u8>) {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].is_before(score, &member) {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEAD;
+                self.nodes[HEAD].levels[i].span = self.length;
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: None,
+            levels: vec![
+                Level {
+                    forward: None,
+                    span: 0,
+                };
+                level
+            ],
+        };
+        let x = match self.free.pop() {
+            Some(x) => {
+                self.nodes[x] = node;
+                x
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let prev = update[i];
+            let passed = rank[0] - rank[i];
+            self.nodes[x].levels[i] = Level {
+                forward: self.nodes[prev].levels[i].forward,
+                span: self.nodes[prev].levels[i].span - passed,
+            };
+            self.nodes[prev].levels[i] = Level {
+                forward: Some(x),
+                span: passed + 1,
+            };
+        }
+        // the links above the new node now move past one more node
+        for (i, prev) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[*prev].levels[i].span += 1;
+        }
+
+        self.nodes[x].backward = if update[0] == HEAD {
+            None
+        } else {
+            Some(update[0])
+        };
+        if let Some(next) = self.forward(x, 0) {
+            self.nodes[next].backward = Some(x);
+        }
+        self.length += 1;
+    }
+
+    pub fn remove(&mut self, score: f64, member: &[u8]) -> bool {
+        let mut update = [HEAD; MAX_LEVEL];
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !self.nodes[next].is_before(score, member) {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let x = match self.forward(x, 0) {
+            Some(x) if self.nodes[x].is(score, member) => x,
+            _ => return false,
+        };
+
+        for (i, prev) in update.iter().enumerate().take(self.level) {
+            let prev = *prev;
+            if self.forward(prev, i) == Some(x) {
+                let link = self.nodes[x].levels[i];
+                self.nodes[prev].levels[i] = Level {
+                    forward: link.forward,
+                    span: self.nodes[prev].levels[i].span + link.span - 1,
+                };
+            } else {
+                self.nodes[prev].levels[i].span -= 1;
+            }
+        }
+        if let Some(next) = self.forward(x, 0) {
+            self.nodes[next].backward = self.nodes[x].backward;
+        }
+        while self.level > 1 && self.forward(HEAD, self.level - 1).is_none() {
+            self.level -= 1;
+        }
+        self.length -= 1;
+
+        self.nodes[x].member = Vec::new();
+        self.nodes[x].levels = Vec::new();
+        self.free.push(x);
+        true
+    }
+
+    // the position of an element from zero, none if it is not in the list
+    pub fn rank(&self, score: f64, member: &[u8]) -> Option<usize> {
+        let mut rank = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                let node = &self.nodes[next];
+                if !node.is_before(score, member) && !node.is(score, member) {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEAD && self.nodes[x].is(score, member) {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    // the number of leading elements which satisfy the predicate,
+    // which must hold for a prefix of the list
+    pub fn count_while(&self, predicate: impl Fn(f64, &[u8]) -> bool) -> usize {
+        let mut count = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if !predicate(self.nodes[next].score, &self.nodes[next].member) {
+                    break;
+                }
+                count += self.nodes[x].levels[i].span;
+                x = next;
+            }
+        }
+        count
+    }
+
+    // the node at a position from zero
+    fn node_at(&self, index: usize) -> Option<usize> {
+        let rank = index + 1;
+        let mut traversed = 0;
+        let mut x = HEAD;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.forward(x, i) {
+                if traversed + self.nodes[x].levels[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    // the elements from a position, towards the lowest scores if `rev` is set
+    pub fn iter_from(&self, index: usize, rev: bool) -> Iter<'_> {
+        Iter {
+            list: self,
+            node: self.node_at(index),
+            rev,
+        }
+    }
+}
+
+pub struct Iter<'a> {
+    list: &'a SkipList,
+    node: Option<usize>,
+    rev: bool,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a [u8], f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = &self.list.nodes[self.node?];
+        self.node = if self.rev {
+            node.backward
+        } else {
+            node.levels[0].forward
+        };
+        Some((node.member.as_slice(), node.score))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn member(i: usize) -> Vec<u8> {
+        format!("m{:04}", i).into_bytes()
+    }
+
+    #[test]
+    fn test_insert_remove_and_rank() {
+        let mut list = SkipList::new();
+        // insert in a scrambled order, the scores of even and odd members collide
+        for i in (0..1000).map(|i| i * 7919 % 1000) {
+            list.insert((i / 2) as f64, member(i));
+        }
+        assert_eq!(1000, list.len());
+        for i in 0..1000 {
+            assert_eq!(Some(i), list.rank((i / 2) as f64, &member(i)));
+        }
+        let forward: Vec<Vec<u8>> = list.iter_from(0, false).map(|(m, _)| m.to_vec()).collect();
+        assert_eq!((0..1000).map(member).collect::<Vec<_>>(), forward);
+
+        for i in (0..1000).filter(|i| i % 3 == 0) {
+            assert!(list.remove((i / 2) as f64, &member(i)));
+        }
+        assert!(!list.remove(0.0, &member(0)));
+        let kept: Vec<usize> = (0..1000).filter(|i| i % 3 != 0).collect();
+        assert_eq!(kept.len(), list.len());
+        for (rank, i) in kept.iter().enumerate() {
+            assert_eq!(Some(rank), list.rank((i / 2) as f64, &member(*i)));
+        }
+        let backward: Vec<Vec<u8>> = list
+            .iter_from(kept.len() - 1, true)
+            .map(|(m, _)| m.to_vec())
+            .collect();
+        assert_eq!(
+            kept.iter().rev().map(|i| member(*i)).collect::<Vec<_>>(),
+            backward
+        );
+        assert_eq!(
+            kept.iter().filter(|i| **i < 100).count(),
+            list.count_while(|score, _| score < 50.0)
+        );
+        assert!(list.iter_from(kept.len(), false).next().is_none());
+    }
+}
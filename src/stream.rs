@@ -0,0 +1,2021 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Display;
+use std::ops::Bound;
+
+use crate::command::{check_key, CommandArgs, RedisCommandError};
+use crate::object::{wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::value::{RedisBulkString, RedisValue};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Hash, Default)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn next(&self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_add(1)?,
+                seq: 0,
+            }),
+        }
+    }
+
+    pub fn prev(&self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId { ms: self.ms, seq }),
+            None => Some(StreamId {
+                ms: self.ms.checked_sub(1)?,
+                seq: u64::MAX,
+            }),
+        }
+    }
+
+    // `<ms>-<seq>`, or `<ms>` alone with the given sequence number
+    fn parse(id: &[u8], default_seq: u64) -> Option<StreamId> {
+        let id = std::str::from_utf8(id).ok()?;
+        let (ms, seq) = match id.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (id.parse().ok()?, default_seq),
+        };
+        Some(StreamId { ms, seq })
+    }
+}
+
+impl Display for StreamId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl From<&StreamId> for RedisValue {
+    fn from(id: &StreamId) -> RedisValue {
+        RedisValue::bulk_string(id.to_string().as_str())
+    }
+}
+
+fn invalid_id() -> RedisCommandError {
+    RedisCommandError::Invalid("Invalid stream ID specified as stream command argument".to_string())
+}
+
+pub type StreamFields = Vec<(Vec<u8>, Vec<u8>)>;
+pub type StreamEntries = BTreeMap<StreamId, StreamFields>;
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Stream {
+    pub entries: StreamEntries,
+    // the greatest id ever added, new entries must be greater even if it has been trimmed
+    pub last_id: StreamId,
+    pub entries_added: u64,
+    pub groups: BTreeMap<Vec<u8>, ConsumerGroup>,
+}
+
+// an entry delivered to a consumer of a group and not acknowledged yet
+#[derive(PartialEq, Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: Vec<u8>,
+    // unix time in milliseconds of the last delivery
+    pub delivery_time: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct Consumer {
+    // unix times in milliseconds of the last attempted and the last successful interaction
+    pub seen_time: u64,
+    pub active_time: u64,
+    pub pending: BTreeSet<StreamId>,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct ConsumerGroup {
+    // the id of the last entry delivered to any consumer of the group
+    pub last_id: StreamId,
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Vec<u8>, Consumer>,
+}
+
+impl ConsumerGroup {
+    fn new(last_id: StreamId) -> ConsumerGroup {
+        ConsumerGroup {
+            last_id,
+            ..Default::default()
+        }
+    }
+
+    // the consumer of the name, created if missing along with the command creating it on replicas
+    fn consumer(
+        &mut self,
+        key: &RedisBulkString,
+        group: &RedisBulkString,
+        name: &RedisBulkString,
+        replicated: &mut Vec<StreamCommand>,
+    ) -> &mut Consumer {
+        if !self.consumers.contains_key(&name.data) {
+            replicated.push(StreamCommand::Group(
+                key.clone(),
+                group.clone(),
+                GroupAction::CreateConsumer(name.clone()),
+            ));
+        }
+        self.consumers.entry(name.data.clone()).or_default()
+    }
+
+    // hand a pending entry over to a consumer, the entry is created if missing
+    fn deliver(&mut self, id: StreamId, consumer: &[u8], delivery_time: u64) -> &mut PendingEntry {
+        let previous = self.pending.get(&id).map(|entry| entry.consumer.clone());
+        if let Some(previous) = previous {
+            if let Some(previous) = self.consumers.get_mut(&previous) {
+                previous.pending.remove(&id);
+            }
+        }
+        self.consumers
+            .entry(consumer.to_vec())
+            .or_default()
+            .pending
+            .insert(id);
+        let entry = self.pending.entry(id).or_insert(PendingEntry {
+            consumer: Vec::new(),
+            delivery_time,
+            delivery_count: 0,
+        });
+        entry.consumer = consumer.to_vec();
+        entry.delivery_time = delivery_time;
+        entry
+    }
+
+    fn acknowledge(&mut self, id: &StreamId) -> bool {
+        match self.pending.remove(id) {
+            Some(entry) => {
+                if let Some(consumer) = self.consumers.get_mut(&entry.consumer) {
+                    consumer.pending.remove(id);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Stream {
+    pub fn trim(&mut self, options: &TrimOptions) -> usize {
+        let limit = options.limit.unwrap_or(usize::MAX);
+        let mut trimmed = 0;
+        while trimmed < limit {
+            let first = match self.entries.keys().next() {
+                Some(first) => *first,
+                None => break,
+            };
+            let exceeding = match options.strategy {
+                TrimStrategy::MaxLen(max) => self.entries.len() > max,
+                TrimStrategy::MinId(min) => first < min,
+            };
+            if !exceeding {
+                break;
+            }
+            self.entries.remove(&first);
+            trimmed += 1;
+        }
+        trimmed
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TrimStrategy {
+    MaxLen(usize),
+    MinId(StreamId),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct TrimOptions {
+    pub strategy: TrimStrategy,
+    // `~`, which lets redis keep a few more entries; the trimming here is always exact
+    pub approximate: bool,
+    // the maximum number of entries to remove, only along with `~`
+    pub limit: Option<usize>,
+}
+
+impl TrimOptions {
+    fn parse(args: &mut CommandArgs, strategy: &str) -> Result<TrimOptions, RedisCommandError> {
+        let mut threshold = args.next_bulk_string()?;
+        let approximate = threshold.data == b"~";
+        if approximate || threshold.data == b"=" {
+            threshold = args.next_bulk_string()?;
+        }
+        let strategy = if strategy == "maxlen" {
+            let threshold: String = (&threshold).into();
+            match threshold.parse::<i64>() {
+                Ok(max) if max >= 0 => TrimStrategy::MaxLen(max as usize),
+                Ok(_) => {
+                    return Err(RedisCommandError::Invalid(
+                        "The MAXLEN argument must be >= 0.".to_string(),
+                    ))
+                }
+                Err(_) => return Err(RedisCommandError::NotInteger),
+            }
+        } else {
+            TrimStrategy::MinId(StreamId::parse(&threshold.data, 0).ok_or_else(invalid_id)?)
+        };
+        Ok(TrimOptions {
+            strategy,
+            approximate,
+            limit: None,
+        })
+    }
+
+    fn to_args(self) -> Vec<RedisValue> {
+        let (name, threshold) = match self.strategy {
+            TrimStrategy::MaxLen(max) => ("maxlen", max.to_string()),
+            TrimStrategy::MinId(min) => ("minid", min.to_string()),
+        };
+        let mut vs = vec![RedisValue::bulk_string(name)];
+        if self.approximate {
+            vs.push(RedisValue::bulk_string("~"));
+        }
+        vs.push(RedisValue::bulk_string(threshold.as_str()));
+        if let Some(limit) = self.limit {
+            vs.push(RedisValue::bulk_string("limit"));
+            vs.push(RedisValue::bulk_string(limit.to_string().as_str()));
+        }
+        vs
+    }
+}
+
+// the trimming options shared by xadd and xtrim, `LIMIT` only goes along with `~`
+fn parse_trim_limit(
+    args: &mut CommandArgs,
+    options: &mut TrimOptions,
+) -> Result<(), RedisCommandError> {
+    let limit: i64 = args.next_integer()?;
+    if limit < 0 {
+        return Err(RedisCommandError::NotPositive);
+    }
+    if !options.approximate {
+        return Err(RedisCommandError::Invalid(
+            "syntax error, LIMIT cannot be used without the special ~ option".to_string(),
+        ));
+    }
+    options.limit = Some(limit as usize);
+    Ok(())
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum AddId {
+    // `*`, generated from the current time
+    Auto,
+    // `<ms>-*`, with a generated sequence number
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ReadId {
+    Id(StreamId),
+    // `$`, the last id of the stream when the command is received
+    Last,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GroupId {
+    Id(StreamId),
+    // `$`, the last id of the stream
+    Last,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum GroupAction {
+    // the id the group starts after, whether to create a missing stream
+    Create(GroupId, bool),
+    SetId(GroupId),
+    Destroy,
+    CreateConsumer(RedisBulkString),
+    DelConsumer(RedisBulkString),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GroupReadOptions {
+    pub group: RedisBulkString,
+    pub consumer: RedisBulkString,
+    pub count: Option<usize>,
+    pub block: Option<u64>,
+    // whether the delivered entries are acknowledged right away
+    pub noack: bool,
+    pub keys: Vec<RedisBulkString>,
+    // none stands for `>`, the entries never delivered to the group, and an id for the
+    // history of the entries pending for the consumer after it
+    pub ids: Vec<Option<StreamId>>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct PendingRange {
+    pub idle: Option<u64>,
+    pub start: StreamId,
+    pub end: StreamId,
+    pub count: usize,
+    pub consumer: Option<RedisBulkString>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ClaimOptions {
+    pub min_idle: u64,
+    pub ids: Vec<StreamId>,
+    // the idle time or the unix time in milliseconds of the delivery set to the claimed entries
+    pub idle: Option<u64>,
+    pub time: Option<u64>,
+    pub retry_count: Option<u64>,
+    // whether to create the pending entries which are missing
+    pub force: bool,
+    // whether to reply the ids alone, without counting a delivery
+    pub justid: bool,
+    // the last delivered id of the group, set if greater
+    pub last_id: Option<StreamId>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct AutoClaimOptions {
+    pub min_idle: u64,
+    pub start: StreamId,
+    pub count: usize,
+    pub justid: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct ReadOptions {
+    pub count: Option<usize>,
+    // milliseconds to wait for new entries, zero waits forever
+    pub block: Option<u64>,
+    pub keys: Vec<RedisBulkString>,
+    pub ids: Vec<ReadId>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StreamCommand {
+    // key, whether to not create a missing stream, trimming, id, fields
+    Add(
+        RedisBulkString,
+        bool,
+        Option<TrimOptions>,
+        AddId,
+        Vec<(RedisBulkString, RedisBulkString)>,
+    ),
+    // key, start, end and count, whether from the end
+    Range(RedisBulkString, StreamId, StreamId, Option<usize>, bool),
+    Len(RedisBulkString),
+    Trim(RedisBulkString, TrimOptions),
+    Read(ReadOptions),
+    // key, group
+    Group(RedisBulkString, RedisBulkString, GroupAction),
+    ReadGroup(GroupReadOptions),
+    Ack(RedisBulkString, RedisBulkString, Vec<StreamId>),
+    // key, group and the range of the extended form
+    Pending(RedisBulkString, RedisBulkString, Option<PendingRange>),
+    // key, group, consumer
+    Claim(
+        RedisBulkString,
+        RedisBulkString,
+        RedisBulkString,
+        ClaimOptions,
+    ),
+    AutoClaim(
+        RedisBulkString,
+        RedisBulkString,
+        RedisBulkString,
+        AutoClaimOptions,
+    ),
+}
+
+// the arguments shared by `XREAD` and `XREADGROUP`, in any order before the streams:
+// `[GROUP group consumer] [COUNT count] [BLOCK ms] [NOACK] STREAMS key... id...`
+struct StreamsArgs {
+    group: Option<(RedisBulkString, RedisBulkString)>,
+    count: Option<usize>,
+    block: Option<u64>,
+    noack: bool,
+    keys: Vec<RedisBulkString>,
+    ids: Vec<RedisBulkString>,
+}
+
+impl StreamsArgs {
+    fn parse(args: &mut CommandArgs, command_name: &str) -> Result<StreamsArgs, RedisCommandError> {
+        let xreadgroup = command_name == "xreadgroup";
+        let mut streams = StreamsArgs {
+            group: None,
+            count: None,
+            block: None,
+            noack: false,
+            keys: vec![],
+            ids: vec![],
+        };
+        loop {
+            match args.next_string()?.to_lowercase().as_str() {
+                "count" => {
+                    let n: i64 = args.next_integer()?;
+                    // a count of zero does not limit the reply
+                    streams.count = if n > 0 { Some(n as usize) } else { None };
+                }
+                "block" => {
+                    let timeout: i64 = args.next_integer()?;
+                    if timeout < 0 {
+                        return Err(RedisCommandError::Invalid(
+                            "timeout is negative".to_string(),
+                        ));
+                    }
+                    streams.block = Some(timeout as u64);
+                }
+                "group" if xreadgroup => {
+                    streams.group = Some((args.next_bulk_string()?, args.next_bulk_string()?));
+                }
+                "group" => return Err(RedisCommandError::Invalid(
+                    "The GROUP option is only supported by XREADGROUP. You called XREAD instead."
+                        .to_string(),
+                )),
+                "noack" if xreadgroup => streams.noack = true,
+                "streams" => break,
+                _ => return Err(RedisCommandError::IlleagalArg),
+            }
+        }
+        let rest = args.rest()?;
+        if rest.len() % 2 != 0 {
+            return Err(RedisCommandError::Invalid(format!(
+                "Unbalanced '{}' list of streams: for each stream key an ID or '{}' must be specified.",
+                command_name,
+                if xreadgroup { ">" } else { "$" }
+            )));
+        }
+        let (keys, ids) = rest.split_at(rest.len() / 2);
+        keys.iter().try_for_each(check_key)?;
+        streams.keys = keys.to_vec();
+        streams.ids = ids.to_vec();
+        Ok(streams)
+    }
+}
+
+// a bound of `XRANGE`: `-` and `+`, an id possibly prefixed by `(` to exclude it,
+// or a time alone which stands for all its sequence numbers
+fn parse_range_bound(bound: &RedisBulkString, start: bool) -> Result<StreamId, RedisCommandError> {
+    match bound.data.as_slice() {
+        b"-" => return Ok(StreamId::MIN),
+        b"+" => return Ok(StreamId::MAX),
+        _ => {}
+    }
+    let (exclusive, id) = match bound.data.strip_prefix(b"(") {
+        Some(id) => (true, id),
+        None => (false, bound.data.as_slice()),
+    };
+    let id = StreamId::parse(id, if start { 0 } else { u64::MAX }).ok_or_else(invalid_id)?;
+    if !exclusive {
+        return Ok(id);
+    }
+    let id = if start { id.next() } else { id.prev() };
+    id.ok_or_else(|| RedisCommandError::Invalid("invalid start ID for the interval".to_string()))
+}
+
+impl StreamCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<StreamCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "xadd" => {
+                let key = args.next_key()?;
+                let mut nomkstream = false;
+                let mut trim = None;
+                let id = loop {
+                    let arg = args.next_bulk_string()?;
+                    let option: String = (&arg).into();
+                    match option.to_lowercase().as_str() {
+                        "nomkstream" => nomkstream = true,
+                        strategy @ ("maxlen" | "minid") => {
+                            trim = Some(TrimOptions::parse(&mut args, strategy)?)
+                        }
+                        "limit" if trim.is_some() => {
+                            parse_trim_limit(&mut args, trim.as_mut().unwrap())?
+                        }
+                        "*" => break AddId::Auto,
+                        _ => {
+                            let id = match option.strip_suffix("-*") {
+                                Some(ms) => ms.parse().map(AddId::AutoSeq).ok(),
+                                None => StreamId::parse(&arg.data, 0).map(AddId::Explicit),
+                            };
+                            break id.ok_or_else(invalid_id)?;
+                        }
+                    }
+                };
+                let fields = args.rest()?;
+                if fields.len() % 2 != 0 {
+                    return Err(args.wrong_number());
+                }
+                let fields = fields
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                StreamCommand::Add(key, nomkstream, trim, id, fields)
+            }
+            "xrange" | "xrevrange" => {
+                let key = args.next_key()?;
+                let rev = command_name == "xrevrange";
+                // the end comes first in a reversed range
+                let (first, second) = (args.next_bulk_string()?, args.next_bulk_string()?);
+                let (start, end) = if rev {
+                    (second, first)
+                } else {
+                    (first, second)
+                };
+                let start = parse_range_bound(&start, true)?;
+                let end = parse_range_bound(&end, false)?;
+                let count = if args.is_empty() {
+                    None
+                } else {
+                    if args.next_string()?.to_lowercase() != "count" {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    let count: i64 = args.next_integer()?;
+                    Some(count.max(0) as usize)
+                };
+                StreamCommand::Range(key, start, end, count, rev)
+            }
+            "xlen" => StreamCommand::Len(args.next_key()?),
+            "xtrim" => {
+                let key = args.next_key()?;
+                let strategy = args.next_string()?.to_lowercase();
+                if strategy != "maxlen" && strategy != "minid" {
+                    return Err(RedisCommandError::IlleagalArg);
+                }
+                let mut options = TrimOptions::parse(&mut args, &strategy)?;
+                if !args.is_empty() {
+                    if args.next_string()?.to_lowercase() != "limit" {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    parse_trim_limit(&mut args, &mut options)?;
+                }
+                StreamCommand::Trim(key, options)
+            }
+            "xread" => {
+                let streams = StreamsArgs::parse(&mut args, command_name)?;
+                let ids = streams
+                    .ids
+                    .iter()
+                    .map(|id| match id.data.as_slice() {
+                        b"$" => Ok(ReadId::Last),
+                        id => StreamId::parse(id, 0)
+                            .map(ReadId::Id)
+                            .ok_or_else(invalid_id),
+                    })
+                    .collect::<Result<Vec<ReadId>, RedisCommandError>>()?;
+                StreamCommand::Read(ReadOptions {
+                    count: streams.count,
+                    block: streams.block,
+                    keys: streams.keys,
+                    ids,
+                })
+            }
+            "xreadgroup" => {
+                let streams = StreamsArgs::parse(&mut args, command_name)?;
+                let (group, consumer) = streams.group.ok_or_else(|| {
+                    RedisCommandError::Invalid("Missing GROUP option for XREADGROUP".to_string())
+                })?;
+                let ids = streams
+                    .ids
+                    .iter()
+                    .map(|id| match id.data.as_slice() {
+                        b">" => Ok(None),
+                        b"$" => Err(RedisCommandError::Invalid(
+                            "The $ ID is meaningless in the context of XREADGROUP: you want to read the history of this consumer by specifying a proper ID, or use the > ID to get new messages. The $ ID would just return an empty result set."
+                                .to_string(),
+                        )),
+                        id => StreamId::parse(id, 0).map(Some).ok_or_else(invalid_id),
+                    })
+                    .collect::<Result<Vec<Option<StreamId>>, RedisCommandError>>()?;
+                StreamCommand::ReadGroup(GroupReadOptions {
+                    group,
+                    consumer,
+                    count: streams.count,
+                    block: streams.block,
+                    noack: streams.noack,
+                    keys: streams.keys,
+                    ids,
+                })
+            }
+            "xgroup" => {
+                let subcommand = args.next_string()?.to_lowercase();
+                let key = args.next_key()?;
+                let group = args.next_bulk_string()?;
+                let action = match subcommand.as_str() {
+                    "create" | "setid" => {
+                        let id = args.next_bulk_string()?;
+                        let id = match id.data.as_slice() {
+                            b"$" => GroupId::Last,
+                            id => GroupId::Id(StreamId::parse(id, 0).ok_or_else(invalid_id)?),
+                        };
+                        if subcommand == "setid" {
+                            GroupAction::SetId(id)
+                        } else if args.is_empty() {
+                            GroupAction::Create(id, false)
+                        } else if args.next_string()?.to_lowercase() == "mkstream" {
+                            GroupAction::Create(id, true)
+                        } else {
+                            return Err(RedisCommandError::IlleagalArg);
+                        }
+                    }
+                    "destroy" => GroupAction::Destroy,
+                    "createconsumer" => GroupAction::CreateConsumer(args.next_bulk_string()?),
+                    "delconsumer" => GroupAction::DelConsumer(args.next_bulk_string()?),
+                    _ => {
+                        return Err(RedisCommandError::Invalid(format!(
+                            "unknown subcommand '{}'. Try XGROUP HELP.",
+                            subcommand
+                        )))
+                    }
+                };
+                StreamCommand::Group(key, group, action)
+            }
+            "xack" => {
+                let key = args.next_key()?;
+                let group = args.next_bulk_string()?;
+                let ids = args
+                    .rest()?
+                    .iter()
+                    .map(|id| StreamId::parse(&id.data, 0).ok_or_else(invalid_id))
+                    .collect::<Result<Vec<StreamId>, RedisCommandError>>()?;
+                StreamCommand::Ack(key, group, ids)
+            }
+            "xpending" => {
+                let key = args.next_key()?;
+                let group = args.next_bulk_string()?;
+                let range = if args.is_empty() {
+                    None
+                } else {
+                    let mut start = args.next_bulk_string()?;
+                    let mut idle = None;
+                    if start.data.eq_ignore_ascii_case(b"idle") {
+                        let min: i64 = args.next_integer()?;
+                        idle = Some(min.max(0) as u64);
+                        start = args.next_bulk_string()?;
+                    }
+                    let start = parse_range_bound(&start, true)?;
+                    let end = parse_range_bound(&args.next_bulk_string()?, false)?;
+                    let count: i64 = args.next_integer()?;
+                    let consumer = if args.is_empty() {
+                        None
+                    } else {
+                        Some(args.next_bulk_string()?)
+                    };
+                    Some(PendingRange {
+                        idle,
+                        start,
+                        end,
+                        count: count.max(0) as usize,
+                        consumer,
+                    })
+                };
+                StreamCommand::Pending(key, group, range)
+            }
+            "xclaim" => {
+                let key = args.next_key()?;
+                let group = args.next_bulk_string()?;
+                let consumer = args.next_bulk_string()?;
+                let min_idle: i64 = args.next_integer()?;
+                let mut options = ClaimOptions {
+                    min_idle: min_idle.max(0) as u64,
+                    ids: vec![],
+                    idle: None,
+                    time: None,
+                    retry_count: None,
+                    force: false,
+                    justid: false,
+                    last_id: None,
+                };
+                // the ids come first, up to the first argument which is not one
+                let mut reading_ids = true;
+                while !args.is_empty() {
+                    let arg = args.next_bulk_string()?;
+                    if reading_ids {
+                        if let Some(id) = StreamId::parse(&arg.data, 0) {
+                            options.ids.push(id);
+                            continue;
+                        }
+                        if options.ids.is_empty() {
+                            return Err(invalid_id());
+                        }
+                        reading_ids = false;
+                    }
+                    let option: String = (&arg).into();
+                    match option.to_lowercase().as_str() {
+                        "idle" => {
+                            let idle: i64 = args.next_integer()?;
+                            options.idle = Some(idle.max(0) as u64);
+                        }
+                        "time" => {
+                            let time: i64 = args.next_integer()?;
+                            options.time = Some(time.max(0) as u64);
+                        }
+                        "retrycount" => {
+                            let count: i64 = args.next_integer()?;
+                            options.retry_count = Some(count.max(0) as u64);
+                        }
+                        "force" => options.force = true,
+                        "justid" => options.justid = true,
+                        "lastid" => {
+                            let id = args.next_bulk_string()?;
+                            options.last_id =
+                                Some(StreamId::parse(&id.data, 0).ok_or_else(invalid_id)?);
+                        }
+                        _ => {
+                            return Err(RedisCommandError::Invalid(format!(
+                                "Unrecognized XCLAIM option '{}'",
+                                option
+                            )))
+                        }
+                    }
+                }
+                if options.ids.is_empty() {
+                    return Err(args.wrong_number());
+                }
+                StreamCommand::Claim(key, group, consumer, options)
+            }
+            "xautoclaim" => {
+                let key = args.next_key()?;
+                let group = args.next_bulk_string()?;
+                let consumer = args.next_bulk_string()?;
+                let min_idle: i64 = args.next_integer()?;
+                let start = parse_range_bound(&args.next_bulk_string()?, true)?;
+                let mut options = AutoClaimOptions {
+                    min_idle: min_idle.max(0) as u64,
+                    start,
+                    count: 100,
+                    justid: false,
+                };
+                while !args.is_empty() {
+                    match args.next_string()?.to_lowercase().as_str() {
+                        "count" => {
+                            let count: i64 = args.next_integer()?;
+                            if count <= 0 {
+                                return Err(RedisCommandError::Invalid(
+                                    "COUNT must be > 0".to_string(),
+                                ));
+                            }
+                            options.count = count as usize;
+                        }
+                        "justid" => options.justid = true,
+                        _ => return Err(RedisCommandError::IlleagalArg),
+                    }
+                }
+                StreamCommand::AutoClaim(key, group, consumer, options)
+            }
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            StreamCommand::Range(..)
+                | StreamCommand::Len(..)
+                | StreamCommand::Read(..)
+                | StreamCommand::Pending(..)
+        )
+    }
+}
+
+impl From<&StreamCommand> for Vec<RedisValue> {
+    fn from(stream_command: &StreamCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        match stream_command {
+            StreamCommand::Add(key, nomkstream, trim, id, fields) => {
+                let mut vs = command("xadd", key);
+                if *nomkstream {
+                    vs.push(RedisValue::bulk_string("nomkstream"));
+                }
+                if let Some(trim) = trim {
+                    vs.extend(trim.to_args());
+                }
+                let id = match id {
+                    AddId::Auto => "*".to_string(),
+                    AddId::AutoSeq(ms) => format!("{}-*", ms),
+                    AddId::Explicit(id) => id.to_string(),
+                };
+                vs.push(RedisValue::bulk_string(id.as_str()));
+                for (field, value) in fields {
+                    vs.push(field.into());
+                    vs.push(value.into());
+                }
+                vs
+            }
+            StreamCommand::Range(key, start, end, count, rev) => {
+                let mut vs = command(if *rev { "xrevrange" } else { "xrange" }, key);
+                if *rev {
+                    vs.extend([end.into(), start.into()]);
+                } else {
+                    vs.extend([start.into(), end.into()]);
+                }
+                if let Some(count) = count {
+                    vs.push(RedisValue::bulk_string("count"));
+                    vs.push(RedisValue::bulk_string(count.to_string().as_str()));
+                }
+                vs
+            }
+            StreamCommand::Len(key) => command("xlen", key),
+            StreamCommand::Trim(key, options) => {
+                let mut vs = command("xtrim", key);
+                vs.extend(options.to_args());
+                vs
+            }
+            StreamCommand::Read(options) => {
+                let mut vs = vec![RedisValue::bulk_string("xread")];
+                if let Some(count) = options.count {
+                    vs.push(RedisValue::bulk_string("count"));
+                    vs.push(RedisValue::bulk_string(count.to_string().as_str()));
+                }
+                if let Some(block) = options.block {
+                    vs.push(RedisValue::bulk_string("block"));
+                    vs.push(RedisValue::bulk_string(block.to_string().as_str()));
+                }
+                vs.push(RedisValue::bulk_string("streams"));
+                vs.extend(options.keys.iter().map(|key| key.into()));
+                for id in options.ids.iter() {
+                    vs.push(match id {
+                        ReadId::Id(id) => id.into(),
+                        ReadId::Last => RedisValue::bulk_string("$"),
+                    });
+                }
+                vs
+            }
+            StreamCommand::Group(key, group, action) => {
+                let subcommand = match action {
+                    GroupAction::Create(..) => "create",
+                    GroupAction::SetId(_) => "setid",
+                    GroupAction::Destroy => "destroy",
+                    GroupAction::CreateConsumer(_) => "createconsumer",
+                    GroupAction::DelConsumer(_) => "delconsumer",
+                };
+                let mut vs = vec![
+                    RedisValue::bulk_string("xgroup"),
+                    RedisValue::bulk_string(subcommand),
+                    key.into(),
+                    group.into(),
+                ];
+                let id = |id: &GroupId| match id {
+                    GroupId::Id(id) => id.into(),
+                    GroupId::Last => RedisValue::bulk_string("$"),
+                };
+                match action {
+                    GroupAction::Create(start, mkstream) => {
+                        vs.push(id(start));
+                        if *mkstream {
+                            vs.push(RedisValue::bulk_string("mkstream"));
+                        }
+                    }
+                    GroupAction::SetId(start) => vs.push(id(start)),
+                    GroupAction::Destroy => {}
+                    GroupAction::CreateConsumer(consumer) | GroupAction::DelConsumer(consumer) => {
+                        vs.push(consumer.into())
+                    }
+                }
+                vs
+            }
+            StreamCommand::ReadGroup(options) => {
+                let mut vs = vec![
+                    RedisValue::bulk_string("xreadgroup"),
+                    RedisValue::bulk_string("group"),
+                    (&options.group).into(),
+                    (&options.consumer).into(),
+                ];
+                if let Some(count) = options.count {
+                    vs.push(RedisValue::bulk_string("count"));
+                    vs.push(RedisValue::bulk_string(count.to_string().as_str()));
+                }
+                if let Some(block) = options.block {
+                    vs.push(RedisValue::bulk_string("block"));
+                    vs.push(RedisValue::bulk_string(block.to_string().as_str()));
+                }
+                if options.noack {
+                    vs.push(RedisValue::bulk_string("noack"));
+                }
+                vs.push(RedisValue::bulk_string("streams"));
+                vs.extend(options.keys.iter().map(|key| key.into()));
+                for id in options.ids.iter() {
+                    vs.push(match id {
+                        Some(id) => id.into(),
+                        None => RedisValue::bulk_string(">"),
+                    });
+                }
+                vs
+            }
+            StreamCommand::Ack(key, group, ids) => {
+                let mut vs = command("xack", key);
+                vs.push(group.into());
+                vs.extend(ids.iter().map(|id| id.into()));
+                vs
+            }
+            StreamCommand::Pending(key, group, range) => {
+                let mut vs = command("xpending", key);
+                vs.push(group.into());
+                if let Some(range) = range {
+                    if let Some(idle) = range.idle {
+                        vs.push(RedisValue::bulk_string("idle"));
+                        vs.push(RedisValue::bulk_string(idle.to_string().as_str()));
+                    }
+                    vs.push((&range.start).into());
+                    vs.push((&range.end).into());
+                    vs.push(RedisValue::bulk_string(range.count.to_string().as_str()));
+                    if let Some(consumer) = &range.consumer {
+                        vs.push(consumer.into());
+                    }
+                }
+                vs
+            }
+            StreamCommand::Claim(key, group, consumer, options) => {
+                let mut vs = command("xclaim", key);
+                vs.push(group.into());
+                vs.push(consumer.into());
+                vs.push(RedisValue::bulk_string(
+                    options.min_idle.to_string().as_str(),
+                ));
+                vs.extend(options.ids.iter().map(|id| id.into()));
+                let mut option = |name: &str, value: Option<String>| {
+                    if let Some(value) = value {
+                        vs.push(RedisValue::bulk_string(name));
+                        vs.push(RedisValue::bulk_string(value.as_str()));
+                    }
+                };
+                option("idle", options.idle.map(|idle| idle.to_string()));
+                option("time", options.time.map(|time| time.to_string()));
+                option("retrycount", options.retry_count.map(|n| n.to_string()));
+                option("lastid", options.last_id.map(|id| id.to_string()));
+                if options.force {
+                    vs.push(RedisValue::bulk_string("force"));
+                }
+                if options.justid {
+                    vs.push(RedisValue::bulk_string("justid"));
+                }
+                vs
+            }
+            StreamCommand::AutoClaim(key, group, consumer, options) => {
+                let mut vs = command("xautoclaim", key);
+                vs.push(group.into());
+                vs.push(consumer.into());
+                vs.push(RedisValue::bulk_string(
+                    options.min_idle.to_string().as_str(),
+                ));
+                vs.push((&options.start).into());
+                vs.push(RedisValue::bulk_string("count"));
+                vs.push(RedisValue::bulk_string(options.count.to_string().as_str()));
+                if options.justid {
+                    vs.push(RedisValue::bulk_string("justid"));
+                }
+                vs
+            }
+        }
+    }
+}
+
+// the stream stored at the key, none if the key does not exist
+fn get_stream<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut Stream>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::Stream(stream),
+            ..
+        }) => Ok(Some(stream)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+// the id of a new entry, which must be greater than every id of the stream
+fn next_id(stream: &Stream, id: AddId, now: u64) -> Result<StreamId, RedisValue> {
+    let last = stream.last_id;
+    let id = match id {
+        AddId::Auto if now > last.ms => Some(StreamId { ms: now, seq: 0 }),
+        AddId::Auto => last.next(),
+        AddId::AutoSeq(ms) if ms == last.ms => {
+            last.seq.checked_add(1).map(|seq| StreamId { ms, seq })
+        }
+        AddId::AutoSeq(ms) => Some(StreamId {
+            ms,
+            seq: if ms == 0 { 1 } else { 0 },
+        }),
+        AddId::Explicit(id) => Some(id),
+    };
+    match id {
+        Some(StreamId::MIN) => Err(RedisValue::error(
+            "ERR The ID specified in XADD must be greater than 0-0",
+        )),
+        Some(id) if id > last => Ok(id),
+        _ => Err(RedisValue::error(
+            "ERR The ID specified in XADD is equal or smaller than the target stream top item",
+        )),
+    }
+}
+
+fn entry_reply(id: &StreamId, fields: &StreamFields) -> RedisValue {
+    let mut values = vec![];
+    for (field, value) in fields {
+        values.push(RedisValue::bulk_string_from_bytes(field.as_slice()));
+        values.push(RedisValue::bulk_string_from_bytes(value.as_slice()));
+    }
+    RedisValue::Array(vec![id.into(), RedisValue::Array(values)])
+}
+
+// the ids `XREAD` starts after, `$` being resolved when the command is received
+pub fn resolve_ids(
+    store: &mut Store,
+    options: &ReadOptions,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Vec<StreamId>, RedisValue> {
+    let mut ids = Vec::with_capacity(options.ids.len());
+    for (key, id) in options.keys.iter().zip(options.ids.iter()) {
+        let key: String = key.into();
+        let stream = get_stream(store, &key, now, mode)?;
+        ids.push(match id {
+            ReadId::Id(id) => *id,
+            ReadId::Last => stream.map_or(StreamId::MIN, |stream| stream.last_id),
+        });
+    }
+    Ok(ids)
+}
+
+// the entries following the id of every stream, none if there is no such entry
+pub fn read(
+    store: &mut Store,
+    keys: &[RedisBulkString],
+    ids: &[StreamId],
+    count: Option<usize>,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<RedisValue>, RedisValue> {
+    let mut streams = vec![];
+    for (key, id) in keys.iter().zip(ids) {
+        let name: String = key.into();
+        let stream = match get_stream(store, &name, now, mode)? {
+            Some(stream) => stream,
+            None => continue,
+        };
+        let entries: Vec<RedisValue> = stream
+            .entries
+            .range((Bound::Excluded(*id), Bound::Unbounded))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| entry_reply(id, fields))
+            .collect();
+        if !entries.is_empty() {
+            streams.push(RedisValue::Array(vec![
+                key.into(),
+                RedisValue::Array(entries),
+            ]));
+        }
+    }
+    if streams.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(RedisValue::Array(streams)))
+    }
+}
+
+// the entries of a stream and one of its groups, none if either does not exist
+fn get_group<'a>(
+    store: &'a mut Store,
+    key: &RedisBulkString,
+    group: &RedisBulkString,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<(&'a StreamEntries, &'a mut ConsumerGroup)>, RedisValue> {
+    let key: String = key.into();
+    Ok(get_stream(store, &key, now, mode)?.and_then(|stream| {
+        let group = stream.groups.get_mut(&group.data)?;
+        Some((&stream.entries, group))
+    }))
+}
+
+fn no_group(key: &RedisBulkString, group: &RedisBulkString, context: &str) -> RedisValue {
+    let key: String = key.into();
+    let group: String = group.into();
+    RedisValue::error(
+        format!(
+            "NOGROUP No such key '{}' or consumer group '{}'{}",
+            key, group, context
+        )
+        .as_str(),
+    )
+}
+
+// the command replicas replay to hand an entry over to a consumer as it has been here
+fn replicated_claim(
+    key: &RedisBulkString,
+    group: &RedisBulkString,
+    consumer: &RedisBulkString,
+    id: StreamId,
+    entry: &PendingEntry,
+    last_id: StreamId,
+) -> StreamCommand {
+    StreamCommand::Claim(
+        key.clone(),
+        group.clone(),
+        consumer.clone(),
+        ClaimOptions {
+            min_idle: 0,
+            ids: vec![id],
+            idle: None,
+            time: Some(entry.delivery_time),
+            retry_count: Some(entry.delivery_count),
+            force: true,
+            justid: true,
+            last_id: Some(last_id),
+        },
+    )
+}
+
+// the entries read by a consumer of a group, none if there is no new entry yet
+pub fn read_group(
+    store: &mut Store,
+    options: &GroupReadOptions,
+    now: u64,
+    mode: ExpiryMode,
+    replicated: &mut Vec<StreamCommand>,
+) -> Result<Option<RedisValue>, RedisValue> {
+    // every group must exist before any entry is delivered
+    for key in options.keys.iter() {
+        if get_group(store, key, &options.group, now, mode)?.is_none() {
+            return Err(no_group(
+                key,
+                &options.group,
+                " in XREADGROUP with GROUP option",
+            ));
+        }
+    }
+    let count = options.count.unwrap_or(usize::MAX);
+    let name = &options.consumer.data;
+    let mut streams = vec![];
+    for (key, id) in options.keys.iter().zip(options.ids.iter()) {
+        let (entries, group) = match get_group(store, key, &options.group, now, mode)? {
+            Some(found) => found,
+            None => continue,
+        };
+        group
+            .consumer(key, &options.group, &options.consumer, replicated)
+            .seen_time = now;
+        let reply: Vec<RedisValue> = match id {
+            // the history of the consumer, counted as delivered once more
+            Some(start) => {
+                let ids: Vec<StreamId> = group.consumers[name]
+                    .pending
+                    .range((Bound::Excluded(*start), Bound::Unbounded))
+                    .take(count)
+                    .copied()
+                    .collect();
+                ids.iter()
+                    .map(|id| match entries.get(id) {
+                        Some(fields) => {
+                            if let Some(entry) = group.pending.get_mut(id) {
+                                entry.delivery_time = now;
+                                entry.delivery_count += 1;
+                            }
+                            entry_reply(id, fields)
+                        }
+                        // an entry trimmed away while pending
+                        None => RedisValue::Array(vec![id.into(), RedisValue::null_bulk_string()]),
+                    })
+                    .collect()
+            }
+            None => {
+                let delivered: Vec<(&StreamId, &StreamFields)> = entries
+                    .range((Bound::Excluded(group.last_id), Bound::Unbounded))
+                    .take(count)
+                    .collect();
+                if let Some((last, _)) = delivered.last() {
+                    group.last_id = **last;
+                    if let Some(consumer) = group.consumers.get_mut(name) {
+                        consumer.active_time = now;
+                    }
+                    if options.noack {
+                        replicated.push(StreamCommand::Group(
+                            key.clone(),
+                            options.group.clone(),
+                            GroupAction::SetId(GroupId::Id(group.last_id)),
+                        ));
+                    }
+                }
+                if !options.noack {
+                    for (id, _) in delivered.iter() {
+                        let last_id = group.last_id;
+                        let entry = group.deliver(**id, name, now);
+                        entry.delivery_count = 1;
+                        replicated.push(replicated_claim(
+                            key,
+                            &options.group,
+                            &options.consumer,
+                            **id,
+                            entry,
+                            last_id,
+                        ));
+                    }
+                }
+                delivered
+                    .iter()
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            }
+        };
+        // the history is always replied, even if empty
+        if id.is_some() || !reply.is_empty() {
+            streams.push(RedisValue::Array(vec![
+                key.into(),
+                RedisValue::Array(reply),
+            ]));
+        }
+    }
+    if streams.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(RedisValue::Array(streams)))
+    }
+}
+
+fn run_group_action(
+    store: &mut Store,
+    key: &RedisBulkString,
+    group: &RedisBulkString,
+    action: &GroupAction,
+    now: u64,
+    mode: ExpiryMode,
+    replicated: &mut Vec<StreamCommand>,
+) -> Result<RedisValue, RedisValue> {
+    let name: String = key.into();
+    if get_stream(store, &name, now, mode)?.is_none() {
+        if !matches!(action, GroupAction::Create(_, true)) {
+            return Err(RedisValue::error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically."));
+        }
+        store.insert(
+            name.clone(),
+            StoreItem {
+                value: RedisObject::Stream(Stream::default()),
+                expired_at: 0,
+            },
+        );
+    }
+    let stream = match get_stream(store, &name, now, mode)? {
+        Some(stream) => stream,
+        None => return Err(wrong_type()),
+    };
+    let resolve = |id: &GroupId| match id {
+        GroupId::Id(id) => *id,
+        GroupId::Last => stream.last_id,
+    };
+    if let GroupAction::Create(id, mkstream) = action {
+        if stream.groups.contains_key(&group.data) {
+            return Err(RedisValue::error(
+                "BUSYGROUP Consumer Group name already exists",
+            ));
+        }
+        let id = resolve(id);
+        stream
+            .groups
+            .insert(group.data.clone(), ConsumerGroup::new(id));
+        replicated.push(StreamCommand::Group(
+            key.clone(),
+            group.clone(),
+            GroupAction::Create(GroupId::Id(id), *mkstream),
+        ));
+        return Ok(RedisValue::simple_string("OK"));
+    }
+    if let GroupAction::Destroy = action {
+        let destroyed = stream.groups.remove(&group.data).is_some();
+        if destroyed {
+            replicated.push(StreamCommand::Group(
+                key.clone(),
+                group.clone(),
+                action.clone(),
+            ));
+        }
+        return Ok(RedisValue::Integer(destroyed as i64));
+    }
+
+    let last_id = stream.last_id;
+    let consumer_group = match stream.groups.get_mut(&group.data) {
+        Some(consumer_group) => consumer_group,
+        None => {
+            let group: String = group.into();
+            return Err(RedisValue::error(
+                format!(
+                    "NOGROUP No such consumer group '{}' for key name '{}'",
+                    group, name
+                )
+                .as_str(),
+            ));
+        }
+    };
+    let (response, changed) = match action {
+        GroupAction::SetId(id) => {
+            let id = match id {
+                GroupId::Id(id) => *id,
+                GroupId::Last => last_id,
+            };
+            consumer_group.last_id = id;
+            replicated.push(StreamCommand::Group(
+                key.clone(),
+                group.clone(),
+                GroupAction::SetId(GroupId::Id(id)),
+            ));
+            return Ok(RedisValue::simple_string("OK"));
+        }
+        GroupAction::CreateConsumer(consumer) => {
+            let created = !consumer_group.consumers.contains_key(&consumer.data);
+            if created {
+                consumer_group.consumers.insert(
+                    consumer.data.clone(),
+                    Consumer {
+                        seen_time: now,
+                        ..Default::default()
+                    },
+                );
+            }
+            (RedisValue::Integer(created as i64), created)
+        }
+        GroupAction::DelConsumer(consumer) => {
+            // the entries pending for the consumer are no longer pending for the group
+            let pending = match consumer_group.consumers.remove(&consumer.data) {
+                Some(removed) => {
+                    for id in removed.pending.iter() {
+                        consumer_group.pending.remove(id);
+                    }
+                    Some(removed.pending.len())
+                }
+                None => None,
+            };
+            (
+                RedisValue::Integer(pending.unwrap_or(0) as i64),
+                pending.is_some(),
+            )
+        }
+        GroupAction::Create(..) | GroupAction::Destroy => unreachable!(),
+    };
+    if changed {
+        replicated.push(StreamCommand::Group(
+            key.clone(),
+            group.clone(),
+            action.clone(),
+        ));
+    }
+    Ok(response)
+}
+
+fn claim(
+    (entries, group): (&StreamEntries, &mut ConsumerGroup),
+    key: &RedisBulkString,
+    group_name: &RedisBulkString,
+    consumer: &RedisBulkString,
+    options: &ClaimOptions,
+    now: u64,
+    replicated: &mut Vec<StreamCommand>,
+) -> Result<RedisValue, RedisValue> {
+    if let Some(last_id) = options.last_id {
+        group.last_id = group.last_id.max(last_id);
+    }
+    let delivery_time = options
+        .time
+        .unwrap_or_else(|| now.saturating_sub(options.idle.unwrap_or(0)));
+    group
+        .consumer(key, group_name, consumer, replicated)
+        .seen_time = now;
+
+    let mut claimed = vec![];
+    for id in options.ids.iter() {
+        let exists = entries.contains_key(id);
+        match group.pending.get(id) {
+            None if options.force && exists => {}
+            None => continue,
+            // an entry trimmed away while pending is no longer pending
+            Some(_) if !exists => {
+                group.acknowledge(id);
+                replicated.push(StreamCommand::Ack(
+                    key.clone(),
+                    group_name.clone(),
+                    vec![*id],
+                ));
+                continue;
+            }
+            Some(entry) if now.saturating_sub(entry.delivery_time) < options.min_idle => continue,
+            Some(_) => {}
+        }
+        let last_id = group.last_id;
+        let entry = group.deliver(*id, &consumer.data, delivery_time);
+        match options.retry_count {
+            Some(count) => entry.delivery_count = count,
+            None if !options.justid => entry.delivery_count += 1,
+            None => {}
+        }
+        replicated.push(replicated_claim(
+            key, group_name, consumer, *id, entry, last_id,
+        ));
+        claimed.push(if options.justid {
+            id.into()
+        } else {
+            entry_reply(id, &entries[id])
+        });
+    }
+    if !claimed.is_empty() && !options.justid {
+        if let Some(consumer) = group.consumers.get_mut(&consumer.data) {
+            consumer.active_time = now;
+        }
+    }
+    Ok(RedisValue::Array(claimed))
+}
+
+// claim the entries idle for long enough from a cursor, examining at most ten times
+// as many entries as there are to claim
+fn auto_claim(
+    (entries, group): (&StreamEntries, &mut ConsumerGroup),
+    key: &RedisBulkString,
+    group_name: &RedisBulkString,
+    consumer: &RedisBulkString,
+    options: &AutoClaimOptions,
+    now: u64,
+    replicated: &mut Vec<StreamCommand>,
+) -> Result<RedisValue, RedisValue> {
+    group
+        .consumer(key, group_name, consumer, replicated)
+        .seen_time = now;
+
+    let mut attempts = options.count.saturating_mul(10);
+    // at most one id past the attempts is needed, as the cursor
+    let ids: Vec<StreamId> = group
+        .pending
+        .range(options.start..)
+        .map(|(id, _)| *id)
+        .take(attempts.saturating_add(1))
+        .collect();
+    let mut ids = ids.into_iter();
+    let mut claimed = vec![];
+    let mut deleted = vec![];
+    while claimed.len() < options.count && attempts > 0 {
+        let id = match ids.next() {
+            Some(id) => id,
+            None => break,
+        };
+        attempts -= 1;
+        if !entries.contains_key(&id) {
+            group.acknowledge(&id);
+            replicated.push(StreamCommand::Ack(
+                key.clone(),
+                group_name.clone(),
+                vec![id],
+            ));
+            deleted.push((&id).into());
+            continue;
+        }
+        if now.saturating_sub(group.pending[&id].delivery_time) < options.min_idle {
+            continue;
+        }
+        let last_id = group.last_id;
+        let entry = group.deliver(id, &consumer.data, now);
+        if !options.justid {
+            entry.delivery_count += 1;
+        }
+        replicated.push(replicated_claim(
+            key, group_name, consumer, id, entry, last_id,
+        ));
+        claimed.push(if options.justid {
+            (&id).into()
+        } else {
+            entry_reply(&id, &entries[&id])
+        });
+    }
+    if !claimed.is_empty() && !options.justid {
+        if let Some(consumer) = group.consumers.get_mut(&consumer.data) {
+            consumer.active_time = now;
+        }
+    }
+    // the cursor is the next pending entry, the scan is over at 0-0
+    let cursor = ids.next().unwrap_or(StreamId::MIN);
+    Ok(RedisValue::Array(vec![
+        (&cursor).into(),
+        RedisValue::Array(claimed),
+        RedisValue::Array(deleted),
+    ]))
+}
+
+fn pending(group: &ConsumerGroup, range: &Option<PendingRange>, now: u64) -> RedisValue {
+    let range = match range {
+        Some(range) => range,
+        // the summary: the number of pending entries, the smallest and greatest ids,
+        // and the number of entries pending for every consumer
+        None => {
+            let (first, last) = match (group.pending.keys().next(), group.pending.keys().last()) {
+                (Some(first), Some(last)) => (first, last),
+                _ => {
+                    return RedisValue::Array(vec![
+                        RedisValue::Integer(0),
+                        RedisValue::null_bulk_string(),
+                        RedisValue::null_bulk_string(),
+                        RedisValue::null_bulk_string(),
+                    ])
+                }
+            };
+            let consumers = group
+                .consumers
+                .iter()
+                .filter(|(_, consumer)| !consumer.pending.is_empty())
+                .map(|(name, consumer)| {
+                    RedisValue::Array(vec![
+                        RedisValue::bulk_string_from_bytes(name.as_slice()),
+                        RedisValue::bulk_string(consumer.pending.len().to_string().as_str()),
+                    ])
+                })
+                .collect();
+            return RedisValue::Array(vec![
+                RedisValue::Integer(group.pending.len() as i64),
+                first.into(),
+                last.into(),
+                RedisValue::Array(consumers),
+            ]);
+        }
+    };
+    if range.start > range.end {
+        return RedisValue::Array(vec![]);
+    }
+    let entries = group
+        .pending
+        .range(range.start..=range.end)
+        .filter(|(_, entry)| match &range.consumer {
+            Some(consumer) => consumer.data == entry.consumer,
+            None => true,
+        })
+        .filter(|(_, entry)| now.saturating_sub(entry.delivery_time) >= range.idle.unwrap_or(0))
+        .take(range.count)
+        .map(|(id, entry)| {
+            RedisValue::Array(vec![
+                id.into(),
+                RedisValue::bulk_string_from_bytes(entry.consumer.as_slice()),
+                RedisValue::Integer(now.saturating_sub(entry.delivery_time) as i64),
+                RedisValue::Integer(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    RedisValue::Array(entries)
+}
+
+// the reply along with the commands replicas replay to reach the same state,
+// which spell out what is decided here such as generated ids and delivered entries
+pub fn execute(
+    store: &mut Store,
+    command: &StreamCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> (RedisValue, Vec<StreamCommand>) {
+    let mut replicated = vec![];
+    match run(store, command, now, mode, &mut replicated) {
+        Ok(response) => (response, replicated),
+        Err(e) => (e, replicated),
+    }
+}
+
+fn run(
+    store: &mut Store,
+    command: &StreamCommand,
+    now: u64,
+    mode: ExpiryMode,
+    replicated: &mut Vec<StreamCommand>,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        StreamCommand::Add(name, nomkstream, trim, id, fields) => {
+            let key: String = name.into();
+            if get_stream(store, &key, now, mode)?.is_none() {
+                if *nomkstream {
+                    return Ok(RedisValue::null_bulk_string());
+                }
+                let stream = next_id(&Stream::default(), *id, now)
+                    .map(|_| RedisObject::Stream(Stream::default()))?;
+                store.insert(
+                    key.clone(),
+                    StoreItem {
+                        value: stream,
+                        expired_at: 0,
+                    },
+                );
+            }
+            let stream = match get_stream(store, &key, now, mode)? {
+                Some(stream) => stream,
+                None => return Err(wrong_type()),
+            };
+            let generated = next_id(stream, *id, now)?;
+            let entry = fields
+                .iter()
+                .map(|(field, value)| (field.data.clone(), value.data.clone()))
+                .collect();
+            stream.entries.insert(generated, entry);
+            stream.last_id = generated;
+            stream.entries_added += 1;
+            if let Some(trim) = trim {
+                stream.trim(trim);
+            }
+            // replicas add the entry with the id generated here instead of one of their own
+            replicated.push(StreamCommand::Add(
+                name.clone(),
+                *nomkstream,
+                *trim,
+                AddId::Explicit(generated),
+                fields.clone(),
+            ));
+            (&generated).into()
+        }
+        StreamCommand::Range(key, start, end, count, rev) => {
+            let key: String = key.into();
+            let stream = match get_stream(store, &key, now, mode)? {
+                Some(stream) if start <= end => stream,
+                _ => return Ok(RedisValue::Array(vec![])),
+            };
+            let range = stream.entries.range(start..=end);
+            let count = count.unwrap_or(usize::MAX);
+            let entries = if *rev {
+                range
+                    .rev()
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            } else {
+                range
+                    .take(count)
+                    .map(|(id, fields)| entry_reply(id, fields))
+                    .collect()
+            };
+            RedisValue::Array(entries)
+        }
+        StreamCommand::Len(key) => {
+            let key: String = key.into();
+            let length =
+                get_stream(store, &key, now, mode)?.map_or(0, |stream| stream.entries.len());
+            RedisValue::Integer(length as i64)
+        }
+        StreamCommand::Trim(key, options) => {
+            let name: String = key.into();
+            let trimmed = match get_stream(store, &name, now, mode)? {
+                Some(stream) => stream.trim(options),
+                None => 0,
+            };
+            if trimmed > 0 {
+                replicated.push(command.clone());
+            }
+            RedisValue::Integer(trimmed as i64)
+        }
+        StreamCommand::Read(options) => {
+            let ids = resolve_ids(store, options, now, mode)?;
+            read(store, &options.keys, &ids, options.count, now, mode)?
+                .unwrap_or(RedisValue::null_bulk_string())
+        }
+        StreamCommand::Group(key, group, action) => {
+            run_group_action(store, key, group, action, now, mode, replicated)?
+        }
+        StreamCommand::ReadGroup(options) => read_group(store, options, now, mode, replicated)?
+            .unwrap_or(RedisValue::null_bulk_string()),
+        StreamCommand::Ack(key, group, ids) => {
+            let acknowledged = match get_group(store, key, group, now, mode)? {
+                Some((_, group)) => ids.iter().filter(|id| group.acknowledge(id)).count(),
+                None => 0,
+            };
+            if acknowledged > 0 {
+                replicated.push(command.clone());
+            }
+            RedisValue::Integer(acknowledged as i64)
+        }
+        StreamCommand::Pending(key, group, range) => {
+            let (_, found) =
+                get_group(store, key, group, now, mode)?.ok_or_else(|| no_group(key, group, ""))?;
+            pending(found, range, now)
+        }
+        StreamCommand::Claim(key, group, consumer, options) => {
+            let found =
+                get_group(store, key, group, now, mode)?.ok_or_else(|| no_group(key, group, ""))?;
+            claim(found, key, group, consumer, options, now, replicated)?
+        }
+        StreamCommand::AutoClaim(key, group, consumer, options) => {
+            let found =
+                get_group(store, key, group, now, mode)?.ok_or_else(|| no_group(key, group, ""))?;
+            auto_claim(found, key, group, consumer, options, now, replicated)?
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command_at;
+
+    fn ids(value: RedisValue) -> Vec<String> {
+        match value {
+            RedisValue::Array(entries) => entries
+                .iter()
+                .map(|entry| match entry {
+                    RedisValue::Array(entry) => match &entry[0] {
+                        RedisValue::BulkString(Some(id)) => id.into(),
+                        _ => panic!("{:?} has no id", entry),
+                    },
+                    _ => panic!("{:?} is not an entry", entry),
+                })
+                .collect(),
+            value => panic!("{:?} is not an array", value),
+        }
+    }
+
+    #[test]
+    fn test_add_ids() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::error("ERR The ID specified in XADD must be greater than 0-0"),
+            run_command_at(&mut store, &["xadd", "s", "0-0", "f", "v"], 0)
+        );
+        assert!(store.is_empty());
+        assert_eq!(
+            RedisValue::bulk_string("0-1"),
+            run_command_at(&mut store, &["xadd", "s", "0-*", "f", "v"], 0)
+        );
+        assert_eq!(
+            RedisValue::bulk_string("5-0"),
+            run_command_at(&mut store, &["xadd", "s", "5", "f", "v"], 0)
+        );
+        assert_eq!(
+            RedisValue::error(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+            ),
+            run_command_at(&mut store, &["xadd", "s", "5-0", "f", "v"], 0)
+        );
+        assert_eq!(
+            RedisValue::bulk_string("5-1"),
+            run_command_at(&mut store, &["xadd", "s", "*", "f", "v"], 3)
+        );
+        assert_eq!(
+            RedisValue::bulk_string("10-0"),
+            run_command_at(&mut store, &["xadd", "s", "*", "f", "v"], 10)
+        );
+        assert_eq!(
+            RedisValue::error("ERR Invalid stream ID specified as stream command argument"),
+            run_command_at(&mut store, &["xadd", "s", "x-1", "f", "v"], 10)
+        );
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command_at(&mut store, &["xadd", "t", "nomkstream", "*", "f", "v"], 10)
+        );
+    }
+
+    #[test]
+    fn test_range_and_trim() {
+        let mut store = Store::default();
+        for id in ["1-1", "1-2", "2-0", "3-5"] {
+            run_command_at(&mut store, &["xadd", "s", id, "f", "v"], 0);
+        }
+        assert_eq!(
+            vec!["1-1", "1-2"],
+            ids(run_command_at(&mut store, &["xrange", "s", "-", "1"], 0))
+        );
+        assert_eq!(
+            vec!["3-5", "2-0"],
+            ids(run_command_at(
+                &mut store,
+                &["xrevrange", "s", "+", "(1-2"],
+                0
+            ))
+        );
+        assert_eq!(
+            vec!["1-2"],
+            ids(run_command_at(
+                &mut store,
+                &["xrange", "s", "(1-1", "+", "count", "1"],
+                0
+            ))
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["xtrim", "s", "minid", "1-2"], 0)
+        );
+        assert_eq!(
+            RedisValue::bulk_string("4-0"),
+            run_command_at(
+                &mut store,
+                &["xadd", "s", "maxlen", "=", "2", "4-0", "f", "v"],
+                0
+            )
+        );
+        assert_eq!(
+            vec!["3-5", "4-0"],
+            ids(run_command_at(&mut store, &["xrange", "s", "-", "+"], 0))
+        );
+        // an emptied stream is kept along with its last id
+        run_command_at(&mut store, &["xtrim", "s", "maxlen", "0"], 0);
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command_at(&mut store, &["xlen", "s"], 0)
+        );
+        assert_eq!(
+            RedisValue::bulk_string("4-1"),
+            run_command_at(&mut store, &["xadd", "s", "4-*", "f", "v"], 0)
+        );
+    }
+
+    #[test]
+    fn test_read() {
+        let mut store = Store::default();
+        run_command_at(&mut store, &["xadd", "a", "1-1", "f", "v"], 0);
+        run_command_at(&mut store, &["xadd", "a", "1-2", "f", "v"], 0);
+        run_command_at(&mut store, &["xadd", "b", "2-0", "f", "v"], 0);
+        let response = run_command_at(
+            &mut store,
+            &["xread", "count", "1", "streams", "a", "b", "1-1", "$"],
+            0,
+        );
+        assert_eq!(
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                RedisValue::bulk_string("a"),
+                RedisValue::Array(vec![RedisValue::Array(vec![
+                    RedisValue::bulk_string("1-2"),
+                    RedisValue::Array(vec![
+                        RedisValue::bulk_string("f"),
+                        RedisValue::bulk_string("v")
+                    ]),
+                ])]),
+            ])]),
+            response
+        );
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command_at(&mut store, &["xread", "streams", "a", "b", "1-2", "2-0"], 0)
+        );
+    }
+
+    #[test]
+    fn test_group_read_and_ack() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command_at(
+                &mut store,
+                &["xgroup", "create", "s", "g", "$", "mkstream"],
+                0
+            )
+        );
+        assert_eq!(
+            RedisValue::error("BUSYGROUP Consumer Group name already exists"),
+            run_command_at(&mut store, &["xgroup", "create", "s", "g", "0"], 0)
+        );
+        for id in ["1-1", "1-2", "1-3"] {
+            run_command_at(&mut store, &["xadd", "s", id, "f", "v"], 0);
+        }
+        let read = |store: &mut Store, consumer: &str, id: &str, now| {
+            let response = run_command_at(
+                store,
+                &[
+                    "xreadgroup",
+                    "group",
+                    "g",
+                    consumer,
+                    "count",
+                    "2",
+                    "streams",
+                    "s",
+                    id,
+                ],
+                now,
+            );
+            match response {
+                RedisValue::Array(streams) => match &streams[0] {
+                    RedisValue::Array(stream) => ids(stream[1].clone()),
+                    value => panic!("{:?} is not a stream", value),
+                },
+                RedisValue::BulkString(None) => vec![],
+                value => panic!("{:?} is not a reply of streams", value),
+            }
+        };
+        assert_eq!(vec!["1-1", "1-2"], read(&mut store, "alice", ">", 10));
+        assert_eq!(vec!["1-3"], read(&mut store, "bob", ">", 20));
+        assert!(read(&mut store, "bob", ">", 20).is_empty());
+        // the history is read again without moving the group forward
+        assert_eq!(vec!["1-2"], read(&mut store, "alice", "1-1", 30));
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(&mut store, &["xack", "s", "g", "1-1", "9-9"], 30)
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::Integer(2),
+                RedisValue::bulk_string("1-2"),
+                RedisValue::bulk_string("1-3"),
+                RedisValue::Array(vec![
+                    RedisValue::Array(vec![
+                        RedisValue::bulk_string("alice"),
+                        RedisValue::bulk_string("1"),
+                    ]),
+                    RedisValue::Array(vec![
+                        RedisValue::bulk_string("bob"),
+                        RedisValue::bulk_string("1"),
+                    ]),
+                ]),
+            ]),
+            run_command_at(&mut store, &["xpending", "s", "g"], 40)
+        );
+        assert_eq!(
+            RedisValue::Array(vec![RedisValue::Array(vec![
+                RedisValue::bulk_string("1-2"),
+                RedisValue::bulk_string("alice"),
+                RedisValue::Integer(10),
+                RedisValue::Integer(2),
+            ])]),
+            run_command_at(
+                &mut store,
+                &["xpending", "s", "g", "-", "+", "10", "alice"],
+                40
+            )
+        );
+        assert_eq!(
+            RedisValue::error(
+                "NOGROUP No such key 's' or consumer group 'h' in XREADGROUP with GROUP option"
+            ),
+            run_command_at(
+                &mut store,
+                &["xreadgroup", "group", "h", "c", "streams", "s", ">"],
+                40
+            )
+        );
+    }
+
+    #[test]
+    fn test_claim() {
+        let mut store = Store::default();
+        run_command_at(
+            &mut store,
+            &["xgroup", "create", "s", "g", "0", "mkstream"],
+            0,
+        );
+        for id in ["1-1", "1-2", "1-3"] {
+            run_command_at(&mut store, &["xadd", "s", id, "f", "v"], 0);
+        }
+        run_command_at(
+            &mut store,
+            &["xreadgroup", "group", "g", "alice", "streams", "s", ">"],
+            0,
+        );
+        // only the entries idle for long enough are claimed
+        run_command_at(
+            &mut store,
+            &["xclaim", "s", "g", "bob", "0", "1-1", "justid"],
+            50,
+        );
+        assert_eq!(
+            RedisValue::Array(vec![RedisValue::bulk_string("1-2")]),
+            run_command_at(
+                &mut store,
+                &["xclaim", "s", "g", "bob", "100", "1-1", "1-2", "justid"],
+                100
+            )
+        );
+        // an entry trimmed away while pending is reported as deleted
+        run_command_at(&mut store, &["xtrim", "s", "minid", "1-3"], 100);
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::bulk_string("0-0"),
+                RedisValue::Array(vec![RedisValue::bulk_string("1-3")]),
+                RedisValue::Array(vec![
+                    RedisValue::bulk_string("1-1"),
+                    RedisValue::bulk_string("1-2"),
+                ]),
+            ]),
+            run_command_at(
+                &mut store,
+                &["xautoclaim", "s", "g", "carol", "10", "-", "justid"],
+                200
+            )
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command_at(
+                &mut store,
+                &["xgroup", "delconsumer", "s", "g", "carol"],
+                200
+            )
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::Integer(0),
+                RedisValue::null_bulk_string(),
+                RedisValue::null_bulk_string(),
+                RedisValue::null_bulk_string(),
+            ]),
+            run_command_at(&mut store, &["xpending", "s", "g"], 200)
+        );
+    }
+}
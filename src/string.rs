@@ -0,0 +1,643 @@
+use crate::command::{
+    check_key, CommandArgs, RedisCommand, RedisCommandError, SetExpiry, SetOptions,
+};
+use crate::object::{normalize_range, wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+
+// the largest string a command may produce, as the default `proto-max-bulk-len`
+pub const MAX_STRING_SIZE: usize = 512 * 1024 * 1024;
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum GetExExpiry {
+    // never `SetExpiry::KeepTtl`
+    Expire(SetExpiry),
+    Persist,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum StringCommand {
+    // INCR, DECR and DECRBY are increments by 1, -1 and the opposite of the decrement
+    IncrBy(RedisBulkString, i64),
+    IncrByFloat(RedisBulkString, f64),
+    Append(RedisBulkString, RedisBulkString),
+    StrLen(RedisBulkString),
+    // start and end, both inclusive
+    GetRange(RedisBulkString, i64, i64),
+    SetRange(RedisBulkString, usize, RedisBulkString),
+    GetDel(RedisBulkString),
+    GetEx(RedisBulkString, Option<GetExExpiry>),
+    MGet(Vec<RedisBulkString>),
+    MSet(Vec<(RedisBulkString, RedisBulkString)>),
+    MSetNx(Vec<(RedisBulkString, RedisBulkString)>),
+    SetNx(RedisBulkString, RedisBulkString),
+}
+
+impl StringCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<StringCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "incr" => StringCommand::IncrBy(args.next_key()?, 1),
+            "decr" => StringCommand::IncrBy(args.next_key()?, -1),
+            "incrby" => StringCommand::IncrBy(args.next_key()?, args.next_integer()?),
+            "decrby" => {
+                let key = args.next_key()?;
+                let decrement: i64 = args.next_integer()?;
+                match decrement.checked_neg() {
+                    Some(increment) => StringCommand::IncrBy(key, increment),
+                    None => {
+                        return Err(RedisCommandError::Invalid(
+                            "decrement would overflow".to_string(),
+                        ))
+                    }
+                }
+            }
+            "incrbyfloat" => StringCommand::IncrByFloat(args.next_key()?, args.next_float()?),
+            "append" => StringCommand::Append(args.next_key()?, args.next_bulk_string()?),
+            "strlen" => StringCommand::StrLen(args.next_key()?),
+            "getrange" | "substr" => StringCommand::GetRange(
+                args.next_key()?,
+                args.next_integer()?,
+                args.next_integer()?,
+            ),
+            "setrange" => {
+                let key = args.next_key()?;
+                let offset: i64 = args.next_integer()?;
+                let offset = match usize::try_from(offset) {
+                    Ok(offset) => offset,
+                    Err(_) => {
+                        return Err(RedisCommandError::Invalid(
+                            "offset is out of range".to_string(),
+                        ))
+                    }
+                };
+                StringCommand::SetRange(key, offset, args.next_bulk_string()?)
+            }
+            "getdel" => StringCommand::GetDel(args.next_key()?),
+            "getex" => {
+                let key = args.next_key()?;
+                let mut expiry = None;
+                while !args.is_empty() {
+                    if expiry.is_some() {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    let option = args.next_string()?.to_lowercase();
+                    if option == "persist" {
+                        expiry = Some(GetExExpiry::Persist);
+                        continue;
+                    }
+                    if args.is_empty() {
+                        return Err(RedisCommandError::IlleagalArg);
+                    }
+                    expiry = Some(GetExExpiry::Expire(match option.as_str() {
+                        "ex" => SetExpiry::Ex(args.next_expire_time()?),
+                        "px" => SetExpiry::Px(args.next_expire_time()?),
+                        "exat" => SetExpiry::ExAt(args.next_expire_time()?),
+                        "pxat" => SetExpiry::PxAt(args.next_expire_time()?),
+                        _ => return Err(RedisCommandError::IlleagalArg),
+                    }));
+                }
+                StringCommand::GetEx(key, expiry)
+            }
+            "mget" => StringCommand::MGet(args.rest_keys()?),
+            "mset" | "msetnx" => {
+                let rest = args.rest()?;
+                if rest.len() % 2 != 0 {
+                    return Err(args.wrong_number());
+                }
+                rest.iter().step_by(2).try_for_each(check_key)?;
+                let pairs = rest
+                    .chunks(2)
+                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                    .collect();
+                match command_name {
+                    "mset" => StringCommand::MSet(pairs),
+                    _ => StringCommand::MSetNx(pairs),
+                }
+            }
+            "setnx" => StringCommand::SetNx(args.next_key()?, args.next_bulk_string()?),
+            name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    // the first key for the commands of several keys
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            StringCommand::IncrBy(key, _)
+            | StringCommand::IncrByFloat(key, _)
+            | StringCommand::Append(key, _)
+            | StringCommand::StrLen(key)
+            | StringCommand::GetRange(key, _, _)
+            | StringCommand::SetRange(key, _, _)
+            | StringCommand::GetDel(key)
+            | StringCommand::GetEx(key, _)
+            | StringCommand::SetNx(key, _) => key,
+            StringCommand::MGet(keys) => &keys[0],
+            StringCommand::MSet(pairs) | StringCommand::MSetNx(pairs) => &pairs[0].0,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        !matches!(
+            self,
+            StringCommand::StrLen(_) | StringCommand::GetRange(..) | StringCommand::MGet(_)
+        )
+    }
+}
+
+impl From<&StringCommand> for Vec<RedisValue> {
+    fn from(string_command: &StringCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let pairs = |name: &str, pairs: &Vec<(RedisBulkString, RedisBulkString)>| {
+            let mut vs = vec![RedisValue::bulk_string(name)];
+            for (key, value) in pairs {
+                vs.push(key.into());
+                vs.push(value.into());
+            }
+            vs
+        };
+        match string_command {
+            StringCommand::IncrBy(key, increment) => {
+                let mut vs = command("incrby", key);
+                vs.push(RedisValue::bulk_string(increment.to_string().as_str()));
+                vs
+            }
+            StringCommand::IncrByFloat(key, increment) => {
+                let mut vs = command("incrbyfloat", key);
+                vs.push(RedisValue::bulk_string(
+                    utilities::format_float(*increment).as_str(),
+                ));
+                vs
+            }
+            StringCommand::Append(key, value) => {
+                let mut vs = command("append", key);
+                vs.push(value.into());
+                vs
+            }
+            StringCommand::StrLen(key) => command("strlen", key),
+            StringCommand::GetRange(key, start, end) => {
+                let mut vs = command("getrange", key);
+                vs.push(RedisValue::bulk_string(start.to_string().as_str()));
+                vs.push(RedisValue::bulk_string(end.to_string().as_str()));
+                vs
+            }
+            StringCommand::SetRange(key, offset, value) => {
+                let mut vs = command("setrange", key);
+                vs.push(RedisValue::bulk_string(offset.to_string().as_str()));
+                vs.push(value.into());
+                vs
+            }
+            StringCommand::GetDel(key) => command("getdel", key),
+            StringCommand::GetEx(key, expiry) => {
+                let mut vs = command("getex", key);
+                let option = match expiry {
+                    None => None,
+                    Some(GetExExpiry::Persist) => {
+                        vs.push(RedisValue::bulk_string("persist"));
+                        None
+                    }
+                    Some(GetExExpiry::Expire(SetExpiry::Ex(s))) => Some(("ex", s)),
+                    Some(GetExExpiry::Expire(SetExpiry::Px(ms))) => Some(("px", ms)),
+                    Some(GetExExpiry::Expire(SetExpiry::ExAt(s))) => Some(("exat", s)),
+                    Some(GetExExpiry::Expire(SetExpiry::PxAt(ms))) => Some(("pxat", ms)),
+                    Some(GetExExpiry::Expire(SetExpiry::KeepTtl)) => None,
+                };
+                if let Some((name, time)) = option {
+                    vs.push(RedisValue::bulk_string(name));
+                    vs.push(RedisValue::bulk_string(time.to_string().as_str()));
+                }
+                vs
+            }
+            StringCommand::MGet(keys) => {
+                let mut vs = vec![RedisValue::bulk_string("mget")];
+                vs.extend(keys.iter().map(|key| key.into()));
+                vs
+            }
+            StringCommand::MSet(kvs) => pairs("mset", kvs),
+            StringCommand::MSetNx(kvs) => pairs("msetnx", kvs),
+            StringCommand::SetNx(key, value) => {
+                let mut vs = command("setnx", key);
+                vs.push(value.into());
+                vs
+            }
+        }
+    }
+}
+
+// the command replicas replay, INCRBYFLOAT sets the resulting value so that the
+// rounding of replicas cannot drift from the one here, and the expiry of GETEX is
+// sent as an absolute time so that both sides expire the key together
+pub fn replicated(command: &StringCommand, response: &RedisValue, now: u64) -> RedisCommand {
+    match (command, response) {
+        (StringCommand::IncrByFloat(key, _), RedisValue::BulkString(Some(value))) => {
+            RedisCommand::Set(
+                key.clone(),
+                value.clone(),
+                SetOptions {
+                    expiry: Some(SetExpiry::KeepTtl),
+                    ..Default::default()
+                },
+            )
+        }
+        (StringCommand::GetEx(key, Some(GetExExpiry::Expire(expiry))), _) => {
+            let expiry = match expiry.expired_at(now) {
+                Some(expired_at) => GetExExpiry::Expire(SetExpiry::PxAt(expired_at)),
+                None => GetExExpiry::Expire(*expiry),
+            };
+            RedisCommand::String(StringCommand::GetEx(key.clone(), Some(expiry)))
+        }
+        (command, _) => RedisCommand::String(command.clone()),
+    }
+}
+
+// the string stored at the key, none if the key does not exist
+pub fn get_string<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut Vec<u8>>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::String(s),
+            ..
+        }) => Ok(Some(s)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+// replace the string of the key, keeping its time to live
+pub fn put_string(store: &mut Store, key: &str, value: Vec<u8>, now: u64, mode: ExpiryMode) {
+    match get_string(store, key, now, mode) {
+        Ok(Some(s)) => *s = value,
+        _ => {
+            store.insert(
+                key.to_string(),
+                StoreItem {
+                    value: RedisObject::String(value),
+                    expired_at: 0,
+                },
+            );
+        }
+    }
+}
+
+fn too_large() -> RedisValue {
+    RedisValue::error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")
+}
+
+fn bulk_string(value: Option<&mut Vec<u8>>) -> RedisValue {
+    match value {
+        Some(value) => RedisValue::bulk_string_from_bytes(value.as_slice()),
+        None => RedisValue::null_bulk_string(),
+    }
+}
+
+pub fn execute(
+    store: &mut Store,
+    command: &StringCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> RedisValue {
+    match run(store, command, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    }
+}
+
+fn run(
+    store: &mut Store,
+    command: &StringCommand,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let key: String = command.key().into();
+    let response = match command {
+        StringCommand::IncrBy(_, increment) => {
+            let current: i64 = match get_string(store, &key, now, mode)? {
+                Some(value) => match std::str::from_utf8(value).ok().and_then(|v| v.parse().ok()) {
+                    Some(current) => current,
+                    None => return Err((&RedisCommandError::NotInteger).into()),
+                },
+                None => 0,
+            };
+            let value = match current.checked_add(*increment) {
+                Some(value) => value,
+                None => {
+                    return Err(RedisValue::error(
+                        "ERR increment or decrement would overflow",
+                    ))
+                }
+            };
+            put_string(store, &key, value.to_string().into_bytes(), now, mode);
+            RedisValue::Integer(value)
+        }
+        StringCommand::IncrByFloat(_, increment) => {
+            let current: f64 = match get_string(store, &key, now, mode)? {
+                Some(value) => match std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|v| v.parse::<f64>().ok())
+                    .filter(|v| !v.is_nan())
+                {
+                    Some(current) => current,
+                    None => return Err((&RedisCommandError::NotFloat).into()),
+                },
+                None => 0.0,
+            };
+            let value = current + increment;
+            if !value.is_finite() {
+                return Err(RedisValue::error(
+                    "ERR increment would produce NaN or Infinity",
+                ));
+            }
+            let value = utilities::format_float(value);
+            put_string(store, &key, value.clone().into_bytes(), now, mode);
+            RedisValue::bulk_string(value.as_str())
+        }
+        StringCommand::Append(_, value) => match get_string(store, &key, now, mode)? {
+            Some(s) => {
+                if s.len() + value.data.len() > MAX_STRING_SIZE {
+                    return Err(too_large());
+                }
+                s.extend_from_slice(&value.data);
+                RedisValue::Integer(s.len() as i64)
+            }
+            None => {
+                put_string(store, &key, value.data.clone(), now, mode);
+                RedisValue::Integer(value.data.len() as i64)
+            }
+        },
+        StringCommand::StrLen(_) => {
+            RedisValue::Integer(get_string(store, &key, now, mode)?.map_or(0, |s| s.len()) as i64)
+        }
+        StringCommand::GetRange(_, start, end) => {
+            let s = get_string(store, &key, now, mode)?.map_or(&[][..], |s| s.as_slice());
+            match normalize_range(*start, *end, s.len()) {
+                Some((start, end)) => RedisValue::bulk_string_from_bytes(&s[start..=end]),
+                None => RedisValue::bulk_string(""),
+            }
+        }
+        StringCommand::SetRange(_, offset, value) => {
+            let current = get_string(store, &key, now, mode)?;
+            // an empty value neither creates nor pads the string
+            if value.data.is_empty() {
+                return Ok(RedisValue::Integer(current.map_or(0, |s| s.len()) as i64));
+            }
+            if offset + value.data.len() > MAX_STRING_SIZE {
+                return Err(too_large());
+            }
+            let s = match current {
+                Some(s) => s,
+                None => {
+                    put_string(store, &key, Vec::new(), now, mode);
+                    get_string(store, &key, now, mode)?.unwrap()
+                }
+            };
+            let end = offset + value.data.len();
+            if s.len() < end {
+                s.resize(end, 0);
+            }
+            s[*offset..end].copy_from_slice(&value.data);
+            RedisValue::Integer(s.len() as i64)
+        }
+        StringCommand::GetDel(_) => {
+            let response = bulk_string(get_string(store, &key, now, mode)?);
+            store.remove(&key);
+            response
+        }
+        StringCommand::GetEx(_, expiry) => {
+            let response = bulk_string(get_string(store, &key, now, mode)?);
+            if let Some(item) = store.get_alive(&key, now, mode) {
+                let expired_at = match expiry {
+                    Some(GetExExpiry::Expire(expiry)) => {
+                        expiry.expired_at(now).unwrap_or(item.expired_at)
+                    }
+                    Some(GetExExpiry::Persist) => 0,
+                    None => item.expired_at,
+                };
+                store.set_expiry(&key, expired_at);
+            }
+            response
+        }
+        StringCommand::MGet(keys) => {
+            let mut values = Vec::with_capacity(keys.len());
+            for key in keys {
+                let key: String = key.into();
+                values.push(match get_string(store, &key, now, mode) {
+                    Ok(value) => bulk_string(value),
+                    // the values of other types are nil
+                    Err(_) => RedisValue::null_bulk_string(),
+                });
+            }
+            RedisValue::Array(values)
+        }
+        StringCommand::MSet(pairs) => {
+            mset(store, pairs);
+            RedisValue::simple_string("OK")
+        }
+        StringCommand::MSetNx(pairs) => {
+            // none of the keys is set if any of them exists
+            if pairs.iter().any(|(key, _)| {
+                let key: String = key.into();
+                store.get_alive(&key, now, mode).is_some()
+            }) {
+                RedisValue::Integer(0)
+            } else {
+                mset(store, pairs);
+                RedisValue::Integer(1)
+            }
+        }
+        StringCommand::SetNx(_, value) => {
+            if store.get_alive(&key, now, mode).is_some() {
+                RedisValue::Integer(0)
+            } else {
+                put_string(store, &key, value.data.clone(), now, mode);
+                RedisValue::Integer(1)
+            }
+        }
+    };
+    Ok(response)
+}
+
+// set the keys as `SET` does, dropping their time to live
+fn mset(store: &mut Store, pairs: &[(RedisBulkString, RedisBulkString)]) {
+    for (key, value) in pairs {
+        store.insert(
+            key.into(),
+            StoreItem {
+                value: RedisObject::String(value.data.clone()),
+                expired_at: 0,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    #[test]
+    fn test_counters() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["incr", "n"])
+        );
+        assert_eq!(
+            RedisValue::Integer(-9),
+            run_command(&mut store, &["decrby", "n", "10"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR value is not an integer or out of range"),
+            run_command(&mut store, &["incrby", "n", "1.5"])
+        );
+        run_command(&mut store, &["incrby", "max", &i64::MAX.to_string()]);
+        assert_eq!(
+            RedisValue::error("ERR increment or decrement would overflow"),
+            run_command(&mut store, &["incr", "max"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR decrement would overflow"),
+            run_command(&mut store, &["decrby", "n", &i64::MIN.to_string()])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("-8.5"),
+            run_command(&mut store, &["incrbyfloat", "n", "0.5"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR value is not an integer or out of range"),
+            run_command(&mut store, &["incr", "n"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("5000"),
+            run_command(&mut store, &["incrbyfloat", "f", "5.0e3"])
+        );
+        store.insert(
+            "l".to_string(),
+            StoreItem {
+                value: RedisObject::List(vec![b"a".to_vec()].into()),
+                expired_at: 0,
+            },
+        );
+        assert_eq!(wrong_type(), run_command(&mut store, &["decr", "l"]));
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::Integer(5),
+            run_command(&mut store, &["append", "s", "Hello"])
+        );
+        assert_eq!(
+            RedisValue::Integer(11),
+            run_command(&mut store, &["append", "s", " World"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("Hell"),
+            run_command(&mut store, &["getrange", "s", "0", "3"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("orld"),
+            run_command(&mut store, &["getrange", "s", "-4", "-1"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("Hello World"),
+            run_command(&mut store, &["getrange", "s", "-100", "100"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string(""),
+            run_command(&mut store, &["getrange", "s", "5", "3"])
+        );
+        assert_eq!(
+            RedisValue::Integer(11),
+            run_command(&mut store, &["setrange", "s", "6", "Redis"])
+        );
+        assert_eq!(
+            RedisValue::Integer(11),
+            run_command(&mut store, &["strlen", "s"])
+        );
+
+        // the string is padded with zero bytes
+        assert_eq!(
+            RedisValue::Integer(5),
+            run_command(&mut store, &["setrange", "p", "3", "ab"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string_from_bytes(&b"\0\0\0ab"[..]),
+            run_command(&mut store, &["getrange", "p", "0", "-1"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["setrange", "e", "3", ""])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["strlen", "e"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR offset is out of range"),
+            run_command(&mut store, &["setrange", "s", "-1", "a"])
+        );
+        assert_eq!(
+            too_large(),
+            run_command(&mut store, &["setrange", "s", "536870911", "ab"])
+        );
+    }
+
+    #[test]
+    fn test_multiple_keys_and_expiry() {
+        let mut store = Store::default();
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            run_command(&mut store, &["mset", "a", "1", "b", "2"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR wrong number of arguments for 'mset' command"),
+            run_command(&mut store, &["mset", "a", "1", "b"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["msetnx", "c", "3", "a", "4"])
+        );
+        assert_eq!(
+            RedisValue::Array(vec![
+                RedisValue::bulk_string("1"),
+                RedisValue::bulk_string("2"),
+                RedisValue::null_bulk_string(),
+            ]),
+            run_command(&mut store, &["mget", "a", "b", "c"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["setnx", "a", "5"])
+        );
+
+        assert_eq!(
+            RedisValue::bulk_string("1"),
+            run_command(&mut store, &["getex", "a", "pxat", "100"])
+        );
+        assert_eq!(100, store["a"].expired_at);
+        run_command(&mut store, &["getex", "a", "persist"]);
+        assert_eq!(0, store["a"].expired_at);
+        assert_eq!(
+            RedisValue::error("ERR syntax error"),
+            run_command(&mut store, &["getex", "a", "ex", "1", "persist"])
+        );
+
+        assert_eq!(
+            RedisValue::bulk_string("2"),
+            run_command(&mut store, &["getdel", "b"])
+        );
+        assert!(!store.contains_key("b"));
+    }
+}
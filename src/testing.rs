@@ -0,0 +1,36 @@
+use crate::command::{RedisCommand, RedisCommandError};
+use crate::redis::{ExpiryMode, Store};
+use crate::value::RedisValue;
+use crate::{bitmap, geo, hash, hyperloglog, keyspace, list, set, stream, string, zset};
+
+// run a command given by its arguments in a database, as a master would, a command which
+// does not parse replies its error
+pub fn run_command_in(dbs: &mut [Store], db: usize, args: &[&str], now: u64) -> RedisValue {
+    let args = args.iter().map(|s| RedisValue::bulk_string(*s)).collect();
+    let command: Result<RedisCommand, RedisCommandError> = RedisValue::Array(args).try_into();
+    let mode = ExpiryMode::Delete;
+    match command {
+        Ok(RedisCommand::String(command)) => string::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::Bitmap(command)) => bitmap::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::HyperLogLog(command)) => {
+            hyperloglog::execute(&mut dbs[db], &command, now, mode)
+        }
+        Ok(RedisCommand::Geo(command)) => geo::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::Keyspace(command)) => keyspace::execute(dbs, db, &command, now, mode),
+        Ok(RedisCommand::List(command)) => list::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::Hash(command)) => hash::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::Sets(command)) => set::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::ZSet(command)) => zset::execute(&mut dbs[db], &command, now, mode),
+        Ok(RedisCommand::Stream(command)) => stream::execute(&mut dbs[db], &command, now, mode).0,
+        Ok(command) => panic!("{:?} is served by the worker", command),
+        Err(e) => (&e).into(),
+    }
+}
+
+pub fn run_command_at(store: &mut Store, args: &[&str], now: u64) -> RedisValue {
+    run_command_in(std::slice::from_mut(store), 0, args, now)
+}
+
+pub fn run_command(store: &mut Store, args: &[&str]) -> RedisValue {
+    run_command_at(store, args, 0)
+}
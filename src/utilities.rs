@@ -1,7 +1,136 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub fn now() -> u64 {
     let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    let now = now.as_secs() * 1000 + now.subsec_millis() as u64;
-    now
+    now.as_secs() * 1000 + now.subsec_millis() as u64
+}
+
+// a random 40 characters hex string, as used for replication ids
+pub fn random_id() -> String {
+    let mut id = String::new();
+    while id.len() < 40 {
+        id.push_str(format!("{:016x}", random_u64()).as_str());
+    }
+    id.truncate(40);
+    id
+}
+
+// a random number, good enough to pick members at random but not for cryptography
+pub fn random_u64() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(now());
+    hasher.finish()
+}
+
+// the shortest representation which parses back to the same number, like redis replies floats
+pub fn format_float(f: f64) -> String {
+    if f.is_infinite() {
+        if f > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        format!("{}", f)
+    }
+}
+
+// glob-style matching of redis patterns: `*`, `?`, `[abc]`, `[^a-z]` and `\` escapes.
+// on a mismatch only the last `*` is retried one byte further, so that matching takes
+// at most the length of the pattern times the length of the string
+pub fn glob_match(pattern: &[u8], s: &[u8]) -> bool {
+    let (mut p, mut i) = (0, 0);
+    // the pattern after the last `*`, and where in the string it was last tried
+    let mut star: Option<(usize, usize)> = None;
+    while i < s.len() {
+        if pattern.get(p) == Some(&b'*') {
+            p += 1;
+            star = Some((p, i));
+            continue;
+        }
+        match glob_match_one(pattern, p, s[i]) {
+            Some(next) => {
+                p = next;
+                i += 1;
+            }
+            None => match star {
+                Some((star_p, star_i)) => {
+                    p = star_p;
+                    i = star_i + 1;
+                    star = Some((star_p, i));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == b'*')
+}
+
+// whether the part of the pattern at `p` matches a byte, giving where the pattern goes on
+fn glob_match_one(pattern: &[u8], p: usize, c: u8) -> Option<usize> {
+    match pattern.get(p)? {
+        b'?' => Some(p + 1),
+        b'[' => {
+            let mut i = p + 1;
+            let negate = pattern.get(i) == Some(&b'^');
+            if negate {
+                i += 1;
+            }
+            let mut matched = false;
+            while i < pattern.len() && pattern[i] != b']' {
+                if pattern[i] == b'\\' && i + 1 < pattern.len() {
+                    matched |= pattern[i + 1] == c;
+                    i += 2;
+                } else if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']'
+                {
+                    let (low, high) = (
+                        pattern[i].min(pattern[i + 2]),
+                        pattern[i].max(pattern[i + 2]),
+                    );
+                    matched |= low <= c && c <= high;
+                    i += 3;
+                } else {
+                    matched |= pattern[i] == c;
+                    i += 1;
+                }
+            }
+            // an unterminated class matches up to the end of the pattern
+            (matched != negate).then_some((i + 1).min(pattern.len()))
+        }
+        b'\\' if p + 1 < pattern.len() => (pattern[p + 1] == c).then_some(p + 2),
+        b => (*b == c).then_some(p + 1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(glob_match(b"h*llo", b"heeeello"));
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"user:\\*", b"user:*"));
+        assert!(!glob_match(b"user:\\*", b"user:1"));
+        assert!(!glob_match(b"a*b", b"acd"));
+        assert!(glob_match(b"*a*b*", b"xxaxxbxx"));
+        assert!(!glob_match(b"a*", b""));
+        assert!(glob_match(b"h[ae", b"ha"));
+
+        // backtracking on every `*` would take exponential time
+        let s = [b'a'; 64];
+        assert!(!glob_match(b"*a*a*a*a*a*a*a*a*a*a*b", &s));
+        assert!(glob_match(b"*a*a*a*a*a*a*a*a*a*a*", &s));
+    }
+
+    #[test]
+    fn test_format_float() {
+        assert_eq!("10.5", format_float(10.5));
+        assert_eq!("3000", format_float(3000.0));
+        assert_eq!("-inf", format_float(f64::NEG_INFINITY));
+    }
 }
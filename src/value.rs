@@ -7,28 +7,28 @@ pub struct RedisBulkString {
     pub data: Vec<u8>,
 }
 
-impl Into<RedisBulkString> for Vec<u8> {
-    fn into(self) -> RedisBulkString {
-        RedisBulkString { data: self }
+impl From<Vec<u8>> for RedisBulkString {
+    fn from(data: Vec<u8>) -> RedisBulkString {
+        RedisBulkString { data }
     }
 }
 
-impl Into<RedisBulkString> for &str {
-    fn into(self) -> RedisBulkString {
-        self.as_bytes().to_vec().into()
+impl From<&str> for RedisBulkString {
+    fn from(s: &str) -> RedisBulkString {
+        s.as_bytes().to_vec().into()
     }
 }
 
-impl Into<String> for &RedisBulkString {
-    fn into(self) -> String {
-        String::from_utf8(self.data.to_owned()).unwrap()
+impl From<&RedisBulkString> for String {
+    fn from(s: &RedisBulkString) -> String {
+        String::from_utf8_lossy(&s.data).to_string()
     }
 }
 
-impl Into<RedisValue> for &RedisBulkString {
-    fn into(self) -> RedisValue {
+impl From<&RedisBulkString> for RedisValue {
+    fn from(s: &RedisBulkString) -> RedisValue {
         RedisValue::BulkString(Some(RedisBulkString {
-            data: self.data.clone(),
+            data: s.data.clone(),
         }))
     }
 }
@@ -50,8 +50,10 @@ pub enum RedisValue {
     SimpleString(String),
     BulkString(Option<RedisBulkString>),
     Array(Vec<RedisValue>),
-    Integer(usize),
+    NullArray,
+    Integer(i64),
     Rdb(Vec<u8>),
+    Error(String),
 }
 
 impl Debug for RedisValue {
@@ -66,8 +68,10 @@ impl Debug for RedisValue {
                 let elements: Vec<String> = a.iter().map(|r| format!("{:?}", r)).collect();
                 write!(f, "Array[{}]", elements.join(", "))
             }
+            RedisValue::NullArray => write!(f, "Array[nil]"),
             RedisValue::Integer(s) => write!(f, "Integer[{}]", s),
             RedisValue::Rdb(content) => write!(f, "Rdb[{:?}]", content),
+            RedisValue::Error(s) => write!(f, "Error[{}]", s),
         }
     }
 }
@@ -85,22 +89,30 @@ impl RedisValue {
         RedisValue::BulkString(None)
     }
 
+    pub fn null_array() -> RedisValue {
+        RedisValue::NullArray
+    }
+
     pub fn simple_string<'a, S: Into<&'a str>>(s: S) -> RedisValue {
         RedisValue::SimpleString(s.into().to_string())
     }
 
+    pub fn error<'a, S: Into<&'a str>>(s: S) -> RedisValue {
+        RedisValue::Error(s.into().to_string())
+    }
+
     pub fn simple_string_from_bytes<'a, S: Into<&'a [u8]>>(s: S) -> RedisValue {
-        RedisValue::SimpleString(String::from_utf8(s.into().to_vec()).unwrap())
+        RedisValue::SimpleString(String::from_utf8_lossy(s.into()).to_string())
     }
 }
 
-impl Into<Vec<u8>> for &RedisValue {
-    fn into(self) -> Vec<u8> {
+impl From<&RedisValue> for Vec<u8> {
+    fn from(value: &RedisValue) -> Vec<u8> {
         let mut buffer: Vec<u8> = Vec::new();
-        match self {
+        match value {
             RedisValue::SimpleString(s) => {
                 buffer.push(b'+');
-                buffer.extend_from_slice(&s.as_bytes());
+                buffer.extend_from_slice(s.as_bytes());
                 buffer.extend_from_slice(CRLF);
             }
             RedisValue::BulkString(s) => {
@@ -124,25 +136,31 @@ impl Into<Vec<u8>> for &RedisValue {
                     buffer.extend(b);
                 }
             }
+            RedisValue::NullArray => buffer.extend_from_slice(b"*-1\r\n"),
             RedisValue::Rdb(c) => {
                 buffer.push(b'$');
                 buffer.extend_from_slice(c.len().to_string().as_bytes());
                 buffer.extend_from_slice(CRLF);
-                buffer.extend_from_slice(&c);
+                buffer.extend_from_slice(c);
             }
             RedisValue::Integer(i) => {
                 buffer.push(b':');
                 buffer.extend_from_slice(i.to_string().as_bytes());
                 buffer.extend_from_slice(CRLF);
             }
+            RedisValue::Error(s) => {
+                buffer.push(b'-');
+                buffer.extend_from_slice(s.as_bytes());
+                buffer.extend_from_slice(CRLF);
+            }
         }
         buffer
     }
 }
 
-impl Into<RedisValue> for Vec<RedisValue> {
-    fn into(self) -> RedisValue {
-        RedisValue::Array(self)
+impl From<Vec<RedisValue>> for RedisValue {
+    fn from(values: Vec<RedisValue>) -> RedisValue {
+        RedisValue::Array(values)
     }
 }
 
@@ -156,6 +174,18 @@ mod tests {
         assert_eq!(b"$5\r\nabcde\r\n", s1.as_slice());
     }
 
+    #[test]
+    fn test_redis_error_to_string() {
+        let s1: Vec<u8> = (&RedisValue::error("ERR unknown")).into();
+        assert_eq!(b"-ERR unknown\r\n", s1.as_slice());
+    }
+
+    #[test]
+    fn test_null_array_to_string() {
+        let s1: Vec<u8> = (&RedisValue::null_array()).into();
+        assert_eq!(b"*-1\r\n", s1.as_slice());
+    }
+
     #[test]
     fn test_redis_value_to_string2() {
         let s1: Vec<u8> = (&RedisValue::bulk_string("abcde")).into();
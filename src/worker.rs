@@ -1,56 +1,89 @@
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 
-use command::RedisCommand;
+use command::{RedisCommand, RedisCommandError, SetCondition, SetExpiry, SetOptions};
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::RwLock;
+use tokio::sync::{oneshot, RwLock};
 use tokio::task::{self};
+use tokio::time::{interval, sleep_until, Duration, Instant};
 
-use crate::redis::{Redis, StoreItem};
-use crate::replica::ReplicationInfo;
-use crate::{command, utilities};
+use crate::client::Disconnected;
+use crate::keyspace::KeyCommand;
+use crate::object::{wrong_type, RedisObject};
+use crate::parser::RedisValueParser;
+use crate::redis::{ExpiryMode, Redis, Store, StoreItem};
+use crate::replica::{LinkState, MasterLinkInfo, ReplicaState, ReplicationInfo};
+use crate::stream::{ReadId, StreamCommand};
+use crate::{
+    bitmap, command, geo, hash, hyperloglog, keyspace, list, rdb, set, stream, string, utilities,
+    zset,
+};
 
 use crate::value::RedisValue;
 
+// the active expiry visits this many keys at a time, going on while more than the given
+// percentage of them turn out expired, within a budget of time every period
+const EXPIRE_CYCLE_PERIOD: Duration = Duration::from_millis(100);
+const EXPIRE_CYCLE_BUDGET: Duration = Duration::from_millis(25);
+const EXPIRE_CYCLE_KEYS: usize = 20;
+const EXPIRE_CYCLE_STALE_PERCENT: usize = 10;
+
 #[derive(Debug)]
 pub struct WorkerMessage {
-    pub command: RedisCommand,
+    pub command: Result<RedisCommand, RedisCommandError>,
     pub client_id: Option<String>,
     pub responser: Option<Arc<RwLock<Sender<RedisValue>>>>,
     pub offset: usize,
+    // the database of a command the worker sent back to itself, instead of the one
+    // selected by the connection
+    pub db: Option<usize>,
 }
 
+type Responser = Option<Arc<RwLock<Sender<RedisValue>>>>;
+
 macro_rules! respond {
     ($responser:ident, $response:expr) => {{
         if let Some($responser) = ($responser) {
             let responser = ($responser).read().await;
-            for m in ($response).iter() {
-                match responser.send(m.clone()).await {
-                    Ok(_) => {}
-                    Err(e) => panic!("{:?}", e),
+            let response = $response;
+            for m in response.iter() {
+                if let Err(e) = responser.send(m.clone()).await {
+                    println!("[worker] unable to respond, the client is gone: {:?}", e);
+                    break;
                 }
             }
-            println!("[worker] send response: {:?}", $response);
+            println!("[worker] send response: {:?}", response);
         }
     }};
 }
 
-pub async fn worker_process(redis: Redis, mut receiver: Receiver<WorkerMessage>) {
+pub async fn worker_process(
+    redis: Redis,
+    sender: Sender<WorkerMessage>,
+    mut receiver: Receiver<WorkerMessage>,
+) {
     println!("[worker] process launched; {}", redis.host());
 
+    let mut expire_tick = interval(EXPIRE_CYCLE_PERIOD);
     loop {
-        let message = if let Some(v) = receiver.recv().await {
-            v
-        } else {
-            continue;
+        // the keys expired while serving the last message
+        propagate_expired(&redis).await;
+
+        let message = tokio::select! {
+            message = receiver.recv() => match message {
+                Some(message) => message,
+                None => continue,
+            },
+            _ = expire_tick.tick() => {
+                if redis.replication.read().await.master.is_none() {
+                    expire_cycle(&redis).await;
+                }
+                continue;
+            }
         };
         println!("[worker] messaged received: {:?}", message);
-        let command: RedisCommand = message.command.clone();
 
         let client_id = message.client_id.clone();
-        let is_replica = {
-            let replicas = redis.replicas.read().await;
-            replicas.contains_key(&client_id.clone().unwrap_or_default())
-        };
         let responser = if let Some(responser) = message.responser {
             println!("[worker][{:?}] has a responser", client_id);
             Some(responser)
@@ -58,7 +91,44 @@ pub async fn worker_process(redis: Redis, mut receiver: Receiver<WorkerMessage>)
             None
         };
 
-        match message.command {
+        let command: RedisCommand = match message.command {
+            Ok(command) => command,
+            Err(e) => {
+                println!("[worker][{:?}] invalid command: {:?}", client_id, e);
+                let response: RedisValue = (&e).into();
+                respond!(responser, vec![response]);
+                continue;
+            }
+        };
+
+        // a replica leaves its expired keys to the master, which sends their deletion
+        let is_replica = redis.replication.read().await.master.is_some();
+        let mode = match (is_replica, client_id.is_some()) {
+            (false, _) => ExpiryMode::Delete,
+            (true, true) => ExpiryMode::Hide,
+            (true, false) => ExpiryMode::Keep,
+        };
+
+        // the database selected by the connection, or by the master for its stream
+        let db = match (message.db, &client_id) {
+            (Some(db), _) => db,
+            (None, Some(id)) => selected_db(&redis, id).await,
+            (None, None) => redis.replication.read().await.master_db,
+        };
+
+        // only the master may write to a read only replica
+        if client_id.is_some() && command.is_write() && redis.config.replica_read_only && is_replica
+        {
+            respond!(
+                responser,
+                vec![RedisValue::error(
+                    "READONLY You can't write against a read only replica."
+                )]
+            );
+            continue;
+        }
+
+        match command.clone() {
             RedisCommand::Ping => {
                 respond!(responser, vec![RedisValue::simple_string("PONG")]);
             }
@@ -67,33 +137,58 @@ pub async fn worker_process(redis: Redis, mut receiver: Receiver<WorkerMessage>)
             }
             RedisCommand::Get(key) => {
                 let key: String = (&key).into();
-                let store = redis.store.read().await;
-                println!("[worker][{:?}] store: {:?}", client_id, store);
-                let response = match store.get(&key) {
-                    Some(item) => {
-                        if item.expired_at == 0 || item.expired_at >= utilities::now() {
-                            vec![item.value.clone()]
-                        } else {
-                            drop(store);
-                            let mut store = redis.store.write().await;
-                            store.remove(&key);
-                            vec![RedisValue::null_bulk_string()]
-                        }
-                    }
-                    None => vec![RedisValue::null_bulk_string()],
+                let mut dbs = redis.store.write().await;
+                let store = &mut dbs[db];
+                let response = match store.get_alive(&key, utilities::now(), mode) {
+                    Some(StoreItem {
+                        value: RedisObject::String(s),
+                        ..
+                    }) => RedisValue::bulk_string_from_bytes(s.as_slice()),
+                    Some(_) => wrong_type(),
+                    None => RedisValue::null_bulk_string(),
                 };
-                respond!(responser, response)
-            }
-            RedisCommand::Info(_) => {
-                let value: RedisValue = ReplicationInfo {
-                    role: match redis.config.clone().get_replica_of() {
-                        Some((_, _)) => "slave".to_string(),
-                        None => "master".to_string(),
-                    },
-                    replica_id: message.client_id.unwrap(),
+                drop(dbs);
+                respond!(responser, vec![response])
+            }
+            RedisCommand::Info(section) => {
+                let section: String = (&section).into();
+                let section = section.to_lowercase();
+                let all = matches!(section.as_str(), "default" | "all" | "everything");
+                let mut sections: Vec<String> = vec![];
+                if all || section == "replication" {
+                    let replication = redis.replication.read().await;
+                    let master = replication
+                        .master
+                        .clone()
+                        .map(|(host, port)| MasterLinkInfo {
+                            host,
+                            port,
+                            link: replication.link,
+                            last_io_ago: match replication.last_io {
+                                0 => None,
+                                last_io => Some(utilities::now().saturating_sub(last_io)),
+                            },
+                        });
+                    sections.push(
+                        ReplicationInfo {
+                            role: match master {
+                                Some(_) => "slave".to_string(),
+                                None => "master".to_string(),
+                            },
+                            master,
+                            master_replid: replication.replid.clone(),
+                            master_replid2: replication.replid2.clone(),
+                            master_repl_offset: replication.offset,
+                        }
+                        .into(),
+                    );
+                }
+                if all || section == "keyspace" {
+                    let dbs = redis.store.read().await;
+                    sections.push(keyspace::info(&dbs, utilities::now()));
                 }
-                .into();
-                respond!(responser, vec![value.clone()]);
+                let value = RedisValue::bulk_string(sections.join("\n\n").as_str());
+                respond!(responser, vec![value]);
             }
             RedisCommand::Replconf(v1, v2) => {
                 let key: String = (&v1).into();
@@ -109,12 +204,12 @@ pub async fn worker_process(redis: Redis, mut receiver: Receiver<WorkerMessage>)
                     "ack" => {
                         let mut replicas = redis.replicas.write().await;
                         let v2s: String = (&v2).into();
-                        let offset = v2s.parse().unwrap();
-                        replicas.insert(client_id.clone().unwrap(), offset);
-                        println!(
-                            "replicas: {:?}, replica {:?} offset update to {}",
-                            replicas, client_id, offset
-                        );
+                        let replica = client_id.as_ref().and_then(|id| replicas.get_mut(id));
+                        if let (Some(replica), Ok(offset)) = (replica, v2s.parse()) {
+                            replica.offset = offset;
+                            redis.replica_acked.notify_waiters();
+                            println!("replica {:?} offset update to {}", client_id, offset);
+                        }
                         vec![]
                     }
                     "capa" => vec![RedisValue::simple_string("OK")],
@@ -122,167 +217,1150 @@ pub async fn worker_process(redis: Redis, mut receiver: Receiver<WorkerMessage>)
                 };
                 respond!(responser, response);
             }
-            RedisCommand::Psync(_, _) => {
-                let id = message.client_id.unwrap();
-                let response = format!("FULLRESYNC {} 0", id);
-                {
-                    let mut replicas = redis.replicas.write().await;
-                    replicas.insert(id, 0);
-                    println!("replicas: {:?}", replicas);
-                }
-                respond!(responser, vec![
-                            RedisValue::simple_string(response.as_str()),
-                            RedisValue::Rdb(
-                                #[allow(warnings)]
-                                base64::decode("UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==").unwrap(),
-                            ),
-                        ]);
-            }
-            RedisCommand::Set(key, value, px) => {
-                let key = String::from_utf8(key.data.to_vec()).unwrap();
-                let value = RedisValue::BulkString(Some(value));
-                let expired_at = match px {
-                    None => 0,
-                    Some(px) => px + utilities::now(),
+            RedisCommand::Psync(replid, offset) => {
+                let id = match message.client_id {
+                    Some(id) => id,
+                    None => continue,
                 };
-                // update store
-                {
-                    let mut store = redis.store.write().await;
-                    store.insert(key, StoreItem { value, expired_at });
-                    println!("[worker][{:?}] store: {:?}", client_id, store);
-                }
-                // if current node is master node, broadcast the write commmand to all replicas
-                if redis.config.get_replica_of() == None {
-                    brocast_to_replicas(redis.clone(), command).await.unwrap();
+                let replid: String = (&replid).into();
+                let offset: String = (&offset).into();
+
+                // the replica asks for the stream right after the last byte it processed
+                // the replica is registered before the backlog is released, so the stream
+                // goes on right after it
+                let backlog = {
+                    let replication = redis.replication.read().await;
+                    let backlog = offset
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|offset| *offset > 0)
+                        .and_then(|offset| replication.continue_from(&replid, offset - 1))
+                        .map(|backlog| (replication.replid.clone(), backlog));
+                    if backlog.is_some() {
+                        let mut replicas = redis.replicas.write().await;
+                        replicas.insert(id.clone(), ReplicaState::online());
+                    }
+                    backlog
+                };
+                if let Some((replid, backlog)) = backlog {
+                    println!(
+                        "[worker][psync] partial resync with replica {}, {} bytes to send",
+                        id,
+                        backlog.len()
+                    );
+                    let mut response = vec![RedisValue::simple_string(
+                        format!("CONTINUE {}", replid).as_str(),
+                    )];
+                    let mut parser = RedisValueParser::new();
+                    parser.append(&backlog);
+                    while let Ok((Some(value), _)) = parser.parse() {
+                        response.push(value);
+                    }
+                    respond!(responser, response);
+                } else {
+                    let (response, store) = {
+                        let mut replication = redis.replication.write().await;
+                        // the replica starts from the snapshot, its stream must select a database
+                        replication.selected_db = None;
+                        let response =
+                            format!("FULLRESYNC {} {}", replication.replid, replication.offset);
+                        let store = redis.store.read().await.clone();
+                        // registered before the lock is released, so the next write is queued
+                        // after the select it needs
+                        let mut replicas = redis.replicas.write().await;
+                        replicas.insert(id.clone(), ReplicaState::syncing());
+                        println!("replicas: {:?}", replicas);
+                        (response, store)
+                    };
+                    let rdb_responser = responser.clone();
+                    respond!(
+                        responser,
+                        vec![RedisValue::simple_string(response.as_str())]
+                    );
+
+                    // the snapshot is encoded in background, writes propagated
+                    // in the meantime are queued and sent right after it
+                    let _redis = redis.clone();
+                    task::spawn(async move {
+                        let data = rdb::encode(&store);
+                        let mut replicas = _redis.replicas.write().await;
+                        let pending = replicas
+                            .get_mut(&id)
+                            .and_then(|replica| replica.pending.take())
+                            .unwrap_or_default();
+                        println!(
+                            "[worker][psync] snapshot for replica {} ready ({} bytes), {} commands pending",
+                            id,
+                            data.len(),
+                            pending.len()
+                        );
+                        let mut response = vec![RedisValue::Rdb(data)];
+                        response.extend(pending);
+                        respond!(rdb_responser, response);
+                    });
                 }
-                respond!(responser, vec![RedisValue::simple_string("OK")]);
             }
-            RedisCommand::Type(key) => {
+            RedisCommand::Set(key, value, options) => {
+                let name = key.clone();
                 let key: String = (&key).into();
-                let store = redis.store.read().await;
-                println!("[worker][{:?}] store: {:?}", client_id, store);
-                let value = match store.get(&key) {
-                    Some(item) => {
-                        if item.expired_at == 0 || item.expired_at >= utilities::now() {
-                            Some(item.value.clone())
-                        } else {
-                            drop(store);
-                            let mut store = redis.store.write().await;
-                            store.remove(&key);
-                            None
-                        }
+                let now = utilities::now();
+                // update store
+                let (old, expired_at) = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    let old = store.get_alive(&key, now, mode).cloned();
+                    // the old value is only replied for a string
+                    if options.get
+                        && !matches!(
+                            old,
+                            None | Some(StoreItem {
+                                value: RedisObject::String(_),
+                                ..
+                            })
+                        )
+                    {
+                        drop(dbs);
+                        respond!(responser, vec![wrong_type()]);
+                        continue;
                     }
-                    None => None,
+                    let accepted = match options.condition {
+                        Some(SetCondition::Nx) => old.is_none(),
+                        Some(SetCondition::Xx) => old.is_some(),
+                        None => true,
+                    };
+                    let expired_at = if accepted {
+                        let expired_at = match options.expiry {
+                            None => 0,
+                            Some(expiry) => expiry
+                                .expired_at(now)
+                                .unwrap_or_else(|| old.as_ref().map_or(0, |item| item.expired_at)),
+                        };
+                        store.insert(
+                            key,
+                            StoreItem {
+                                value: RedisObject::String(value.data.clone()),
+                                expired_at,
+                            },
+                        );
+                        println!("[worker][{:?}] store: {:?}", client_id, store);
+                        Some(expired_at)
+                    } else {
+                        None
+                    };
+                    (old, expired_at)
                 };
-                let response = if let Some(value) = value {
-                    match value {
-                        RedisValue::BulkString(_) => "string",
-                        RedisValue::SimpleString(_) => "string",
-                        RedisValue::Integer(_) => "integer",
-                        _ => panic!(),
+                // if current node is master node, broadcast the write commmand to all replicas,
+                // the expiry is sent as an absolute time so that both sides expire the key together
+                if let Some(expired_at) = expired_at {
+                    let options = SetOptions {
+                        expiry: match expired_at {
+                            0 => None,
+                            expired_at => Some(SetExpiry::PxAt(expired_at)),
+                        },
+                        ..Default::default()
+                    };
+                    replicate(&redis, db, RedisCommand::Set(name, value, options)).await;
+                }
+                let response = if options.get {
+                    match old {
+                        Some(StoreItem {
+                            value: RedisObject::String(s),
+                            ..
+                        }) => RedisValue::bulk_string_from_bytes(s.as_slice()),
+                        _ => RedisValue::null_bulk_string(),
                     }
+                } else if expired_at.is_some() {
+                    RedisValue::simple_string("OK")
                 } else {
-                    "none"
+                    RedisValue::null_bulk_string()
                 };
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Type(key) => {
+                let key: String = (&key).into();
+                let mut dbs = redis.store.write().await;
+                let store = &mut dbs[db];
+                let response = match store.get_alive(&key, utilities::now(), mode) {
+                    Some(item) => item.value.type_name(),
+                    None => "none",
+                };
+                drop(dbs);
                 respond!(responser, vec![RedisValue::simple_string(response)]);
             }
+            RedisCommand::String(string_command) => {
+                let now = utilities::now();
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    string::execute(store, &string_command, now, mode)
+                };
+                let replicated = string::replicated(&string_command, &response, now);
+                propagate(&redis, db, &replicated, &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Bitmap(bitmap_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    bitmap::execute(store, &bitmap_command, utilities::now(), mode)
+                };
+                propagate(&redis, db, &RedisCommand::Bitmap(bitmap_command), &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::HyperLogLog(hll_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    hyperloglog::execute(store, &hll_command, utilities::now(), mode)
+                };
+                propagate(
+                    &redis,
+                    db,
+                    &RedisCommand::HyperLogLog(hll_command),
+                    &response,
+                )
+                .await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Geo(geo_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    geo::execute(store, &geo_command, utilities::now(), mode)
+                };
+                propagate(&redis, db, &RedisCommand::Geo(geo_command), &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Keyspace(key_command) => {
+                let now = utilities::now();
+                let (response, lazy_free) = {
+                    let mut dbs = redis.store.write().await;
+                    let response = keyspace::execute(&mut dbs, db, &key_command, now, mode);
+                    let lazy_free: Vec<_> = dbs
+                        .iter_mut()
+                        .flat_map(|store| store.take_lazy_free())
+                        .collect();
+                    (response, lazy_free)
+                };
+                if !lazy_free.is_empty() {
+                    task::spawn_blocking(move || drop(lazy_free));
+                }
+                if let Some(replicated) = keyspace::replicated(&key_command, &response, now) {
+                    propagate(&redis, db, &replicated, &response).await;
+                }
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::List(list_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    list::execute(store, &list_command, utilities::now(), mode)
+                };
+                propagate(&redis, db, &command, &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Hash(hash_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    hash::execute(store, &hash_command, utilities::now(), mode)
+                };
+                let replicated = RedisCommand::Hash(hash::replicated(&hash_command, &response));
+                propagate(&redis, db, &replicated, &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Sets(set_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    set::execute(store, &set_command, utilities::now(), mode)
+                };
+                // replicas remove the members popped here instead of popping their own
+                if let Some(replicated) = set::replicated(&set_command, &response) {
+                    propagate(&redis, db, &RedisCommand::Sets(replicated), &response).await;
+                }
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::ZSet(zset_command) => {
+                let response = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    zset::execute(store, &zset_command, utilities::now(), mode)
+                };
+                propagate(&redis, db, &command, &response).await;
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Stream(StreamCommand::Read(options)) if options.block.is_some() => {
+                let ids = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    stream::resolve_ids(store, &options, utilities::now(), mode)
+                };
+                let ids = match ids {
+                    Ok(ids) => ids,
+                    Err(e) => {
+                        respond!(responser, vec![e]);
+                        continue;
+                    }
+                };
+                let timeout = options.block.unwrap_or(0);
+                let read = |store: &mut Store, now, _: &mut Vec<StreamCommand>| {
+                    stream::read(store, &options.keys, &ids, options.count, now, mode)
+                };
+                // the ids are kept as resolved now, for the entries added in the meantime
+                let retry = {
+                    let ids = ids.iter().cloned().map(ReadId::Id).collect();
+                    let options = stream::ReadOptions {
+                        ids,
+                        ..options.clone()
+                    };
+                    move |block| {
+                        StreamCommand::Read(stream::ReadOptions {
+                            block: Some(block),
+                            ..options
+                        })
+                    }
+                };
+                let client = (responser, message.client_id);
+                serve_stream_read(&redis, &sender, db, client, timeout, read, retry).await;
+            }
+            // only new entries are waited for, the history of a consumer is replied at once
+            RedisCommand::Stream(StreamCommand::ReadGroup(options)) if options.block.is_some() => {
+                let timeout = options.block.unwrap_or(0);
+                let read = |store: &mut Store, now, replicated: &mut Vec<StreamCommand>| {
+                    stream::read_group(store, &options, now, mode, replicated)
+                };
+                let retry = {
+                    let options = options.clone();
+                    move |block| {
+                        StreamCommand::ReadGroup(stream::GroupReadOptions {
+                            block: Some(block),
+                            ..options
+                        })
+                    }
+                };
+                let client = (responser, message.client_id);
+                serve_stream_read(&redis, &sender, db, client, timeout, read, retry).await;
+            }
+            RedisCommand::Stream(stream_command) => {
+                let (response, replicated) = {
+                    let mut dbs = redis.store.write().await;
+                    let store = &mut dbs[db];
+                    stream::execute(store, &stream_command, utilities::now(), mode)
+                };
+                let changed = !replicated.is_empty();
+                for command in replicated {
+                    replicate(&redis, db, RedisCommand::Stream(command)).await;
+                }
+                // waked up after the replicas, which must have the entries before they are read
+                if changed {
+                    redis.stream_appended.notify_waiters();
+                }
+                respond!(responser, vec![response]);
+            }
             RedisCommand::Wait(number, timeout) => {
-                let started_at = utilities::now();
-                let _redis = redis.clone();
-                let _client_id = message.client_id.clone();
-                task::spawn(async move {
-                    println!(
-                        "[worker][{:?}][wait] wait started for {} ms at {}",
-                        _client_id,
-                        timeout,
-                        utilities::now(),
-                    );
-                    loop {
-                        let replica_number = {
-                            let replicas = _redis.replicas.read().await;
-                            replicas.len() as u64
-                        };
-                        let diff = utilities::now() - started_at;
-                        if replica_number >= number || diff > timeout {
-                            break;
-                        }
-                        {
-                            let channels = _redis.channels.read().await;
-                            if let Some(ref client_id) = message.client_id {
-                                if !channels.contains_key(client_id) {
-                                    println!("[worker] the current client {} has down", client_id);
+                // the writes issued so far are durable once the replicas reach this offset
+                let target = redis.replication.read().await.offset;
+                let acked = count_acked_replicas(&redis, target).await;
+                if target == 0 || acked >= number as usize {
+                    respond!(responser, vec![RedisValue::Integer(acked as i64)]);
+                } else {
+                    brocast_to_replicas(redis.clone(), None, RedisCommand::replconf("getack", "*"))
+                        .await
+                        .unwrap();
+
+                    let _redis = redis.clone();
+                    let _client_id = message.client_id.clone();
+                    let disconnected = match &client_id {
+                        Some(id) => match client_disconnected(&redis, id).await {
+                            Some(disconnected) => Some(disconnected),
+                            None => continue,
+                        },
+                        None => None,
+                    };
+                    task::spawn(async move {
+                        println!(
+                            "[worker][{:?}][wait] wait for {} replicas to reach offset {} in {} ms",
+                            _client_id, number, target, timeout,
+                        );
+                        let deadline = Instant::now() + Duration::from_millis(timeout);
+                        let acked = loop {
+                            let notified = _redis.replica_acked.notified();
+                            tokio::pin!(notified);
+                            // register before counting, so an ack arriving in between is not missed
+                            notified.as_mut().enable();
+
+                            let acked = count_acked_replicas(&_redis, target).await;
+                            if acked >= number as usize {
+                                break acked;
+                            }
+                            // nobody is left to reply to once the client is gone
+                            let gone = async {
+                                match &disconnected {
+                                    Some(disconnected) => disconnected.wait().await,
+                                    None => std::future::pending().await,
+                                }
+                            };
+                            tokio::select! {
+                                _ = notified => {}
+                                _ = sleep_until(deadline), if timeout != 0 => {
+                                    break count_acked_replicas(&_redis, target).await
+                                }
+                                _ = gone => {
+                                    println!("[worker][{:?}][wait] the client is gone", _client_id);
                                     return;
                                 }
                             }
                         };
-                    }
-                    let replica_number = {
-                        let replicas = _redis.replicas.read().await;
-                        replicas.len() as u64
-                    };
-                    respond!(
-                        responser,
-                        vec![RedisValue::Integer(replica_number as usize)]
-                    );
-                    println!(
-                        "[worker][{:?}][wait] wait done at {} for {} ms",
-                        _client_id,
-                        utilities::now(),
-                        timeout
-                    );
-                });
+                        respond!(responser, vec![RedisValue::Integer(acked as i64)]);
+                        println!(
+                            "[worker][{:?}][wait] wait done, {} replicas acknowledged",
+                            _client_id, acked
+                        );
+                    });
+                }
             }
-            RedisCommand::Select(_) => {
-                respond!(responser, vec![RedisValue::simple_string("ok")]);
+            RedisCommand::Select(index) => {
+                let response = match keyspace::db_index(index, redis.config.databases) {
+                    Ok(index) => {
+                        match &client_id {
+                            Some(id) => {
+                                if let Some(channel) = redis.channels.read().await.get(id) {
+                                    channel.write().await.db = index;
+                                }
+                            }
+                            None => redis.replication.write().await.master_db = index,
+                        }
+                        RedisValue::simple_string("OK")
+                    }
+                    Err(e) => e,
+                };
+                respond!(responser, vec![response]);
             }
             RedisCommand::Config(action, key) => {
                 let action: String = (&action).into();
                 let key: String = (&key).into();
                 let config = redis.config.clone();
-                let value = match action.to_lowercase().as_str() {
-                    "get" => match key.to_lowercase().as_str() {
-                        "dir" => {
-                            let dir = config.dir.clone();
-                            dir.map_or(RedisValue::null_bulk_string(), |r| {
-                                RedisValue::bulk_string(r.as_str())
-                            })
+                let response = match action.to_lowercase().as_str() {
+                    "get" => {
+                        let value = match key.to_lowercase().as_str() {
+                            "dir" => {
+                                let dir = config.dir.clone();
+                                Some(dir.map_or(RedisValue::null_bulk_string(), |r| {
+                                    RedisValue::bulk_string(r.as_str())
+                                }))
+                            }
+                            "dbfilename" => {
+                                Some(RedisValue::bulk_string(config.dbfilename.clone().as_str()))
+                            }
+                            "databases" => Some(RedisValue::bulk_string(
+                                config.databases.to_string().as_str(),
+                            )),
+                            "replica-read-only" | "slave-read-only" => {
+                                Some(RedisValue::bulk_string(if config.replica_read_only {
+                                    "yes"
+                                } else {
+                                    "no"
+                                }))
+                            }
+                            // unknown parameters match nothing
+                            _ => None,
+                        };
+                        match value {
+                            Some(value) => RedisValue::Array(vec![
+                                RedisValue::bulk_string(key.as_str()),
+                                value,
+                            ]),
+                            None => RedisValue::Array(vec![]),
+                        }
+                    }
+                    action => RedisValue::Error(format!(
+                        "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+                        action
+                    )),
+                };
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Save => {
+                let response = if redis.bgsave_in_progress.load(Ordering::SeqCst) {
+                    RedisValue::error("ERR Background save already in progress")
+                } else {
+                    let dbfile = redis.config.dbfile();
+                    let result = {
+                        let store = redis.store.read().await;
+                        rdb::save(&dbfile, &store).await
+                    };
+                    match result {
+                        Ok(_) => {
+                            redis
+                                .last_save
+                                .store(utilities::now() / 1000, Ordering::SeqCst);
+                            println!("[worker] db saved on disk: {:?}", dbfile);
+                            RedisValue::simple_string("OK")
+                        }
+                        Err(e) => {
+                            println!("[worker] unable to save db to {:?}: {:?}", dbfile, e);
+                            RedisValue::error(format!("ERR {}", e).as_str())
+                        }
+                    }
+                };
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Bgsave => {
+                let response = if redis.bgsave_in_progress.swap(true, Ordering::SeqCst) {
+                    RedisValue::error("ERR Background save already in progress")
+                } else {
+                    // the cloned store is the point-in-time snapshot written in background
+                    let store = redis.store.read().await.clone();
+                    let _redis = redis.clone();
+                    task::spawn(async move {
+                        let dbfile = _redis.config.dbfile();
+                        match rdb::save(&dbfile, &store).await {
+                            Ok(_) => {
+                                _redis
+                                    .last_save
+                                    .store(utilities::now() / 1000, Ordering::SeqCst);
+                                println!("[worker][bgsave] db saved on disk: {:?}", dbfile);
+                            }
+                            Err(e) => println!(
+                                "[worker][bgsave] unable to save db to {:?}: {:?}",
+                                dbfile, e
+                            ),
+                        }
+                        _redis.bgsave_in_progress.store(false, Ordering::SeqCst);
+                    });
+                    RedisValue::simple_string("Background saving started")
+                };
+                respond!(responser, vec![response]);
+            }
+            RedisCommand::Replicaof(master) => {
+                let (response, changed) = {
+                    let mut replication = redis.replication.write().await;
+                    match master {
+                        Some(master) if replication.master.as_ref() == Some(&master) => {
+                            ("OK Already connected to specified master", false)
+                        }
+                        Some((host, port)) => {
+                            println!(
+                                "[worker] become a replica of {}:{}, the dataset will be replaced",
+                                host, port
+                            );
+                            // a former master tries to continue its own history with the new master
+                            replication.resumable =
+                                replication.resumable || replication.master.is_none();
+                            replication.master = Some((host, port));
+                            replication.master_db = 0;
+                            replication.link = LinkState::Connecting;
+                            replication.last_io = 0;
+                            ("OK", true)
+                        }
+                        None if replication.master.is_none() => ("OK", false),
+                        None => {
+                            println!("[worker] promoted to master");
+                            replication.promote();
+                            ("OK", true)
                         }
-                        "dbfilename" => RedisValue::bulk_string(config.dbfilename.clone().as_str()),
-                        _ => panic!(),
-                    },
-                    _ => panic!(),
+                    }
                 };
-                let key: RedisValue = RedisValue::bulk_string(key.as_str());
-                let response = RedisValue::Array(vec![key, value]);
-                respond!(responser, vec![response.to_owned()]);
+                if changed {
+                    redis.master_changed.notify_one();
+                }
+                respond!(responser, vec![RedisValue::simple_string(response)]);
+            }
+            RedisCommand::Lastsave => {
+                let last_save = redis.last_save.load(Ordering::SeqCst);
+                respond!(responser, vec![RedisValue::Integer(last_save as i64)]);
             }
         };
     }
 }
 
-pub async fn brocast_to_replicas(redis: Redis, command: RedisCommand) -> Result<(), ()> {
+// the end of a client connection, none if it is already gone
+async fn client_disconnected(redis: &Redis, client_id: &str) -> Option<Arc<Disconnected>> {
+    let channel = redis.channels.read().await.get(client_id).cloned();
+    match channel {
+        Some(channel) => Some(channel.read().await.disconnected.clone()),
+        None => None,
+    }
+}
+
+// the database selected by a client connection
+async fn selected_db(redis: &Redis, client_id: &str) -> usize {
+    let channel = redis.channels.read().await.get(client_id).cloned();
+    match channel {
+        Some(channel) => channel.read().await.db,
+        None => 0,
+    }
+}
+
+// a successful write on the master is sent to its replicas
+async fn propagate(redis: &Redis, db: usize, command: &RedisCommand, response: &RedisValue) {
+    if !command.is_write() || matches!(response, RedisValue::Error(_)) {
+        return;
+    }
+    replicate(redis, db, command.clone()).await;
+}
+
+// send a command run in a database to the replicas, if this is a master
+async fn replicate(redis: &Redis, db: usize, command: RedisCommand) {
+    // the keys expired meanwhile are deleted first, as they were before the command ran
+    propagate_expired(redis).await;
+    if redis.replication.read().await.master.is_none() {
+        brocast_to_replicas(redis.clone(), Some(db), command)
+            .await
+            .unwrap();
+    }
+}
+
+// the keys removed since they expired are deleted on the replicas too
+async fn propagate_expired(redis: &Redis) {
+    if !redis.store.read().await.iter().any(Store::has_expired) {
+        return;
+    }
+    let expired: Vec<(usize, Vec<String>)> = {
+        let mut dbs = redis.store.write().await;
+        dbs.iter_mut()
+            .map(|store| store.take_expired())
+            .enumerate()
+            .filter(|(_, keys)| !keys.is_empty())
+            .collect()
+    };
+    if expired.is_empty() || redis.replication.read().await.master.is_some() {
+        return;
+    }
+    for (db, keys) in expired {
+        for key in keys {
+            let command = RedisCommand::Keyspace(KeyCommand::Del(vec![key.as_str().into()]));
+            brocast_to_replicas(redis.clone(), Some(db), command)
+                .await
+                .unwrap();
+        }
+    }
+}
+
+// the active expiry of a master, removing the expired keys nobody accesses
+async fn expire_cycle(redis: &Redis) {
+    let start = Instant::now();
+    let removed = {
+        let mut dbs = redis.store.write().await;
+        let mut total = 0;
+        for store in dbs.iter_mut() {
+            loop {
+                let (visited, removed) = store.expire_keys(EXPIRE_CYCLE_KEYS, utilities::now());
+                total += removed;
+                if removed * 100 <= visited * EXPIRE_CYCLE_STALE_PERCENT
+                    || start.elapsed() >= EXPIRE_CYCLE_BUDGET
+                {
+                    break;
+                }
+            }
+        }
+        total
+    };
+    if removed > 0 {
+        println!("[worker] {} expired keys removed", removed);
+        propagate_expired(redis).await;
+    }
+}
+
+// serve a read of streams, which replies nil once the timeout in milliseconds elapses, or never
+// if it is zero. until then the read is sent back to the worker whenever entries are added, so
+// that what it propagates keeps its order with the other commands
+async fn serve_stream_read<R, F>(
+    redis: &Redis,
+    worker: &Sender<WorkerMessage>,
+    db: usize,
+    (responser, client_id): (Responser, Option<String>),
+    timeout: u64,
+    read: R,
+    retry: F,
+) where
+    R: FnOnce(&mut Store, u64, &mut Vec<StreamCommand>) -> Result<Option<RedisValue>, RedisValue>,
+    F: FnOnce(u64) -> StreamCommand + Send + 'static,
+{
+    let mut replicated = vec![];
+    let response = {
+        let mut dbs = redis.store.write().await;
+        read(&mut dbs[db], utilities::now(), &mut replicated)
+    };
+    for command in replicated {
+        replicate(redis, db, RedisCommand::Stream(command)).await;
+    }
+    match response {
+        Ok(Some(response)) | Err(response) => {
+            respond!(responser, vec![response]);
+            return;
+        }
+        Ok(None) => {}
+    }
+
+    let redis = redis.clone();
+    let worker = worker.clone();
+    let (registered, ready) = oneshot::channel();
+    task::spawn(async move {
+        println!(
+            "[worker][{:?}][streams] block for new entries in {} ms",
+            client_id, timeout,
+        );
+        let deadline = Instant::now() + Duration::from_millis(timeout);
+        let notified = redis.stream_appended.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+        let _ = registered.send(());
+
+        if timeout == 0 {
+            notified.await;
+        } else {
+            tokio::select! {
+                _ = notified => {}
+                _ = sleep_until(deadline) => {
+                    respond!(responser, vec![RedisValue::null_bulk_string()]);
+                    println!("[worker][{:?}][streams] block timed out", client_id);
+                    return;
+                }
+            }
+        }
+        // the read goes on for the time left, at least a millisecond as zero waits forever
+        let block = match timeout {
+            0 => 0,
+            _ => (deadline
+                .saturating_duration_since(Instant::now())
+                .as_millis() as u64)
+                .max(1),
+        };
+        let message = WorkerMessage {
+            command: Ok(RedisCommand::Stream(retry(block))),
+            client_id,
+            responser,
+            offset: 0,
+            db: Some(db),
+        };
+        if let Err(e) = worker.send(message).await {
+            println!("[worker][streams] unable to retry the read: {:?}", e);
+        }
+    });
+    // the entries added by the next commands wake the read up only once it waits for them
+    let _ = ready.await;
+}
+
+async fn count_acked_replicas(redis: &Redis, offset: usize) -> usize {
     let replicas = redis.replicas.read().await;
+    replicas
+        .values()
+        .filter(|replica| replica.offset >= offset)
+        .count()
+}
+
+// send a command to the replicas, preceded by a `SELECT` when it runs in another database
+// than the last one the stream selected, which is decided along with the send
+pub async fn brocast_to_replicas(
+    redis: Redis,
+    db: Option<usize>,
+    command: RedisCommand,
+) -> Result<(), ()> {
+    // both are held until the replicas have it, so concurrent broadcasts reach the backlog and
+    // every replica in the same order
+    let mut replication = redis.replication.write().await;
+    let mut replicas = redis.replicas.write().await;
+
+    let mut values: Vec<RedisValue> = vec![];
+    if let Some(db) = db.filter(|db| replication.selected_db != Some(*db)) {
+        replication.selected_db = Some(db);
+        values.push((&RedisCommand::Select(db as i64)).into());
+    }
+    values.push((&command).into());
+    for value in values.iter() {
+        let bytes: Vec<u8> = value.into();
+        replication.feed(&bytes);
+    }
+
     println!(
         "[worker] start to broadcast to replicas({}): {:?}",
         replicas.len(),
         replicas
     );
 
-    for (id, _) in replicas.iter() {
+    for (id, replica) in replicas.iter_mut() {
+        // the replica is still loading the snapshot
+        if let Some(pending) = replica.pending.as_mut() {
+            pending.extend(values.iter().cloned());
+            println!("[worker] queued for replica {}, {:?}", id, command);
+            continue;
+        }
         let channel = {
             let channels = redis.channels.read().await;
-            let channel = channels.get(id).unwrap();
-            channel.clone()
+            match channels.get(id) {
+                Some(channel) => channel.clone(),
+                None => continue,
+            }
         };
         let to_client_sender = {
             let channel = channel.read().await;
             let writer = channel.to_client_sender.read().await;
             writer.clone()
         };
-        to_client_sender.send((&command).into()).await.unwrap();
+        let mut sent = Ok(());
+        for value in values.iter() {
+            sent = to_client_sender.send(value.clone()).await;
+            if sent.is_err() {
+                break;
+            }
+        }
+        if let Err(e) = sent {
+            println!("[worker] unable to broadcast to client {}: {:?}", id, e);
+            continue;
+        }
         println!("[worker] broadcast to client {} done, {:?}", id, command);
     }
 
     println!("[worker] broadcast to replicas done: {}", replicas.len());
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientChannel;
+    use crate::redis::RedisConfig;
+    use structopt::StructOpt;
+    use tokio::sync::mpsc;
+
+    // a client connection to a worker
+    struct Connection {
+        id: String,
+        worker: Sender<WorkerMessage>,
+        responser: Arc<RwLock<Sender<RedisValue>>>,
+        replies: Receiver<RedisValue>,
+    }
+
+    impl Connection {
+        async fn open(redis: &Redis, worker: &Sender<WorkerMessage>, id: &str) -> Connection {
+            let channel = Arc::new(RwLock::new(ClientChannel::new()));
+            redis.channels.write().await.insert(id.to_string(), channel);
+            let (responser, replies) = mpsc::channel(16);
+            Connection {
+                id: id.to_string(),
+                worker: worker.clone(),
+                responser: Arc::new(RwLock::new(responser)),
+                replies,
+            }
+        }
+
+        async fn send(&self, args: &[&str]) {
+            self.worker
+                .send(WorkerMessage {
+                    command: command(args),
+                    client_id: Some(self.id.clone()),
+                    responser: Some(self.responser.clone()),
+                    offset: 0,
+                    db: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        async fn call(&mut self, args: &[&str]) -> RedisValue {
+            self.send(args).await;
+            self.replies.recv().await.unwrap()
+        }
+    }
+
+    fn command(args: &[&str]) -> Result<RedisCommand, RedisCommandError> {
+        let args = args.iter().map(|s| RedisValue::bulk_string(*s)).collect();
+        RedisValue::Array(args).try_into()
+    }
+
+    fn launch(args: &[&str]) -> (Redis, Sender<WorkerMessage>) {
+        let config = RedisConfig::from_iter(["redis"].iter().chain(args));
+        let redis = Redis::from_config(config);
+        let (worker, receiver) = mpsc::channel(16);
+        task::spawn(worker_process(redis.clone(), worker.clone(), receiver));
+        (redis, worker)
+    }
+
+    // the commands queued for a replica still loading the snapshot
+    async fn queued(redis: &Redis, id: &str) -> Vec<Vec<u8>> {
+        let replicas = redis.replicas.read().await;
+        let pending = replicas[id].pending.as_ref().unwrap();
+        pending.iter().map(|value| value.into()).collect()
+    }
+
+    fn encoded(args: &[&str]) -> Vec<u8> {
+        let value: RedisValue = (&command(args).unwrap()).into();
+        (&value).into()
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_propagated() {
+        let (redis, worker) = launch(&[]);
+        let replica = ReplicaState::syncing();
+        redis
+            .replicas
+            .write()
+            .await
+            .insert("r".to_string(), replica);
+        let mut client = Connection::open(&redis, &worker, "c").await;
+
+        assert_eq!(
+            RedisValue::simple_string("OK"),
+            client.call(&["set", "a", "1"]).await
+        );
+        client.call(&["get", "a"]).await;
+        client.call(&["lpush", "a", "x"]).await;
+        client.call(&["select", "2"]).await;
+        client.call(&["incrbyfloat", "n", "1.5"]).await;
+        client.call(&["hincrbyfloat", "h", "f", "0.5"]).await;
+
+        // reads and failed writes are not propagated, the databases are selected
+        assert_eq!(
+            vec![
+                encoded(&["select", "0"]),
+                encoded(&["set", "a", "1"]),
+                encoded(&["select", "2"]),
+                encoded(&["set", "n", "1.5", "keepttl"]),
+                encoded(&["hset", "h", "f", "0.5"]),
+            ],
+            queued(&redis, "r").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_blocked_read_is_propagated_in_order() {
+        let (redis, worker) = launch(&[]);
+        let replica = ReplicaState::syncing();
+        redis
+            .replicas
+            .write()
+            .await
+            .insert("r".to_string(), replica);
+        let mut reader = Connection::open(&redis, &worker, "a").await;
+        let mut writer = Connection::open(&redis, &worker, "b").await;
+
+        writer
+            .call(&["xgroup", "create", "s", "g", "$", "mkstream"])
+            .await;
+        reader
+            .send(&[
+                "xreadgroup",
+                "group",
+                "g",
+                "c",
+                "block",
+                "0",
+                "streams",
+                "s",
+                ">",
+            ])
+            .await;
+        writer.call(&["xadd", "s", "1-1", "f", "v"]).await;
+        reader.replies.recv().await.unwrap();
+        writer.call(&["xgroup", "delconsumer", "s", "g", "c"]).await;
+
+        // the entry is claimed by the consumer after it is added, and before it is deleted
+        let names: Vec<String> = queued(&redis, "r")
+            .await
+            .iter()
+            .map(|value| {
+                String::from_utf8_lossy(value)
+                    .split("\r\n")
+                    .nth(2)
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            vec!["select", "xgroup", "xgroup", "xadd", "xclaim", "xgroup"],
+            names
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_broadcasts() {
+        let (redis, _) = launch(&["--databases", "4"]);
+        let (sender, mut receiver) = mpsc::channel(128);
+        let mut channel = ClientChannel::new();
+        channel.to_client_sender = Arc::new(RwLock::new(sender));
+        redis
+            .channels
+            .write()
+            .await
+            .insert("r".to_string(), Arc::new(RwLock::new(channel)));
+        let replica = ReplicaState::online();
+        redis
+            .replicas
+            .write()
+            .await
+            .insert("r".to_string(), replica);
+
+        let start = Arc::new(tokio::sync::Barrier::new(32));
+        let broadcasts: Vec<_> = (0..32)
+            .map(|i| {
+                let (redis, start) = (redis.clone(), start.clone());
+                let command = command(&["set", "k", &i.to_string()]).unwrap();
+                task::spawn(async move {
+                    start.wait().await;
+                    brocast_to_replicas(redis, Some(i % 4), command).await
+                })
+            })
+            .collect();
+        for broadcast in broadcasts {
+            broadcast.await.unwrap().unwrap();
+        }
+
+        // the replica is sent the stream kept in the backlog
+        let mut sent = vec![];
+        while let Ok(value) = receiver.try_recv() {
+            sent.extend(Vec::<u8>::from(&value));
+        }
+        let replication = redis.replication.read().await;
+        let backlog = replication.continue_from(&replication.replid, 0).unwrap();
+        assert_eq!(backlog, sent);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_broadcasts_select() {
+        let (redis, _) = launch(&["--databases", "4"]);
+        let replica = ReplicaState::syncing();
+        redis
+            .replicas
+            .write()
+            .await
+            .insert("r".to_string(), replica);
+
+        let start = Arc::new(tokio::sync::Barrier::new(32));
+        let broadcasts: Vec<_> = (0..32)
+            .map(|i| {
+                let (redis, start) = (redis.clone(), start.clone());
+                let db = i % 4;
+                let command = command(&["set", &format!("k{}", db), "v"]).unwrap();
+                task::spawn(async move {
+                    start.wait().await;
+                    brocast_to_replicas(redis, Some(db), command).await
+                })
+            })
+            .collect();
+        for broadcast in broadcasts {
+            broadcast.await.unwrap().unwrap();
+        }
+
+        // every write follows the select of its database
+        let mut selected = None;
+        for value in queued(&redis, "r").await {
+            let args: Vec<String> = String::from_utf8(value)
+                .unwrap()
+                .split("\r\n")
+                .skip(2)
+                .step_by(2)
+                .map(String::from)
+                .collect();
+            match args[0].as_str() {
+                "select" => selected = Some(args[1].clone()),
+                _ => assert_eq!(format!("k{}", selected.as_ref().unwrap()), args[1]),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_wrong_type() {
+        let (redis, worker) = launch(&[]);
+        let mut client = Connection::open(&redis, &worker, "c").await;
+        let wrong_type =
+            RedisValue::error("WRONGTYPE Operation against a key holding the wrong kind of value");
+
+        client.call(&["lpush", "l", "x"]).await;
+        assert_eq!(wrong_type, client.call(&["get", "l"]).await);
+        assert_eq!(wrong_type, client.call(&["incr", "l"]).await);
+        assert_eq!(wrong_type, client.call(&["hget", "l", "f"]).await);
+        assert_eq!(
+            RedisValue::simple_string("list"),
+            client.call(&["type", "l"]).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_select() {
+        let (redis, worker) = launch(&["--databases", "4"]);
+        let mut first = Connection::open(&redis, &worker, "a").await;
+        let mut second = Connection::open(&redis, &worker, "b").await;
+
+        first.call(&["select", "3"]).await;
+        first.call(&["set", "k", "v"]).await;
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            second.call(&["get", "k"]).await
+        );
+        assert_eq!(
+            RedisValue::error("ERR DB index is out of range"),
+            second.call(&["select", "4"]).await
+        );
+        second.call(&["select", "3"]).await;
+        assert_eq!(
+            RedisValue::bulk_string("v"),
+            second.call(&["get", "k"]).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_only_replica() {
+        let (redis, worker) = launch(&["--replicaof", "localhost", "6379"]);
+        let mut client = Connection::open(&redis, &worker, "c").await;
+
+        assert_eq!(
+            RedisValue::error("READONLY You can't write against a read only replica."),
+            client.call(&["set", "k", "v"]).await
+        );
+        // the master stream still writes
+        worker
+            .send(WorkerMessage {
+                command: command(&["set", "k", "v"]),
+                client_id: None,
+                responser: None,
+                offset: 0,
+                db: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            RedisValue::bulk_string("v"),
+            client.call(&["get", "k"]).await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wait() {
+        let (redis, worker) = launch(&[]);
+        let replica = ReplicaState::online();
+        redis
+            .replicas
+            .write()
+            .await
+            .insert("r".to_string(), replica);
+        let mut client = Connection::open(&redis, &worker, "c").await;
+
+        // nothing was written yet
+        assert_eq!(
+            RedisValue::Integer(1),
+            client.call(&["wait", "1", "0"]).await
+        );
+
+        client.call(&["set", "a", "1"]).await;
+        assert_eq!(
+            RedisValue::Integer(0),
+            client.call(&["wait", "1", "10"]).await
+        );
+
+        client.send(&["wait", "1", "0"]).await;
+        let offset = redis.replication.read().await.offset.to_string();
+        worker
+            .send(WorkerMessage {
+                command: command(&["replconf", "ack", &offset]),
+                client_id: Some("r".to_string()),
+                responser: None,
+                offset: 0,
+                db: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(RedisValue::Integer(1), client.replies.recv().await.unwrap());
+    }
+}
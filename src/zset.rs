@@ -0,0 +1,985 @@
+use std::collections::HashMap;
+
+use crate::command::{CommandArgs, RedisCommandError, ScanOptions, SetCondition};
+use crate::object::{normalize_range, scan, wrong_type, RedisObject};
+use crate::redis::{ExpiryMode, Store, StoreItem};
+use crate::skiplist::{Iter, SkipList};
+use crate::utilities;
+use crate::value::{RedisBulkString, RedisValue};
+
+// members are indexed both by name, for their score, and by score, for their rank
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<Vec<u8>, f64>,
+    list: SkipList,
+}
+
+impl PartialEq for SortedSet {
+    fn eq(&self, other: &SortedSet) -> bool {
+        self.scores == other.scores
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        SortedSet {
+            scores: HashMap::new(),
+            list: SkipList::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    // add a member or update its score, true if the member is new
+    pub fn insert(&mut self, member: Vec<u8>, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                if old != score {
+                    self.list.remove(old, &member);
+                    self.list.insert(score, member);
+                }
+                false
+            }
+            None => {
+                self.list.insert(score, member);
+                true
+            }
+        }
+    }
+
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.list.remove(score, member),
+            None => false,
+        }
+    }
+
+    // the position of a member from zero, by ascending scores
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        self.list.rank(score, member)
+    }
+
+    pub fn count_while(&self, predicate: impl Fn(f64, &[u8]) -> bool) -> usize {
+        self.list.count_while(predicate)
+    }
+
+    pub fn iter_from(&self, index: usize, rev: bool) -> Iter<'_> {
+        self.list.iter_from(index, rev)
+    }
+
+    // the members in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Vec<u8>, &f64)> {
+        self.scores.iter()
+    }
+
+    // the members from the cursor on with their scores, and the cursor to go on from
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Vec<u8>, f64)>) {
+        scan(
+            self.scores
+                .iter()
+                .map(|(member, score)| (member.as_slice(), (member, *score))),
+            cursor,
+            count,
+        )
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    // a score prefixed by `(` is excluded from the range
+    fn parse(bound: &RedisBulkString) -> Result<ScoreBound, RedisCommandError> {
+        let bound: String = bound.into();
+        let (exclusive, score) = match bound.strip_prefix('(') {
+            Some(score) => (true, score),
+            None => (false, bound.as_str()),
+        };
+        match score.parse::<f64>() {
+            Ok(score) if !score.is_nan() && exclusive => Ok(ScoreBound::Exclusive(score)),
+            Ok(score) if !score.is_nan() => Ok(ScoreBound::Inclusive(score)),
+            _ => Err(RedisCommandError::Invalid(
+                "min or max is not a float".to_string(),
+            )),
+        }
+    }
+
+    fn below_min(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(min) => score < min,
+            ScoreBound::Exclusive(min) => score <= min,
+        }
+    }
+
+    fn within_max(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+impl From<&ScoreBound> for RedisValue {
+    fn from(bound: &ScoreBound) -> RedisValue {
+        let bound = match bound {
+            ScoreBound::Inclusive(score) => utilities::format_float(*score),
+            ScoreBound::Exclusive(score) => format!("({}", utilities::format_float(*score)),
+        };
+        RedisValue::bulk_string(bound.as_str())
+    }
+}
+
+// a bound of a range of members sharing the same score, compared as bytes
+#[derive(PartialEq, Debug, Clone)]
+pub enum LexBound {
+    Inclusive(Vec<u8>),
+    Exclusive(Vec<u8>),
+    // `-` and `+`, before and after every member
+    Min,
+    Max,
+}
+
+impl LexBound {
+    fn parse(bound: &RedisBulkString) -> Result<LexBound, RedisCommandError> {
+        match bound.data.split_first() {
+            Some((b'[', member)) => Ok(LexBound::Inclusive(member.to_vec())),
+            Some((b'(', member)) => Ok(LexBound::Exclusive(member.to_vec())),
+            Some((b'-', [])) => Ok(LexBound::Min),
+            Some((b'+', [])) => Ok(LexBound::Max),
+            _ => Err(RedisCommandError::Invalid(
+                "min or max not valid string range item".to_string(),
+            )),
+        }
+    }
+
+    fn below_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(min) => member < min.as_slice(),
+            LexBound::Exclusive(min) => member <= min.as_slice(),
+            LexBound::Min => false,
+            LexBound::Max => true,
+        }
+    }
+
+    fn within_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(max) => member <= max.as_slice(),
+            LexBound::Exclusive(max) => member < max.as_slice(),
+            LexBound::Min => false,
+            LexBound::Max => true,
+        }
+    }
+}
+
+impl From<&LexBound> for RedisValue {
+    fn from(bound: &LexBound) -> RedisValue {
+        let bound = match bound {
+            LexBound::Inclusive(member) => [b"[", member.as_slice()].concat(),
+            LexBound::Exclusive(member) => [b"(", member.as_slice()].concat(),
+            LexBound::Min => b"-".to_vec(),
+            LexBound::Max => b"+".to_vec(),
+        };
+        RedisValue::bulk_string_from_bytes(bound.as_slice())
+    }
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum RangeBy {
+    // start and stop positions, which may be negative
+    Rank(i64, i64),
+    // min and max, whatever the direction of the range
+    Score(ScoreBound, ScoreBound),
+    Lex(LexBound, LexBound),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct RangeOptions {
+    pub by: RangeBy,
+    // from the highest score to the lowest one
+    pub rev: bool,
+    // offset and count, a negative count returns everything after the offset
+    pub limit: Option<(i64, i64)>,
+    pub withscores: bool,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ScoreComparison {
+    // only update a score to a greater one
+    Gt,
+    // only update a score to a lower one
+    Lt,
+}
+
+#[derive(PartialEq, Debug, Clone, Default)]
+pub struct AddOptions {
+    pub condition: Option<SetCondition>,
+    pub comparison: Option<ScoreComparison>,
+    // count the changed members instead of the added ones
+    pub ch: bool,
+    // increment the score of the single member, like `ZINCRBY`
+    pub incr: bool,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum ZSetCommand {
+    Add(RedisBulkString, AddOptions, Vec<(f64, RedisBulkString)>),
+    IncrBy(RedisBulkString, f64, RedisBulkString),
+    Rem(RedisBulkString, Vec<RedisBulkString>),
+    Card(RedisBulkString),
+    Score(RedisBulkString, RedisBulkString),
+    MScore(RedisBulkString, Vec<RedisBulkString>),
+    // key, member, whether ranks are counted from the highest score
+    Rank(RedisBulkString, RedisBulkString, bool),
+    Count(RedisBulkString, ScoreBound, ScoreBound),
+    Range(RedisBulkString, RangeOptions),
+    // key, whether to pop the highest scores, count
+    Pop(RedisBulkString, bool, Option<usize>),
+    Scan(RedisBulkString, u64, ScanOptions),
+}
+
+fn parse_add(args: &mut CommandArgs) -> Result<ZSetCommand, RedisCommandError> {
+    let key = args.next_key()?;
+    let mut options = AddOptions::default();
+    let mut flags = vec![];
+    let mut elements = Vec::new();
+    while !args.is_empty() {
+        let arg = args.next_bulk_string()?;
+        let flag: String = (&arg).into();
+        let flag = flag.to_lowercase();
+        match flag.as_str() {
+            "nx" | "xx" | "gt" | "lt" if elements.is_empty() => flags.push(flag),
+            "ch" if elements.is_empty() => options.ch = true,
+            "incr" if elements.is_empty() => options.incr = true,
+            _ => {
+                let score = match flag.parse::<f64>() {
+                    Ok(score) if !score.is_nan() => score,
+                    _ => return Err(RedisCommandError::NotFloat),
+                };
+                if args.is_empty() {
+                    return Err(RedisCommandError::IlleagalArg);
+                }
+                elements.push((score, args.next_bulk_string()?));
+            }
+        }
+    }
+    if elements.is_empty() {
+        return Err(args.wrong_number());
+    }
+    let invalid = |message: &str| Err(RedisCommandError::Invalid(message.to_string()));
+    let has = |flag: &str| flags.iter().any(|f| f == flag);
+    if has("nx") && has("xx") {
+        return invalid("XX and NX options at the same time are not compatible");
+    }
+    if [has("nx"), has("gt"), has("lt")]
+        .iter()
+        .filter(|f| **f)
+        .count()
+        > 1
+    {
+        return invalid("GT, LT, and/or NX options at the same time are not compatible");
+    }
+    options.condition = if has("nx") {
+        Some(SetCondition::Nx)
+    } else if has("xx") {
+        Some(SetCondition::Xx)
+    } else {
+        None
+    };
+    options.comparison = if has("gt") {
+        Some(ScoreComparison::Gt)
+    } else if has("lt") {
+        Some(ScoreComparison::Lt)
+    } else {
+        None
+    };
+    if options.incr && elements.len() > 1 {
+        return invalid("INCR option supports a single increment-element pair");
+    }
+    Ok(ZSetCommand::Add(key, options, elements))
+}
+
+// every form of range, `ZRANGE` with its options and the legacy commands
+fn parse_range(
+    command_name: &str,
+    args: &mut CommandArgs,
+) -> Result<ZSetCommand, RedisCommandError> {
+    let key = args.next_key()?;
+    let start = args.next_bulk_string()?;
+    let stop = args.next_bulk_string()?;
+    let accepted: &[&str] = match command_name {
+        "zrange" => &["byscore", "bylex", "rev", "limit", "withscores"],
+        "zrevrange" => &["withscores"],
+        "zrangebyscore" | "zrevrangebyscore" => &["limit", "withscores"],
+        _ => &["limit"],
+    };
+    let mut by = if command_name.ends_with("byscore") {
+        "byscore"
+    } else if command_name.ends_with("bylex") {
+        "bylex"
+    } else {
+        "byrank"
+    };
+    let mut rev = command_name.starts_with("zrev");
+    let mut limit = None;
+    let mut withscores = false;
+    while !args.is_empty() {
+        let option = args.next_string()?.to_lowercase();
+        if !accepted.contains(&option.as_str()) {
+            return Err(RedisCommandError::IlleagalArg);
+        }
+        match option.as_str() {
+            "byscore" => by = "byscore",
+            "bylex" => by = "bylex",
+            "rev" => rev = true,
+            "limit" => limit = Some((args.next_integer()?, args.next_integer()?)),
+            _ => withscores = true,
+        }
+    }
+    if limit.is_some() && by == "byrank" {
+        return Err(RedisCommandError::Invalid(
+            "syntax error, LIMIT is only supported in combination with either BYSCORE or BYLEX"
+                .to_string(),
+        ));
+    }
+    if withscores && by == "bylex" {
+        return Err(RedisCommandError::Invalid(
+            "syntax error, WITHSCORES not supported in combination with BYLEX".to_string(),
+        ));
+    }
+    // the bounds of a reversed range by score or lex are given from max to min
+    let (min, max) = if rev {
+        (&stop, &start)
+    } else {
+        (&start, &stop)
+    };
+    let by = match by {
+        "byscore" => RangeBy::Score(ScoreBound::parse(min)?, ScoreBound::parse(max)?),
+        "bylex" => RangeBy::Lex(LexBound::parse(min)?, LexBound::parse(max)?),
+        _ => {
+            let parse = |index: &RedisBulkString| {
+                let index: String = index.into();
+                index.parse().map_err(|_| RedisCommandError::NotInteger)
+            };
+            RangeBy::Rank(parse(&start)?, parse(&stop)?)
+        }
+    };
+    Ok(ZSetCommand::Range(
+        key,
+        RangeOptions {
+            by,
+            rev,
+            limit,
+            withscores,
+        },
+    ))
+}
+
+impl ZSetCommand {
+    pub fn parse(
+        command_name: &str,
+        args: &[RedisValue],
+    ) -> Result<ZSetCommand, RedisCommandError> {
+        let mut args = CommandArgs::new(command_name, args);
+        let command = match command_name {
+            "zadd" => parse_add(&mut args)?,
+            "zrange" | "zrevrange" | "zrangebyscore" | "zrevrangebyscore" | "zrangebylex"
+            | "zrevrangebylex" => parse_range(command_name, &mut args)?,
+            _ => {
+                let key = args.next_key()?;
+                match command_name {
+                    "zincrby" => {
+                        let increment = args.next_float()?;
+                        ZSetCommand::IncrBy(key, increment, args.next_bulk_string()?)
+                    }
+                    "zrem" => ZSetCommand::Rem(key, args.rest()?),
+                    "zcard" => ZSetCommand::Card(key),
+                    "zscore" => ZSetCommand::Score(key, args.next_bulk_string()?),
+                    "zmscore" => ZSetCommand::MScore(key, args.rest()?),
+                    "zrank" => ZSetCommand::Rank(key, args.next_bulk_string()?, false),
+                    "zrevrank" => ZSetCommand::Rank(key, args.next_bulk_string()?, true),
+                    "zcount" => {
+                        let min = ScoreBound::parse(&args.next_bulk_string()?)?;
+                        ZSetCommand::Count(key, min, ScoreBound::parse(&args.next_bulk_string()?)?)
+                    }
+                    "zpopmin" | "zpopmax" => {
+                        let count = if args.is_empty() {
+                            None
+                        } else {
+                            let count: i64 = args.next_integer()?;
+                            if count < 0 {
+                                return Err(RedisCommandError::NotPositive);
+                            }
+                            Some(count as usize)
+                        };
+                        ZSetCommand::Pop(key, command_name == "zpopmax", count)
+                    }
+                    "zscan" => {
+                        let cursor = args.next_cursor()?;
+                        ZSetCommand::Scan(key, cursor, args.next_scan_options(&[])?)
+                    }
+                    name => return Err(RedisCommandError::UnknownCommand(name.to_string())),
+                }
+            }
+        };
+        args.end()?;
+        Ok(command)
+    }
+
+    pub fn key(&self) -> &RedisBulkString {
+        match self {
+            ZSetCommand::Add(key, _, _)
+            | ZSetCommand::IncrBy(key, _, _)
+            | ZSetCommand::Rem(key, _)
+            | ZSetCommand::Card(key)
+            | ZSetCommand::Score(key, _)
+            | ZSetCommand::MScore(key, _)
+            | ZSetCommand::Rank(key, _, _)
+            | ZSetCommand::Count(key, _, _)
+            | ZSetCommand::Range(key, _)
+            | ZSetCommand::Pop(key, _, _)
+            | ZSetCommand::Scan(key, _, _) => key,
+        }
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(
+            self,
+            ZSetCommand::Add(..)
+                | ZSetCommand::IncrBy(..)
+                | ZSetCommand::Rem(..)
+                | ZSetCommand::Pop(..)
+        )
+    }
+}
+
+impl From<&ZSetCommand> for Vec<RedisValue> {
+    fn from(zset_command: &ZSetCommand) -> Vec<RedisValue> {
+        let command =
+            |name: &str, key: &RedisBulkString| vec![RedisValue::bulk_string(name), key.into()];
+        let float = |f: &f64| RedisValue::bulk_string(utilities::format_float(*f).as_str());
+        let integer = |i: i64| RedisValue::bulk_string(i.to_string().as_str());
+        match zset_command {
+            ZSetCommand::Add(key, options, elements) => {
+                let mut vs = command("zadd", key);
+                match options.condition {
+                    Some(SetCondition::Nx) => vs.push(RedisValue::bulk_string("nx")),
+                    Some(SetCondition::Xx) => vs.push(RedisValue::bulk_string("xx")),
+                    None => {}
+                }
+                match options.comparison {
+                    Some(ScoreComparison::Gt) => vs.push(RedisValue::bulk_string("gt")),
+                    Some(ScoreComparison::Lt) => vs.push(RedisValue::bulk_string("lt")),
+                    None => {}
+                }
+                if options.ch {
+                    vs.push(RedisValue::bulk_string("ch"));
+                }
+                if options.incr {
+                    vs.push(RedisValue::bulk_string("incr"));
+                }
+                for (score, member) in elements {
+                    vs.push(float(score));
+                    vs.push(member.into());
+                }
+                vs
+            }
+            ZSetCommand::IncrBy(key, increment, member) => {
+                let mut vs = command("zincrby", key);
+                vs.extend([float(increment), member.into()]);
+                vs
+            }
+            ZSetCommand::Rem(key, members) | ZSetCommand::MScore(key, members) => {
+                let name = match zset_command {
+                    ZSetCommand::Rem(..) => "zrem",
+                    _ => "zmscore",
+                };
+                let mut vs = command(name, key);
+                vs.extend(members.iter().map(|m| m.into()));
+                vs
+            }
+            ZSetCommand::Card(key) => command("zcard", key),
+            ZSetCommand::Score(key, member) => {
+                let mut vs = command("zscore", key);
+                vs.push(member.into());
+                vs
+            }
+            ZSetCommand::Rank(key, member, rev) => {
+                let mut vs = command(if *rev { "zrevrank" } else { "zrank" }, key);
+                vs.push(member.into());
+                vs
+            }
+            ZSetCommand::Count(key, min, max) => {
+                let mut vs = command("zcount", key);
+                vs.extend([min.into(), max.into()]);
+                vs
+            }
+            ZSetCommand::Range(key, options) => {
+                let mut vs = command("zrange", key);
+                let (min, max, by): (RedisValue, RedisValue, _) = match &options.by {
+                    RangeBy::Rank(start, stop) => (integer(*start), integer(*stop), None),
+                    RangeBy::Score(min, max) => (min.into(), max.into(), Some("byscore")),
+                    RangeBy::Lex(min, max) => (min.into(), max.into(), Some("bylex")),
+                };
+                if options.rev && by.is_some() {
+                    vs.extend([max, min]);
+                } else {
+                    vs.extend([min, max]);
+                }
+                if let Some(by) = by {
+                    vs.push(RedisValue::bulk_string(by));
+                }
+                if options.rev {
+                    vs.push(RedisValue::bulk_string("rev"));
+                }
+                if let Some((offset, count)) = options.limit {
+                    vs.extend([
+                        RedisValue::bulk_string("limit"),
+                        integer(offset),
+                        integer(count),
+                    ]);
+                }
+                if options.withscores {
+                    vs.push(RedisValue::bulk_string("withscores"));
+                }
+                vs
+            }
+            ZSetCommand::Pop(key, max, count) => {
+                let mut vs = command(if *max { "zpopmax" } else { "zpopmin" }, key);
+                if let Some(count) = count {
+                    vs.push(integer(*count as i64));
+                }
+                vs
+            }
+            ZSetCommand::Scan(key, cursor, options) => {
+                let mut vs = command("zscan", key);
+                vs.push(RedisValue::bulk_string(cursor.to_string().as_str()));
+                vs.extend(options.to_args());
+                vs
+            }
+        }
+    }
+}
+
+// the sorted set stored at the key, none if the key does not exist
+pub fn get_zset<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<Option<&'a mut SortedSet>, RedisValue> {
+    match store.get_alive(key, now, mode) {
+        None => Ok(None),
+        Some(StoreItem {
+            value: RedisObject::ZSet(zset),
+            ..
+        }) => Ok(Some(zset)),
+        Some(_) => Err(wrong_type()),
+    }
+}
+
+// the sorted set stored at the key, an empty one is created if the key does not exist
+fn get_or_create_zset<'a>(
+    store: &'a mut Store,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<&'a mut SortedSet, RedisValue> {
+    get_zset(store, key, now, mode)?;
+    let item = store.get_or_insert_with(key, || StoreItem {
+        value: RedisObject::ZSet(SortedSet::new()),
+        expired_at: 0,
+    });
+    match &mut item.value {
+        RedisObject::ZSet(zset) => Ok(zset),
+        _ => Err(wrong_type()),
+    }
+}
+
+fn score_reply(score: f64) -> RedisValue {
+    RedisValue::bulk_string(utilities::format_float(score).as_str())
+}
+
+fn not_a_number() -> RedisValue {
+    RedisValue::error("ERR resulting score is not a number (NaN)")
+}
+
+// the members selected by a range, in the order they are replied
+fn select<'a>(zset: &'a SortedSet, options: &RangeOptions) -> Vec<(&'a [u8], f64)> {
+    let length = zset.len();
+    // the selection is the members from `start` to `end` excluded, by ascending scores
+    let (start, end) = match &options.by {
+        RangeBy::Rank(start, stop) => match normalize_range(*start, *stop, length) {
+            // the positions of a reversed range are counted from the highest score
+            Some((start, stop)) if options.rev => (length - 1 - stop, length - start),
+            Some((start, stop)) => (start, stop + 1),
+            None => return vec![],
+        },
+        RangeBy::Score(min, max) => (
+            zset.count_while(|score, _| min.below_min(score)),
+            zset.count_while(|score, _| max.within_max(score)),
+        ),
+        RangeBy::Lex(min, max) => (
+            zset.count_while(|_, member| min.below_min(member)),
+            zset.count_while(|_, member| max.within_max(member)),
+        ),
+    };
+    if start >= end {
+        return vec![];
+    }
+    let (offset, count) = match options.limit {
+        Some((offset, _)) if offset < 0 => return vec![],
+        Some((offset, count)) => (
+            offset as usize,
+            usize::try_from(count).unwrap_or(usize::MAX),
+        ),
+        None => (0, usize::MAX),
+    };
+    let first = if options.rev { end - 1 } else { start };
+    zset.iter_from(first, options.rev)
+        .take(end - start)
+        .skip(offset)
+        .take(count)
+        .collect()
+}
+
+pub fn execute(store: &mut Store, command: &ZSetCommand, now: u64, mode: ExpiryMode) -> RedisValue {
+    let key: String = command.key().into();
+    let response = match run(store, command, &key, now, mode) {
+        Ok(response) => response,
+        Err(e) => e,
+    };
+    store.remove_if_empty(&key);
+    response
+}
+
+fn run(
+    store: &mut Store,
+    command: &ZSetCommand,
+    key: &str,
+    now: u64,
+    mode: ExpiryMode,
+) -> Result<RedisValue, RedisValue> {
+    let response = match command {
+        ZSetCommand::Add(_, options, elements) => {
+            let zset = get_or_create_zset(store, key, now, mode)?;
+            let mut added = 0;
+            let mut changed = 0;
+            let mut last = None;
+            for (score, member) in elements {
+                let current = zset.score(&member.data);
+                let score = if options.incr {
+                    current.unwrap_or(0.0) + score
+                } else {
+                    *score
+                };
+                if score.is_nan() {
+                    return Err(not_a_number());
+                }
+                let accepted = match current {
+                    None => options.condition != Some(SetCondition::Xx),
+                    Some(current) => {
+                        options.condition != Some(SetCondition::Nx)
+                            && match options.comparison {
+                                Some(ScoreComparison::Gt) => score > current,
+                                Some(ScoreComparison::Lt) => score < current,
+                                None => true,
+                            }
+                    }
+                };
+                if !accepted {
+                    last = None;
+                    continue;
+                }
+                if current.is_none() {
+                    added += 1;
+                }
+                if current != Some(score) {
+                    changed += 1;
+                }
+                zset.insert(member.data.clone(), score);
+                last = Some(score);
+            }
+            if options.incr {
+                last.map_or(RedisValue::null_bulk_string(), score_reply)
+            } else if options.ch {
+                RedisValue::Integer(changed)
+            } else {
+                RedisValue::Integer(added)
+            }
+        }
+        ZSetCommand::IncrBy(_, increment, member) => {
+            let zset = get_or_create_zset(store, key, now, mode)?;
+            let score = zset.score(&member.data).unwrap_or(0.0) + increment;
+            if score.is_nan() {
+                return Err(not_a_number());
+            }
+            zset.insert(member.data.clone(), score);
+            score_reply(score)
+        }
+        ZSetCommand::Rem(_, members) => {
+            let removed = match get_zset(store, key, now, mode)? {
+                Some(zset) => members
+                    .iter()
+                    .filter(|member| zset.remove(&member.data))
+                    .count(),
+                None => 0,
+            };
+            RedisValue::Integer(removed as i64)
+        }
+        ZSetCommand::Card(_) => {
+            let length = get_zset(store, key, now, mode)?.map_or(0, |zset| zset.len());
+            RedisValue::Integer(length as i64)
+        }
+        ZSetCommand::Score(_, member) => {
+            let zset = get_zset(store, key, now, mode)?;
+            match zset.and_then(|zset| zset.score(&member.data)) {
+                Some(score) => score_reply(score),
+                None => RedisValue::null_bulk_string(),
+            }
+        }
+        ZSetCommand::MScore(_, members) => {
+            let zset = get_zset(store, key, now, mode)?;
+            let scores = members
+                .iter()
+                .map(
+                    |member| match zset.as_ref().and_then(|zset| zset.score(&member.data)) {
+                        Some(score) => score_reply(score),
+                        None => RedisValue::null_bulk_string(),
+                    },
+                )
+                .collect();
+            RedisValue::Array(scores)
+        }
+        ZSetCommand::Rank(_, member, rev) => {
+            let zset = match get_zset(store, key, now, mode)? {
+                Some(zset) => zset,
+                None => return Ok(RedisValue::null_bulk_string()),
+            };
+            match zset.rank(&member.data) {
+                Some(rank) if *rev => RedisValue::Integer((zset.len() - 1 - rank) as i64),
+                Some(rank) => RedisValue::Integer(rank as i64),
+                None => RedisValue::null_bulk_string(),
+            }
+        }
+        ZSetCommand::Count(_, min, max) => {
+            let count = match get_zset(store, key, now, mode)? {
+                Some(zset) => {
+                    let end = zset.count_while(|score, _| max.within_max(score));
+                    end.saturating_sub(zset.count_while(|score, _| min.below_min(score)))
+                }
+                None => 0,
+            };
+            RedisValue::Integer(count as i64)
+        }
+        ZSetCommand::Range(_, options) => {
+            let zset = match get_zset(store, key, now, mode)? {
+                Some(zset) => zset,
+                None => return Ok(RedisValue::Array(vec![])),
+            };
+            let mut values = vec![];
+            for (member, score) in select(zset, options) {
+                values.push(RedisValue::bulk_string_from_bytes(member));
+                if options.withscores {
+                    values.push(score_reply(score));
+                }
+            }
+            RedisValue::Array(values)
+        }
+        ZSetCommand::Pop(_, max, count) => {
+            let zset = match get_zset(store, key, now, mode)? {
+                Some(zset) => zset,
+                None => return Ok(RedisValue::Array(vec![])),
+            };
+            let first = if *max { zset.len() - 1 } else { 0 };
+            let popped: Vec<(Vec<u8>, f64)> = zset
+                .iter_from(first, *max)
+                .take(count.unwrap_or(1))
+                .map(|(member, score)| (member.to_vec(), score))
+                .collect();
+            let mut values = vec![];
+            for (member, score) in popped {
+                zset.remove(&member);
+                values.push(RedisValue::bulk_string_from_bytes(member.as_slice()));
+                values.push(score_reply(score));
+            }
+            RedisValue::Array(values)
+        }
+        ZSetCommand::Scan(_, cursor, options) => {
+            let zset = match get_zset(store, key, now, mode)? {
+                Some(zset) => zset,
+                None => {
+                    return Ok(RedisValue::Array(vec![
+                        RedisValue::bulk_string("0"),
+                        RedisValue::Array(vec![]),
+                    ]))
+                }
+            };
+            let (cursor, members) = zset.scan(*cursor, options.count);
+            let mut values = vec![];
+            for (member, score) in members
+                .into_iter()
+                .filter(|(member, _)| options.matches(member))
+            {
+                values.push(RedisValue::bulk_string_from_bytes(member.as_slice()));
+                values.push(score_reply(score));
+            }
+            RedisValue::Array(vec![
+                RedisValue::bulk_string(cursor.to_string().as_str()),
+                RedisValue::Array(values),
+            ])
+        }
+    };
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::run_command;
+
+    fn elements(values: &[&str]) -> RedisValue {
+        RedisValue::Array(values.iter().map(|s| RedisValue::bulk_string(*s)).collect())
+    }
+
+    fn leaderboard() -> Store {
+        let mut store = Store::default();
+        run_command(
+            &mut store,
+            &[
+                "zadd", "z", "1", "a", "2", "b", "2", "c", "3.5", "d", "-inf", "e",
+            ],
+        );
+        store
+    }
+
+    #[test]
+    fn test_add_flags() {
+        let mut store = leaderboard();
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(&mut store, &["zadd", "z", "nx", "10", "a", "10", "f"])
+        );
+        assert_eq!(
+            RedisValue::Integer(1),
+            run_command(
+                &mut store,
+                &["zadd", "z", "xx", "gt", "ch", "0", "a", "5", "b", "7", "g"]
+            )
+        );
+        assert_eq!(
+            elements(&["e", "-inf", "a", "1", "c", "2", "d", "3.5", "b", "5", "f", "10"]),
+            run_command(&mut store, &["zrange", "z", "0", "-1", "withscores"])
+        );
+        assert_eq!(
+            RedisValue::bulk_string("1.5"),
+            run_command(&mut store, &["zadd", "z", "incr", "0.5", "a"])
+        );
+        assert_eq!(
+            RedisValue::null_bulk_string(),
+            run_command(&mut store, &["zadd", "z", "lt", "incr", "1", "a"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR GT, LT, and/or NX options at the same time are not compatible"),
+            run_command(&mut store, &["zadd", "z", "nx", "gt", "1", "a"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR resulting score is not a number (NaN)"),
+            run_command(&mut store, &["zincrby", "z", "+inf", "e"])
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let mut store = leaderboard();
+        assert_eq!(
+            elements(&["d", "c", "b"]),
+            run_command(&mut store, &["zrange", "z", "0", "2", "rev"])
+        );
+        assert_eq!(
+            elements(&["b", "c", "d"]),
+            run_command(&mut store, &["zrangebyscore", "z", "(1", "+inf"])
+        );
+        assert_eq!(
+            elements(&["c", "b"]),
+            run_command(
+                &mut store,
+                &["zrange", "z", "3.5", "-inf", "byscore", "rev", "limit", "1", "2"]
+            )
+        );
+        assert_eq!(
+            elements(&["c", "2"]),
+            run_command(
+                &mut store,
+                &[
+                    "zrevrangebyscore",
+                    "z",
+                    "2",
+                    "2",
+                    "withscores",
+                    "limit",
+                    "0",
+                    "1"
+                ]
+            )
+        );
+        assert_eq!(
+            RedisValue::Integer(3),
+            run_command(&mut store, &["zcount", "z", "1", "(3.5"])
+        );
+        assert_eq!(
+            RedisValue::error("ERR min or max is not a float"),
+            run_command(&mut store, &["zrangebyscore", "z", "x", "1"])
+        );
+
+        let mut store = Store::default();
+        run_command(
+            &mut store,
+            &["zadd", "l", "0", "a", "0", "b", "0", "c", "0", "d"],
+        );
+        assert_eq!(
+            elements(&["b", "c"]),
+            run_command(&mut store, &["zrange", "l", "[b", "(d", "bylex"])
+        );
+        assert_eq!(
+            elements(&["d", "c"]),
+            run_command(&mut store, &["zrevrangebylex", "l", "+", "(b"])
+        );
+    }
+
+    #[test]
+    fn test_rank_and_pop() {
+        let mut store = leaderboard();
+        assert_eq!(
+            RedisValue::Integer(2),
+            run_command(&mut store, &["zrank", "z", "b"])
+        );
+        assert_eq!(
+            RedisValue::Integer(0),
+            run_command(&mut store, &["zrevrank", "z", "d"])
+        );
+        assert_eq!(
+            elements(&["d", "3.5", "c", "2"]),
+            run_command(&mut store, &["zpopmax", "z", "2"])
+        );
+        assert_eq!(
+            elements(&["e", "-inf"]),
+            run_command(&mut store, &["zpopmin", "z"])
+        );
+        run_command(&mut store, &["zrem", "z", "a", "b"]);
+        assert!(store.is_empty());
+    }
+}